@@ -1,82 +0,0 @@
-use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
-use rand::Rng;
-use tokio::task;
-use std::sync::{Arc, Mutex};
-
-#[derive(Debug)]
-struct LobotomySideEffectsRemediation {
-    patient_id: String,
-    symptom_data: Arc<Mutex<Vec<f64>>>,
-    data_sender: mpsc::Sender<f64>,
-    remediation_status: Arc<Mutex<bool>>,
-}
-
-impl LobotomySideEffectsRemediation {
-    fn new(patient_id: &str) -> Self {
-        let (tx, _) = mpsc::channel(100);
-        LobotomySideEffectsRemediation {
-            patient_id: patient_id.to_string(),
-            symptom_data: Arc::new(Mutex::new(vec![])),
-            data_sender: tx,
-            remediation_status: Arc::new(Mutex::new(false)),
-        }
-    }
-
-    async fn initialize(&self) -> Result<(), String> {
-        println!("Initializing remediation process for patient {}", self.patient_id);
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        Ok(())
-    }
-
-    async fn collect_symptoms(&self, duration: Duration) -> Result<(), String> {
-        let start = Instant::now();
-        let mut rng = rand::thread_rng();
-        while start.elapsed() < duration {
-            let symptom_severity = rng.gen_range(0.0..1.0);
-            self.data_sender.send(symptom_severity).await.unwrap();
-            tokio::time::sleep(Duration::from_millis(150)).await;
-        }
-        Ok(())
-    }
-
-    fn analyze_symptoms(&self) -> f64 {
-        let data = self.symptom_data.lock().unwrap();
-        let sum: f64 = data.iter().sum();
-        sum / data.len() as f64
-    }
-
-    async fn apply_remediation(&self) -> Result<(), String> {
-        let severity = self.analyze_symptoms();
-        if severity > 0.7 {
-            println!("Remediation applied to patient {}. High symptom severity detected: {}", self.patient_id, severity);
-            tokio::time::sleep(Duration::from_secs(2)).await;
-            *self.remediation_status.lock().unwrap() = true;
-        } else {
-            println!("No remediation required for patient {}. Symptom severity: {}", self.patient_id, severity);
-        }
-        Ok(())
-    }
-
-    fn reset_data(&self) {
-        let mut data = self.symptom_data.lock().unwrap();
-        data.clear();
-    }
-}
-
-async fn process_remediation_for_patient(device: &LobotomySideEffectsRemediation) -> Result<(), String> {
-    device.initialize().await?;
-    device.collect_symptoms(Duration::from_secs(5)).await?;
-    device.apply_remediation().await?;
-    device.reset_data();
-    Ok(())
-}
-
-#[tokio::main]
-async fn main() -> Result<(), String> {
-    let device = LobotomySideEffectsRemediation::new("P12345");
-    let device_handler = task::spawn(process_remediation_for_patient(&device));
-
-    device_handler.await??;
-    Ok(())
-}
@@ -1,108 +0,0 @@
-```rust
-use std::error::Error;
-use std::time::{Duration, Instant};
-use std::sync::{Arc, Mutex};
-use futures::executor::block_on;
-use tokio::{task, time};
-
-mod bluez;
-mod neurofeedback;
-mod signal_analysis;
-mod data_storage;
-
-#[derive(Debug)]
-struct BluetoothDevice {
-    address: String,
-    connection_state: bool,
-    data_stream: Arc<Mutex<Vec<f32>>>,
-}
-
-impl BluetoothDevice {
-    fn new(address: String) -> Self {
-        BluetoothDevice {
-            address,
-            connection_state: false,
-            data_stream: Arc::new(Mutex::new(vec![])),
-        }
-    }
-
-    async fn connect(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.connection_state {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "Device already connected")));
-        }
-        // Simulate Bluetooth™ connection
-        time::sleep(Duration::from_secs(2)).await;
-        self.connection_state = true;
-        println!("Device {} connected successfully", self.address);
-        Ok(())
-    }
-
-    async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
-        if !self.connection_state {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotConnected, "Device not connected")));
-        }
-        // Simulate Bluetooth™ disconnection
-        time::sleep(Duration::from_secs(1)).await;
-        self.connection_state = false;
-        println!("Device {} disconnected", self.address);
-        Ok(())
-    }
-
-    async fn read_signal(&self) -> Result<f32, Box<dyn Error>> {
-        let random_signal = rand::random::<f32>();
-        time::sleep(Duration::from_millis(100)).await;
-        Ok(random_signal)
-    }
-
-    async fn collect_data(&self, duration: Duration) -> Result<(), Box<dyn Error>> {
-        let start_time = Instant::now();
-        let mut data_lock = self.data_stream.lock().unwrap();
-        
-        while start_time.elapsed() < duration {
-            let signal = self.read_signal().await?;
-            data_lock.push(signal);
-        }
-        
-        Ok(())
-    }
-
-    fn analyze_data(&self) -> Result<f32, Box<dyn Error>> {
-        let data_lock = self.data_stream.lock().unwrap();
-        let sum: f32 = data_lock.iter().sum();
-        let average = sum / data_lock.len() as f32;
-        Ok(average)
-    }
-
-    async fn sync_with_cloud(&self) -> Result<(), Box<dyn Error>> {
-        let data_lock = self.data_stream.lock().unwrap();
-        // Simulate cloud sync
-        time::sleep(Duration::from_secs(1)).await;
-        println!("Synced data to cloud: {:?}", *data_lock);
-        Ok(())
-    }
-}
-
-async fn handle_device_operations(device: &mut BluetoothDevice) -> Result<(), Box<dyn Error>> {
-    device.connect().await?;
-    device.collect_data(Duration::from_secs(5)).await?;
-    let avg_signal = device.analyze_data()?;
-    println!("Average signal: {}", avg_signal);
-    device.sync_with_cloud().await?;
-    device.disconnect().await?;
-    Ok(())
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let mut device = BluetoothDevice::new("00:1A:7D:DA:71:13".to_string());
-    let task1 = task::spawn(handle_device_operations(&mut device));
-
-    let mut device2 = BluetoothDevice::new("00:1A:7D:DA:71:14".to_string());
-    let task2 = task::spawn(handle_device_operations(&mut device2));
-
-    task1.await??;
-    task2.await??;
-
-    Ok(())
-}
-```
\ No newline at end of file
@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+/// Limits for each artifact check; `None` disables a check.
+#[derive(Debug, Clone)]
+pub struct ArtifactThresholds {
+    /// Peak absolute amplitude (blinks, movement).
+    pub max_amplitude: Option<f64>,
+    /// Largest sample-to-sample step (jaw clench, electrode pops).
+    pub max_gradient: Option<f64>,
+    /// Standard deviation below which the electrode is considered flat.
+    pub min_std_dev: Option<f64>,
+    /// Excess kurtosis above which the window is dominated by transients.
+    pub max_kurtosis: Option<f64>,
+    /// Share of window power at the mains frequency.
+    pub max_line_noise_ratio: Option<f64>,
+    /// Amplifier rail; any sample at or beyond it marks saturation.
+    pub saturation_level: Option<f64>,
+}
+
+impl ArtifactThresholds {
+    /// Defaults for scalp EEG in microvolts.
+    pub fn eeg_microvolts() -> Self {
+        ArtifactThresholds {
+            max_amplitude: Some(100.0),
+            max_gradient: Some(50.0),
+            min_std_dev: Some(0.5),
+            max_kurtosis: Some(5.0),
+            max_line_noise_ratio: Some(0.3),
+            saturation_level: Some(187_500.0),
+        }
+    }
+
+    /// Defaults for slow, normalised (0..1) feature streams.
+    pub fn normalized() -> Self {
+        ArtifactThresholds {
+            max_amplitude: None,
+            max_gradient: None,
+            min_std_dev: Some(1e-4),
+            max_kurtosis: None,
+            max_line_noise_ratio: None,
+            saturation_level: Some(1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArtifactReason {
+    Saturation(f64),
+    Amplitude(f64),
+    Gradient(f64),
+    Flatline(f64),
+    Kurtosis(f64),
+    LineNoise(f64),
+}
+
+impl ArtifactReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArtifactReason::Saturation(_) => "saturation",
+            ArtifactReason::Amplitude(_) => "amplitude",
+            ArtifactReason::Gradient(_) => "gradient",
+            ArtifactReason::Flatline(_) => "flatline",
+            ArtifactReason::Kurtosis(_) => "kurtosis",
+            ArtifactReason::LineNoise(_) => "line noise",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowLabel {
+    Clean,
+    Contaminated(ArtifactReason),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Screening {
+    pub clean: Vec<f64>,
+    pub labels: Vec<WindowLabel>,
+}
+
+impl Screening {
+    pub fn rejected_windows(&self) -> usize {
+        self.labels.iter().filter(|label| **label != WindowLabel::Clean).count()
+    }
+
+    pub fn rejection_rate(&self) -> Option<f64> {
+        if self.labels.is_empty() {
+            None
+        } else {
+            Some(self.rejected_windows() as f64 / self.labels.len() as f64)
+        }
+    }
+
+    pub fn reasons(&self) -> BTreeMap<&'static str, usize> {
+        let mut reasons = BTreeMap::new();
+        for label in &self.labels {
+            if let WindowLabel::Contaminated(reason) = label {
+                *reasons.entry(reason.name()).or_insert(0) += 1;
+            }
+        }
+        reasons
+    }
+
+    pub fn summary(&self) -> String {
+        rejection_summary(self.labels.len(), self.rejected_windows(), &self.reasons())
+    }
+}
+
+fn rejection_summary(windows: usize, rejected: usize, reasons: &BTreeMap<&'static str, usize>) -> String {
+    if windows == 0 {
+        return "no windows to screen".to_string();
+    }
+    format!(
+        "rejected {} of {} windows ({:.1}%) {:?}",
+        rejected,
+        windows,
+        rejected as f64 / windows as f64 * 100.0,
+        reasons
+    )
+}
+
+/// What analysing a session produced: the mean of its clean windows and how much screening
+/// rejected to get there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionReport {
+    /// `None` if nothing clean was recorded.
+    pub mean: Option<f64>,
+    pub windows: usize,
+    pub rejected_windows: usize,
+    pub reasons: BTreeMap<&'static str, usize>,
+}
+
+impl SessionReport {
+    pub fn rejection_rate(&self) -> Option<f64> {
+        (self.windows > 0).then(|| self.rejected_windows as f64 / self.windows as f64)
+    }
+
+    pub fn summary(&self) -> String {
+        rejection_summary(self.windows, self.rejected_windows, &self.reasons)
+    }
+}
+
+/// Screens a live stream window by window, keeping only counts of what was rejected.
+#[derive(Debug, Clone)]
+pub struct StreamingScreener {
+    detector: ArtifactDetector,
+    pending: Vec<f64>,
+    windows: usize,
+    reasons: BTreeMap<&'static str, usize>,
+}
+
+impl StreamingScreener {
+    pub fn new(detector: ArtifactDetector) -> Self {
+        StreamingScreener {
+            pending: Vec::with_capacity(detector.window_len),
+            detector,
+            windows: 0,
+            reasons: BTreeMap::new(),
+        }
+    }
+
+    /// Buffers one sample; once a window is complete it is labelled and, if clean, returned.
+    pub fn push(&mut self, x: f64) -> Option<Vec<f64>> {
+        self.pending.push(x);
+        if self.pending.len() < self.detector.window_len {
+            return None;
+        }
+        self.screen_pending()
+    }
+
+    /// Screens whatever is left of a partial window as-is, like `ArtifactDetector::screen`
+    /// does for its trailing window. Call it when the stream ends.
+    pub fn finish(&mut self) -> Option<Vec<f64>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        self.screen_pending()
+    }
+
+    fn screen_pending(&mut self) -> Option<Vec<f64>> {
+        let window = std::mem::replace(&mut self.pending, Vec::with_capacity(self.detector.window_len));
+        self.windows += 1;
+        match self.detector.classify(&window) {
+            WindowLabel::Clean => Some(window),
+            WindowLabel::Contaminated(reason) => {
+                *self.reasons.entry(reason.name()).or_insert(0) += 1;
+                None
+            }
+        }
+    }
+
+    pub fn rejection_rate(&self) -> Option<f64> {
+        (self.windows > 0).then(|| self.rejected_windows() as f64 / self.windows as f64)
+    }
+
+    pub fn rejected_windows(&self) -> usize {
+        self.reasons.values().sum()
+    }
+
+    pub fn summary(&self) -> String {
+        rejection_summary(self.windows, self.rejected_windows(), &self.reasons)
+    }
+
+    /// Report for the windows screened so far; `mean` is computed by the caller from the clean ones.
+    pub fn report(&self, mean: Option<f64>) -> SessionReport {
+        SessionReport {
+            mean,
+            windows: self.windows,
+            rejected_windows: self.rejected_windows(),
+            reasons: self.reasons.clone(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.windows = 0;
+        self.reasons.clear();
+    }
+}
+
+/// Splits a signal into fixed windows and labels each one as clean or contaminated.
+#[derive(Debug, Clone)]
+pub struct ArtifactDetector {
+    thresholds: ArtifactThresholds,
+    sample_rate: f64,
+    window_len: usize,
+    mains_hz: f64,
+}
+
+impl ArtifactDetector {
+    pub fn new(thresholds: ArtifactThresholds, sample_rate: f64, window_len: usize) -> Self {
+        ArtifactDetector {
+            thresholds,
+            sample_rate,
+            window_len: window_len.max(1),
+            mains_hz: 50.0,
+        }
+    }
+
+    pub fn with_mains(mut self, mains_hz: f64) -> Self {
+        self.mains_hz = mains_hz;
+        self
+    }
+
+    pub fn classify(&self, window: &[f64]) -> WindowLabel {
+        if window.is_empty() {
+            return WindowLabel::Clean;
+        }
+        let t = &self.thresholds;
+        let peak = window.iter().fold(0.0_f64, |peak, x| peak.max(x.abs()));
+
+        if let Some(rail) = t.saturation_level {
+            if peak >= rail {
+                return WindowLabel::Contaminated(ArtifactReason::Saturation(peak));
+            }
+        }
+        if let Some(limit) = t.max_amplitude {
+            if peak > limit {
+                return WindowLabel::Contaminated(ArtifactReason::Amplitude(peak));
+            }
+        }
+        if let Some(limit) = t.max_gradient {
+            let gradient = window.windows(2).fold(0.0_f64, |max, pair| max.max((pair[1] - pair[0]).abs()));
+            if gradient > limit {
+                return WindowLabel::Contaminated(ArtifactReason::Gradient(gradient));
+            }
+        }
+
+        let n = window.len() as f64;
+        let mean = window.iter().sum::<f64>() / n;
+        let variance = window.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+        if let Some(limit) = t.min_std_dev {
+            if window.len() > 1 && variance.sqrt() < limit {
+                return WindowLabel::Contaminated(ArtifactReason::Flatline(variance.sqrt()));
+            }
+        }
+        if let Some(limit) = t.max_kurtosis {
+            if variance > 0.0 {
+                let kurtosis = window.iter().map(|x| (x - mean).powi(4)).sum::<f64>() / n / variance.powi(2) - 3.0;
+                if kurtosis > limit {
+                    return WindowLabel::Contaminated(ArtifactReason::Kurtosis(kurtosis));
+                }
+            }
+        }
+        if let Some(limit) = t.max_line_noise_ratio {
+            if self.mains_hz < self.sample_rate / 2.0 && variance > 0.0 {
+                let ratio = goertzel_power(window, mean, self.mains_hz, self.sample_rate) / variance;
+                if ratio > limit {
+                    return WindowLabel::Contaminated(ArtifactReason::LineNoise(ratio));
+                }
+            }
+        }
+        WindowLabel::Clean
+    }
+
+    /// Labels every window of `data` and keeps only the clean ones. A trailing partial
+    /// window is screened as-is.
+    pub fn screen(&self, data: &[f64]) -> Screening {
+        let mut screening = Screening::default();
+        for window in data.chunks(self.window_len) {
+            let label = self.classify(window);
+            if label == WindowLabel::Clean {
+                screening.clean.extend_from_slice(window);
+            }
+            screening.labels.push(label);
+        }
+        screening
+    }
+}
+
+/// Mean-square power of the component at `frequency`, via the Goertzel recurrence.
+fn goertzel_power(window: &[f64], mean: f64, frequency: f64, sample_rate: f64) -> f64 {
+    let coefficient = 2.0 * (2.0 * PI * frequency / sample_rate).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for x in window {
+        let s0 = (x - mean) + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    let magnitude_squared = s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+    let n = window.len() as f64;
+    2.0 * magnitude_squared / (n * n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 250.0;
+
+    /// One second of alpha-like background: 20 µV at 10 Hz plus 5 µV at 7 Hz.
+    fn clean_window() -> Vec<f64> {
+        (0..RATE as usize)
+            .map(|n| {
+                let t = n as f64 / RATE;
+                20.0 * (2.0 * PI * 10.0 * t).sin() + 5.0 * (2.0 * PI * 7.0 * t).sin()
+            })
+            .collect()
+    }
+
+    fn eeg_detector() -> ArtifactDetector {
+        ArtifactDetector::new(ArtifactThresholds::eeg_microvolts(), RATE, RATE as usize)
+    }
+
+    fn reason(label: WindowLabel) -> ArtifactReason {
+        match label {
+            WindowLabel::Contaminated(reason) => reason,
+            WindowLabel::Clean => panic!("window was not rejected"),
+        }
+    }
+
+    #[test]
+    fn clean_background_passes_every_check() {
+        assert_eq!(eeg_detector().classify(&clean_window()), WindowLabel::Clean);
+        assert_eq!(eeg_detector().classify(&[]), WindowLabel::Clean);
+    }
+
+    #[test]
+    fn saturation_detects_samples_at_the_rail() {
+        let mut window = clean_window();
+        window[100] = -187_500.0;
+        assert_eq!(reason(eeg_detector().classify(&window)), ArtifactReason::Saturation(187_500.0));
+    }
+
+    #[test]
+    fn amplitude_detects_a_blink() {
+        let mut window = clean_window();
+        for (n, x) in window.iter_mut().enumerate() {
+            *x += 150.0 * (-((n as f64 - 125.0) / 20.0).powi(2) / 2.0).exp();
+        }
+        match reason(eeg_detector().classify(&window)) {
+            ArtifactReason::Amplitude(peak) => assert!(peak > 100.0 && peak < 180.0, "{}", peak),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn gradient_detects_an_electrode_pop() {
+        let mut window = clean_window();
+        for x in &mut window[125..] {
+            *x += 60.0;
+        }
+        match reason(eeg_detector().classify(&window)) {
+            ArtifactReason::Gradient(step) => assert!(step > 50.0 && step < 70.0, "{}", step),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn flatline_detects_a_disconnected_electrode() {
+        let window: Vec<f64> = clean_window().iter().map(|x| 3.0 + x / 500.0).collect();
+        match reason(eeg_detector().classify(&window)) {
+            ArtifactReason::Flatline(std_dev) => assert!(std_dev < 0.5, "{}", std_dev),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn kurtosis_detects_sparse_transients() {
+        let mut window: Vec<f64> = clean_window().iter().map(|x| x / 5.0).collect();
+        for n in [40, 120, 200] {
+            window[n] += 40.0;
+        }
+        match reason(eeg_detector().classify(&window)) {
+            ArtifactReason::Kurtosis(kurtosis) => assert!(kurtosis > 5.0, "{}", kurtosis),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn line_noise_detects_power_at_the_mains_frequency() {
+        let mut window = clean_window();
+        for (n, x) in window.iter_mut().enumerate() {
+            *x += 20.0 * (2.0 * PI * 50.0 * n as f64 / RATE).sin();
+        }
+        match reason(eeg_detector().classify(&window)) {
+            ArtifactReason::LineNoise(ratio) => assert!((ratio - 200.0 / 412.5).abs() < 0.01, "{}", ratio),
+            other => panic!("{:?}", other),
+        }
+
+        // The same window is clean where the mains run at 60 Hz.
+        assert_eq!(eeg_detector().with_mains(60.0).classify(&window), WindowLabel::Clean);
+    }
+
+    #[test]
+    fn screen_keeps_only_clean_windows_and_counts_reasons() {
+        let clean = clean_window();
+        let mut flat = vec![0.0; clean.len()];
+        flat[0] = 0.1;
+        let data: Vec<f64> = [clean.clone(), flat, clean.clone()].concat();
+
+        let screening = eeg_detector().screen(&data);
+        assert_eq!(screening.clean, [clean.clone(), clean].concat());
+        assert_eq!(screening.rejected_windows(), 1);
+        assert_eq!(screening.reasons().get("flatline"), Some(&1));
+    }
+
+    #[test]
+    fn finish_screens_the_trailing_partial_window() {
+        let mut screener = StreamingScreener::new(ArtifactDetector::new(ArtifactThresholds::normalized(), 10.0, 4));
+        let signal = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        let mut clean: Vec<f64> = signal.iter().filter_map(|x| screener.push(*x)).flatten().collect();
+        assert_eq!(clean, vec![0.1, 0.2, 0.3, 0.4]);
+
+        clean.extend(screener.finish().unwrap());
+        assert_eq!(clean, signal);
+        assert_eq!(screener.finish(), None);
+
+        // The streaming result matches screening the whole signal offline.
+        let offline = ArtifactDetector::new(ArtifactThresholds::normalized(), 10.0, 4).screen(&signal);
+        assert_eq!(offline.clean, clean);
+        assert_eq!(offline.labels.len(), 2);
+    }
+
+    #[test]
+    fn finish_rejects_a_contaminated_remainder() {
+        let mut screener = StreamingScreener::new(ArtifactDetector::new(ArtifactThresholds::normalized(), 10.0, 4));
+        for x in [0.1, 0.2, 0.3, 0.4, 1.0] {
+            screener.push(x);
+        }
+        assert_eq!(screener.finish(), None);
+        assert_eq!(screener.rejected_windows(), 1);
+        assert_eq!(screener.rejection_rate(), Some(0.5));
+
+        let report = screener.report(Some(0.25));
+        assert_eq!((report.mean, report.windows, report.rejected_windows), (Some(0.25), 2, 1));
+        assert_eq!(report.rejection_rate(), Some(0.5));
+        assert_eq!(report.reasons.get("saturation"), Some(&1));
+    }
+}
@@ -0,0 +1,1973 @@
+//! `bluerain`: scan for headsets, record, monitor, replay, analyze and export sessions.
+//!
+//! Every subcommand prints a human-readable report, or with `--json` a single JSON object on
+//! stdout: `{"ok": true, "command": ..., "result": ...}` or `{"ok": false, "error": {"kind", "message"}}`.
+//! Progress and diagnostics go to stderr.
+//!
+//! Exit codes: 0 success, 2 usage, 3 file I/O, 4 device, 5 invalid or unusable data.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+#[allow(dead_code)]
+mod artifacts;
+#[allow(dead_code)]
+mod control_api;
+#[allow(dead_code)]
+mod dashboard;
+#[allow(dead_code)]
+mod device_operations;
+#[allow(dead_code)]
+mod discovery;
+#[allow(dead_code)]
+mod edf;
+#[allow(dead_code)]
+mod features;
+#[allow(dead_code)]
+mod filters;
+#[allow(dead_code)]
+mod gatt;
+#[allow(dead_code)]
+mod ica;
+#[allow(dead_code)]
+mod lsl;
+#[allow(dead_code)]
+mod neurofeedback;
+#[allow(dead_code)]
+mod openbci;
+#[allow(dead_code)]
+mod osc;
+#[allow(dead_code)]
+mod pipeline;
+#[allow(dead_code)]
+mod replay;
+#[allow(dead_code)]
+mod session_store;
+#[allow(dead_code)]
+mod signal_quality;
+#[allow(dead_code)]
+mod streaming_stats;
+#[allow(dead_code)]
+mod xdf;
+#[allow(dead_code)]
+mod xml;
+
+use artifacts::{ArtifactDetector, ArtifactThresholds};
+use control_api::{Params, RpcError, RpcHandler};
+use dashboard::{Dashboard, DeviceMonitor};
+use device_operations::{DeviceOperations, Lifecycle, LifecycleState};
+use discovery::{DeviceAllowlist, DiscoveredDevice, MockAdapter, Scanner};
+use filters::FilterBank;
+use gatt::{ControlCommand, SamplePacket, SequenceTracker, SimulatedPeripheral};
+use ica::ComponentLabel;
+use lsl::{ChannelFormat, LslInlet, StreamInfo};
+use openbci::{OpenBciBoard, OpenBciDecoder, OpenBciDevice, OpenBciSample, SimulatedBoard};
+use osc::{OscArg, OscListener};
+use replay::{load_recording, ReplayDevice, ReplayItem, ReplaySpeed};
+use session_store::{Segment, StoredSession};
+use signal_quality::QualityCriteria;
+use streaming_stats::StreamingStats;
+use xdf::{XdfFormat, XdfStreamHeader, XdfWriter};
+
+type DeviceResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Name prefix BlueRAIN headsets advertise under.
+const HEADSET_PREFIX: &str = "BlueRAIN";
+
+#[derive(Debug, Parser)]
+#[command(name = "bluerain", version, about = "Record, replay, analyze and export BlueRAIN EEG sessions")]
+struct Cli {
+    /// Print one JSON object on stdout instead of a text report.
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List nearby devices and their capabilities.
+    Scan {
+        /// Scan time in seconds.
+        #[arg(long, default_value_t = 2.0)]
+        duration: f64,
+        /// Only report devices whose name starts with this (repeatable).
+        #[arg(long)]
+        name_prefix: Vec<String>,
+        /// Only report this address (repeatable).
+        #[arg(long)]
+        address: Vec<String>,
+    },
+    /// Record from a headset into a session file (.brs), EDF+ (.edf) or XDF (.xdf).
+    Record {
+        output: PathBuf,
+        /// Recording time in seconds.
+        #[arg(long, default_value_t = 10.0)]
+        duration: f64,
+        /// Headset address; the strongest BlueRAIN headset by default.
+        #[arg(long)]
+        address: Option<String>,
+        /// Apply DC removal, a mains notch and a 1-40 Hz band-pass while recording.
+        #[arg(long)]
+        filter: bool,
+        /// Mains frequency for the notch filter.
+        #[arg(long, default_value_t = 50.0)]
+        mains: f64,
+    },
+    /// Stream every headset into a live terminal dashboard (x or Esc: emergency stop).
+    Monitor {
+        /// Seconds to monitor; until stopped by default.
+        #[arg(long)]
+        duration: Option<f64>,
+        /// Only this headset; every BlueRAIN headset by default.
+        #[arg(long)]
+        address: Option<String>,
+        /// Apply DC removal, a mains notch and a 1-40 Hz band-pass.
+        #[arg(long)]
+        filter: bool,
+        /// Mains frequency for the notch filter.
+        #[arg(long, default_value_t = 50.0)]
+        mains: f64,
+    },
+    /// Play a recording back in time and report what it contained.
+    Replay {
+        file: PathBuf,
+        /// realtime, max, or a factor such as 4x.
+        #[arg(long, default_value = "realtime")]
+        speed: ReplaySpeed,
+        /// Channel to average; the first by default.
+        #[arg(long)]
+        channel: Option<String>,
+    },
+    /// Report per-channel statistics, artifacts, signal quality and ICA components.
+    Analyze {
+        file: PathBuf,
+        /// Mains frequency for line-noise checks.
+        #[arg(long, default_value_t = 50.0)]
+        mains: f64,
+        /// Also write a copy with ocular and muscle components removed.
+        #[arg(long)]
+        clean: Option<PathBuf>,
+        /// Seed for the ICA starting weights; the same seed gives the same components.
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// XDF stream to analyze, by name; the first EEG stream by default.
+        #[arg(long)]
+        stream: Option<String>,
+    },
+    /// Convert a recording to another format.
+    Export {
+        input: PathBuf,
+        output: PathBuf,
+        /// Inferred from the output extension when omitted.
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// XDF stream to export, by name; the first EEG stream by default.
+        #[arg(long)]
+        stream: Option<String>,
+    },
+    /// Run the JSON-RPC control API on a Unix socket until Ctrl-C.
+    Serve {
+        /// Socket path; $XDG_RUNTIME_DIR/bluerain.sock by default.
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Send one request to a running `bluerain serve`, e.g. `ctl sessions.pause '{"session": "s1"}'`.
+    Ctl {
+        method: String,
+        /// Named parameters as a JSON object.
+        params: Option<String>,
+        /// Socket path; $XDG_RUNTIME_DIR/bluerain.sock by default.
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Print the OSC messages arriving on a UDP port, e.g. to check an OSC output.
+    OscListen {
+        #[arg(long, default_value = "127.0.0.1:9000")]
+        bind: String,
+        /// Seconds to listen; until Ctrl-C by default.
+        #[arg(long)]
+        duration: Option<f64>,
+    },
+    /// Record LSL streams (EEG, markers, other sensors) into one XDF file with clock offsets.
+    LslRecord {
+        output: PathBuf,
+        /// Streams to record, e.g. "type='EEG'"; every stream on the network by default.
+        #[arg(long, default_value = "")]
+        query: String,
+        /// Seconds to record; until Ctrl-C by default.
+        #[arg(long)]
+        duration: Option<f64>,
+        /// Seconds to look for streams before recording.
+        #[arg(long, default_value_t = 2.0)]
+        resolve: f64,
+    },
+    /// Record from an OpenBCI Cyton or Ganglion on a serial port into a session file.
+    Openbci {
+        output: PathBuf,
+        /// Serial port, e.g. /dev/ttyUSB0.
+        #[arg(long, required_unless_present = "simulate")]
+        port: Option<PathBuf>,
+        /// cyton, cyton-daisy or ganglion; whatever the board reports by default (16 channels if a Daisy is fitted).
+        #[arg(long)]
+        board: Option<OpenBciBoard>,
+        /// Recording time in seconds.
+        #[arg(long, default_value_t = 10.0)]
+        duration: f64,
+        /// Use a simulated board on a pseudo-terminal instead of a serial port.
+        #[arg(long, conflicts_with = "port")]
+        simulate: bool,
+        /// Also save the raw bytes received, e.g. to decode later with openbci-decode.
+        #[arg(long)]
+        capture: Option<PathBuf>,
+    },
+    /// Decode a raw byte capture from an OpenBCI board and report what it contained.
+    OpenbciDecode {
+        capture: PathBuf,
+        #[arg(long, default_value = "cyton")]
+        board: OpenBciBoard,
+        /// Also write the decoded samples to a session file.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ExportFormat {
+    /// Native session format.
+    Brs,
+    Csv,
+    Edf,
+    Xdf,
+}
+
+impl ExportFormat {
+    fn from_path(path: &Path) -> Result<Self, CliError> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "brs" => Ok(ExportFormat::Brs),
+            "csv" => Ok(ExportFormat::Csv),
+            "edf" => Ok(ExportFormat::Edf),
+            "xdf" => Ok(ExportFormat::Xdf),
+            _ => Err(CliError::Usage(format!(
+                "Cannot tell the format of {} from its extension; pass --format",
+                path.display()
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    Io(String),
+    Device(String),
+    Data(String),
+}
+
+impl CliError {
+    fn kind(&self) -> &'static str {
+        match self {
+            CliError::Usage(_) => "usage",
+            CliError::Io(_) => "io",
+            CliError::Device(_) => "device",
+            CliError::Data(_) => "data",
+        }
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Io(_) => 3,
+            CliError::Device(_) => 4,
+            CliError::Data(_) => 5,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            CliError::Usage(message) | CliError::Io(message) | CliError::Device(message) | CliError::Data(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind(), self.message())
+    }
+}
+
+/// A finished command: the JSON result and the same information as text lines.
+struct Report {
+    result: Value,
+    lines: Vec<String>,
+}
+
+/// Loads a recording; `stream` picks an XDF stream other than the first EEG one.
+fn load(path: &Path, stream: Option<&str>) -> Result<StoredSession, CliError> {
+    if !path.is_file() {
+        return Err(CliError::Io(format!("{} does not exist", path.display())));
+    }
+    let session = match stream {
+        Some(stream) if ExportFormat::from_path(path).ok() == Some(ExportFormat::Xdf) => xdf::read_session(path, Some(stream)),
+        Some(_) => return Err(CliError::Usage("--stream only applies to XDF files".to_string())),
+        None => load_recording(path),
+    }
+    .map_err(CliError::Data)?;
+    if session.sample_rate <= 0.0 || session.channels.is_empty() {
+        return Err(CliError::Data(format!("{} has no usable channels", path.display())));
+    }
+    Ok(session)
+}
+
+fn save(session: &StoredSession, path: &Path, format: ExportFormat) -> Result<(), CliError> {
+    match format {
+        ExportFormat::Brs => session.save(path),
+        ExportFormat::Csv => write_csv(session, path),
+        ExportFormat::Edf => edf::write_edf(session, path),
+        ExportFormat::Xdf => xdf::write_session(session, path),
+    }
+    // The writers only fail on I/O, apart from EDF's sample-rate and the channel checks.
+    .map_err(|e| if e.starts_with("EDF") || e.starts_with("Session") { CliError::Data(e) } else { CliError::Io(e) })
+}
+
+/// One row per frame: time, one column per channel, then the labels of events since the previous frame.
+fn write_csv(session: &StoredSession, path: &Path) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+    let mut out = std::io::BufWriter::new(file);
+    let io = |e: std::io::Error| e.to_string();
+    let quote = |text: &str| {
+        if text.contains([',', '"', '\n']) { format!("\"{}\"", text.replace('"', "\"\"")) } else { text.to_string() }
+    };
+
+    let mut events = session.events.clone();
+    events.sort_by(|a, b| a.time_secs.total_cmp(&b.time_secs));
+    let mut next_event = 0;
+    let header: Vec<String> = session.channels.iter().map(|channel| quote(channel)).collect();
+    writeln!(out, "time,{},events", header.join(",")).map_err(io)?;
+    for segment in &session.segments {
+        for (i, frame) in segment.frames.iter().enumerate() {
+            let time_secs = segment.start_secs + i as f64 / session.sample_rate;
+            let mut labels = vec![];
+            while let Some(event) = events.get(next_event).filter(|event| event.time_secs <= time_secs) {
+                labels.push(event.label.as_str());
+                next_event += 1;
+            }
+            let values: Vec<String> = frame.iter().map(|value| value.to_string()).collect();
+            writeln!(out, "{:.6},{},{}", time_secs, values.join(","), quote(&labels.join(";"))).map_err(io)?;
+        }
+    }
+    if next_event < events.len() {
+        let labels: Vec<&str> = events[next_event..].iter().map(|event| event.label.as_str()).collect();
+        writeln!(out, "{:.6},{},{}", session.duration_secs(), vec![""; session.channels.len()].join(","), quote(&labels.join(";")))
+            .map_err(io)?;
+    }
+    out.flush().map_err(io)
+}
+
+fn device_json(device: &DiscoveredDevice) -> Value {
+    json!({
+        "address": device.address,
+        "name": device.name,
+        "rssi": device.rssi,
+        "services": device.services,
+        "capabilities": device.capabilities.as_ref().map(|capabilities| json!({
+            "channel_count": capabilities.channel_count,
+            "sample_rates": capabilities.sample_rates,
+            "battery_level": capabilities.battery_level,
+            "firmware_version": capabilities.firmware_version,
+        })),
+    })
+}
+
+async fn scan_devices(duration: f64, allowlist: Option<DeviceAllowlist>) -> Result<Vec<DiscoveredDevice>, CliError> {
+    if !(duration > 0.0 && duration.is_finite()) {
+        return Err(CliError::Usage("Scan duration must be positive".to_string()));
+    }
+    let mut scanner = Scanner::new(MockAdapter::with_demo_peripherals());
+    if let Some(allowlist) = allowlist {
+        scanner = scanner.with_allowlist(allowlist);
+    }
+    eprintln!("Scanning for {:.1}s...", duration);
+    scanner
+        .scan(Duration::from_secs_f64(duration))
+        .await
+        .map_err(|e| CliError::Device(format!("Scan failed: {}", e)))
+}
+
+async fn scan(duration: f64, name_prefixes: &[String], addresses: &[String]) -> Result<Report, CliError> {
+    let allowlist = if name_prefixes.is_empty() && addresses.is_empty() {
+        None
+    } else {
+        let allowlist = name_prefixes.iter().fold(DeviceAllowlist::new(), |list, prefix| list.allow_name_prefix(prefix));
+        Some(addresses.iter().fold(allowlist, |list, address| list.allow_address(address)))
+    };
+    let devices = scan_devices(duration, allowlist).await?;
+
+    let mut lines = vec![format!("{} device(s) found", devices.len())];
+    for device in &devices {
+        let name = device.name.as_deref().unwrap_or("(unnamed)");
+        lines.push(match &device.capabilities {
+            Some(capabilities) => format!(
+                "  {}  {:<16} {:>4} dBm  {} channels, {:?} Hz, battery {}, firmware {}",
+                device.address,
+                name,
+                device.rssi,
+                capabilities.channel_count,
+                capabilities.sample_rates,
+                capabilities.battery_level.map_or("n/a".to_string(), |level| format!("{}%", level)),
+                capabilities.firmware_version
+            ),
+            None => format!("  {}  {:<16} {:>4} dBm  capabilities unknown", device.address, name, device.rssi),
+        });
+    }
+    Ok(Report {
+        result: json!({ "devices": devices.iter().map(device_json).collect::<Vec<_>>() }),
+        lines,
+    })
+}
+
+/// A BlueRAIN headset found by a scan.
+#[derive(Debug, Clone)]
+struct Headset {
+    address: String,
+    channel_count: u8,
+    sample_rates: Vec<u32>,
+}
+
+/// BlueRAIN headsets that reported capabilities, strongest first; just `address` if given.
+async fn find_headsets(address: Option<&str>) -> Result<Vec<Headset>, CliError> {
+    let allowlist = match address {
+        Some(address) => DeviceAllowlist::new().allow_address(address),
+        None => DeviceAllowlist::new().allow_name_prefix(HEADSET_PREFIX),
+    };
+    let mut headsets = vec![];
+    for device in scan_devices(1.0, Some(allowlist)).await? {
+        let Some(capabilities) = &device.capabilities else {
+            continue;
+        };
+        let channel_count = u8::try_from(capabilities.channel_count)
+            .ok()
+            .filter(|count| (1..=gatt::MAX_CHANNELS).contains(count))
+            .ok_or_else(|| CliError::Device(format!("{} has unsupported channel count {}", device.address, capabilities.channel_count)))?;
+        headsets.push(Headset {
+            address: device.address,
+            channel_count,
+            sample_rates: capabilities.sample_rates.clone(),
+        });
+    }
+    if headsets.is_empty() {
+        return Err(CliError::Device(match address {
+            Some(address) => format!("Headset {} not found", address),
+            None => "No BlueRAIN headset found".to_string(),
+        }));
+    }
+    Ok(headsets)
+}
+
+/// Acquisition settings of a capture. The control API can change them while it runs.
+#[derive(Debug, Clone, PartialEq)]
+struct Protocol {
+    /// `None` streams until stopped.
+    duration_secs: Option<f64>,
+    /// DC removal, a mains notch and a 1-40 Hz band-pass.
+    filter: bool,
+    mains_hz: f64,
+    gain: u8,
+    /// Fixed once streaming starts.
+    sample_rate: u16,
+}
+
+impl Protocol {
+    fn new(duration_secs: Option<f64>, filter: bool, mains_hz: f64) -> Self {
+        Protocol {
+            duration_secs,
+            filter,
+            mains_hz,
+            gain: 24,
+            sample_rate: 250,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "duration_secs": self.duration_secs,
+            "filter": self.filter,
+            "mains_hz": self.mains_hz,
+            "gain": self.gain,
+            "sample_rate": self.sample_rate,
+        })
+    }
+}
+
+/// Shared handle on a capture, so another task can pause, stop or retune it and read its status.
+#[derive(Debug, Clone)]
+struct SessionControl {
+    lifecycle: Lifecycle,
+    protocol: Arc<Mutex<Protocol>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl SessionControl {
+    fn new(protocol: Protocol) -> Self {
+        SessionControl {
+            lifecycle: Lifecycle::new(),
+            protocol: Arc::new(Mutex::new(protocol)),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn protocol(&self) -> Protocol {
+        self.protocol.lock().unwrap().clone()
+    }
+
+    fn set_protocol(&self, protocol: Protocol) {
+        *self.protocol.lock().unwrap() = protocol;
+    }
+
+    /// Ends the capture after the current packet, even while paused.
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.lifecycle.end_stream();
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
+
+/// What one headset produced during `record` or `monitor`.
+struct Capture {
+    session: StoredSession,
+    tracker: SequenceTracker,
+    decode_errors: usize,
+    emergency_stopped: bool,
+}
+
+fn filter_bank(protocol: &Protocol, sample_rate: f64, channel_count: usize) -> Result<Option<FilterBank>, CliError> {
+    match protocol.filter {
+        true => Ok(Some(FilterBank::eeg_default(sample_rate, protocol.mains_hz, channel_count).map_err(CliError::Usage)?)),
+        false => Ok(None),
+    }
+}
+
+/// Streams from a headset until the protocol's duration passes, `control` is stopped, or the
+/// dashboard's emergency stop. Lost packets start a new segment; paused time is not recorded.
+async fn capture(headset: &Headset, control: &SessionControl, monitor: Option<&DeviceMonitor>) -> Result<Capture, CliError> {
+    let address = &headset.address;
+    let mut applied = control.protocol();
+    let mut peripheral = SimulatedPeripheral::new(headset.channel_count);
+    for command in [ControlCommand::SetSampleRate(applied.sample_rate), ControlCommand::SetGain(applied.gain), ControlCommand::StartStream] {
+        peripheral
+            .write_control(&command.encode())
+            .map_err(|e| CliError::Device(format!("Cannot start stream on {}: {:?}", address, e)))?;
+    }
+    let lifecycle = &control.lifecycle;
+    lifecycle.connected().map_err(CliError::Device)?;
+    lifecycle.begin_stream().map_err(CliError::Device)?;
+    let sample_rate = peripheral.sample_rate() as f64;
+    let channels: Vec<String> = (1..=headset.channel_count).map(|channel| format!("Ch{}", channel)).collect();
+    let mut bank = filter_bank(&applied, sample_rate, channels.len())?;
+    if let Some(monitor) = monitor {
+        monitor.set_channels(&channels, Some(sample_rate));
+        monitor.set_state("Streaming");
+    }
+    let mut capture = Capture {
+        session: StoredSession::new(address, sample_rate, channels),
+        tracker: SequenceTracker::default(),
+        decode_errors: 0,
+        emergency_stopped: false,
+    };
+
+    let mut started = Instant::now();
+    // Frames received plus frames lost, i.e. the device's own sample clock.
+    let mut clock = 0;
+    loop {
+        if monitor.is_some_and(|monitor| monitor.dashboard().is_stopped()) {
+            capture.emergency_stopped = true;
+            break;
+        }
+        started += lifecycle.wait_while_paused().await;
+        if control.stop_requested() {
+            break;
+        }
+        let protocol = control.protocol();
+        if protocol != applied {
+            if protocol.gain != applied.gain {
+                peripheral
+                    .write_control(&ControlCommand::SetGain(protocol.gain).encode())
+                    .map_err(|e| CliError::Device(format!("Cannot set gain on {}: {:?}", address, e)))?;
+            }
+            if (protocol.filter, protocol.mains_hz) != (applied.filter, applied.mains_hz) {
+                bank = filter_bank(&protocol, sample_rate, capture.session.channels.len())?;
+            }
+            applied = protocol;
+        }
+        let total_frames = applied.duration_secs.map_or(usize::MAX, |duration| (duration * sample_rate).round() as usize);
+        if clock >= total_frames {
+            break;
+        }
+
+        let notification = peripheral
+            .next_notification()
+            .ok_or_else(|| CliError::Device(format!("{} stopped streaming", address)))?;
+        let packet = match SamplePacket::decode(&notification) {
+            Ok(packet) => packet,
+            Err(e) => {
+                capture.decode_errors += 1;
+                lifecycle.record_error(&format!("Bad packet: {:?}", e));
+                match monitor {
+                    Some(monitor) => monitor.log(format!("Dropping bad packet: {:?}", e)),
+                    None => eprintln!("Dropping bad packet from {}: {:?}", address, e),
+                }
+                continue;
+            }
+        };
+        let missed = capture.tracker.observe(packet.sequence) as usize;
+        clock += missed * packet.frames.len();
+        if missed > 0 || capture.session.segments.is_empty() {
+            capture.session.segments.push(Segment { start_secs: clock as f64 / sample_rate, frames: vec![] });
+        }
+        let segment = capture.session.segments.last_mut().expect("segment pushed above");
+        let frames = packet.frames.iter().take(total_frames.saturating_sub(clock));
+        lifecycle.record_samples(frames.len() as u64);
+        let lsb = gatt::lsb_microvolts(applied.gain) as f64;
+        for raw in frames {
+            let mut frame: Vec<f64> = raw.iter().map(|count| *count as f64 * lsb).collect();
+            if let Some(bank) = &mut bank {
+                bank.process_frame(&mut frame);
+            }
+            if let Some(monitor) = monitor {
+                monitor.push_frame(&frame);
+            }
+            segment.frames.push(frame);
+        }
+        if let Some(monitor) = monitor {
+            monitor.set_dropped(capture.tracker.lost * packet.frames.len() as u64);
+        }
+        clock += packet.frames.len();
+        tokio::time::sleep_until(started + Duration::from_secs_f64(clock as f64 / sample_rate)).await;
+    }
+    let _ = peripheral.write_control(&ControlCommand::StopStream.encode());
+    lifecycle.end_stream();
+    lifecycle.disconnected();
+    if let Some(monitor) = monitor {
+        monitor.set_state(if capture.emergency_stopped { "STOPPED (emergency)" } else { "Stopped" });
+    }
+    Ok(capture)
+}
+
+async fn record(output: &Path, duration: f64, address: Option<&str>, filter: bool, mains: f64) -> Result<Report, CliError> {
+    if !(duration > 0.0 && duration.is_finite()) {
+        return Err(CliError::Usage("Recording duration must be positive".to_string()));
+    }
+    let format = ExportFormat::from_path(output)?;
+    let headset = find_headsets(address).await?.swap_remove(0);
+    let address = &headset.address;
+    eprintln!("Recording {} for {:.1}s...", address, duration);
+    let control = SessionControl::new(Protocol::new(Some(duration), filter, mains));
+    let Capture { session, tracker, decode_errors, .. } = capture(&headset, &control, None).await?;
+    save(&session, output, format)?;
+
+    let result = json!({
+        "device": address,
+        "output": output.display().to_string(),
+        "format": format!("{:?}", format).to_lowercase(),
+        "sample_rate": session.sample_rate,
+        "channels": session.channels,
+        "frames": session.frame_count(),
+        "duration_secs": session.duration_secs(),
+        "segments": session.segments.len(),
+        "packets_received": tracker.received,
+        "packets_lost": tracker.lost,
+        "decode_errors": decode_errors,
+        "filtered": filter,
+    });
+    let lines = vec![
+        format!("Recorded {} frames ({:.1}s, {} channels) from {} to {}", session.frame_count(), session.duration_secs(), session.channels.len(), address, output.display()),
+        format!("Packets: {} received, {} lost, {} undecodable; {} segment(s)", tracker.received, tracker.lost, decode_errors, session.segments.len()),
+    ];
+    Ok(Report { result, lines })
+}
+
+/// Streams every headset into the live dashboard until `duration` passes or the operator stops.
+async fn monitor(duration: Option<f64>, address: Option<&str>, filter: bool, mains: f64) -> Result<Report, CliError> {
+    if duration.is_some_and(|duration| !(duration > 0.0 && duration.is_finite())) {
+        return Err(CliError::Usage("Monitoring duration must be positive".to_string()));
+    }
+    let headsets = find_headsets(address).await?;
+    let (dashboard, handle) = Dashboard::new("bluerain monitor");
+    let ui = tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            let result = dashboard.run().await;
+            if result.is_err() {
+                // Without a view nobody can stop the sessions, so stop them now.
+                handle.emergency_stop();
+            }
+            result
+        }
+    });
+
+    let captures = headsets.into_iter().map(|headset| {
+        let device_monitor = handle.add_device(&headset.address);
+        device_monitor.set_phase("monitoring");
+        let control = SessionControl::new(Protocol::new(duration, filter, mains));
+        tokio::spawn(async move {
+            let result = capture(&headset, &control, Some(&device_monitor)).await;
+            if let Err(e) = &result {
+                device_monitor.set_state("Failed");
+                device_monitor.log(e);
+            }
+            (headset.address, result)
+        })
+    });
+    let captures = futures::future::join_all(captures).await;
+    handle.finish();
+    ui.await
+        .map_err(|e| CliError::Io(format!("Dashboard task failed: {}", e)))?
+        .map_err(CliError::Io)?;
+
+    let mut devices = vec![];
+    let mut lines = vec![];
+    let mut emergency_stopped = false;
+    for capture in captures {
+        let (address, result) = capture.map_err(|e| CliError::Device(format!("Capture task failed: {}", e)))?;
+        let capture = result?;
+        emergency_stopped |= capture.emergency_stopped;
+        lines.push(format!(
+            "{}: {} frames ({:.1}s), {} packets lost{}",
+            address,
+            capture.session.frame_count(),
+            capture.session.duration_secs(),
+            capture.tracker.lost,
+            if capture.emergency_stopped { ", emergency stopped" } else { "" }
+        ));
+        devices.push(json!({
+            "device": address,
+            "frames": capture.session.frame_count(),
+            "duration_secs": capture.session.duration_secs(),
+            "packets_received": capture.tracker.received,
+            "packets_lost": capture.tracker.lost,
+            "decode_errors": capture.decode_errors,
+            "emergency_stopped": capture.emergency_stopped,
+        }));
+    }
+    if emergency_stopped {
+        lines.push("Monitoring ended by emergency stop".to_string());
+    }
+    Ok(Report {
+        result: json!({ "devices": devices, "emergency_stop": emergency_stopped }),
+        lines,
+    })
+}
+
+async fn replay(file: &Path, speed: ReplaySpeed, channel: Option<&str>) -> Result<Report, CliError> {
+    let session = load(file, None)?;
+    let mut device = ReplayDevice::new(session).with_speed(speed);
+    if let Some(channel) = channel {
+        device = device.with_channel(channel).map_err(CliError::Usage)?;
+    }
+    let mut items = device.subscribe(1024);
+    device.connect().await.map_err(CliError::Device)?;
+
+    let collect = async {
+        let (mut frames, mut events, mut gaps) = (0usize, vec![], vec![]);
+        while let Some(item) = items.recv().await {
+            match item {
+                ReplayItem::Frame { .. } => frames += 1,
+                ReplayItem::Gap { from_secs, to_secs } => gaps.push((from_secs, to_secs)),
+                ReplayItem::Event(event) => events.push(event),
+            }
+        }
+        (frames, events, gaps)
+    };
+    let stream = async {
+        let result = device.stream(Duration::MAX).await;
+        // Dropping the sender ends `collect`.
+        let average = device.analyze_data();
+        let _ = device.disconnect().await;
+        result.map(|_| average)
+    };
+    let (streamed, (frames, events, gaps)) = tokio::join!(stream, collect);
+    let average = streamed.map_err(CliError::Device)?;
+
+    let mut lines = vec![format!("Replayed {} frames from {}", frames, file.display())];
+    lines.extend(gaps.iter().map(|(from, to)| format!("  gap {:.3}s to {:.3}s", from, to)));
+    lines.extend(events.iter().map(|event| format!("  event {:.3}s {}", event.time_secs, event.label)));
+    lines.push(match average {
+        Some(average) => format!("Average signal: {:.3}", average),
+        None => "No frames to average".to_string(),
+    });
+    let result = json!({
+        "file": file.display().to_string(),
+        "frames": frames,
+        "gaps": gaps.iter().map(|(from, to)| json!({ "from_secs": from, "to_secs": to })).collect::<Vec<_>>(),
+        "events": events.iter().map(|event| json!({ "time_secs": event.time_secs, "label": event.label })).collect::<Vec<_>>(),
+        "average": average,
+    });
+    Ok(Report { result, lines })
+}
+
+fn analyze(file: &Path, mains: f64, clean: Option<&Path>, seed: u64, stream: Option<&str>) -> Result<Report, CliError> {
+    let mut session = load(file, stream)?;
+    let clean_format = clean.map(ExportFormat::from_path).transpose()?;
+    let data = session.to_channels();
+    if data.iter().all(Vec::is_empty) {
+        return Err(CliError::Data(format!("{} contains no samples", file.display())));
+    }
+    let fs = session.sample_rate;
+    let mut lines = vec![format!(
+        "{}: device {}, {} channels at {} Hz, {} frames ({:.1}s), {} segment(s), {} event(s)",
+        file.display(), session.device_id, session.channels.len(), fs, session.frame_count(), session.duration_secs(), session.segments.len(), session.events.len()
+    )];
+
+    let criteria = QualityCriteria { mains_hz: mains, ..QualityCriteria::default() };
+    let labels: Vec<&str> = session.channels.iter().map(String::as_str).collect();
+    let quality = signal_quality::assess(&criteria, &labels, fs, &data, None).map_err(CliError::Data)?;
+    let detector = ArtifactDetector::new(ArtifactThresholds::eeg_microvolts(), fs, (fs.round() as usize).max(1)).with_mains(mains);
+
+    let mut channels = vec![];
+    for ((label, samples), channel_quality) in session.channels.iter().zip(&data).zip(&quality.channels) {
+        let mut stats = StreamingStats::new((fs.round() as usize).max(1));
+        stats.extend(samples.iter().copied());
+        let screening = detector.screen(samples);
+        let summary = stats.summary();
+        lines.push(match &summary {
+            Some(summary) => format!(
+                "  {:<6} mean {:8.2}  sd {:7.2}  range [{:.1}, {:.1}]  p95 {:.1}  noise {:.1} µV  line {:.1} µV  {}  artifacts: {}",
+                label,
+                summary.mean,
+                summary.std_dev.unwrap_or(0.0),
+                summary.min,
+                summary.max,
+                summary.p95,
+                channel_quality.noise_floor_uv,
+                channel_quality.line_noise_uv,
+                if channel_quality.passed() { "PASS".to_string() } else { format!("FAIL ({})", channel_quality.failures.join(", ")) },
+                screening.summary()
+            ),
+            None => format!("  {:<6} no finite samples", label),
+        });
+        channels.push(json!({
+            "label": label,
+            "stats": summary.map(|summary| json!({
+                "count": summary.count,
+                "mean": summary.mean,
+                "std_dev": summary.std_dev,
+                "min": summary.min,
+                "max": summary.max,
+                "median": summary.median,
+                "p95": summary.p95,
+            })),
+            "quality": {
+                "passed": channel_quality.passed(),
+                "noise_floor_uv": channel_quality.noise_floor_uv,
+                "line_noise_uv": channel_quality.line_noise_uv,
+                "failures": channel_quality.failures,
+            },
+            "artifacts": {
+                "windows": screening.labels.len(),
+                "rejected": screening.rejected_windows(),
+                "rejection_rate": screening.rejection_rate(),
+                "reasons": screening.reasons(),
+            },
+        }));
+    }
+
+    // ICA needs at least two channels; a failed decomposition is reported, not fatal, unless cleaning was asked for.
+    let mut components = Value::Null;
+    let mut cleaned = Value::Null;
+    if session.channels.len() >= 2 {
+        match ica::fast_ica(&data, 200, 1e-6, seed) {
+            Ok(decomposition) => {
+                let reports = ica::classify_components(&decomposition, fs, &session.channels);
+                let excluded: Vec<usize> = reports
+                    .iter()
+                    .filter(|report| report.label != ComponentLabel::Brain)
+                    .map(|report| report.index)
+                    .collect();
+                lines.push(format!("ICA: {} components, {} ocular/muscle", reports.len(), excluded.len()));
+                for report in &reports {
+                    lines.push(format!(
+                        "  component {:<3} {:?} (low {:.2}, high {:.2}, frontal {:.2})",
+                        report.index, report.label, report.low_frequency_ratio, report.high_frequency_ratio, report.frontal_weight
+                    ));
+                }
+                components = reports
+                    .iter()
+                    .map(|report| json!({
+                        "index": report.index,
+                        "label": format!("{:?}", report.label).to_lowercase(),
+                        "low_frequency_ratio": report.low_frequency_ratio,
+                        "high_frequency_ratio": report.high_frequency_ratio,
+                        "frontal_weight": report.frontal_weight,
+                    }))
+                    .collect();
+                if let (Some(path), Some(format)) = (clean, clean_format) {
+                    session.replace_channels(&ica::reconstruct(&decomposition, &excluded)).map_err(CliError::Data)?;
+                    save(&session, path, format)?;
+                    lines.push(format!("Removed {} components, cleaned copy written to {}", excluded.len(), path.display()));
+                    cleaned = json!({ "output": path.display().to_string(), "removed_components": excluded });
+                }
+            }
+            Err(e) if clean.is_some() => return Err(CliError::Data(format!("ICA failed: {}", e))),
+            Err(e) => lines.push(format!("ICA failed: {}", e)),
+        }
+    } else if clean.is_some() {
+        return Err(CliError::Data("ICA cleaning needs at least two channels".to_string()));
+    }
+
+    let result = json!({
+        "file": file.display().to_string(),
+        "device": session.device_id,
+        "sample_rate": fs,
+        "frames": session.frame_count(),
+        "duration_secs": session.duration_secs(),
+        "segments": session.segments.len(),
+        "events": session.events.len(),
+        "quality_passed": quality.passed(),
+        "channels": channels,
+        "components": components,
+        "cleaned": cleaned,
+    });
+    Ok(Report { result, lines })
+}
+
+fn export(input: &Path, output: &Path, format: Option<ExportFormat>, stream: Option<&str>) -> Result<Report, CliError> {
+    let session = load(input, stream)?;
+    let format = match format {
+        Some(format) => format,
+        None => ExportFormat::from_path(output)?,
+    };
+    save(&session, output, format)?;
+    let format_name = format!("{:?}", format).to_lowercase();
+    Ok(Report {
+        result: json!({
+            "input": input.display().to_string(),
+            "output": output.display().to_string(),
+            "format": format_name,
+            "frames": session.frame_count(),
+            "channels": session.channels.len(),
+            "events": session.events.len(),
+        }),
+        lines: vec![format!("Exported {} frames from {} to {} ({})", session.frame_count(), input.display(), output.display(), format_name)],
+    })
+}
+
+/// Longest session the control API will run; sessions without a duration stop here.
+const MAX_SESSION_SECS: f64 = 4.0 * 3600.0;
+/// Amplifier gains the headset firmware accepts.
+const ALLOWED_GAINS: [u8; 7] = [1, 2, 4, 6, 8, 12, 24];
+const ALLOWED_MAINS_HZ: [f64; 2] = [50.0, 60.0];
+const PROTOCOL_PARAMS: [&str; 5] = ["duration_secs", "filter", "mains_hz", "gain", "sample_rate"];
+
+fn limits_json() -> Value {
+    json!({
+        "max_duration_secs": MAX_SESSION_SECS,
+        "gains": ALLOWED_GAINS,
+        "mains_hz": ALLOWED_MAINS_HZ,
+        "sample_rates": "as advertised by the device",
+    })
+}
+
+/// `protocol` with the protocol parameters in `params` applied, if all are within limits.
+fn update_protocol(protocol: &Protocol, params: &Params, sample_rates: &[u32], running: bool) -> Result<Protocol, RpcError> {
+    let mut updated = protocol.clone();
+    if let Some(duration) = params.f64("duration_secs")? {
+        if !(duration > 0.0 && duration <= MAX_SESSION_SECS) {
+            return Err(RpcError::limit_exceeded(format!("duration_secs must be in (0, {}]", MAX_SESSION_SECS)));
+        }
+        updated.duration_secs = Some(duration);
+    }
+    if let Some(filter) = params.bool("filter")? {
+        updated.filter = filter;
+    }
+    if let Some(mains_hz) = params.f64("mains_hz")? {
+        if !ALLOWED_MAINS_HZ.contains(&mains_hz) {
+            return Err(RpcError::limit_exceeded(format!("mains_hz must be one of {:?}", ALLOWED_MAINS_HZ)));
+        }
+        updated.mains_hz = mains_hz;
+    }
+    if let Some(gain) = params.u64("gain")? {
+        updated.gain = u8::try_from(gain)
+            .ok()
+            .filter(|gain| ALLOWED_GAINS.contains(gain))
+            .ok_or_else(|| RpcError::limit_exceeded(format!("gain must be one of {:?}", ALLOWED_GAINS)))?;
+    }
+    if let Some(sample_rate) = params.u64("sample_rate")? {
+        if running {
+            return Err(RpcError::invalid_state("sample_rate can only be set when starting a session"));
+        }
+        updated.sample_rate = u16::try_from(sample_rate)
+            .ok()
+            .filter(|rate| sample_rates.contains(&(*rate as u32)))
+            .ok_or_else(|| RpcError::limit_exceeded(format!("sample_rate must be one of {:?}", sample_rates)))?;
+    }
+    Ok(updated)
+}
+
+fn rpc_error(error: CliError) -> RpcError {
+    let code = match error {
+        CliError::Usage(_) => control_api::INVALID_PARAMS,
+        CliError::Io(_) => control_api::IO_ERROR,
+        CliError::Device(_) | CliError::Data(_) => control_api::DEVICE_ERROR,
+    };
+    RpcError::new(code, error.message())
+}
+
+/// A capture started over the control API.
+struct ControlledSession {
+    headset: Headset,
+    output: Option<PathBuf>,
+    control: SessionControl,
+    /// Taken when the capture is collected.
+    task: Option<JoinHandle<Result<Capture, CliError>>>,
+    /// Summary, or the error, once collected.
+    outcome: Option<Result<Value, String>>,
+}
+
+impl ControlledSession {
+    fn to_json(&self, id: &str) -> Value {
+        let status = self.control.lifecycle.status();
+        let state = match &self.outcome {
+            Some(Ok(_)) => "Finished".to_string(),
+            Some(Err(_)) => "Failed".to_string(),
+            None if status.state == LifecycleState::Disconnected => "Starting".to_string(),
+            None => format!("{:?}", status.state),
+        };
+        json!({
+            "session": id,
+            "device": self.headset.address,
+            "output": self.output.as_ref().map(|path| path.display().to_string()),
+            "state": state,
+            "protocol": self.control.protocol().to_json(),
+            "uptime_secs": status.uptime.map(|uptime| uptime.as_secs_f64()),
+            "samples_received": status.samples_received,
+            "errors": status.errors,
+            "last_error": status.last_error,
+            "result": self.outcome.as_ref().and_then(|outcome| outcome.as_ref().ok()),
+            "error": self.outcome.as_ref().and_then(|outcome| outcome.as_ref().err()),
+        })
+    }
+}
+
+/// Answers control API requests for `bluerain serve`.
+struct Daemon {
+    sessions: Mutex<BTreeMap<String, ControlledSession>>,
+    next_session: AtomicU64,
+    shutdown: Notify,
+}
+
+impl Daemon {
+    fn new() -> Self {
+        Daemon {
+            sessions: Mutex::new(BTreeMap::new()),
+            next_session: AtomicU64::new(1),
+            shutdown: Notify::new(),
+        }
+    }
+
+    fn with_session<T>(&self, id: &str, f: impl FnOnce(&ControlledSession) -> Result<T, RpcError>) -> Result<T, RpcError> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(id)
+            .ok_or_else(|| RpcError::invalid_params(format!("Unknown session '{}'", id)))?;
+        f(session)
+    }
+
+    fn session_json(&self, id: &str) -> Result<Value, RpcError> {
+        self.with_session(id, |session| Ok(session.to_json(id)))
+    }
+
+    /// Like `with_session`, for changes that only make sense while the capture runs.
+    fn with_running_session<T>(&self, id: &str, f: impl FnOnce(&ControlledSession) -> Result<T, RpcError>) -> Result<T, RpcError> {
+        self.with_session(id, |session| match session.task {
+            Some(_) => f(session),
+            None => Err(RpcError::invalid_state(format!("Session {} has ended", id))),
+        })
+    }
+
+    /// Waits for a capture that has ended (or been told to stop) and saves its output.
+    async fn collect(&self, id: &str) {
+        let Some((task, output)) = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get_mut(id)
+            .and_then(|session| Some((session.task.take()?, session.output.clone())))
+        else {
+            return;
+        };
+        let outcome = match task.await {
+            Ok(Ok(capture)) => {
+                let saved = match &output {
+                    Some(path) => ExportFormat::from_path(path).and_then(|format| save(&capture.session, path, format)),
+                    None => Ok(()),
+                };
+                saved.map(|_| {
+                    json!({
+                        "frames": capture.session.frame_count(),
+                        "duration_secs": capture.session.duration_secs(),
+                        "segments": capture.session.segments.len(),
+                        "packets_received": capture.tracker.received,
+                        "packets_lost": capture.tracker.lost,
+                        "decode_errors": capture.decode_errors,
+                    })
+                })
+                .map_err(|e| e.to_string())
+            }
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(e) => Err(format!("Capture task failed: {}", e)),
+        };
+        if let Err(e) = &outcome {
+            eprintln!("Session {}: {}", id, e);
+        }
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(id) {
+            session.outcome = Some(outcome);
+        }
+    }
+
+    /// Collects every capture that has already ended on its own.
+    async fn collect_finished(&self) {
+        let finished: Vec<String> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, session)| session.task.as_ref().is_some_and(|task| task.is_finished()))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in finished {
+            self.collect(&id).await;
+        }
+    }
+
+    /// Stops a session and returns its final state.
+    async fn stop(&self, id: &str) -> Result<Value, RpcError> {
+        self.with_session(id, |session| {
+            session.control.stop();
+            Ok(())
+        })?;
+        self.collect(id).await;
+        self.session_json(id)
+    }
+
+    async fn stop_all(&self) -> Vec<Value> {
+        let ids: Vec<String> = self.sessions.lock().unwrap().keys().cloned().collect();
+        let mut stopped = vec![];
+        for id in ids {
+            if let Ok(session) = self.stop(&id).await {
+                stopped.push(session);
+            }
+        }
+        stopped
+    }
+
+    /// (device address, session id) of every running capture.
+    fn busy_devices(sessions: &BTreeMap<String, ControlledSession>) -> Vec<(String, String)> {
+        sessions
+            .iter()
+            .filter(|(_, session)| session.task.is_some())
+            .map(|(id, session)| (session.headset.address.clone(), id.clone()))
+            .collect()
+    }
+
+    async fn list_devices(&self, params: Params) -> Result<Value, RpcError> {
+        let params = params.only(&["duration"])?;
+        let duration = params.f64("duration")?.unwrap_or(1.0);
+        if !(duration > 0.0 && duration <= 30.0) {
+            return Err(RpcError::limit_exceeded("duration must be in (0, 30] seconds"));
+        }
+        let devices = scan_devices(duration, None).await.map_err(rpc_error)?;
+        let busy = Self::busy_devices(&self.sessions.lock().unwrap());
+        let devices: Vec<Value> = devices
+            .iter()
+            .map(|device| {
+                let mut json = device_json(device);
+                let session = busy.iter().find(|(address, _)| *address == device.address).map(|(_, id)| id);
+                json["session"] = json!(session);
+                json
+            })
+            .collect();
+        Ok(json!({ "devices": devices }))
+    }
+
+    async fn start(&self, params: Params) -> Result<Value, RpcError> {
+        let mut known = vec!["device", "output"];
+        known.extend(PROTOCOL_PARAMS);
+        let params = params.only(&known)?;
+        let output = params.str("output")?.map(PathBuf::from);
+        if let Some(output) = &output {
+            ExportFormat::from_path(output).map_err(rpc_error)?;
+        }
+        let address = params.str("device")?;
+        let headsets = find_headsets(address.as_deref()).await.map_err(rpc_error)?;
+        // Pick and register the headset under one lock, so concurrent starts never share one.
+        let mut sessions = self.sessions.lock().unwrap();
+        let busy = Self::busy_devices(&sessions);
+        let headset = headsets
+            .into_iter()
+            .find(|headset| busy.iter().all(|(address, _)| *address != headset.address))
+            .ok_or_else(|| RpcError::invalid_state(match &address {
+                Some(address) => format!("{} is already in a session", address),
+                None => "Every headset is already in a session".to_string(),
+            }))?;
+
+        let default = Protocol {
+            duration_secs: Some(MAX_SESSION_SECS),
+            ..Protocol::new(None, false, 50.0)
+        };
+        let protocol = update_protocol(&default, &params, &headset.sample_rates, false)?;
+        let control = SessionControl::new(protocol);
+        let id = format!("s{}", self.next_session.fetch_add(1, Ordering::Relaxed));
+        let task = tokio::spawn({
+            let headset = headset.clone();
+            let control = control.clone();
+            async move { capture(&headset, &control, None).await }
+        });
+        eprintln!("Session {} started on {}", id, headset.address);
+        let session = ControlledSession {
+            headset,
+            output,
+            control,
+            task: Some(task),
+            outcome: None,
+        };
+        let json = session.to_json(&id);
+        sessions.insert(id, session);
+        Ok(json)
+    }
+
+    fn set_protocol(&self, id: &str, params: &Params) -> Result<Value, RpcError> {
+        self.with_running_session(id, |session| {
+            let protocol = update_protocol(&session.control.protocol(), params, &session.headset.sample_rates, true)?;
+            session.control.set_protocol(protocol);
+            Ok(session.to_json(id))
+        })
+    }
+}
+
+#[async_trait]
+impl RpcHandler for Daemon {
+    async fn handle(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let params = Params::new(params)?;
+        self.collect_finished().await;
+        match method {
+            "devices.list" => self.list_devices(params).await,
+            "sessions.list" => {
+                params.only(&[])?;
+                let sessions = self.sessions.lock().unwrap();
+                Ok(json!({ "sessions": sessions.iter().map(|(id, session)| session.to_json(id)).collect::<Vec<_>>() }))
+            }
+            "sessions.start" => self.start(params).await,
+            "sessions.status" => self.session_json(&params.only(&["session"])?.required_str("session")?),
+            "sessions.pause" | "sessions.resume" => {
+                let id = params.only(&["session"])?.required_str("session")?;
+                self.with_running_session(&id, |session| {
+                    let lifecycle = &session.control.lifecycle;
+                    match method {
+                        "sessions.pause" => lifecycle.pause(),
+                        _ => lifecycle.resume(),
+                    }
+                    .map_err(RpcError::invalid_state)?;
+                    Ok(session.to_json(&id))
+                })
+            }
+            "sessions.set_protocol" => {
+                let mut known = vec!["session"];
+                known.extend(PROTOCOL_PARAMS);
+                let params = params.only(&known)?;
+                self.set_protocol(&params.required_str("session")?, &params)
+            }
+            "sessions.stop" => self.stop(&params.only(&["session"])?.required_str("session")?).await,
+            "protocol.limits" => Ok(limits_json()),
+            "daemon.shutdown" => {
+                let sessions = self.stop_all().await;
+                self.shutdown.notify_one();
+                Ok(json!({ "sessions": sessions }))
+            }
+            _ => Err(RpcError::new(control_api::METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        }
+    }
+}
+
+/// Runs the control API until Ctrl-C or `daemon.shutdown`, then stops any running sessions.
+async fn serve(socket: &Path) -> Result<Report, CliError> {
+    let daemon = Arc::new(Daemon::new());
+    let server = control_api::serve(socket, daemon.clone()).await.map_err(CliError::Io)?;
+    eprintln!("Control API listening on {}", socket.display());
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = daemon.shutdown.notified() => {}
+    }
+    server.abort();
+    let _ = std::fs::remove_file(socket);
+    let sessions = daemon.stop_all().await;
+    let lines = sessions
+        .iter()
+        .map(|session| {
+            let field = |key: &str| session[key].as_str().unwrap_or_default().to_string();
+            format!("Session {} on {}: {}", field("session"), field("device"), field("state"))
+        })
+        .collect();
+    Ok(Report {
+        result: json!({ "sessions": sessions }),
+        lines,
+    })
+}
+
+/// Sends one request to `bluerain serve` and reports its result.
+async fn ctl(socket: &Path, method: &str, params: Option<&str>) -> Result<Report, CliError> {
+    let params = match params {
+        Some(params) => serde_json::from_str(params).map_err(|e| CliError::Usage(format!("Params are not valid JSON: {}", e)))?,
+        None => Value::Null,
+    };
+    let result = control_api::call(socket, method, params)
+        .await
+        .map_err(CliError::Io)?
+        .map_err(|e| match e.code {
+            control_api::DEVICE_ERROR => CliError::Device(e.message),
+            control_api::IO_ERROR => CliError::Io(e.message),
+            _ => CliError::Usage(e.message),
+        })?;
+    let text = serde_json::to_string_pretty(&result).unwrap_or_default();
+    Ok(Report {
+        lines: text.lines().map(str::to_string).collect(),
+        result,
+    })
+}
+
+fn osc_arg_json(arg: &OscArg) -> Value {
+    match arg {
+        OscArg::Int(value) => json!(value),
+        OscArg::Float(value) => json!(value),
+        OscArg::Str(value) => json!(value),
+    }
+}
+
+/// Echoes each message to stderr and reports how often each address was seen, with its last arguments.
+async fn osc_listen(bind: &str, duration: Option<f64>) -> Result<Report, CliError> {
+    let listener = OscListener::bind(bind).await.map_err(CliError::Io)?;
+    eprintln!("Listening for OSC on udp://{}, Ctrl-C to stop", listener.local_addr().map_err(CliError::Io)?);
+    let deadline = duration.map(|secs| Instant::now() + Duration::from_secs_f64(secs.max(0.0)));
+    let mut addresses: BTreeMap<String, (u64, Vec<OscArg>)> = BTreeMap::new();
+    let (mut packets, mut malformed) = (0u64, 0u64);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let timeout = match deadline {
+            Some(deadline) if Instant::now() >= deadline => break,
+            Some(deadline) => deadline - Instant::now(),
+            None => Duration::from_secs(3600),
+        };
+        let received = tokio::select! {
+            _ = &mut ctrl_c => break,
+            received = listener.recv(timeout) => received,
+        };
+        match received {
+            Ok(Some((from, messages))) => {
+                packets += 1;
+                for message in messages {
+                    eprintln!("{} {}", from, message);
+                    let entry = addresses.entry(message.address).or_default();
+                    entry.0 += 1;
+                    entry.1 = message.args;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                malformed += 1;
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    let mut lines = vec![format!("{} packets, {} malformed", packets, malformed)];
+    lines.extend(addresses.iter().map(|(address, (count, args))| {
+        let args: Vec<String> = args.iter().map(|arg| osc_arg_json(arg).to_string()).collect();
+        format!("{:>6}  {} {}", count, address, args.join(" "))
+    }));
+    let addresses: serde_json::Map<String, Value> = addresses
+        .into_iter()
+        .map(|(address, (count, args))| {
+            let args: Vec<Value> = args.iter().map(osc_arg_json).collect();
+            (address, json!({ "count": count, "last": args }))
+        })
+        .collect();
+    Ok(Report {
+        result: json!({ "packets": packets, "malformed": malformed, "addresses": addresses }),
+        lines,
+    })
+}
+
+/// Seconds between clock offset measurements while recording LSL streams.
+const CLOCK_OFFSET_INTERVAL_SECS: f64 = 5.0;
+
+/// What a stream's recording task hands the XDF writer.
+enum Recorded {
+    Samples(u32, Vec<(f64, Vec<f64>)>),
+    ClockOffset(u32, f64, f64),
+}
+
+fn xdf_header(info: &StreamInfo) -> XdfStreamHeader {
+    let format = match info.channel_format {
+        ChannelFormat::Float32 => XdfFormat::Float32,
+        ChannelFormat::Double64 => XdfFormat::Double64,
+    };
+    let header = XdfStreamHeader::new(&info.name, &info.stream_type, info.channel_count, info.nominal_srate, format)
+        .with_source_id(&info.source_id)
+        .with_created_at(info.created_at);
+    match info.channels.first() {
+        Some(channel) if info.channels.len() == info.channel_count => header.with_channels(&info.labels(), &channel.unit),
+        _ => header,
+    }
+}
+
+/// Pulls one stream until it ends or recording stops, passing samples on a quarter second at
+/// a time and measuring its clock offset every few seconds. Returns the samples pulled.
+async fn record_inlet(id: u32, mut inlet: LslInlet, output: mpsc::UnboundedSender<Recorded>, mut stop: watch::Receiver<bool>) -> Result<u64, String> {
+    let batch = (inlet.info().nominal_srate / 4.0).ceil().max(1.0) as usize;
+    let mut pending = Vec::with_capacity(batch);
+    let mut pulled = 0u64;
+    let mut next_offset = 0.0;
+    loop {
+        if lsl::local_clock() >= next_offset {
+            // A missed measurement is not fatal; readers use the ones around it.
+            if let Ok(offset) = inlet.time_correction().await {
+                let _ = output.send(Recorded::ClockOffset(id, lsl::local_clock(), offset));
+            }
+            next_offset = lsl::local_clock() + CLOCK_OFFSET_INTERVAL_SECS;
+        }
+        let sample = tokio::select! {
+            _ = stop.changed() => None,
+            sample = inlet.pull_sample() => sample?,
+        };
+        let Some(sample) = sample else { break };
+        pending.push(sample);
+        pulled += 1;
+        if pending.len() >= batch {
+            let _ = output.send(Recorded::Samples(id, std::mem::take(&mut pending)));
+        }
+    }
+    if !pending.is_empty() {
+        let _ = output.send(Recorded::Samples(id, pending));
+    }
+    Ok(pulled)
+}
+
+/// Records every stream matching `query` into one XDF file until `duration` passes, Ctrl-C,
+/// or every stream has ended.
+async fn lsl_record(output: &Path, query: &str, duration: Option<f64>, resolve: f64) -> Result<Report, CliError> {
+    if duration.is_some_and(|duration| !(duration > 0.0 && duration.is_finite())) {
+        return Err(CliError::Usage("Recording duration must be positive".to_string()));
+    }
+    if !(resolve > 0.0 && resolve.is_finite()) {
+        return Err(CliError::Usage("Resolve time must be positive".to_string()));
+    }
+    eprintln!("Looking for LSL streams for {:.1}s...", resolve);
+    let found = lsl::resolve(query, Duration::from_secs_f64(resolve)).await.map_err(CliError::Device)?;
+    if found.is_empty() {
+        return Err(CliError::Device(match query.trim() {
+            "" => "No LSL streams found".to_string(),
+            query => format!("No LSL streams match {}", query),
+        }));
+    }
+
+    let mut writer = XdfWriter::create(output).map_err(CliError::Io)?;
+    let (sender, mut received) = mpsc::unbounded_channel();
+    let (stop, stopped) = watch::channel(false);
+    let mut streams = vec![];
+    for info in found {
+        let inlet = match LslInlet::open(&info).await {
+            Ok(inlet) => inlet,
+            Err(e) => {
+                eprintln!("Skipping {}: {}", info.name, e);
+                continue;
+            }
+        };
+        let info = inlet.info().clone();
+        let id = writer.add_stream(&xdf_header(&info)).map_err(CliError::Io)?;
+        eprintln!("Recording {} ({}, {} channels at {} Hz)", info.name, info.stream_type, info.channel_count, info.nominal_srate);
+        let task = tokio::spawn(record_inlet(id, inlet, sender.clone(), stopped.clone()));
+        streams.push((id, info, task));
+    }
+    drop(sender);
+    if streams.is_empty() {
+        return Err(CliError::Device("None of the LSL streams could be opened".to_string()));
+    }
+
+    // Samples written and clock offsets measured, per stream id.
+    let mut written: BTreeMap<u32, (u64, Vec<f64>)> = BTreeMap::new();
+    let mut write = |writer: &mut XdfWriter, item: Recorded| -> Result<(), CliError> {
+        match item {
+            Recorded::Samples(id, samples) => {
+                writer.write_samples(id, &samples).map_err(CliError::Io)?;
+                written.entry(id).or_default().0 += samples.len() as u64;
+            }
+            Recorded::ClockOffset(id, collected, offset) => {
+                writer.write_clock_offset(id, collected, offset).map_err(CliError::Io)?;
+                written.entry(id).or_default().1.push(offset);
+            }
+        }
+        Ok(())
+    };
+    let deadline = duration.map(|secs| Instant::now() + Duration::from_secs_f64(secs));
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let item = tokio::select! {
+            _ = &mut ctrl_c => break,
+            _ = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            } => break,
+            item = received.recv() => item,
+        };
+        match item {
+            Some(item) => write(&mut writer, item)?,
+            None => break,
+        }
+    }
+    let _ = stop.send(true);
+    while let Some(item) = received.recv().await {
+        write(&mut writer, item)?;
+    }
+    writer.finish().map_err(CliError::Io)?;
+
+    let mut lines = vec![format!("Recorded {} stream(s) to {}", streams.len(), output.display())];
+    let mut results = vec![];
+    for (id, info, task) in streams {
+        let ended = match task.await {
+            Ok(Ok(_)) => None,
+            Ok(Err(e)) => Some(e),
+            Err(e) => Some(e.to_string()),
+        };
+        let (samples, offsets) = written.remove(&id).unwrap_or_default();
+        lines.push(format!(
+            "{:>8} samples  {} ({}, {} channels at {} Hz), {} clock offsets{}",
+            samples,
+            info.name,
+            info.stream_type,
+            info.channel_count,
+            info.nominal_srate,
+            offsets.len(),
+            ended.as_ref().map(|e| format!("; stopped early: {}", e)).unwrap_or_default()
+        ));
+        results.push(json!({
+            "name": info.name,
+            "type": info.stream_type,
+            "channels": info.channel_count,
+            "sample_rate": info.nominal_srate,
+            "samples": samples,
+            "clock_offsets": offsets.len(),
+            "last_clock_offset": offsets.last(),
+            "error": ended,
+        }));
+    }
+    Ok(Report {
+        result: json!({ "output": output.display().to_string(), "streams": results }),
+        lines,
+    })
+}
+
+/// Builds a session from decoded samples; lost packets end a segment.
+fn openbci_session(device_id: &str, board: OpenBciBoard, samples: &[OpenBciSample]) -> StoredSession {
+    let mut session = StoredSession::new(device_id, board.sample_rate(), board.channel_labels());
+    let mut next_index = None;
+    for sample in samples {
+        if next_index != Some(sample.index) {
+            session.segments.push(Segment { start_secs: sample.index as f64 / board.sample_rate(), frames: vec![] });
+        }
+        if let Some(segment) = session.segments.last_mut() {
+            segment.frames.push(sample.microvolts.clone());
+        }
+        next_index = Some(sample.index + 1);
+    }
+    session
+}
+
+/// What `openbci` and `openbci-decode` both report about the samples.
+fn openbci_summary(board: OpenBciBoard, session: &StoredSession, samples: &[OpenBciSample], lost_packets: u64, skipped_bytes: u64) -> (Value, Vec<String>) {
+    let readings: Vec<[f64; 3]> = samples.iter().filter_map(|sample| sample.accelerometer).collect();
+    let mean_accel = (!readings.is_empty())
+        .then(|| [0, 1, 2].map(|axis| readings.iter().map(|reading| reading[axis]).sum::<f64>() / readings.len() as f64));
+    let result = json!({
+        "board": format!("{:?}", board),
+        "sample_rate": board.sample_rate(),
+        "channels": session.channels,
+        "frames": session.frame_count(),
+        "duration_secs": session.duration_secs(),
+        "segments": session.segments.len(),
+        "packets_lost": lost_packets,
+        "bytes_skipped": skipped_bytes,
+        "accelerometer_readings": readings.len(),
+        "accelerometer_mean_g": mean_accel,
+    });
+    let mut lines = vec![
+        format!(
+            "{:?}: {} samples ({:.2}s, {} channels at {} Hz) in {} segment(s)",
+            board,
+            session.frame_count(),
+            session.duration_secs(),
+            session.channels.len(),
+            board.sample_rate(),
+            session.segments.len()
+        ),
+        format!("Packets lost: {}; bytes skipped while framing: {}", lost_packets, skipped_bytes),
+    ];
+    lines.push(match mean_accel {
+        Some([x, y, z]) => format!("Accelerometer: {} readings, mean ({:.3}, {:.3}, {:.3}) g", readings.len(), x, y, z),
+        None => "Accelerometer: no readings".to_string(),
+    });
+    (result, lines)
+}
+
+async fn openbci(
+    output: &Path,
+    port: Option<&Path>,
+    board: Option<OpenBciBoard>,
+    duration: f64,
+    simulate: bool,
+    capture: Option<&Path>,
+) -> Result<Report, CliError> {
+    if !(duration > 0.0 && duration.is_finite()) {
+        return Err(CliError::Usage("Recording duration must be positive".to_string()));
+    }
+    let format = ExportFormat::from_path(output)?;
+    let simulated = if simulate {
+        Some(SimulatedBoard::spawn(board.unwrap_or(OpenBciBoard::Cyton)).map_err(CliError::Device)?)
+    } else {
+        None
+    };
+    let path = match (&simulated, port) {
+        (Some(simulated), _) => simulated.path().to_path_buf(),
+        (None, Some(port)) => port.to_path_buf(),
+        (None, None) => return Err(CliError::Usage("Pass --port or --simulate".to_string())),
+    };
+    let mut device = OpenBciDevice::new(&path);
+    if let Some(board) = board {
+        device = device.with_board(board);
+    }
+    if let Some(capture) = capture {
+        device = device.with_capture(capture).map_err(CliError::Io)?;
+    }
+    let mut received = device.subscribe(4096);
+    let collect = tokio::spawn(async move {
+        let mut samples = vec![];
+        while let Some(sample) = received.recv().await {
+            samples.push(sample);
+        }
+        samples
+    });
+    device.connect().await.map_err(CliError::Device)?;
+    let board = device.board().unwrap_or(OpenBciBoard::Cyton);
+    eprintln!("Recording {} for {:.1}s...", path.display(), duration);
+    let streamed = device.stream(Duration::from_secs_f64(duration)).await;
+    let _ = device.disconnect().await;
+    streamed.map_err(CliError::Device)?;
+    let samples = collect.await.map_err(|e| CliError::Device(e.to_string()))?;
+    if samples.is_empty() {
+        return Err(CliError::Device(format!("No samples from {}", path.display())));
+    }
+
+    let session = openbci_session(&device.id, board, &samples);
+    save(&session, output, format)?;
+    let (mut result, mut lines) = openbci_summary(board, &session, &samples, device.lost_packets(), device.skipped_bytes());
+    result["port"] = json!(path.display().to_string());
+    result["output"] = json!(output.display().to_string());
+    lines.insert(0, format!("Recorded {} to {}", path.display(), output.display()));
+    Ok(Report { result, lines })
+}
+
+fn openbci_decode(capture: &Path, board: OpenBciBoard, output: Option<&Path>) -> Result<Report, CliError> {
+    let format = output.map(ExportFormat::from_path).transpose()?;
+    let bytes = std::fs::read(capture).map_err(|e| CliError::Io(format!("Cannot read {}: {}", capture.display(), e)))?;
+    let mut decoder = OpenBciDecoder::new(board);
+    let (samples, packet_count) = decoder.push(&bytes);
+    if samples.is_empty() {
+        return Err(CliError::Data(format!("{} contains no OpenBCI {:?} samples", capture.display(), board)));
+    }
+
+    let session = openbci_session(&format!("openbci:{}", capture.display()), board, &samples);
+    if let (Some(output), Some(format)) = (output, format) {
+        save(&session, output, format)?;
+    }
+    let (mut result, mut lines) = openbci_summary(board, &session, &samples, decoder.lost_packets(), decoder.skipped_bytes());
+    result["capture"] = json!(capture.display().to_string());
+    result["packets"] = json!(packet_count);
+    result["first_sample_uv"] = json!(samples[0].microvolts);
+    lines.insert(0, format!("{}: {} bytes, {} packets", capture.display(), bytes.len(), packet_count));
+    let first: Vec<String> = samples[0].microvolts.iter().map(|uv| format!("{:.2}", uv)).collect();
+    lines.push(format!("First sample (µV): {}", first.join(", ")));
+    if let Some(output) = output {
+        lines.push(format!("Wrote {}", output.display()));
+    }
+    Ok(Report { result, lines })
+}
+
+async fn run(command: &Command) -> Result<Report, CliError> {
+    match command {
+        Command::Scan { duration, name_prefix, address } => scan(*duration, name_prefix, address).await,
+        Command::Record { output, duration, address, filter, mains } => {
+            record(output, *duration, address.as_deref(), *filter, *mains).await
+        }
+        Command::Monitor { duration, address, filter, mains } => monitor(*duration, address.as_deref(), *filter, *mains).await,
+        Command::Replay { file, speed, channel } => replay(file, *speed, channel.as_deref()).await,
+        Command::Analyze { file, mains, clean, seed, stream } => analyze(file, *mains, clean.as_deref(), *seed, stream.as_deref()),
+        Command::Export { input, output, format, stream } => export(input, output, *format, stream.as_deref()),
+        Command::Serve { socket } => serve(&socket.clone().unwrap_or_else(control_api::default_socket_path)).await,
+        Command::Ctl { method, params, socket } => {
+            ctl(&socket.clone().unwrap_or_else(control_api::default_socket_path), method, params.as_deref()).await
+        }
+        Command::OscListen { bind, duration } => osc_listen(bind, *duration).await,
+        Command::LslRecord { output, query, duration, resolve } => lsl_record(output, query, *duration, *resolve).await,
+        Command::Openbci { output, port, board, duration, simulate, capture } => {
+            openbci(output, port.as_deref(), *board, *duration, *simulate, capture.as_deref()).await
+        }
+        Command::OpenbciDecode { capture, board, output } => openbci_decode(capture, *board, output.as_deref()),
+    }
+}
+
+fn command_name(command: &Command) -> &'static str {
+    match command {
+        Command::Scan { .. } => "scan",
+        Command::Record { .. } => "record",
+        Command::Monitor { .. } => "monitor",
+        Command::Replay { .. } => "replay",
+        Command::Analyze { .. } => "analyze",
+        Command::Export { .. } => "export",
+        Command::Serve { .. } => "serve",
+        Command::Ctl { .. } => "ctl",
+        Command::OscListen { .. } => "osc-listen",
+        Command::LslRecord { .. } => "lsl-record",
+        Command::Openbci { .. } => "openbci",
+        Command::OpenbciDecode { .. } => "openbci-decode",
+    }
+}
+
+/// Writes a command's outcome to `out` (stdout) and returns the exit code. A reader that goes
+/// away early, as in `bluerain scan | head -1`, is not an error.
+fn emit(out: &mut impl Write, json_output: bool, command: &str, outcome: Result<Report, CliError>) -> ExitCode {
+    let (lines, code) = match outcome {
+        Ok(report) if json_output => {
+            let envelope = json!({ "ok": true, "command": command, "result": report.result });
+            (vec![envelope.to_string()], ExitCode::SUCCESS)
+        }
+        Ok(report) => (report.lines, ExitCode::SUCCESS),
+        Err(error) if json_output => {
+            let envelope = json!({ "ok": false, "error": { "kind": error.kind(), "message": error.message() } });
+            (vec![envelope.to_string()], ExitCode::from(error.exit_code()))
+        }
+        Err(error) => {
+            eprintln!("bluerain: {}", error);
+            return ExitCode::from(error.exit_code());
+        }
+    };
+    let written = lines.iter().try_for_each(|line| writeln!(out, "{}", line)).and_then(|_| out.flush());
+    match written {
+        Ok(()) => code,
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => code,
+        Err(e) => {
+            let error = CliError::Io(format!("Cannot write output: {}", e));
+            eprintln!("bluerain: {}", error);
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            // Help and version requests are not errors; clap exits 0 for them.
+            if !e.use_stderr() || !std::env::args().any(|arg| arg == "--json") {
+                e.exit();
+            }
+            let message = e.to_string();
+            let message = message.lines().next().unwrap_or_default().trim_start_matches("error: ");
+            return emit(&mut io::stdout().lock(), true, "", Err(CliError::Usage(message.to_string())));
+        }
+    };
+
+    let outcome = run(&cli.command).await;
+    emit(&mut io::stdout().lock(), cli.json, command_name(&cli.command), outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(params: Value, running: bool) -> Result<Protocol, RpcError> {
+        update_protocol(&Protocol::new(None, false, 50.0), &Params::new(params)?, &[250, 500], running)
+    }
+
+    #[test]
+    fn protocol_updates_within_limits_are_applied() {
+        let protocol = update(json!({ "duration_secs": 60.0, "filter": true, "mains_hz": 60, "gain": 12, "sample_rate": 500 }), false).unwrap();
+        assert_eq!(protocol.duration_secs, Some(60.0));
+        assert!(protocol.filter);
+        assert_eq!((protocol.mains_hz, protocol.gain, protocol.sample_rate), (60.0, 12, 500));
+
+        let unchanged = update(json!({}), true).unwrap();
+        assert_eq!(unchanged.to_json(), Protocol::new(None, false, 50.0).to_json());
+        assert_eq!(update(json!({ "duration_secs": MAX_SESSION_SECS }), true).unwrap().duration_secs, Some(MAX_SESSION_SECS));
+    }
+
+    #[test]
+    fn protocol_updates_outside_limits_are_refused() {
+        let limit_exceeded = [
+            json!({ "duration_secs": 0.0 }),
+            json!({ "duration_secs": -5.0 }),
+            json!({ "duration_secs": MAX_SESSION_SECS + 1.0 }),
+            json!({ "mains_hz": 55 }),
+            json!({ "gain": 3 }),
+            json!({ "gain": 280 }),
+            json!({ "sample_rate": 1000 }),
+            json!({ "sample_rate": 65786 }),
+            // Nothing is applied when any parameter is out of range.
+            json!({ "filter": true, "gain": 0 }),
+        ];
+        for params in limit_exceeded {
+            assert_eq!(update(params.clone(), false).unwrap_err().code, control_api::LIMIT_EXCEEDED, "{}", params);
+        }
+        let error = update(json!({ "sample_rate": 500 }), true).unwrap_err();
+        assert_eq!(error.code, control_api::INVALID_STATE);
+        for params in [json!({ "gain": "24" }), json!({ "gain": -1 }), json!({ "filter": 1 }), json!({ "mains_hz": "50" })] {
+            assert_eq!(update(params.clone(), false).unwrap_err().code, control_api::INVALID_PARAMS, "{}", params);
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_starts_never_share_a_headset() {
+        let daemon = Daemon::new();
+        let start = || daemon.handle("sessions.start", json!({ "device": "00:1A:7D:DA:71:13" }));
+        let (first, second) = tokio::join!(start(), start());
+        let (started, refused) = match (first, second) {
+            (Ok(started), Err(refused)) | (Err(refused), Ok(started)) => (started, refused),
+            other => panic!("expected exactly one session to start, got {:?}", other),
+        };
+        assert_eq!(started["device"], json!("00:1A:7D:DA:71:13"));
+        assert_eq!(refused.code, control_api::INVALID_STATE);
+        assert_eq!(Daemon::busy_devices(&daemon.sessions.lock().unwrap()).len(), 1);
+
+        let stopped = daemon.stop_all().await;
+        assert_eq!(stopped.len(), 1);
+        assert!(Daemon::busy_devices(&daemon.sessions.lock().unwrap()).is_empty());
+    }
+
+    /// A stdout whose reader has gone away, or that fails some other way.
+    struct FailingOutput(io::ErrorKind);
+
+    impl Write for FailingOutput {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(self.0.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn report() -> Report {
+        Report {
+            result: json!({ "frames": 3 }),
+            lines: vec!["Exported 3 frames".to_string(), "Done".to_string()],
+        }
+    }
+
+    /// What `bluerain <args>` prints on stdout, and its exit code.
+    async fn run_cli(args: &[&str]) -> (String, ExitCode) {
+        let cli = Cli::try_parse_from(std::iter::once("bluerain").chain(args.iter().copied())).unwrap();
+        let outcome = run(&cli.command).await;
+        let mut out = vec![];
+        let code = emit(&mut out, cli.json, command_name(&cli.command), outcome);
+        (String::from_utf8(out).unwrap(), code)
+    }
+
+    #[test]
+    fn outcomes_are_printed_as_text_or_json_envelopes() {
+        let mut out = vec![];
+        assert_eq!(emit(&mut out, false, "export", Ok(report())), ExitCode::SUCCESS);
+        assert_eq!(String::from_utf8(out).unwrap(), "Exported 3 frames\nDone\n");
+
+        let mut out = vec![];
+        assert_eq!(emit(&mut out, true, "export", Ok(report())), ExitCode::SUCCESS);
+        let envelope: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(envelope, json!({ "ok": true, "command": "export", "result": { "frames": 3 } }));
+
+        let errors = [
+            (CliError::Usage("bad flag".to_string()), "usage", 2),
+            (CliError::Io("no such file".to_string()), "io", 3),
+            (CliError::Device("not found".to_string()), "device", 4),
+            (CliError::Data("no channels".to_string()), "data", 5),
+        ];
+        for (error, kind, code) in errors {
+            let message = error.message().to_string();
+            let mut out = vec![];
+            assert_eq!(emit(&mut out, true, "scan", Err(error)), ExitCode::from(code));
+            let envelope: Value = serde_json::from_slice(&out).unwrap();
+            assert_eq!(envelope, json!({ "ok": false, "error": { "kind": kind, "message": message } }));
+        }
+        let mut out = vec![];
+        assert_eq!(emit(&mut out, false, "scan", Err(CliError::Device("not found".to_string()))), ExitCode::from(4));
+        assert!(out.is_empty(), "text errors go to stderr");
+    }
+
+    #[test]
+    fn a_closed_pipe_is_a_clean_exit() {
+        let mut closed = FailingOutput(io::ErrorKind::BrokenPipe);
+        assert_eq!(emit(&mut closed, false, "scan", Ok(report())), ExitCode::SUCCESS);
+        assert_eq!(emit(&mut closed, true, "scan", Err(CliError::Data("bad".to_string()))), ExitCode::from(5));
+        let mut full = FailingOutput(io::ErrorKind::StorageFull);
+        assert_eq!(emit(&mut full, false, "scan", Ok(report())), ExitCode::from(3));
+    }
+
+    #[tokio::test]
+    async fn commands_report_failures_with_their_exit_codes() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("bluerain-cli-test-{}.brs", std::process::id()));
+        let output = dir.join(format!("bluerain-cli-test-{}.csv", std::process::id()));
+        let (input_arg, output_arg) = (input.to_str().unwrap(), output.to_str().unwrap());
+        let session = StoredSession::from_channels("A123", 250.0, vec!["Fp1".to_string()], &[vec![1.0, 2.0, 3.0]]).unwrap();
+        session.save(&input).unwrap();
+
+        let (stdout, code) = run_cli(&["--json", "export", input_arg, output_arg]).await;
+        assert_eq!(code, ExitCode::SUCCESS);
+        let envelope: Value = serde_json::from_str(&stdout).unwrap();
+        assert_eq!((envelope["ok"].clone(), envelope["command"].clone()), (json!(true), json!("export")));
+        assert_eq!(envelope["result"]["frames"], json!(3));
+        let (stdout, code) = run_cli(&["export", input_arg, output_arg]).await;
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert!(stdout.starts_with("Exported 3 frames"));
+
+        let (_, code) = run_cli(&["--json", "export", input_arg, output_arg, "--stream", "EEG"]).await;
+        assert_eq!(code, ExitCode::from(2));
+        let (stdout, code) = run_cli(&["--json", "export", "/nonexistent/session.brs", output_arg]).await;
+        assert_eq!(code, ExitCode::from(3));
+        assert_eq!(serde_json::from_str::<Value>(&stdout).unwrap()["error"]["kind"], json!("io"));
+        std::fs::write(&input, "not a session").unwrap();
+        let (_, code) = run_cli(&["--json", "export", input_arg, output_arg]).await;
+        assert_eq!(code, ExitCode::from(5));
+        assert!(Cli::try_parse_from(["bluerain", "--json", "frobnicate"]).is_err());
+
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+}
@@ -0,0 +1,803 @@
+//! C ABI for embedding BlueRAIN in Unity and other game engines.
+//!
+//! Built as a `cdylib`. `ffi/bluerain.h` is generated from this file:
+//!
+//! ```text
+//! cbindgen --config src/ffi/cbindgen.toml --output src/ffi/bluerain.h src/bluerain_ffi.rs
+//! ```
+//!
+//! A session streams on its own thread into bounded queues that the host polls, typically once
+//! per rendered frame, so no call blocks for long except opening and stopping. Functions may be
+//! called from any thread. Failures return a negative `BR_ERR_*` code, and
+//! `bluerain_last_error` then describes the failure on the calling thread.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+mod device_operations;
+#[allow(dead_code)]
+mod discovery;
+#[allow(dead_code)]
+mod edf;
+#[allow(dead_code)]
+mod features;
+#[allow(dead_code)]
+mod gatt;
+#[allow(dead_code)]
+mod neurofeedback;
+#[allow(dead_code)]
+mod replay;
+#[allow(dead_code)]
+mod session_store;
+#[allow(dead_code)]
+mod streaming_stats;
+#[allow(dead_code)]
+mod xdf;
+#[allow(dead_code)]
+mod xml;
+
+use device_operations::DeviceOperations;
+use discovery::{DeviceAllowlist, MockAdapter, Scanner};
+use features::{Band, BandPowerTracker, BandPowers};
+use gatt::{ControlCommand, SamplePacket, SequenceTracker, SimulatedPeripheral, StatusPacket};
+use neurofeedback::{RewardRule, RewardTracker};
+use replay::{ReplayDevice, ReplayItem, ReplaySpeed};
+
+type DeviceResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Bumped whenever a function signature or struct layout in this file changes.
+pub const BR_ABI_VERSION: u32 = 1;
+
+pub const BR_OK: i32 = 0;
+/// A null pointer, invalid UTF-8 or an out-of-range value was passed.
+pub const BR_ERR_INVALID_ARGUMENT: i32 = -1;
+/// No matching headset, or the recording does not exist.
+pub const BR_ERR_NOT_FOUND: i32 = -2;
+/// The recording could not be read.
+pub const BR_ERR_IO: i32 = -3;
+/// The device or replay failed while streaming.
+pub const BR_ERR_DEVICE: i32 = -4;
+/// A bug inside the library; close the session.
+pub const BR_ERR_INTERNAL: i32 = -5;
+
+pub const BR_STATE_STREAMING: i32 = 1;
+/// The recording ended or the session was stopped; queued data can still be polled.
+pub const BR_STATE_FINISHED: i32 = 2;
+pub const BR_STATE_FAILED: i32 = 3;
+
+pub const BR_BAND_COUNT: usize = 5;
+
+/// Frames kept for the host before the oldest are dropped (16 s at 250 Hz).
+const FRAME_QUEUE: usize = 4096;
+const FEATURE_QUEUE: usize = 256;
+const BAND_WINDOW_SECS: f64 = 2.0;
+/// Band powers are computed this many times per second.
+const FEATURE_RATE: f64 = 4.0;
+
+/// Index into `BrBandPowers` arrays, and the `band` of reward rules and rewards.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrBand {
+    Delta = 0,
+    Theta = 1,
+    Alpha = 2,
+    Beta = 3,
+    Gamma = 4,
+}
+
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BrSessionInfo {
+    pub sample_rate: f64,
+    pub channel_count: u32,
+    /// One of `BR_STATE_*`.
+    pub state: i32,
+    pub frames_received: u64,
+    /// Frames discarded because the host did not poll them in time.
+    pub frames_dropped: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BrFrame {
+    /// Seconds since the start of the stream (recording time for replays).
+    pub time_secs: f64,
+    pub channel_count: u32,
+}
+
+/// Band powers averaged across channels, indexed by `BrBand`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BrBandPowers {
+    pub time_secs: f64,
+    /// Absolute power in µV².
+    pub power: [f64; BR_BAND_COUNT],
+    /// Share of the total, 0 to 1.
+    pub relative: [f64; BR_BAND_COUNT],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BrRewardRule {
+    /// A `BrBand`.
+    pub band: u32,
+    /// Relative band power, 0 to 1, that must be exceeded.
+    pub threshold: f64,
+    /// Seconds above threshold per reward.
+    pub hold_secs: f64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BrReward {
+    pub time_secs: f64,
+    /// A `BrBand`.
+    pub band: u32,
+    /// Relative band power when the reward was given.
+    pub score: f64,
+    /// 1 for the first reward of a run above threshold, then 2, 3...
+    pub streak: u32,
+}
+
+
+#[derive(Debug)]
+struct Queues {
+    frames: VecDeque<(f64, Vec<f64>)>,
+    band_powers: VecDeque<BrBandPowers>,
+    rewards: VecDeque<BrReward>,
+    rewarder: RewardTracker,
+    state: i32,
+    error: Option<String>,
+    frames_received: u64,
+    frames_dropped: u64,
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, item: T, capacity: usize) -> bool {
+    let full = queue.len() >= capacity;
+    if full {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+    full
+}
+
+#[derive(Debug)]
+struct Shared {
+    queues: Mutex<Queues>,
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn stopping(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    fn finish(&self, result: Result<(), String>) {
+        let mut queues = self.queues.lock().unwrap();
+        match result {
+            Ok(()) => queues.state = BR_STATE_FINISHED,
+            Err(e) => {
+                queues.state = BR_STATE_FAILED;
+                queues.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Runs on the session thread: queues frames and derives band powers and rewards from them.
+struct FrameSink {
+    shared: Arc<Shared>,
+    trackers: Vec<BandPowerTracker>,
+    hop: usize,
+    count: usize,
+}
+
+impl FrameSink {
+    fn new(shared: Arc<Shared>, sample_rate: f64, channel_count: usize) -> Self {
+        FrameSink {
+            shared,
+            trackers: vec![BandPowerTracker::new(sample_rate, BAND_WINDOW_SECS); channel_count],
+            hop: ((sample_rate / FEATURE_RATE).round() as usize).max(1),
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, time_secs: f64, values: Vec<f64>) {
+        for (tracker, value) in self.trackers.iter_mut().zip(&values) {
+            tracker.push(*value);
+        }
+        self.count += 1;
+        let powers = if self.count >= self.hop {
+            self.count = 0;
+            let per_channel: Option<Vec<BandPowers>> = self.trackers.iter().map(BandPowerTracker::powers).collect();
+            per_channel.map(|per_channel| BandPowers::average(&per_channel))
+        } else {
+            None
+        };
+
+        let mut queues = self.shared.queues.lock().unwrap();
+        queues.frames_received += 1;
+        if push_bounded(&mut queues.frames, (time_secs, values), FRAME_QUEUE) {
+            queues.frames_dropped += 1;
+        }
+        let Some(powers) = powers else {
+            return;
+        };
+        let mut features = BrBandPowers { time_secs, ..Default::default() };
+        for band in Band::ALL {
+            features.power[band as usize] = powers.get(band);
+            features.relative[band as usize] = powers.relative(band);
+        }
+        push_bounded(&mut queues.band_powers, features, FEATURE_QUEUE);
+        if let Some(reward) = queues.rewarder.update(time_secs, &powers) {
+            let reward = BrReward {
+                time_secs: reward.time_secs,
+                band: reward.band as u32,
+                score: reward.score,
+                streak: reward.streak,
+            };
+            push_bounded(&mut queues.rewards, reward, FEATURE_QUEUE);
+        }
+    }
+}
+
+/// An open device or replay. Opaque to C.
+pub struct BrSession {
+    shared: Arc<Shared>,
+    /// Behind a lock because `bluerain_stop` may run while other threads poll the session.
+    thread: Mutex<Option<JoinHandle<()>>>,
+    sample_rate: f64,
+    channels: Vec<CString>,
+}
+
+impl BrSession {
+    fn start(sample_rate: f64, channels: Vec<String>, run: impl FnOnce(FrameSink) -> Result<(), String> + Send + 'static) -> Self {
+        let shared = Arc::new(Shared {
+            queues: Mutex::new(Queues {
+                frames: VecDeque::new(),
+                band_powers: VecDeque::new(),
+                rewards: VecDeque::new(),
+                rewarder: RewardTracker::new(RewardRule::default()).expect("default reward rule is valid"),
+                state: BR_STATE_STREAMING,
+                error: None,
+                frames_received: 0,
+                frames_dropped: 0,
+            }),
+            stop: AtomicBool::new(false),
+        });
+        let sink = FrameSink::new(shared.clone(), sample_rate, channels.len());
+        let thread = std::thread::spawn({
+            let shared = shared.clone();
+            move || {
+                let result = catch_unwind(AssertUnwindSafe(|| run(sink))).unwrap_or_else(|_| Err("Session thread panicked".to_string()));
+                shared.finish(result);
+            }
+        });
+        BrSession {
+            shared,
+            thread: Mutex::new(Some(thread)),
+            sample_rate,
+            // Labels come from our own files and devices; an interior NUL is replaced rather than fatal.
+            channels: channels
+                .into_iter()
+                .map(|label| CString::new(label.replace('\0', " ")).expect("NULs replaced"))
+                .collect(),
+        }
+    }
+
+    fn stop(&self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        let thread = self.thread.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+
+    /// An empty queue is only an error once the session has failed.
+    fn empty_queue_status(&self, queues: &Queues) -> Result<i32, FfiError> {
+        match (&queues.error, queues.state) {
+            (Some(e), BR_STATE_FAILED) => Err(FfiError(BR_ERR_DEVICE, e.clone())),
+            _ => Ok(0),
+        }
+    }
+}
+
+impl Drop for BrSession {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn runtime() -> Result<tokio::runtime::Runtime, String> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Cannot start runtime: {}", e))
+}
+
+/// Streams a simulated headset until stopped, pacing packets to its sample clock.
+fn stream_headset(channel_count: u8, mut sink: FrameSink) -> Result<(), String> {
+    let mut peripheral = SimulatedPeripheral::new(channel_count);
+    let status = peripheral
+        .write_control(&ControlCommand::RequestStatus.encode())
+        .map_err(|e| format!("Cannot read headset status: {:?}", e))?
+        .ok_or("Headset sent no status")?;
+    let status = StatusPacket::decode(&status).map_err(|e| format!("Bad status packet: {:?}", e))?;
+    // Counts are scaled by whatever gain the headset is running at.
+    let lsb = gatt::lsb_microvolts(status.gain) as f64;
+    peripheral
+        .write_control(&ControlCommand::StartStream.encode())
+        .map_err(|e| format!("Cannot start stream: {:?}", e))?;
+    let sample_rate = peripheral.sample_rate() as f64;
+    let mut tracker = SequenceTracker::default();
+    let started = Instant::now();
+    let mut clock = 0;
+    while !sink.shared.stopping() {
+        let notification = peripheral.next_notification().ok_or("Headset stopped streaming")?;
+        let Ok(packet) = SamplePacket::decode(&notification) else {
+            continue;
+        };
+        clock += tracker.observe(packet.sequence) as usize * packet.frames.len();
+        for raw in &packet.frames {
+            let values = raw.iter().map(|count| *count as f64 * lsb).collect();
+            sink.push(clock as f64 / sample_rate, values);
+            clock += 1;
+        }
+        let due = started + Duration::from_secs_f64(clock as f64 / sample_rate);
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+    }
+    let _ = peripheral.write_control(&ControlCommand::StopStream.encode());
+    Ok(())
+}
+
+/// Plays a recording to the end, or until stopped.
+fn stream_replay(mut device: ReplayDevice, mut sink: FrameSink) -> Result<(), String> {
+    let shared = sink.shared.clone();
+    runtime()?.block_on(async {
+        let mut items = device.subscribe(256);
+        device.connect().await?;
+        let mut handle = |item: ReplayItem| {
+            if let ReplayItem::Frame { time_secs, values } = item {
+                sink.push(time_secs, values);
+            }
+        };
+        let play = device.stream(Duration::MAX);
+        tokio::pin!(play);
+        let mut check_stop = tokio::time::interval(Duration::from_millis(50));
+        loop {
+            tokio::select! {
+                Some(item) = items.recv() => handle(item),
+                result = &mut play => {
+                    while let Ok(item) = items.try_recv() {
+                        handle(item);
+                    }
+                    return result;
+                }
+                _ = check_stop.tick() => {
+                    if shared.stopping() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[derive(Debug)]
+struct FfiError(i32, String);
+
+impl FfiError {
+    fn invalid(message: impl Into<String>) -> Self {
+        FfiError(BR_ERR_INVALID_ARGUMENT, message.into())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Runs an exported function body, turning errors and panics into status codes.
+fn guard(name: &str, body: impl FnOnce() -> Result<i32, FfiError>) -> i32 {
+    let (status, message) = match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(status)) => return status,
+        Ok(Err(FfiError(status, message))) => (status, message),
+        Err(_) => (BR_ERR_INTERNAL, format!("{} panicked", name)),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message.replace('\0', " ")).expect("NULs replaced"));
+    status
+}
+
+unsafe fn session_ref<'a>(session: *const BrSession) -> Result<&'a BrSession, FfiError> {
+    session.as_ref().ok_or_else(|| FfiError::invalid("Session is null"))
+}
+
+unsafe fn out_ref<'a, T>(out: *mut T, what: &str) -> Result<&'a mut T, FfiError> {
+    out.as_mut().ok_or_else(|| FfiError::invalid(format!("{} pointer is null", what)))
+}
+
+unsafe fn optional_str<'a>(text: *const c_char, what: &str) -> Result<Option<&'a str>, FfiError> {
+    if text.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map(Some)
+        .map_err(|_| FfiError::invalid(format!("{} is not valid UTF-8", what)))
+}
+
+/// `BR_ABI_VERSION` of the loaded library; hosts should refuse to run on a mismatch.
+#[no_mangle]
+pub extern "C" fn bluerain_abi_version() -> u32 {
+    BR_ABI_VERSION
+}
+
+/// Describes the last failure on this thread. Valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn bluerain_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Finds a BlueRAIN headset (blocking for about a second) and starts streaming from it.
+/// Only simulated headsets are opened: the scan runs against the demo adapter and the stream
+/// comes from a simulated peripheral, so no radio is used. Use `bluerain_open_replay` for real data.
+///
+/// # Safety
+/// `address` is null (strongest headset) or a NUL-terminated string; `out` points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_open_device(address: *const c_char, out: *mut *mut BrSession) -> i32 {
+    guard("bluerain_open_device", || {
+        let out = out_ref(out, "Output")?;
+        let address = optional_str(address, "Address")?;
+        let allowlist = match address {
+            Some(address) => DeviceAllowlist::new().allow_address(address),
+            None => DeviceAllowlist::new().allow_name_prefix("BlueRAIN"),
+        };
+        let scanner = Scanner::new(MockAdapter::with_demo_peripherals()).with_allowlist(allowlist);
+        let devices = runtime()
+            .map_err(|e| FfiError(BR_ERR_INTERNAL, e))?
+            .block_on(scanner.scan(Duration::from_secs(1)))
+            .map_err(|e| FfiError(BR_ERR_DEVICE, format!("Scan failed: {}", e)))?;
+        let (address, channel_count) = devices
+            .iter()
+            .find_map(|device| {
+                let channel_count = u8::try_from(device.capabilities.as_ref()?.channel_count).ok()?;
+                (1..=gatt::MAX_CHANNELS).contains(&channel_count).then(|| (device.address.clone(), channel_count))
+            })
+            .ok_or_else(|| FfiError(BR_ERR_NOT_FOUND, format!("Headset {} not found", address.unwrap_or("(any)"))))?;
+
+        let sample_rate = SimulatedPeripheral::new(channel_count).sample_rate() as f64;
+        let channels = (1..=channel_count).map(|channel| format!("Ch{}", channel)).collect();
+        let session = BrSession::start(sample_rate, channels, move |sink| stream_headset(channel_count, sink));
+        eprintln!("Streaming headset {}", address);
+        *out = Box::into_raw(Box::new(session));
+        Ok(BR_OK)
+    })
+}
+
+/// Replays a session file (.brs), EDF or XDF recording. `speed` is a multiple of real time;
+/// 0 plays as fast as possible.
+///
+/// # Safety
+/// `path` is a NUL-terminated string; `out` points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_open_replay(path: *const c_char, speed: f64, out: *mut *mut BrSession) -> i32 {
+    guard("bluerain_open_replay", || {
+        let out = out_ref(out, "Output")?;
+        let path = Path::new(optional_str(path, "Path")?.ok_or_else(|| FfiError::invalid("Path is null"))?);
+        let speed = if speed == 0.0 {
+            ReplaySpeed::AsFastAsPossible
+        } else if speed == 1.0 {
+            ReplaySpeed::Realtime
+        } else if speed > 0.0 && speed.is_finite() {
+            ReplaySpeed::Accelerated(speed)
+        } else {
+            return Err(FfiError::invalid(format!("Invalid replay speed {}", speed)));
+        };
+        if !path.is_file() {
+            return Err(FfiError(BR_ERR_NOT_FOUND, format!("{} does not exist", path.display())));
+        }
+        let recording = replay::load_recording(path).map_err(|e| FfiError(BR_ERR_IO, e))?;
+        if recording.channels.is_empty() || recording.sample_rate <= 0.0 {
+            return Err(FfiError(BR_ERR_IO, format!("{} has no usable channels", path.display())));
+        }
+        let (sample_rate, channels) = (recording.sample_rate, recording.channels.clone());
+        let device = ReplayDevice::new(recording).with_speed(speed);
+        *out = Box::into_raw(Box::new(BrSession::start(sample_rate, channels, move |sink| stream_replay(device, sink))));
+        Ok(BR_OK)
+    })
+}
+
+/// # Safety
+/// `session` comes from `bluerain_open_*` and is not closed; `out` points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_session_info(session: *const BrSession, out: *mut BrSessionInfo) -> i32 {
+    guard("bluerain_session_info", || {
+        let session = session_ref(session)?;
+        let queues = session.shared.queues.lock().unwrap();
+        *out_ref(out, "Output")? = BrSessionInfo {
+            sample_rate: session.sample_rate,
+            channel_count: session.channels.len() as u32,
+            state: queues.state,
+            frames_received: queues.frames_received,
+            frames_dropped: queues.frames_dropped,
+        };
+        Ok(BR_OK)
+    })
+}
+
+/// Label of channel `index`, or null if out of range. Valid until the session is closed.
+///
+/// # Safety
+/// `session` comes from `bluerain_open_*` and is not closed.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_channel_label(session: *const BrSession, index: u32) -> *const c_char {
+    session
+        .as_ref()
+        .and_then(|session| session.channels.get(index as usize))
+        .map_or(std::ptr::null(), |label| label.as_ptr())
+}
+
+/// Takes the oldest queued frame: writes its header to `frame` and `channel_count` values (µV)
+/// to `values`. Returns 1 if a frame was taken, 0 if none is queued.
+///
+/// # Safety
+/// `session` comes from `bluerain_open_*` and is not closed; `frame` points to writable memory;
+/// `values` has room for `capacity` doubles.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_poll_frame(session: *const BrSession, frame: *mut BrFrame, values: *mut f64, capacity: usize) -> i32 {
+    guard("bluerain_poll_frame", || {
+        let session = session_ref(session)?;
+        let frame = out_ref(frame, "Frame")?;
+        if values.is_null() || capacity < session.channels.len() {
+            return Err(FfiError::invalid(format!("Need room for {} values", session.channels.len())));
+        }
+        let mut queues = session.shared.queues.lock().unwrap();
+        let Some((time_secs, samples)) = queues.frames.pop_front() else {
+            return session.empty_queue_status(&queues);
+        };
+        std::slice::from_raw_parts_mut(values, samples.len()).copy_from_slice(&samples);
+        *frame = BrFrame { time_secs, channel_count: samples.len() as u32 };
+        Ok(1)
+    })
+}
+
+/// Takes the oldest queued band powers (computed 4 times a second over a 2-second window).
+/// Returns 1 if taken, 0 if none is queued.
+///
+/// # Safety
+/// `session` comes from `bluerain_open_*` and is not closed; `out` points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_poll_band_powers(session: *const BrSession, out: *mut BrBandPowers) -> i32 {
+    guard("bluerain_poll_band_powers", || {
+        let session = session_ref(session)?;
+        let out = out_ref(out, "Output")?;
+        let mut queues = session.shared.queues.lock().unwrap();
+        match queues.band_powers.pop_front() {
+            Some(powers) => {
+                *out = powers;
+                Ok(1)
+            }
+            None => session.empty_queue_status(&queues),
+        }
+    })
+}
+
+/// Replaces the neurofeedback reward rule (alpha above 0.4 for 0.5 s by default).
+///
+/// # Safety
+/// `session` comes from `bluerain_open_*` and is not closed; `rule` points to a valid rule.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_set_reward_rule(session: *const BrSession, rule: *const BrRewardRule) -> i32 {
+    guard("bluerain_set_reward_rule", || {
+        let session = session_ref(session)?;
+        let rule = rule.as_ref().ok_or_else(|| FfiError::invalid("Rule is null"))?;
+        let band = *Band::ALL
+            .get(rule.band as usize)
+            .ok_or_else(|| FfiError::invalid(format!("Unknown band {}", rule.band)))?;
+        let rewarder = RewardTracker::new(RewardRule {
+            band,
+            threshold: rule.threshold,
+            hold_secs: rule.hold_secs,
+        })
+        .map_err(FfiError::invalid)?;
+        session.shared.queues.lock().unwrap().rewarder = rewarder;
+        Ok(BR_OK)
+    })
+}
+
+/// Takes the oldest neurofeedback reward. Returns 1 if taken, 0 if none is queued.
+///
+/// # Safety
+/// `session` comes from `bluerain_open_*` and is not closed; `out` points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_poll_reward(session: *const BrSession, out: *mut BrReward) -> i32 {
+    guard("bluerain_poll_reward", || {
+        let session = session_ref(session)?;
+        let out = out_ref(out, "Output")?;
+        let mut queues = session.shared.queues.lock().unwrap();
+        match queues.rewards.pop_front() {
+            Some(reward) => {
+                *out = reward;
+                Ok(1)
+            }
+            None => session.empty_queue_status(&queues),
+        }
+    })
+}
+
+/// Stops streaming and waits for the session thread. Queued data can still be polled.
+///
+/// # Safety
+/// `session` comes from `bluerain_open_*` and is not closed.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_stop(session: *const BrSession) -> i32 {
+    guard("bluerain_stop", || {
+        session_ref(session)?.stop();
+        Ok(BR_OK)
+    })
+}
+
+/// Stops the session if needed and frees it. Null is ignored.
+///
+/// # Safety
+/// `session` comes from `bluerain_open_*` and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn bluerain_close(session: *mut BrSession) {
+    if !session.is_null() {
+        guard("bluerain_close", || {
+            drop(Box::from_raw(session));
+            Ok(BR_OK)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use session_store::StoredSession;
+    use std::f64::consts::PI;
+
+    const RATE: f64 = 250.0;
+
+    /// Writes a two-channel recording of a 10 Hz (alpha) sine and returns its path as a C string.
+    fn alpha_recording(name: &str, secs: f64) -> (std::path::PathBuf, CString) {
+        let frames = (secs * RATE) as usize;
+        let data: Vec<Vec<f64>> = (0..2)
+            .map(|channel| (0..frames).map(|i| 20.0 * (2.0 * PI * 10.0 * i as f64 / RATE).sin() + channel as f64).collect())
+            .collect();
+        let session = StoredSession::from_channels("ffi-test", RATE, vec!["Fp1".into(), "Fp2".into()], &data).unwrap();
+        let path = std::env::temp_dir().join(format!("bluerain-ffi-test-{}-{}.brs", std::process::id(), name));
+        session.save(&path).unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        (path, c_path)
+    }
+
+    fn info(session: *const BrSession) -> BrSessionInfo {
+        let mut info = BrSessionInfo::default();
+        assert_eq!(unsafe { bluerain_session_info(session, &mut info) }, BR_OK);
+        info
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(bluerain_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn replay_delivers_every_frame_with_band_powers_and_rewards() {
+        let (path, c_path) = alpha_recording("replay", 4.0);
+        let mut session = std::ptr::null_mut();
+        assert_eq!(unsafe { bluerain_open_replay(c_path.as_ptr(), 0.0, &mut session) }, BR_OK);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while info(session).state == BR_STATE_STREAMING {
+            assert!(Instant::now() < deadline, "replay did not finish");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let info = info(session);
+        assert_eq!((info.sample_rate, info.channel_count, info.state), (RATE, 2, BR_STATE_FINISHED));
+        assert_eq!((info.frames_received, info.frames_dropped), (1000, 0));
+        let label = unsafe { CStr::from_ptr(bluerain_channel_label(session, 1)) };
+        assert_eq!(label.to_str().unwrap(), "Fp2");
+        assert!(unsafe { bluerain_channel_label(session, 2) }.is_null());
+
+        let (mut frame, mut values, mut frames) = (BrFrame::default(), [0.0; 2], 0);
+        while unsafe { bluerain_poll_frame(session, &mut frame, values.as_mut_ptr(), values.len()) } == 1 {
+            assert_eq!(frame.channel_count, 2);
+            assert!((frame.time_secs - frames as f64 / RATE).abs() < 1e-9);
+            assert!((values[1] - values[0] - 1.0).abs() < 1e-9);
+            frames += 1;
+        }
+        assert_eq!(frames, 1000);
+
+        let mut powers = BrBandPowers::default();
+        let mut polled = 0;
+        while unsafe { bluerain_poll_band_powers(session, &mut powers) } == 1 {
+            assert!(powers.relative[BrBand::Alpha as usize] > 0.5, "{:?}", powers);
+            polled += 1;
+        }
+        assert!(polled > 0);
+        let mut reward = BrReward::default();
+        assert_eq!(unsafe { bluerain_poll_reward(session, &mut reward) }, 1);
+        assert_eq!((reward.band, reward.streak), (BrBand::Alpha as u32, 1));
+
+        unsafe { bluerain_close(session) };
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stop_is_safe_while_other_threads_poll() {
+        let (path, c_path) = alpha_recording("stop", 60.0);
+        let mut session = std::ptr::null_mut();
+        assert_eq!(unsafe { bluerain_open_replay(c_path.as_ptr(), 1.0, &mut session) }, BR_OK);
+        let address = session as usize;
+        let pollers: Vec<_> = (0..2)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    let session = address as *const BrSession;
+                    let (mut frame, mut values) = (BrFrame::default(), [0.0; 2]);
+                    let deadline = Instant::now() + Duration::from_millis(300);
+                    while Instant::now() < deadline {
+                        let status = unsafe { bluerain_poll_frame(session, &mut frame, values.as_mut_ptr(), values.len()) };
+                        assert!(status == 0 || status == 1, "poll failed with {}", status);
+                    }
+                })
+            })
+            .collect();
+        std::thread::sleep(Duration::from_millis(100));
+        let stoppers: Vec<_> = (0..2)
+            .map(|_| std::thread::spawn(move || unsafe { bluerain_stop(address as *const BrSession) }))
+            .collect();
+        for stopper in stoppers {
+            assert_eq!(stopper.join().unwrap(), BR_OK);
+        }
+        for poller in pollers {
+            poller.join().unwrap();
+        }
+        assert_eq!(info(session).state, BR_STATE_FINISHED);
+
+        unsafe { bluerain_close(session) };
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_arguments_return_codes_and_describe_the_failure() {
+        assert_eq!(bluerain_abi_version(), BR_ABI_VERSION);
+
+        assert_eq!(unsafe { bluerain_stop(std::ptr::null()) }, BR_ERR_INVALID_ARGUMENT);
+        assert_eq!(last_error(), "Session is null");
+        let mut info = BrSessionInfo::default();
+        assert_eq!(unsafe { bluerain_session_info(std::ptr::null(), &mut info) }, BR_ERR_INVALID_ARGUMENT);
+        unsafe { bluerain_close(std::ptr::null_mut()) };
+
+        let mut session = std::ptr::null_mut();
+        let missing = CString::new("/nonexistent/bluerain.brs").unwrap();
+        assert_eq!(unsafe { bluerain_open_replay(missing.as_ptr(), 0.0, &mut session) }, BR_ERR_NOT_FOUND);
+        assert!(last_error().contains("does not exist"));
+        assert_eq!(unsafe { bluerain_open_replay(missing.as_ptr(), -1.0, &mut session) }, BR_ERR_INVALID_ARGUMENT);
+        assert_eq!(unsafe { bluerain_open_replay(std::ptr::null(), 0.0, &mut session) }, BR_ERR_INVALID_ARGUMENT);
+        assert!(session.is_null());
+
+        let (path, c_path) = alpha_recording("invalid", 1.0);
+        assert_eq!(unsafe { bluerain_open_replay(c_path.as_ptr(), 0.0, &mut session) }, BR_OK);
+        let (mut frame, mut values) = (BrFrame::default(), [0.0; 1]);
+        let status = unsafe { bluerain_poll_frame(session, &mut frame, values.as_mut_ptr(), values.len()) };
+        assert_eq!(status, BR_ERR_INVALID_ARGUMENT);
+        assert_eq!(last_error(), "Need room for 2 values");
+        let rule = BrRewardRule { band: BR_BAND_COUNT as u32, threshold: 0.5, hold_secs: 1.0 };
+        assert_eq!(unsafe { bluerain_set_reward_rule(session, &rule) }, BR_ERR_INVALID_ARGUMENT);
+        assert_eq!(unsafe { bluerain_stop(session) }, BR_OK);
+        assert_eq!(unsafe { bluerain_stop(session) }, BR_OK);
+
+        unsafe { bluerain_close(session) };
+        std::fs::remove_file(path).unwrap();
+    }
+}
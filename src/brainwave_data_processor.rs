@@ -1,69 +1,360 @@
+use std::path::Path;
 use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
+use features::Band;
 use rand::Rng;
-use tokio::sync::mpsc;
 use tokio::task;
 
+#[allow(dead_code)]
+mod artifacts;
+#[allow(dead_code)]
+mod features;
+#[allow(dead_code)]
+mod filters;
+#[allow(dead_code)]
+mod ica;
+#[allow(dead_code)]
+mod lsl;
+#[allow(dead_code)]
+mod neurofeedback;
+#[allow(dead_code)]
+mod osc;
+#[allow(dead_code)]
+mod pipeline;
+#[allow(dead_code)]
+mod ring_buffer;
+#[allow(dead_code)]
+mod session_store;
+#[allow(dead_code)]
+mod signal_quality;
+#[allow(dead_code)]
+mod stream_server;
+#[allow(dead_code)]
+mod streaming_stats;
+#[allow(dead_code)]
+mod xml;
+
+use artifacts::{ArtifactDetector, ArtifactThresholds, SessionReport, StreamingScreener};
+use filters::FilterBank;
+use ica::ComponentLabel;
+use lsl::{LslInlet, LslSource};
+use neurofeedback::{InhibitRule, RewardRule};
+use osc::{OscAddresses, OscSender, OscSink};
+use pipeline::{BandPowerStage, ConsoleSink, FilterStage, PipelineBuilder, RecorderSink, Registry, RmsStage, SimulatedSource, ThresholdDecision, UploaderSink};
+use ring_buffer::{sample_buffer, Consumer, OverflowPolicy, Producer};
+use session_store::StoredSession;
+use signal_quality::{simulate_channel, QualityCriteria, QualityGate};
+use stream_server::StreamHub;
+use streaming_stats::StreamingStats;
+
 #[derive(Debug)]
 struct BrainwaveModule {
     device_id: String,
-    signal_data: Arc<Mutex<Vec<f64>>>,
-    data_sender: mpsc::Sender<f64>,
+    signal_data: Arc<Mutex<StreamingStats>>,
+    data_sender: Producer<f64>,
+    data_receiver: Mutex<Consumer<f64>>,
+    screener: Mutex<StreamingScreener>,
+    quality_gate: QualityGate,
 }
 
 impl BrainwaveModule {
     fn new(device_id: &str) -> Self {
-        let (tx, _) = mpsc::channel(100);
+        let (tx, rx) = sample_buffer(1024, OverflowPolicy::DropOldest);
         BrainwaveModule {
             device_id: device_id.to_string(),
-            signal_data: Arc::new(Mutex::new(vec![])),
+            signal_data: Arc::new(Mutex::new(StreamingStats::new(50))),
             data_sender: tx,
+            data_receiver: Mutex::new(rx),
+            // Live signals arrive at 10 Hz; screen them in 1-second windows.
+            screener: Mutex::new(StreamingScreener::new(ArtifactDetector::new(ArtifactThresholds::normalized(), 10.0, 10))),
+            quality_gate: QualityGate::new(QualityCriteria::default()),
         }
     }
 
     async fn initialize(&self) -> Result<(), String> {
         println!("Initializing device {}", self.device_id);
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        self.quality_gate
+            .run(&self.device_id, &["Cz"], 250.0, |attempt| async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                // Electrode contact settles over the first few checks.
+                let settle = 0.5_f64.powi(attempt as i32 - 1);
+                Ok((vec![simulate_channel(250.0, 1.0, 4.0 + 12.0 * settle, 8.0 * settle, 50.0)], None))
+            })
+            .await?;
         Ok(())
     }
 
     async fn collect_data(&self, duration: Duration) -> Result<(), String> {
         let start = Instant::now();
-        let mut rng = rand::thread_rng();
         while start.elapsed() < duration {
-            let signal = rng.gen_range(0.0..1.0);
-            self.data_sender.send(signal).await.unwrap();
+            let signal = rand::thread_rng().gen_range(0.0..1.0);
+            self.data_sender.send(signal).await.map_err(|_| "Sample buffer closed".to_string())?;
+            self.drain_samples();
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
         Ok(())
     }
 
-    fn analyze_data(&self) -> f64 {
-        let data = self.signal_data.lock().unwrap();
-        let sum: f64 = data.iter().sum();
-        sum / data.len() as f64
+    /// Screens buffered samples and moves the clean windows into the running statistics.
+    fn drain_samples(&self) {
+        let mut screener = self.screener.lock().unwrap();
+        let mut stats = self.signal_data.lock().unwrap();
+        let mut receiver = self.data_receiver.lock().unwrap();
+        receiver.drain(|batch| {
+            for signal in batch {
+                if let Some(clean) = screener.push(*signal) {
+                    stats.extend(clean);
+                }
+            }
+        });
+        let buffer = receiver.stats();
+        if buffer.dropped > 0 {
+            println!("Device {} overran its sample buffer: {} of {} samples dropped", self.device_id, buffer.dropped, buffer.accepted + buffer.dropped);
+        }
     }
 
-    fn reset_data(&self) {
+    /// Mean of the clean windows and the share of windows rejected.
+    fn analyze_data(&self) -> SessionReport {
+        self.drain_samples();
+        let mut screener = self.screener.lock().unwrap();
+        if let Some(clean) = screener.finish() {
+            self.signal_data.lock().unwrap().extend(clean);
+        }
+        screener.report(self.signal_data.lock().unwrap().mean())
+    }
+
+    /// Feeds one channel of a stored session into the module for offline analysis, screening
+    /// it in 1-second windows against the microvolt thresholds.
+    fn load_session(&self, session: &StoredSession, channel: usize) -> Result<(), String> {
+        if channel >= session.channels.len() {
+            return Err(format!("Session has no channel {}", channel));
+        }
+        let window_len = (session.sample_rate.round() as usize).max(1);
+        let mut screener = self.screener.lock().unwrap();
+        *screener = StreamingScreener::new(ArtifactDetector::new(ArtifactThresholds::eeg_microvolts(), session.sample_rate, window_len));
         let mut data = self.signal_data.lock().unwrap();
-        data.clear();
+        data.reset();
+        for value in session.segments.iter().flat_map(|segment| segment.frames.iter().map(|frame| frame[channel])) {
+            if let Some(clean) = screener.push(value) {
+                data.extend(clean);
+            }
+        }
+        Ok(())
+    }
+
+    fn reset_data(&self) {
+        self.data_receiver.lock().unwrap().drain(|_| {});
+        self.signal_data.lock().unwrap().reset();
+        self.screener.lock().unwrap().reset();
     }
 }
 
 async fn process_device_data(device: &BrainwaveModule) -> Result<(), String> {
     device.initialize().await?;
     device.collect_data(Duration::from_secs(5)).await?;
-    let avg_signal = device.analyze_data();
-    println!("Average brainwave signal for {}: {}", device.device_id, avg_signal);
+    let report = device.analyze_data();
+    println!("Brainwave data for {}: {}", device.device_id, report.summary());
+    match report.mean {
+        Some(avg_signal) => println!("Average brainwave signal for {}: {}", device.device_id, avg_signal),
+        None => println!("No clean brainwave data collected for {}", device.device_id),
+    }
     device.reset_data();
     Ok(())
 }
 
+/// Removes ocular and muscle components from a stored session with FastICA and writes the cleaned copy.
+fn clean_stored_session(input: &Path, output: &Path) -> Result<StoredSession, String> {
+    let mut session = StoredSession::load(input)?;
+    let decomposition = ica::fast_ica(&session.to_channels(), 200, 1e-6, 0)?;
+    let reports = ica::classify_components(&decomposition, session.sample_rate, &session.channels);
+
+    let excluded: Vec<usize> = reports
+        .iter()
+        .filter(|report| report.label != ComponentLabel::Brain)
+        .map(|report| report.index)
+        .collect();
+    for report in &reports {
+        println!(
+            "Component {}: {:?} (low {:.2}, high {:.2}, frontal {:.2})",
+            report.index, report.label, report.low_frequency_ratio, report.high_frequency_ratio, report.frontal_weight
+        );
+    }
+
+    session.replace_channels(&ica::reconstruct(&decomposition, &excluded))?;
+    session.save(output)?;
+    println!("Removed {} components, cleaned session written to {}", excluded.len(), output.display());
+    Ok(session)
+}
+
+fn analyze_stored_session(path: &Path) -> Result<(), String> {
+    let cleaned_path = path.with_extension("clean.brs");
+    let session = clean_stored_session(path, &cleaned_path)?;
+    let device = BrainwaveModule::new(&session.device_id);
+    for (channel, label) in session.channels.iter().enumerate() {
+        device.load_session(&session, channel)?;
+        let report = device.analyze_data();
+        match report.mean {
+            Some(avg_signal) => println!("Average brainwave signal for {} {}: {} ({})", device.device_id, label, avg_signal, report.summary()),
+            None => println!("No clean samples for {} {} ({})", device.device_id, label, report.summary()),
+        }
+    }
+    device.reset_data();
+    Ok(())
+}
+
+const PIPELINE_CONFIG: &str = "
+device = simulated
+filters = eeg-filters <- device
+alpha = alpha-rms <- filters
+decision = alpha-decision <- alpha
+console = console <- decision
+bands = band-powers <- filters
+";
+
+const PIPELINE_CHANNELS: &[&str] = &["Fp1", "Fp2", "C3", "C4", "P3", "P4", "O1", "O2"];
+
+const DEFAULT_SERVE_ADDR: &str = "127.0.0.1:8765";
+
+fn pipeline_registry(duration: Duration) -> Registry {
+    Registry::new()
+        .register_source("simulated", move || SimulatedSource::new(PIPELINE_CHANNELS.len(), 250.0, duration))
+        .register_stage("eeg-filters", || {
+            FilterStage::new(FilterBank::eeg_default(250.0, 50.0, PIPELINE_CHANNELS.len()).expect("default filter bank is valid"))
+        })
+        .register_stage("alpha-rms", || RmsStage::new(25))
+        .register_stage("alpha-decision", || ThresholdDecision::new(15.0, "high alpha"))
+        .register_stage("band-powers", || BandPowerStage::new(250.0, 2.0, 25))
+        .register_sink("console", || ConsoleSink::new(10))
+}
+
+/// Runs the configured pipeline, attaching a recorder and an uploader while it streams.
+async fn run_pipeline() -> Result<(), String> {
+    let mut pipeline = PipelineBuilder::from_config(PIPELINE_CONFIG, &pipeline_registry(Duration::from_secs(10)))?.start()?;
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let channels = PIPELINE_CHANNELS.iter().map(|label| label.to_string()).collect();
+    pipeline.attach_sink("recorder", "filters", RecorderSink::new("pipeline_session.brs", "D987", 250.0, channels))?;
+    pipeline.attach_sink("uploader", "decision", UploaderSink::new(8, Duration::from_millis(300)))?;
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    pipeline.detach("recorder")?;
+    pipeline.print_metrics();
+
+    pipeline.wait().await;
+    Ok(())
+}
+
+/// Runs the pipeline for up to an hour, streaming it to WebSocket clients until Ctrl-C.
+async fn serve_pipeline(addr: &str) -> Result<(), String> {
+    let mut pipeline = PipelineBuilder::from_config(PIPELINE_CONFIG, &pipeline_registry(Duration::from_secs(3600)))?.start()?;
+    let hub = StreamHub::new();
+    let (local_addr, server) = stream_server::start_server(addr, hub.clone()).await?;
+    let channels: Vec<String> = PIPELINE_CHANNELS.iter().map(|label| label.to_string()).collect();
+    pipeline.attach_sink("ws-frames", "filters", hub.frame_sink("D987", channels.clone()))?;
+    pipeline.attach_sink("ws-alpha", "decision", hub.feature_sink("D987", "alpha-rms", channels))?;
+    pipeline.attach_sink("ws-bands", "bands", hub.feature_sink("D987", "band-powers", BandPowerStage::labels()))?;
+    drop(hub);
+    println!("Streaming on ws://{0}/ (viewer at http://{0}/), Ctrl-C to stop", local_addr);
+
+    let _ = tokio::signal::ctrl_c().await;
+    pipeline.stop();
+    pipeline.print_metrics();
+    pipeline.wait().await;
+    server.abort();
+    Ok(())
+}
+
+/// Runs the pipeline for up to an hour, sending band powers, ratios, alpha rewards and theta
+/// inhibits as OSC to `targets` (comma-separated `host:port`) until Ctrl-C. `assignments` are
+/// `kind=pattern` address overrides.
+async fn osc_pipeline(targets: &str, assignments: &[String]) -> Result<(), String> {
+    let mut addresses = OscAddresses::default();
+    for assignment in assignments {
+        addresses.set(assignment)?;
+    }
+    let targets: Vec<String> = targets.split(',').map(|target| target.trim().to_string()).collect();
+    let sender = Arc::new(OscSender::new("D987", &targets)?.with_addresses(addresses));
+    let bands = OscSink::new(sender.clone())
+        .with_reward(RewardRule::default())?
+        .with_inhibit(InhibitRule { band: Band::Theta, threshold: 0.3 })?;
+
+    let mut pipeline = PipelineBuilder::from_config(PIPELINE_CONFIG, &pipeline_registry(Duration::from_secs(3600)))?.start()?;
+    pipeline.attach_sink("osc-bands", "bands", bands)?;
+    pipeline.attach_sink("osc-events", "decision", OscSink::new(sender.clone()))?;
+    sender.send_phase("streaming");
+    println!("Sending OSC to {:?}, Ctrl-C to stop", sender.targets());
+
+    let _ = tokio::signal::ctrl_c().await;
+    pipeline.stop();
+    pipeline.print_metrics();
+    pipeline.wait().await;
+    sender.send_phase("stopped");
+    let (sent, failed) = sender.counts();
+    println!("OSC: {} messages sent, {} failed", sent, failed);
+    Ok(())
+}
+
+/// Analyzes the first LSL stream matching `query` (e.g. `type='EEG'`) as if it were a headset:
+/// filtering and band powers, printed until the stream ends or Ctrl-C.
+async fn lsl_pipeline(query: &str) -> Result<(), String> {
+    println!("Looking for LSL streams matching \"{}\"", query);
+    let streams = lsl::resolve(query, Duration::from_secs(3)).await?;
+    for info in &streams {
+        println!(
+            "Found {} ({}, {} channels at {} Hz) on {} from {:?}",
+            info.name, info.stream_type, info.channel_count, info.nominal_srate, info.hostname, info.address
+        );
+    }
+    let info = streams.first().ok_or("No matching LSL stream")?;
+    let sample_rate = info.nominal_srate;
+    if sample_rate <= 0.0 {
+        return Err(format!("Stream {} has an irregular rate and cannot be filtered", info.name));
+    }
+    let inlet = LslInlet::open(info).await?;
+    println!("Channels: {}", inlet.info().labels().join(", "));
+    let source = LslSource::new(inlet).await?;
+    println!("Clock correction {:+.6}s", source.correction());
+
+    let channel_count = info.channel_count;
+    let pipeline = PipelineBuilder::new()
+        .source("device", source)
+        .stage("filters", "device", FilterStage::new(FilterBank::eeg_default(sample_rate, 50.0, channel_count)?))
+        // Band powers twice a second.
+        .stage("bands", "filters", BandPowerStage::new(sample_rate, 2.0, ((sample_rate / 2.0).round() as usize).max(1)))
+        .sink("console", "bands", ConsoleSink::new(1))
+        .start()?;
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    while !pipeline.is_finished() {
+        tokio::select! {
+            _ = &mut ctrl_c => break,
+            _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+        }
+    }
+    pipeline.stop();
+    pipeline.print_metrics();
+    pipeline.wait().await;
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), String> {
+    match std::env::args().nth(1).as_deref() {
+        Some("--pipeline") => return run_pipeline().await,
+        Some("--lsl") => return lsl_pipeline(&std::env::args().nth(2).unwrap_or_default()).await,
+        Some("--osc") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            let (targets, assignments) = args.split_first().ok_or("--osc needs host:port[,host:port...]")?;
+            return osc_pipeline(targets, assignments).await;
+        }
+        Some("--serve") => return serve_pipeline(&std::env::args().nth(2).unwrap_or_else(|| DEFAULT_SERVE_ADDR.to_string())).await,
+        Some(path) => return analyze_stored_session(Path::new(path)),
+        None => {}
+    }
+
     let device = BrainwaveModule::new("D987");
-    let device_handler = task::spawn(process_device_data(&device));
+    let device_handler = task::spawn(async move { process_device_data(&device).await });
 
-    device_handler.await??;
+    device_handler.await.map_err(|e| e.to_string())??;
     Ok(())
 }
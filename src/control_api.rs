@@ -0,0 +1,368 @@
+//! JSON-RPC 2.0 over a Unix socket, one request or batch per line.
+//!
+//! ```text
+//! -> {"jsonrpc": "2.0", "id": 1, "method": "sessions.pause", "params": {"session": "s1"}}
+//! <- {"jsonrpc": "2.0", "id": 1, "result": {"session": "s1", "state": "Paused"}}
+//! ```
+//!
+//! Requests without an `id` are notifications and get no reply. Methods and their parameters are
+//! up to the `RpcHandler`; this module only frames, validates and dispatches.
+
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The device failed or could not be reached.
+pub const DEVICE_ERROR: i64 = -32000;
+/// The operation is not allowed in the current state (e.g. pausing a stopped session).
+pub const INVALID_STATE: i64 = -32001;
+/// A parameter is outside its safety limits.
+pub const LIMIT_EXCEEDED: i64 = -32002;
+/// Reading or writing a file failed.
+pub const IO_ERROR: i64 = -32003;
+
+/// Longest request line accepted; a client sending more is answered with an error and disconnected.
+pub const MAX_REQUEST_LINE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        RpcError::new(INVALID_PARAMS, message)
+    }
+
+    pub fn invalid_state(message: impl Into<String>) -> Self {
+        RpcError::new(INVALID_STATE, message)
+    }
+
+    pub fn limit_exceeded(message: impl Into<String>) -> Self {
+        RpcError::new(LIMIT_EXCEEDED, message)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
+
+#[async_trait]
+pub trait RpcHandler: Send + Sync {
+    /// `params` is `Null` when the request has none.
+    async fn handle(&self, method: &str, params: Value) -> Result<Value, RpcError>;
+}
+
+/// Named parameters of one request, with typed accessors that report the offending key.
+pub struct Params {
+    fields: Map<String, Value>,
+}
+
+impl Params {
+    /// Accepts an object or no params; positional (array) params are rejected.
+    pub fn new(params: Value) -> Result<Self, RpcError> {
+        match params {
+            Value::Null => Ok(Params { fields: Map::new() }),
+            Value::Object(fields) => Ok(Params { fields }),
+            _ => Err(RpcError::invalid_params("Params must be an object")),
+        }
+    }
+
+    /// Rejects keys outside `known`, so a misspelled parameter is not silently ignored.
+    pub fn only(self, known: &[&str]) -> Result<Self, RpcError> {
+        match self.fields.keys().find(|key| !known.contains(&key.as_str())) {
+            Some(key) => Err(RpcError::invalid_params(format!("Unknown parameter '{}'", key))),
+            None => Ok(self),
+        }
+    }
+
+    fn typed<T>(&self, key: &str, kind: &str, convert: impl Fn(&Value) -> Option<T>) -> Result<Option<T>, RpcError> {
+        match self.fields.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => convert(value)
+                .map(Some)
+                .ok_or_else(|| RpcError::invalid_params(format!("'{}' must be {}", key, kind))),
+        }
+    }
+
+    pub fn str(&self, key: &str) -> Result<Option<String>, RpcError> {
+        self.typed(key, "a string", |value| value.as_str().map(str::to_string))
+    }
+
+    pub fn f64(&self, key: &str) -> Result<Option<f64>, RpcError> {
+        self.typed(key, "a number", Value::as_f64)
+    }
+
+    pub fn u64(&self, key: &str) -> Result<Option<u64>, RpcError> {
+        self.typed(key, "a non-negative integer", Value::as_u64)
+    }
+
+    pub fn bool(&self, key: &str) -> Result<Option<bool>, RpcError> {
+        self.typed(key, "true or false", Value::as_bool)
+    }
+
+    pub fn required_str(&self, key: &str) -> Result<String, RpcError> {
+        self.str(key)?.ok_or_else(|| RpcError::invalid_params(format!("Missing parameter '{}'", key)))
+    }
+}
+
+/// Listens on `path` (replacing a stale socket file) until the returned task is aborted.
+/// The socket is only accessible to the current user.
+pub async fn serve(path: &Path, handler: Arc<dyn RpcHandler>) -> Result<JoinHandle<()>, String> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        // Only ever replace a socket, never a file or link that happens to share the name.
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+        // Refuse to take over a socket something is still listening on.
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(format!("{} is already in use", path.display()));
+        }
+        std::fs::remove_file(path).map_err(|e| format!("Cannot remove stale socket {}: {}", path.display(), e))?;
+    }
+    let listener = bind_private(path)?;
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let handler = handler.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, handler).await {
+                            eprintln!("Control client: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Control socket accept failed: {}", e),
+            }
+        }
+    }))
+}
+
+/// Binds in a fresh 0700 directory beside `path`, restricts the socket to 0600 and only then
+/// moves it to `path`, so other users never get a window to connect.
+fn bind_private(path: &Path) -> Result<UnixListener, String> {
+    let name = path.file_name().ok_or_else(|| format!("{} is not a socket path", path.display()))?;
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let staging = parent.join(format!(".{}.{}", name.to_string_lossy(), std::process::id()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .map_err(|e| format!("Cannot create {}: {}", staging.display(), e))?;
+    let staged = staging.join(name);
+    let listener = UnixListener::bind(&staged)
+        .map_err(|e| format!("Cannot listen on {}: {}", path.display(), e))
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))
+                .map_err(|e| format!("Cannot restrict {}: {}", path.display(), e))?;
+            std::fs::rename(&staged, path).map_err(|e| format!("Cannot move socket to {}: {}", path.display(), e))?;
+            Ok(listener)
+        });
+    // The socket is only still staged if something failed.
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+    listener
+}
+
+async fn handle_connection(stream: UnixStream, handler: Arc<dyn RpcHandler>) -> Result<(), String> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut bytes = vec![];
+    loop {
+        bytes.clear();
+        // One byte over the limit is enough to tell an overlong line from one that just fits.
+        let read = (&mut reader)
+            .take(MAX_REQUEST_LINE as u64 + 1)
+            .read_until(b'\n', &mut bytes)
+            .await
+            .map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Ok(());
+        }
+        if bytes.len() > MAX_REQUEST_LINE && !bytes.ends_with(b"\n") {
+            let error = RpcError::new(INVALID_REQUEST, format!("Request line longer than {} bytes", MAX_REQUEST_LINE));
+            writer
+                .write_all(format!("{}\n", error_response(Value::Null, &error)).as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+            return Err(error.message);
+        }
+        let line = std::str::from_utf8(&bytes).map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = respond(line, handler.as_ref()).await {
+            writer
+                .write_all(format!("{}\n", response).as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+}
+
+/// The reply to one line, or `None` if it only held notifications.
+async fn respond(line: &str, handler: &dyn RpcHandler) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return Some(error_response(Value::Null, &RpcError::new(PARSE_ERROR, format!("Parse error: {}", e)))),
+    };
+    match request {
+        Value::Array(batch) if batch.is_empty() => Some(error_response(Value::Null, &RpcError::new(INVALID_REQUEST, "Empty batch"))),
+        Value::Array(batch) => {
+            let mut responses = vec![];
+            for request in batch {
+                responses.extend(dispatch(request, handler).await);
+            }
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        request => dispatch(request, handler).await,
+    }
+}
+
+async fn dispatch(request: Value, handler: &dyn RpcHandler) -> Option<Value> {
+    let Value::Object(mut request) = request else {
+        return Some(error_response(Value::Null, &RpcError::new(INVALID_REQUEST, "Request must be an object")));
+    };
+    let id = request.remove("id");
+    let version = request.remove("jsonrpc");
+    let method = match (version.as_ref().and_then(Value::as_str), request.remove("method")) {
+        (Some("2.0"), Some(Value::String(method))) => method,
+        _ => {
+            let error = RpcError::new(INVALID_REQUEST, "Expected \"jsonrpc\": \"2.0\" and a method name");
+            return Some(error_response(id.unwrap_or(Value::Null), &error));
+        }
+    };
+    let result = handler.handle(&method, request.remove("params").unwrap_or(Value::Null)).await;
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, &error),
+    })
+}
+
+fn error_response(id: Value, error: &RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": error.to_json() })
+}
+
+/// Sends one request and waits for its reply. The outer error is a transport failure, the
+/// inner one what the server answered.
+pub async fn call(path: &Path, method: &str, params: Value) -> Result<Result<Value, RpcError>, String> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| format!("Cannot connect to {}: {}", path.display(), e))?;
+    let (reader, mut writer) = stream.into_split();
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    writer
+        .write_all(format!("{}\n", request).as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Server closed the connection without replying")?;
+    let response: Value = serde_json::from_str(&line).map_err(|e| format!("Invalid reply: {}", e))?;
+    if let Some(error) = response.get("error") {
+        return Ok(Err(RpcError::new(
+            error.get("code").and_then(Value::as_i64).unwrap_or(INTERNAL_ERROR),
+            error.get("message").and_then(Value::as_str).unwrap_or("Unknown error"),
+        )));
+    }
+    Ok(Ok(response.get("result").cloned().unwrap_or(Value::Null)))
+}
+
+/// Default socket location: `$XDG_RUNTIME_DIR/bluerain.sock`, else the temp directory.
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("bluerain.sock")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    #[async_trait]
+    impl RpcHandler for Echo {
+        async fn handle(&self, _method: &str, params: Value) -> Result<Value, RpcError> {
+            Ok(params)
+        }
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bluerain-ctl-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn socket_is_private_from_the_start() {
+        let path = socket_path("mode");
+        let server = serve(&path, Arc::new(Echo)).await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        let staging = path.with_file_name(format!(".{}.{}", path.file_name().unwrap().to_string_lossy(), std::process::id()));
+        assert_eq!(mode, 0o600);
+        assert!(!staging.exists());
+
+        let reply = call(&path, "echo", json!({ "value": 1 })).await.unwrap();
+        assert_eq!(reply, Ok(json!({ "value": 1 })));
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overlong_request_lines_are_refused() {
+        let path = socket_path("long");
+        let server = serve(&path, Arc::new(Echo)).await.unwrap();
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        let request = format!("{{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"echo\", \"params\": \"{}\"}}\n", "x".repeat(MAX_REQUEST_LINE));
+        // The server may hang up before the whole request is written.
+        let _ = stream.write_all(request.as_bytes()).await;
+
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await.unwrap();
+        let reply: Value = serde_json::from_str(reply.trim()).unwrap();
+        assert_eq!(reply["error"]["code"], json!(INVALID_REQUEST));
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_stale_sockets_are_replaced() {
+        let path = socket_path("stale");
+        let file = path.with_extension("txt");
+        std::fs::write(&path, "notes").unwrap();
+        assert!(serve(&path, Arc::new(Echo)).await.unwrap_err().contains("not a socket"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "notes");
+        std::fs::rename(&path, &file).unwrap();
+        std::os::unix::fs::symlink(&file, &path).unwrap();
+        assert!(serve(&path, Arc::new(Echo)).await.unwrap_err().contains("not a socket"));
+        assert!(file.exists());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        // A socket left behind by a process that exited is taken over; a live one is not.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let server = serve(&path, Arc::new(Echo)).await.unwrap();
+        assert!(serve(&path, Arc::new(Echo)).await.unwrap_err().contains("already in use"));
+        assert_eq!(call(&path, "echo", json!(2)).await.unwrap(), Ok(json!(2)));
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,501 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Bar, BarChart, BarGroup, Block, Borders, Paragraph, Sparkline};
+use ratatui::{Frame, Terminal};
+use tokio::sync::watch;
+
+use super::features::{Band, BandPowerTracker, BandPowers};
+
+/// Points kept per channel trace; wider than any terminal.
+const TRACE_LEN: usize = 512;
+/// Band powers are recomputed at most this often per device.
+const BAND_INTERVAL: Duration = Duration::from_millis(250);
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct ChannelTrace {
+    label: String,
+    points: VecDeque<f64>,
+    bands: Option<BandPowerTracker>,
+    powers: Option<BandPowers>,
+}
+
+#[derive(Debug, Clone)]
+struct Threshold {
+    name: String,
+    value: Option<f64>,
+    limit: f64,
+}
+
+#[derive(Debug)]
+struct DevicePanel {
+    id: String,
+    state: String,
+    phase: String,
+    channels: Vec<ChannelTrace>,
+    thresholds: Vec<Threshold>,
+    received: u64,
+    dropped: u64,
+    last_message: Option<String>,
+    bands_updated: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    panels: Mutex<Vec<DevicePanel>>,
+    stop: watch::Sender<bool>,
+    finished: watch::Sender<bool>,
+}
+
+/// Cheap, cloneable access to the dashboard for sessions: register devices and watch for
+/// the operator's emergency stop.
+#[derive(Debug, Clone)]
+pub struct DashboardHandle {
+    shared: Arc<Shared>,
+}
+
+impl DashboardHandle {
+    pub fn add_device(&self, id: &str) -> DeviceMonitor {
+        let mut panels = self.shared.panels.lock().unwrap();
+        panels.push(DevicePanel {
+            id: id.to_string(),
+            state: "Disconnected".to_string(),
+            phase: "-".to_string(),
+            channels: vec![],
+            thresholds: vec![],
+            received: 0,
+            dropped: 0,
+            last_message: None,
+            bands_updated: None,
+        });
+        DeviceMonitor {
+            handle: self.clone(),
+            index: panels.len() - 1,
+        }
+    }
+
+    pub fn emergency_stop(&self) {
+        if !self.shared.stop.send_replace(true) {
+            for panel in self.shared.panels.lock().unwrap().iter_mut() {
+                panel.last_message = Some("EMERGENCY STOP requested by operator".to_string());
+            }
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.shared.stop.borrow()
+    }
+
+    /// Resolves once the emergency stop has been pressed.
+    pub async fn stopped(&self) {
+        let mut stop = self.shared.stop.subscribe();
+        let _ = stop.wait_for(|stopped| *stopped).await;
+    }
+
+    /// Tells the dashboard the sessions are over; it keeps showing the final state until closed.
+    pub fn finish(&self) {
+        self.shared.finished.send_replace(true);
+    }
+}
+
+/// Updates one device's panel. All methods are cheap enough to call per sample.
+#[derive(Debug, Clone)]
+pub struct DeviceMonitor {
+    handle: DashboardHandle,
+    index: usize,
+}
+
+impl DeviceMonitor {
+    fn update(&self, f: impl FnOnce(&mut DevicePanel)) {
+        if let Some(panel) = self.handle.shared.panels.lock().unwrap().get_mut(self.index) {
+            f(panel);
+        }
+    }
+
+    pub fn dashboard(&self) -> &DashboardHandle {
+        &self.handle
+    }
+
+    pub fn set_state(&self, state: impl ToString) {
+        let state = state.to_string();
+        self.update(|panel| panel.state = state);
+    }
+
+    pub fn set_phase(&self, phase: &str) {
+        self.update(|panel| panel.phase = phase.to_string());
+    }
+
+    /// Declares the channels; band powers are shown only when a sample rate is given.
+    pub fn set_channels(&self, labels: &[String], sample_rate: Option<f64>) {
+        self.update(|panel| {
+            panel.channels = labels
+                .iter()
+                .map(|label| ChannelTrace {
+                    label: label.clone(),
+                    points: VecDeque::with_capacity(TRACE_LEN),
+                    bands: sample_rate.map(|rate| BandPowerTracker::new(rate, 1.0)),
+                    powers: None,
+                })
+                .collect();
+        });
+    }
+
+    /// Adds one multichannel frame to the traces.
+    pub fn push_frame(&self, frame: &[f64]) {
+        self.update(|panel| {
+            for (channel, value) in panel.channels.iter_mut().zip(frame) {
+                if channel.points.len() == TRACE_LEN {
+                    channel.points.pop_front();
+                }
+                channel.points.push_back(*value);
+                if let Some(bands) = &mut channel.bands {
+                    bands.push(*value);
+                }
+            }
+            panel.received += 1;
+            if panel.bands_updated.is_none_or(|at| at.elapsed() >= BAND_INTERVAL) {
+                for channel in &mut panel.channels {
+                    channel.powers = channel.bands.as_ref().and_then(BandPowerTracker::powers);
+                }
+                panel.bands_updated = Some(Instant::now());
+            }
+        });
+    }
+
+    /// Shows `value` against `limit`; over the limit is flagged. `None` means no reading yet.
+    pub fn set_threshold(&self, name: &str, value: Option<f64>, limit: f64) {
+        self.update(|panel| match panel.thresholds.iter_mut().find(|threshold| threshold.name == name) {
+            Some(threshold) => {
+                threshold.value = value;
+                threshold.limit = limit;
+            }
+            None => panel.thresholds.push(Threshold { name: name.to_string(), value, limit }),
+        });
+    }
+
+    pub fn set_dropped(&self, dropped: u64) {
+        self.update(|panel| panel.dropped = dropped);
+    }
+
+    /// Replaces the panel's status line; used instead of `println!` while the dashboard owns the terminal.
+    pub fn log(&self, message: impl ToString) {
+        let message = message.to_string();
+        self.update(|panel| panel.last_message = Some(message));
+    }
+}
+
+/// Restores the terminal even if drawing panics.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+    }
+}
+
+/// Live terminal view of every registered device. `x` or Esc is the emergency stop; `q` closes
+/// the view, stopping any session still running since nothing would be watching it.
+pub struct Dashboard {
+    shared: Arc<Shared>,
+    title: String,
+}
+
+impl Dashboard {
+    pub fn new(title: &str) -> (Dashboard, DashboardHandle) {
+        let (stop, _) = watch::channel(false);
+        let (finished, _) = watch::channel(false);
+        let shared = Arc::new(Shared {
+            panels: Mutex::new(vec![]),
+            stop,
+            finished,
+        });
+        let handle = DashboardHandle { shared: shared.clone() };
+        (Dashboard { shared, title: title.to_string() }, handle)
+    }
+
+    /// Takes over the terminal until closed. Runs on a blocking thread, as terminal input is synchronous.
+    pub async fn run(self) -> Result<(), String> {
+        tokio::task::spawn_blocking(move || self.run_blocking())
+            .await
+            .map_err(|e| format!("Dashboard task failed: {}", e))?
+    }
+
+    fn run_blocking(&self) -> Result<(), String> {
+        let io = |e: io::Error| format!("Terminal error: {}", e);
+        enable_raw_mode().map_err(io)?;
+        let _guard = TerminalGuard;
+        execute!(io::stdout(), EnterAlternateScreen).map_err(io)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout())).map_err(io)?;
+
+        let handle = DashboardHandle { shared: self.shared.clone() };
+        loop {
+            terminal.draw(|frame| self.draw(frame)).map_err(io)?;
+            if !event::poll(FRAME_INTERVAL).map_err(io)? {
+                continue;
+            }
+            let Event::Key(key) = event::read().map_err(io)? else {
+                continue;
+            };
+            if self.handle_key(key) {
+                break;
+            }
+        }
+        if !*self.shared.finished.borrow() {
+            handle.emergency_stop();
+        }
+        Ok(())
+    }
+
+    /// Acts on one key event; returns true when the view should close.
+    fn handle_key(&self, key: KeyEvent) -> bool {
+        if key.kind != KeyEventKind::Press {
+            return false;
+        }
+        let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('x') | KeyCode::Esc => {
+                DashboardHandle { shared: self.shared.clone() }.emergency_stop();
+                false
+            }
+            // Raw mode swallows SIGINT, so Ctrl-C quits like `q`.
+            KeyCode::Char('q') => true,
+            _ => ctrl_c,
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let stopped = *self.shared.stop.borrow();
+        let finished = *self.shared.finished.borrow();
+        let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+
+        let status = if stopped {
+            Span::styled(" EMERGENCY STOP ", Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD))
+        } else if finished {
+            Span::styled(" finished ", Style::default().fg(Color::Black).bg(Color::Green))
+        } else {
+            Span::styled(" live ", Style::default().fg(Color::Black).bg(Color::Cyan))
+        };
+        frame.render_widget(Paragraph::new(Line::from(vec![Span::raw(format!("{} ", self.title)), status])), header);
+
+        let panels = self.shared.panels.lock().unwrap();
+        if panels.is_empty() {
+            frame.render_widget(Paragraph::new("Waiting for devices..."), body);
+        } else {
+            let areas = Layout::vertical(vec![Constraint::Ratio(1, panels.len() as u32); panels.len()]).split(body);
+            for (panel, area) in panels.iter().zip(areas.iter()) {
+                draw_panel(frame, panel, *area);
+            }
+        }
+
+        let keys = if finished { "[q] quit" } else { "[x/Esc] emergency stop   [q] stop and quit" };
+        frame.render_widget(Paragraph::new(keys).style(Style::default().fg(Color::DarkGray)), footer);
+    }
+}
+
+fn draw_panel(frame: &mut Frame, panel: &DevicePanel, area: Rect) {
+    let title = format!(" {} | {} | phase: {} ", panel.id, panel.state, panel.phase);
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let has_bands = panel.channels.iter().any(|channel| channel.bands.is_some());
+    let [traces, side] = Layout::horizontal([Constraint::Min(20), Constraint::Length(if has_bands { 34 } else { 30 })]).areas(inner);
+    draw_traces(frame, &panel.channels, traces);
+
+    let [bands, status] = Layout::vertical([Constraint::Min(0), Constraint::Length(panel.thresholds.len() as u16 + 3)]).areas(side);
+    if has_bands {
+        draw_bands(frame, panel, bands);
+    }
+
+    let mut lines: Vec<Line> = panel
+        .thresholds
+        .iter()
+        .map(|threshold| {
+            let (text, color) = match threshold.value {
+                Some(value) if value > threshold.limit => (format!("{:.2} > {:.2} OVER", value, threshold.limit), Color::Red),
+                Some(value) => (format!("{:.2} <= {:.2} ok", value, threshold.limit), Color::Green),
+                None => (format!("- / {:.2}", threshold.limit), Color::DarkGray),
+            };
+            Line::from(vec![Span::raw(format!("{}: ", threshold.name)), Span::styled(text, Style::default().fg(color))])
+        })
+        .collect();
+    let drop_color = if panel.dropped > 0 { Color::Yellow } else { Color::Reset };
+    lines.push(Line::from(vec![
+        Span::raw(format!("samples {}  ", panel.received)),
+        Span::styled(format!("dropped {}", panel.dropped), Style::default().fg(drop_color)),
+    ]));
+    if let Some(message) = &panel.last_message {
+        lines.push(Line::from(Span::styled(message.clone(), Style::default().add_modifier(Modifier::ITALIC))));
+    }
+    frame.render_widget(Paragraph::new(lines), status);
+}
+
+/// One sparkline row per channel, as many as fit; each is scaled to its own visible range.
+fn draw_traces(frame: &mut Frame, channels: &[ChannelTrace], area: Rect) {
+    let shown = channels.len().min(area.height as usize);
+    if shown == 0 {
+        return;
+    }
+    let rows = Layout::vertical(vec![Constraint::Length(1); shown]).split(area);
+    for (channel, row) in channels.iter().zip(rows.iter()) {
+        let [label, trace] = Layout::horizontal([Constraint::Length(7), Constraint::Min(0)]).areas(*row);
+        frame.render_widget(Paragraph::new(channel.label.clone()), label);
+
+        let visible: Vec<f64> = channel.points.iter().rev().take(trace.width as usize).rev().copied().collect();
+        let (min, max) = visible.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), x| (min.min(*x), max.max(*x)));
+        let span = if max > min { max - min } else { 1.0 };
+        let data: Vec<u64> = visible.iter().map(|x| ((x - min) / span * 100.0).round() as u64).collect();
+        frame.render_widget(Sparkline::default().data(&data).max(100).style(Style::default().fg(Color::Cyan)), trace);
+    }
+}
+
+/// Relative band power averaged over the device's channels.
+fn draw_bands(frame: &mut Frame, panel: &DevicePanel, area: Rect) {
+    let powers: Vec<&BandPowers> = panel.channels.iter().filter_map(|channel| channel.powers.as_ref()).collect();
+    if powers.is_empty() {
+        frame.render_widget(Paragraph::new("band power: filling window..."), area);
+        return;
+    }
+    let average = BandPowers::average(powers);
+    let bars: Vec<Bar> = Band::ALL
+        .iter()
+        .map(|band| {
+            let percent = (average.relative(*band) * 100.0).round() as u64;
+            Bar::default().label(Line::from(band.symbol())).value(percent).text_value(format!("{}%", percent))
+        })
+        .collect();
+    let chart = BarChart::default()
+        .block(Block::default().title("relative band power"))
+        .direction(Direction::Vertical)
+        .data(BarGroup::default().bars(&bars))
+        .bar_width(5)
+        .bar_gap(1)
+        .max(100)
+        .bar_style(Style::default().fg(Color::Magenta));
+    frame.render_widget(chart, area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::KeyEventState;
+
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn render(dashboard: &Dashboard, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| (0..width).map(|x| buffer[(x, y)].symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn renders_device_state_thresholds_and_counters() {
+        let (dashboard, handle) = Dashboard::new("bluerain");
+        assert!(render(&dashboard, 80, 10).contains("Waiting for devices..."));
+
+        let monitor = handle.add_device("A123");
+        monitor.set_state("Streaming");
+        monitor.set_phase("baseline");
+        monitor.set_channels(&["Fp1".to_string(), "Fp2".to_string()], None);
+        for i in 0..3 {
+            monitor.push_frame(&[i as f64, -(i as f64)]);
+        }
+        monitor.set_threshold("rms", Some(12.5), 10.0);
+        monitor.set_threshold("kurtosis", None, 5.0);
+        monitor.set_threshold("drift", Some(1.0), 2.0);
+        monitor.set_threshold("rms", Some(8.0), 10.0);
+        monitor.set_dropped(2);
+        monitor.log("impedance check passed");
+
+        let screen = render(&dashboard, 100, 14);
+        assert!(screen.contains("bluerain  live"), "{}", screen);
+        assert!(screen.contains("A123 | Streaming | phase: baseline"), "{}", screen);
+        assert!(screen.contains("Fp1") && screen.contains("Fp2"), "{}", screen);
+        // Updating a threshold replaces it rather than adding a second line.
+        assert!(screen.contains("rms: 8.00 <= 10.00 ok"), "{}", screen);
+        assert!(!screen.contains("12.50"), "{}", screen);
+        assert!(screen.contains("kurtosis: - / 5.00"), "{}", screen);
+        assert!(screen.contains("drift: 1.00 <= 2.00 ok"), "{}", screen);
+        assert!(screen.contains("samples 3  dropped 2"), "{}", screen);
+        assert!(screen.contains("impedance check passed"), "{}", screen);
+        assert!(screen.contains("[x/Esc] emergency stop"), "{}", screen);
+
+        handle.finish();
+        let screen = render(&dashboard, 100, 14);
+        assert!(screen.contains("bluerain  finished"), "{}", screen);
+        assert!(screen.contains("[q] quit"), "{}", screen);
+    }
+
+    #[test]
+    fn traces_keep_only_the_latest_points() {
+        let (_dashboard, handle) = Dashboard::new("bluerain");
+        let monitor = handle.add_device("A123");
+        monitor.set_channels(&["Cz".to_string()], Some(250.0));
+        for i in 0..TRACE_LEN + 10 {
+            monitor.push_frame(&[i as f64]);
+        }
+        let panels = handle.shared.panels.lock().unwrap();
+        let points = &panels[0].channels[0].points;
+        assert_eq!(points.len(), TRACE_LEN);
+        assert_eq!(points.front(), Some(&10.0));
+        assert_eq!(panels[0].received, (TRACE_LEN + 10) as u64);
+    }
+
+    #[tokio::test]
+    async fn emergency_stop_key_stops_running_sessions() {
+        let (dashboard, handle) = Dashboard::new("bluerain");
+        let monitor = handle.add_device("A123");
+        let session = tokio::spawn({
+            let monitor = monitor.clone();
+            async move { monitor.dashboard().stopped().await }
+        });
+
+        let mut release = press(KeyCode::Char('x'));
+        release.kind = KeyEventKind::Release;
+        release.state = KeyEventState::NONE;
+        assert!(!dashboard.handle_key(release));
+        assert!(!dashboard.handle_key(press(KeyCode::Char('c'))));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!handle.is_stopped());
+        assert!(!session.is_finished());
+
+        assert!(!dashboard.handle_key(press(KeyCode::Char('x'))));
+        assert!(handle.is_stopped());
+        tokio::time::timeout(Duration::from_secs(5), session).await.expect("session stops").unwrap();
+        let screen = render(&dashboard, 100, 14);
+        assert!(screen.contains("EMERGENCY STOP requested"), "{}", screen);
+        assert!(screen.contains(" EMERGENCY STOP "), "{}", screen);
+
+        // Esc stops too, and a session that starts waiting afterwards returns at once.
+        let (dashboard, handle) = Dashboard::new("bluerain");
+        assert!(!dashboard.handle_key(press(KeyCode::Esc)));
+        tokio::time::timeout(Duration::from_secs(1), handle.stopped()).await.expect("already stopped");
+    }
+
+    #[test]
+    fn quit_keys_close_the_view() {
+        let (dashboard, handle) = Dashboard::new("bluerain");
+        assert!(dashboard.handle_key(press(KeyCode::Char('q'))));
+        assert!(dashboard.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+        assert!(!dashboard.handle_key(press(KeyCode::Enter)));
+        // Closing itself is handled by `run`, which stops unfinished sessions.
+        assert!(!handle.is_stopped());
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures::future::BoxFuture;
+use tokio::sync::watch;
+use tokio::task::{self, JoinHandle};
+use tokio::time;
+
+use super::{handle_device_operations, BluetoothDevice, DeviceProfile};
+
+/// What the supervisor does when a device task ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestartPolicy {
+    Never,
+    OnFailure { max_restarts: u32, backoff: Duration },
+    Always { backoff: Duration },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceState {
+    Starting,
+    Running,
+    Restarting { attempt: u32, last_error: String },
+    Completed,
+    Failed(String),
+    Stopped,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceStatus {
+    pub state: DeviceState,
+    pub restarts: u32,
+}
+
+/// One run of a device, from connecting to disconnecting; called again for every restart.
+type DeviceRunner = Arc<dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+#[derive(Debug)]
+struct ManagedDevice {
+    status: Arc<Mutex<DeviceStatus>>,
+    shutdown: watch::Sender<bool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Owns a set of `BluetoothDevice`s and runs each one in its own supervised task.
+#[derive(Debug)]
+pub struct DeviceManager {
+    policy: RestartPolicy,
+    devices: HashMap<String, ManagedDevice>,
+}
+
+impl DeviceManager {
+    pub fn new(policy: RestartPolicy) -> Self {
+        DeviceManager {
+            policy,
+            devices: HashMap::new(),
+        }
+    }
+
+    pub fn add_device(&mut self, address: &str) -> Result<(), String> {
+        self.add_device_with_profile(address, DeviceProfile::BlueRain)
+    }
+
+    pub fn add_device_with_profile(&mut self, address: &str, profile: DeviceProfile) -> Result<(), String> {
+        let device_address = address.to_string();
+        self.add_runner(
+            address,
+            Arc::new(move || {
+                let (address, profile) = (device_address.clone(), profile.clone());
+                Box::pin(async move {
+                    let mut device = BluetoothDevice::with_profile(address, profile)?;
+                    handle_device_operations(&mut device).await.map_err(|e| e.to_string())
+                })
+            }),
+        )
+    }
+
+    fn add_runner(&mut self, address: &str, runner: DeviceRunner) -> Result<(), String> {
+        if self.devices.contains_key(address) {
+            return Err(format!("Device {} is already managed", address));
+        }
+
+        let status = Arc::new(Mutex::new(DeviceStatus {
+            state: DeviceState::Starting,
+            restarts: 0,
+        }));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let handle = task::spawn(supervise(address.to_string(), runner, self.policy, status.clone(), shutdown_rx));
+
+        self.devices.insert(
+            address.to_string(),
+            ManagedDevice {
+                status,
+                shutdown: shutdown_tx,
+                handle: Some(handle),
+            },
+        );
+        println!("Device {} added to manager", address);
+        Ok(())
+    }
+
+    pub async fn remove_device(&mut self, address: &str) -> Result<(), String> {
+        let mut device = self
+            .devices
+            .remove(address)
+            .ok_or_else(|| format!("Device {} is not managed", address))?;
+
+        let _ = device.shutdown.send(true);
+        if let Some(handle) = device.handle.take() {
+            let _ = handle.await;
+        }
+        println!("Device {} removed from manager", address);
+        Ok(())
+    }
+
+    pub fn device_status(&self, address: &str) -> Option<DeviceStatus> {
+        self.devices
+            .get(address)
+            .map(|device| device.status.lock().unwrap().clone())
+    }
+
+    pub fn statuses(&self) -> Vec<(String, DeviceStatus)> {
+        let mut statuses: Vec<_> = self
+            .devices
+            .iter()
+            .map(|(address, device)| (address.clone(), device.status.lock().unwrap().clone()))
+            .collect();
+        statuses.sort_by(|a, b| a.0.cmp(&b.0));
+        statuses
+    }
+
+    pub fn device_count(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Waits until every supervisor has given up or completed.
+    pub async fn wait_all(&mut self) {
+        for device in self.devices.values_mut() {
+            if let Some(handle) = device.handle.take() {
+                let _ = handle.await;
+            }
+        }
+    }
+
+    pub async fn shutdown(&mut self) {
+        for device in self.devices.values() {
+            let _ = device.shutdown.send(true);
+        }
+        self.wait_all().await;
+    }
+}
+
+fn set_state(status: &Mutex<DeviceStatus>, state: DeviceState) {
+    status.lock().unwrap().state = state;
+}
+
+async fn supervise(
+    address: String,
+    runner: DeviceRunner,
+    policy: RestartPolicy,
+    status: Arc<Mutex<DeviceStatus>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut attempt = 0;
+
+    loop {
+        set_state(&status, DeviceState::Running);
+
+        // Each run gets its own task so a panic inside the device code is reported here
+        // instead of taking the supervisor down with it.
+        let mut run = task::spawn(runner());
+
+        let outcome = tokio::select! {
+            result = &mut run => result,
+            _ = shutdown.changed() => {
+                // Wait for the run to be dropped, so the device is released once removal returns.
+                run.abort();
+                let _ = run.await;
+                set_state(&status, DeviceState::Stopped);
+                return;
+            }
+        };
+
+        let error = match outcome {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e),
+            Err(e) if e.is_panic() => Some("device task panicked".to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+
+        let backoff = match (policy, &error) {
+            (RestartPolicy::Always { backoff }, _) => backoff,
+            (RestartPolicy::OnFailure { max_restarts, backoff }, Some(_)) if attempt < max_restarts => backoff,
+            (_, None) => {
+                set_state(&status, DeviceState::Completed);
+                return;
+            }
+            (_, Some(e)) => {
+                println!("Device {} failed permanently: {}", address, e);
+                set_state(&status, DeviceState::Failed(e.clone()));
+                return;
+            }
+        };
+
+        attempt += 1;
+        let last_error = error.unwrap_or_else(|| "completed".to_string());
+        println!("Restarting device {} (attempt {}): {}", address, attempt, last_error);
+        {
+            let mut status = status.lock().unwrap();
+            status.restarts = attempt;
+            status.state = DeviceState::Restarting { attempt, last_error };
+        }
+
+        tokio::select! {
+            _ = time::sleep(backoff) => {}
+            _ = shutdown.changed() => {
+                set_state(&status, DeviceState::Stopped);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Instant;
+
+    const BACKOFF: Duration = Duration::from_millis(10);
+
+    /// A runner whose `n`-th run (from 1) ends as `outcome(n)` says, counting its runs.
+    fn runner(outcome: impl Fn(u32) -> Result<(), String> + Send + Sync + 'static) -> (DeviceRunner, Arc<AtomicU32>) {
+        let runs = Arc::new(AtomicU32::new(0));
+        let (counter, outcome) = (runs.clone(), Arc::new(outcome));
+        let runner: DeviceRunner = Arc::new(move || {
+            let run = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            let outcome = outcome.clone();
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                outcome(run)
+            })
+        });
+        (runner, runs)
+    }
+
+    /// A runner that never finishes; the flag is set once its run is dropped.
+    fn endless() -> (DeviceRunner, Arc<AtomicU32>) {
+        struct SetOnDrop(Arc<AtomicU32>);
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(1, Ordering::SeqCst);
+            }
+        }
+        let dropped = Arc::new(AtomicU32::new(0));
+        let flag = dropped.clone();
+        let runner: DeviceRunner = Arc::new(move || {
+            let guard = SetOnDrop(flag.clone());
+            Box::pin(async move {
+                let _guard = guard;
+                std::future::pending::<()>().await;
+                Ok(())
+            })
+        });
+        (runner, dropped)
+    }
+
+    async fn wait_for(manager: &DeviceManager, address: &str, done: impl Fn(&DeviceStatus) -> bool) -> DeviceStatus {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let status = manager.device_status(address).expect("device is managed");
+            if done(&status) {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "still {:?}", status.state);
+            time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn never_policy_reports_the_first_outcome() {
+        let mut manager = DeviceManager::new(RestartPolicy::Never);
+        let (failing, failed_runs) = runner(|_| Err("link lost".to_string()));
+        let (passing, passed_runs) = runner(|_| Ok(()));
+        manager.add_runner("a", failing).unwrap();
+        manager.add_runner("b", passing).unwrap();
+        manager.wait_all().await;
+
+        let statuses = manager.statuses();
+        assert_eq!(statuses[0].0, "a");
+        assert_eq!((&statuses[0].1.state, statuses[0].1.restarts), (&DeviceState::Failed("link lost".to_string()), 0));
+        assert_eq!((&statuses[1].1.state, statuses[1].1.restarts), (&DeviceState::Completed, 0));
+        assert_eq!((failed_runs.load(Ordering::SeqCst), passed_runs.load(Ordering::SeqCst)), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn on_failure_restarts_until_success_or_the_limit() {
+        let mut manager = DeviceManager::new(RestartPolicy::OnFailure { max_restarts: 2, backoff: BACKOFF });
+        let (flaky, flaky_runs) = runner(|run| if run < 2 { Err(format!("attempt {}", run)) } else { Ok(()) });
+        let (broken, broken_runs) = runner(|run| Err(format!("attempt {}", run)));
+        manager.add_runner("flaky", flaky).unwrap();
+        manager.add_runner("broken", broken).unwrap();
+        manager.wait_all().await;
+
+        let flaky = manager.device_status("flaky").unwrap();
+        assert_eq!((flaky.state, flaky.restarts, flaky_runs.load(Ordering::SeqCst)), (DeviceState::Completed, 1, 2));
+        let broken = manager.device_status("broken").unwrap();
+        assert_eq!(broken.state, DeviceState::Failed("attempt 3".to_string()));
+        assert_eq!((broken.restarts, broken_runs.load(Ordering::SeqCst)), (2, 3));
+    }
+
+    #[tokio::test]
+    async fn always_restarts_after_success_until_shut_down() {
+        let mut manager = DeviceManager::new(RestartPolicy::Always { backoff: BACKOFF });
+        let (passing, runs) = runner(|_| Ok(()));
+        manager.add_runner("a", passing).unwrap();
+
+        let status = wait_for(&manager, "a", |status| status.restarts >= 3).await;
+        if let DeviceState::Restarting { last_error, .. } = status.state {
+            assert_eq!(last_error, "completed");
+        }
+        manager.shutdown().await;
+        let status = manager.device_status("a").unwrap();
+        assert_eq!(status.state, DeviceState::Stopped);
+        assert!(runs.load(Ordering::SeqCst) >= 3);
+    }
+
+    #[tokio::test]
+    async fn a_panicking_run_is_restarted() {
+        let mut manager = DeviceManager::new(RestartPolicy::OnFailure { max_restarts: 1, backoff: BACKOFF });
+        let (panicky, runs) = runner(|run| if run == 1 { panic!("firmware fault") } else { Ok(()) });
+        manager.add_runner("a", panicky).unwrap();
+        manager.wait_all().await;
+        let status = manager.device_status("a").unwrap();
+        assert_eq!((status.state, status.restarts, runs.load(Ordering::SeqCst)), (DeviceState::Completed, 1, 2));
+
+        let mut manager = DeviceManager::new(RestartPolicy::Never);
+        manager.add_runner("b", runner(|_| panic!("firmware fault")).0).unwrap();
+        manager.wait_all().await;
+        assert_eq!(manager.device_status("b").unwrap().state, DeviceState::Failed("device task panicked".to_string()));
+    }
+
+    #[tokio::test]
+    async fn devices_are_added_and_removed_while_others_run() {
+        let mut manager = DeviceManager::new(RestartPolicy::Never);
+        let (first, first_dropped) = endless();
+        let (second, second_dropped) = endless();
+        manager.add_runner("a", first).unwrap();
+        assert_eq!(manager.add_runner("a", endless().0), Err("Device a is already managed".to_string()));
+        wait_for(&manager, "a", |status| status.state == DeviceState::Running).await;
+
+        manager.add_runner("b", second).unwrap();
+        assert_eq!(manager.device_count(), 2);
+        manager.remove_device("a").await.unwrap();
+        assert_eq!(first_dropped.load(Ordering::SeqCst), 1);
+        assert_eq!((manager.device_count(), manager.device_status("a").is_none()), (1, true));
+        assert_eq!(manager.remove_device("a").await, Err("Device a is not managed".to_string()));
+
+        // The address can be reused once the device is gone.
+        manager.add_runner("a", runner(|_| Ok(())).0).unwrap();
+        wait_for(&manager, "a", |status| status.state == DeviceState::Completed).await;
+        assert_eq!(manager.device_status("b").unwrap().state, DeviceState::Running);
+
+        manager.shutdown().await;
+        assert_eq!(manager.device_status("b").unwrap().state, DeviceState::Stopped);
+        assert_eq!(second_dropped.load(Ordering::SeqCst), 1);
+    }
+}
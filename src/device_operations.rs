@@ -0,0 +1,393 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LifecycleState {
+    Disconnected,
+    Connected,
+    Streaming,
+    Paused,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusReport {
+    pub state: LifecycleState,
+    /// Time since the current connection was made.
+    pub uptime: Option<Duration>,
+    pub samples_received: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl HealthCheck {
+    pub fn new(name: &str, passed: bool, detail: impl Into<String>) -> Self {
+        HealthCheck {
+            name: name.to_string(),
+            passed,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", if self.healthy() { "healthy" } else { "UNHEALTHY" })?;
+        for check in &self.checks {
+            write!(f, "\n  [{}] {}: {}", if check.passed { "ok" } else { "FAIL" }, check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct LifecycleInner {
+    state: Mutex<LifecycleState>,
+    connected_at: Mutex<Option<Instant>>,
+    samples: AtomicU64,
+    errors: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    paused: watch::Sender<bool>,
+}
+
+/// Shared lifecycle bookkeeping for a device. Clones share state, so a clone taken before
+/// `stream` can pause, resume or query the device from another task while it streams.
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    inner: Arc<LifecycleInner>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle::new()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        let (paused, _) = watch::channel(false);
+        Lifecycle {
+            inner: Arc::new(LifecycleInner {
+                state: Mutex::new(LifecycleState::Disconnected),
+                connected_at: Mutex::new(None),
+                samples: AtomicU64::new(0),
+                errors: AtomicU64::new(0),
+                last_error: Mutex::new(None),
+                paused,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> LifecycleState {
+        *self.inner.state.lock().unwrap()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state() != LifecycleState::Disconnected
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state() == LifecycleState::Paused
+    }
+
+    /// Moves from `from` to `to`, or explains why the operation is not allowed now.
+    fn transition(&self, from: &[LifecycleState], to: LifecycleState, operation: &str) -> Result<(), String> {
+        let mut state = self.inner.state.lock().unwrap();
+        if !from.contains(&state) {
+            return Err(format!("Cannot {} while {:?}", operation, *state));
+        }
+        *state = to;
+        Ok(())
+    }
+
+    pub fn connected(&self) -> Result<(), String> {
+        self.transition(&[LifecycleState::Disconnected], LifecycleState::Connected, "connect")?;
+        *self.inner.connected_at.lock().unwrap() = Some(Instant::now());
+        Ok(())
+    }
+
+    pub fn check_can_disconnect(&self) -> Result<(), String> {
+        match self.state() {
+            LifecycleState::Disconnected => Err("Device not connected".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn disconnected(&self) {
+        *self.inner.state.lock().unwrap() = LifecycleState::Disconnected;
+        *self.inner.connected_at.lock().unwrap() = None;
+        // Wake a stream waiting on a pause so it can see the disconnect.
+        self.inner.paused.send_replace(false);
+    }
+
+    pub fn begin_stream(&self) -> Result<(), String> {
+        self.transition(&[LifecycleState::Connected], LifecycleState::Streaming, "stream")
+    }
+
+    pub fn end_stream(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        if matches!(*state, LifecycleState::Streaming | LifecycleState::Paused) {
+            *state = LifecycleState::Connected;
+        }
+        self.inner.paused.send_replace(false);
+    }
+
+    pub fn pause(&self) -> Result<(), String> {
+        self.transition(&[LifecycleState::Streaming], LifecycleState::Paused, "pause")?;
+        self.inner.paused.send_replace(true);
+        Ok(())
+    }
+
+    pub fn resume(&self) -> Result<(), String> {
+        self.transition(&[LifecycleState::Paused], LifecycleState::Streaming, "resume")?;
+        self.inner.paused.send_replace(false);
+        Ok(())
+    }
+
+    /// Returns at once unless paused; otherwise waits for resume (or disconnect) and
+    /// returns how long the pause lasted, so streams can leave it out of their duration.
+    pub async fn wait_while_paused(&self) -> Duration {
+        let mut paused = self.inner.paused.subscribe();
+        if !*paused.borrow_and_update() {
+            return Duration::ZERO;
+        }
+        let started = Instant::now();
+        while *paused.borrow_and_update() {
+            if paused.changed().await.is_err() {
+                break;
+            }
+        }
+        started.elapsed()
+    }
+
+    pub fn record_samples(&self, count: u64) {
+        self.inner.samples.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self, error: &str) {
+        self.inner.errors.fetch_add(1, Ordering::Relaxed);
+        *self.inner.last_error.lock().unwrap() = Some(error.to_string());
+    }
+
+    pub fn status(&self) -> StatusReport {
+        StatusReport {
+            state: self.state(),
+            uptime: self.inner.connected_at.lock().unwrap().map(|at| at.elapsed()),
+            samples_received: self.inner.samples.load(Ordering::Relaxed),
+            errors: self.inner.errors.load(Ordering::Relaxed),
+            last_error: self.inner.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    /// Checks every device shares: connected, and no errors recorded.
+    pub fn common_checks(&self) -> Vec<HealthCheck> {
+        let status = self.status();
+        vec![
+            HealthCheck::new("connection", status.state != LifecycleState::Disconnected, format!("{:?}", status.state)),
+            HealthCheck::new(
+                "errors",
+                status.errors == 0,
+                match &status.last_error {
+                    Some(error) => format!("{} (last: {})", status.errors, error),
+                    None => "none".to_string(),
+                },
+            ),
+        ]
+    }
+}
+
+/// Full device lifecycle: connect, stream (pausable), disconnect, plus status and health.
+#[async_trait]
+pub trait DeviceOperations: Send {
+    fn id(&self) -> &str;
+
+    fn lifecycle(&self) -> &Lifecycle;
+
+    async fn connect(&mut self) -> Result<(), String>;
+
+    /// Streams for `duration` of unpaused time, or until the device cannot continue.
+    async fn stream(&mut self, duration: Duration) -> Result<(), String>;
+
+    async fn disconnect(&mut self) -> Result<(), String>;
+
+    /// Device-specific health checks; the connection and error checks are added by `health_check`.
+    async fn device_checks(&mut self) -> Vec<HealthCheck> {
+        vec![]
+    }
+
+    fn pause(&self) -> Result<(), String> {
+        self.lifecycle().pause()?;
+        println!("Device {} paused", self.id());
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), String> {
+        self.lifecycle().resume()?;
+        println!("Device {} resumed", self.id());
+        Ok(())
+    }
+
+    fn status(&self) -> StatusReport {
+        self.lifecycle().status()
+    }
+
+    async fn health_check(&mut self) -> HealthReport {
+        let mut checks = self.lifecycle().common_checks();
+        checks.extend(self.device_checks().await);
+        HealthReport { checks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts samples while streaming; its one device check fails until it has streamed.
+    struct StubDevice {
+        lifecycle: Lifecycle,
+        streamed: bool,
+    }
+
+    #[async_trait]
+    impl DeviceOperations for StubDevice {
+        fn id(&self) -> &str {
+            "stub"
+        }
+
+        fn lifecycle(&self) -> &Lifecycle {
+            &self.lifecycle
+        }
+
+        async fn connect(&mut self) -> Result<(), String> {
+            self.lifecycle.connected()
+        }
+
+        async fn stream(&mut self, _duration: Duration) -> Result<(), String> {
+            self.lifecycle.begin_stream()?;
+            self.lifecycle.record_samples(10);
+            self.streamed = true;
+            self.lifecycle.end_stream();
+            Ok(())
+        }
+
+        async fn disconnect(&mut self) -> Result<(), String> {
+            self.lifecycle.check_can_disconnect()?;
+            self.lifecycle.disconnected();
+            Ok(())
+        }
+
+        async fn device_checks(&mut self) -> Vec<HealthCheck> {
+            vec![HealthCheck::new("streamed", self.streamed, "")]
+        }
+    }
+
+    #[test]
+    fn illegal_transitions_are_rejected_and_leave_the_state_alone() {
+        let lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.begin_stream(), Err("Cannot stream while Disconnected".to_string()));
+        assert!(lifecycle.pause().is_err());
+        assert!(lifecycle.resume().is_err());
+        assert!(lifecycle.check_can_disconnect().is_err());
+        assert_eq!(lifecycle.state(), LifecycleState::Disconnected);
+
+        lifecycle.connected().unwrap();
+        assert_eq!(lifecycle.connected(), Err("Cannot connect while Connected".to_string()));
+        assert!(lifecycle.pause().is_err());
+        assert!(lifecycle.resume().is_err());
+
+        lifecycle.begin_stream().unwrap();
+        assert!(lifecycle.begin_stream().is_err());
+        assert!(lifecycle.resume().is_err());
+        lifecycle.pause().unwrap();
+        assert!(lifecycle.is_paused());
+        assert_eq!(lifecycle.pause(), Err("Cannot pause while Paused".to_string()));
+        assert!(lifecycle.begin_stream().is_err());
+        assert!(lifecycle.connected().is_err());
+        lifecycle.resume().unwrap();
+        assert_eq!(lifecycle.state(), LifecycleState::Streaming);
+
+        // Ending a paused stream returns to Connected; disconnecting works from any connected state.
+        lifecycle.pause().unwrap();
+        lifecycle.end_stream();
+        assert_eq!(lifecycle.state(), LifecycleState::Connected);
+        lifecycle.check_can_disconnect().unwrap();
+        lifecycle.disconnected();
+        assert!(!lifecycle.is_connected());
+        assert!(lifecycle.status().uptime.is_none());
+    }
+
+    #[tokio::test]
+    async fn waiting_while_paused_ends_on_resume_or_disconnect() {
+        let lifecycle = Lifecycle::new();
+        lifecycle.connected().unwrap();
+        lifecycle.begin_stream().unwrap();
+        assert_eq!(lifecycle.wait_while_paused().await, Duration::ZERO);
+
+        paused_until(&lifecycle, |lifecycle| lifecycle.resume().unwrap()).await;
+        assert_eq!(lifecycle.state(), LifecycleState::Streaming);
+        paused_until(&lifecycle, Lifecycle::disconnected).await;
+        assert_eq!(lifecycle.state(), LifecycleState::Disconnected);
+    }
+
+    /// Pauses, checks that a waiter stays blocked, then runs `end` and checks that it wakes.
+    async fn paused_until(lifecycle: &Lifecycle, end: impl FnOnce(&Lifecycle)) {
+        lifecycle.pause().unwrap();
+        let waiting = tokio::spawn({
+            let lifecycle = lifecycle.clone();
+            async move { lifecycle.wait_while_paused().await }
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!waiting.is_finished());
+        end(lifecycle);
+        let paused_for = tokio::time::timeout(Duration::from_secs(5), waiting).await.expect("wait ends").unwrap();
+        assert!(paused_for >= Duration::from_millis(25), "{:?}", paused_for);
+    }
+
+    #[tokio::test]
+    async fn health_reports_combine_common_and_device_checks() {
+        let mut device = StubDevice { lifecycle: Lifecycle::new(), streamed: false };
+        let report = device.health_check().await;
+        let names: Vec<&str> = report.checks.iter().map(|check| check.name.as_str()).collect();
+        assert_eq!(names, vec!["connection", "errors", "streamed"]);
+        assert!(!report.healthy());
+        assert!(report.to_string().starts_with("UNHEALTHY"));
+
+        device.connect().await.unwrap();
+        device.stream(Duration::from_secs(1)).await.unwrap();
+        let report = device.health_check().await;
+        assert!(report.healthy(), "{}", report);
+        assert_eq!(device.status().samples_received, 10);
+        assert!(device.status().uptime.is_some());
+
+        device.lifecycle().record_error("checksum mismatch");
+        let report = device.health_check().await;
+        assert!(!report.healthy());
+        assert!(report.to_string().contains("[FAIL] errors: 1 (last: checksum mismatch)"), "{}", report);
+        assert_eq!(device.status().last_error.as_deref(), Some("checksum mismatch"));
+
+        device.disconnect().await.unwrap();
+        assert!(device.disconnect().await.is_err());
+        assert!(!device.health_check().await.checks[0].passed);
+    }
+}
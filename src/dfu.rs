@@ -0,0 +1,355 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use tokio::time;
+
+/// Image container: magic, semver, payload length (LE), payload, Ed25519 signature over everything before it.
+const IMAGE_MAGIC: &[u8; 4] = b"BRFW";
+const HEADER_LEN: usize = 4 + 3 + 4;
+const SIGNATURE_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl std::fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FirmwareImage {
+    pub version: FirmwareVersion,
+    pub payload: Vec<u8>,
+    pub digest: [u8; 32],
+}
+
+impl FirmwareImage {
+    /// Builds a signed image container. Used by release tooling and the mock peripheral.
+    pub fn sign(version: FirmwareVersion, payload: &[u8], key: &SigningKey) -> Vec<u8> {
+        let mut image = Vec::with_capacity(HEADER_LEN + payload.len() + SIGNATURE_LEN);
+        image.extend_from_slice(IMAGE_MAGIC);
+        image.extend_from_slice(&[version.major, version.minor, version.patch]);
+        image.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        image.extend_from_slice(payload);
+        let signature = key.sign(&image);
+        image.extend_from_slice(&signature.to_bytes());
+        image
+    }
+
+    /// Parses an image container and checks its signature against the trusted key.
+    pub fn verify(image: &[u8], key: &VerifyingKey) -> Result<Self, String> {
+        if image.len() < HEADER_LEN + SIGNATURE_LEN || &image[..4] != IMAGE_MAGIC {
+            return Err("Not a BlueRAIN firmware image".to_string());
+        }
+        let version = FirmwareVersion {
+            major: image[4],
+            minor: image[5],
+            patch: image[6],
+        };
+        let payload_len = u32::from_le_bytes([image[7], image[8], image[9], image[10]]) as usize;
+        if image.len() != HEADER_LEN + payload_len + SIGNATURE_LEN {
+            return Err(format!("Image length mismatch: header says {} payload bytes", payload_len));
+        }
+
+        let (signed, signature) = image.split_at(HEADER_LEN + payload_len);
+        let signature = Signature::from_slice(signature).map_err(|e| e.to_string())?;
+        key.verify(signed, &signature)
+            .map_err(|_| "Firmware image signature is invalid".to_string())?;
+
+        let payload = signed[HEADER_LEN..].to_vec();
+        Ok(FirmwareImage {
+            version,
+            digest: Sha256::digest(&payload).into(),
+            payload,
+        })
+    }
+}
+
+/// The peripheral side of the transfer, as exposed over the DFU control/packet characteristics.
+#[async_trait]
+pub trait DfuTarget: Send {
+    async fn running_version(&mut self) -> Result<FirmwareVersion, String>;
+    /// Starts (or resumes) staging an image; returns how many bytes the target already holds for it.
+    async fn begin(&mut self, version: FirmwareVersion, size: u32, digest: [u8; 32]) -> Result<u32, String>;
+    async fn write_chunk(&mut self, offset: u32, data: &[u8]) -> Result<(), String>;
+    /// Digest of the staged bytes as computed by the target.
+    async fn staged_digest(&mut self) -> Result<[u8; 32], String>;
+    /// Swaps to the staged image and reboots.
+    async fn activate(&mut self) -> Result<(), String>;
+    async fn rollback(&mut self) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone)]
+pub struct DfuUpdater {
+    trusted_key: VerifyingKey,
+    chunk_size: usize,
+    max_retries: u32,
+    allow_downgrade: bool,
+}
+
+impl DfuUpdater {
+    pub fn new(trusted_key: VerifyingKey) -> Self {
+        DfuUpdater {
+            trusted_key,
+            chunk_size: 244,
+            max_retries: 3,
+            allow_downgrade: false,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn allow_downgrade(mut self, allow: bool) -> Self {
+        self.allow_downgrade = allow;
+        self
+    }
+
+    /// Validates, transfers, verifies and activates an image. An interrupted transfer can be
+    /// resumed by calling `update` again with the same image; a failed verification or boot
+    /// rolls the target back to its previous firmware.
+    pub async fn update(&self, target: &mut dyn DfuTarget, image: &[u8]) -> Result<FirmwareVersion, String> {
+        let image = FirmwareImage::verify(image, &self.trusted_key)?;
+        let current = target.running_version().await?;
+        if image.version == current {
+            return Err(format!("Firmware {} is already installed", current));
+        }
+        if image.version < current && !self.allow_downgrade {
+            return Err(format!("Refusing to downgrade from {} to {}", current, image.version));
+        }
+
+        let size = image.payload.len() as u32;
+        let mut offset = target.begin(image.version, size, image.digest).await?;
+        if offset > 0 {
+            println!("Resuming firmware transfer at byte {} of {}", offset, size);
+        }
+
+        while offset < size {
+            let end = (offset as usize + self.chunk_size).min(image.payload.len());
+            let chunk = &image.payload[offset as usize..end];
+            self.write_with_retry(target, offset, chunk).await?;
+            offset = end as u32;
+            println!("Firmware transfer {:.0}%", offset as f32 / size as f32 * 100.0);
+        }
+
+        if target.staged_digest().await? != image.digest {
+            target.rollback().await?;
+            return Err("Staged firmware does not match the image digest; rolled back".to_string());
+        }
+
+        target.activate().await?;
+        let running = target.running_version().await?;
+        if running != image.version {
+            target.rollback().await?;
+            return Err(format!("Device booted {} instead of {}; rolled back", running, image.version));
+        }
+
+        println!("Firmware updated from {} to {}", current, running);
+        Ok(running)
+    }
+
+    async fn write_with_retry(&self, target: &mut dyn DfuTarget, offset: u32, chunk: &[u8]) -> Result<(), String> {
+        let mut attempt = 0;
+        loop {
+            match target.write_chunk(offset, chunk).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.max_retries => {
+                    attempt += 1;
+                    println!("Chunk at {} failed ({}), retry {}/{}", offset, e, attempt, self.max_retries);
+                    time::sleep(Duration::from_millis(50 * attempt as u64)).await;
+                }
+                Err(e) => return Err(format!("Transfer interrupted at byte {}: {}", offset, e)),
+            }
+        }
+    }
+}
+
+/// In-process stand-in for a BlueRAIN peripheral's DFU bootloader with dual image slots.
+#[derive(Debug)]
+pub struct MockDfuPeripheral {
+    active: FirmwareVersion,
+    previous: Option<FirmwareVersion>,
+    staged: Vec<u8>,
+    staged_for: Option<(FirmwareVersion, [u8; 32])>,
+    /// Fail every n-th chunk write (0 disables).
+    pub fail_every: u32,
+    /// Corrupt the byte at this offset when it is written.
+    pub corrupt_at: Option<u32>,
+    /// Refuse to boot the staged image after activation.
+    pub fail_boot: bool,
+    writes: u32,
+}
+
+impl MockDfuPeripheral {
+    pub fn new(active: FirmwareVersion) -> Self {
+        MockDfuPeripheral {
+            active,
+            previous: None,
+            staged: vec![],
+            staged_for: None,
+            fail_every: 0,
+            corrupt_at: None,
+            fail_boot: false,
+            writes: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl DfuTarget for MockDfuPeripheral {
+    async fn running_version(&mut self) -> Result<FirmwareVersion, String> {
+        Ok(self.active)
+    }
+
+    async fn begin(&mut self, version: FirmwareVersion, size: u32, digest: [u8; 32]) -> Result<u32, String> {
+        if size as usize > 512 * 1024 {
+            return Err("Image does not fit in the staging slot".to_string());
+        }
+        if self.staged_for != Some((version, digest)) {
+            // Staging overwrites the inactive slot, which held the previous image.
+            self.staged.clear();
+            self.staged_for = Some((version, digest));
+            self.previous = None;
+        }
+        Ok(self.staged.len() as u32)
+    }
+
+    async fn write_chunk(&mut self, offset: u32, data: &[u8]) -> Result<(), String> {
+        self.writes += 1;
+        if self.fail_every > 0 && self.writes.is_multiple_of(self.fail_every) {
+            return Err("Link dropped".to_string());
+        }
+        if offset as usize != self.staged.len() {
+            return Err(format!("Expected offset {}, got {}", self.staged.len(), offset));
+        }
+        self.staged.extend_from_slice(data);
+        if let Some(corrupt) = self.corrupt_at {
+            if corrupt >= offset && (corrupt as usize) < self.staged.len() {
+                self.staged[corrupt as usize] ^= 0xFF;
+            }
+        }
+        Ok(())
+    }
+
+    async fn staged_digest(&mut self) -> Result<[u8; 32], String> {
+        Ok(Sha256::digest(&self.staged).into())
+    }
+
+    async fn activate(&mut self) -> Result<(), String> {
+        let (version, _) = self.staged_for.take().ok_or("No image staged")?;
+        time::sleep(Duration::from_millis(200)).await;
+        self.staged.clear();
+        if !self.fail_boot {
+            self.previous = Some(self.active);
+            self.active = version;
+        }
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<(), String> {
+        self.staged.clear();
+        self.staged_for = None;
+        if let Some(previous) = self.previous.take() {
+            self.active = previous;
+        }
+        println!("Peripheral rolled back to firmware {}", self.active);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: FirmwareVersion = FirmwareVersion { major: 1, minor: 0, patch: 0 };
+    const V2: FirmwareVersion = FirmwareVersion { major: 1, minor: 1, patch: 0 };
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn image(version: FirmwareVersion) -> Vec<u8> {
+        let payload: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 251) as u8).collect();
+        FirmwareImage::sign(version, &payload, &signing_key())
+    }
+
+    fn updater() -> DfuUpdater {
+        DfuUpdater::new(signing_key().verifying_key()).with_chunk_size(100)
+    }
+
+    #[tokio::test]
+    async fn successful_update() {
+        let mut peripheral = MockDfuPeripheral::new(V1);
+        assert_eq!(updater().update(&mut peripheral, &image(V2)).await, Ok(V2));
+        assert_eq!(peripheral.active, V2);
+        assert_eq!(peripheral.writes, 10);
+    }
+
+    #[tokio::test]
+    async fn failed_chunks_are_retried() {
+        let mut peripheral = MockDfuPeripheral::new(V1);
+        peripheral.fail_every = 3;
+        assert_eq!(updater().update(&mut peripheral, &image(V2)).await, Ok(V2));
+        // Every third write dropped: 10 chunks need 14 writes.
+        assert_eq!(peripheral.writes, 14);
+    }
+
+    #[tokio::test]
+    async fn interrupted_transfer_resumes() {
+        let mut peripheral = MockDfuPeripheral::new(V1);
+        peripheral.fail_every = 4;
+        let error = updater().with_max_retries(0).update(&mut peripheral, &image(V2)).await.unwrap_err();
+        assert!(error.contains("interrupted at byte 300"), "{}", error);
+        assert_eq!(peripheral.active, V1);
+
+        peripheral.fail_every = 0;
+        let writes = peripheral.writes;
+        assert_eq!(updater().update(&mut peripheral, &image(V2)).await, Ok(V2));
+        assert_eq!(peripheral.writes - writes, 7);
+    }
+
+    #[tokio::test]
+    async fn digest_mismatch_rolls_back() {
+        let mut peripheral = MockDfuPeripheral::new(V1);
+        peripheral.corrupt_at = Some(512);
+        let error = updater().update(&mut peripheral, &image(V2)).await.unwrap_err();
+        assert!(error.contains("does not match"), "{}", error);
+        assert_eq!(peripheral.active, V1);
+        assert!(peripheral.staged.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_boot_rolls_back() {
+        let mut peripheral = MockDfuPeripheral::new(V1);
+        peripheral.fail_boot = true;
+        let error = updater().update(&mut peripheral, &image(V2)).await.unwrap_err();
+        assert_eq!(error, "Device booted 1.0.0 instead of 1.1.0; rolled back");
+        assert_eq!(peripheral.active, V1);
+    }
+
+    #[tokio::test]
+    async fn untrusted_and_older_images_are_refused() {
+        let mut peripheral = MockDfuPeripheral::new(V2);
+        let other_key = SigningKey::from_bytes(&[9; 32]);
+        let forged = FirmwareImage::sign(FirmwareVersion { major: 2, minor: 0, patch: 0 }, b"payload", &other_key);
+        assert_eq!(updater().update(&mut peripheral, &forged).await, Err("Firmware image signature is invalid".to_string()));
+
+        let error = updater().update(&mut peripheral, &image(V1)).await.unwrap_err();
+        assert!(error.contains("downgrade"), "{}", error);
+        assert_eq!(updater().allow_downgrade(true).update(&mut peripheral, &image(V1)).await, Ok(V1));
+        assert_eq!(peripheral.writes, 10);
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use async_trait::async_trait;
+use rand::Rng;
+use tokio::time;
+
+use super::DeviceResult;
+
+/// Standard Device Information and Battery services, as advertised by BlueRAIN headsets.
+pub const DEVICE_INFORMATION_SERVICE: &str = "0000180a-0000-1000-8000-00805f9b34fb";
+pub const BATTERY_SERVICE: &str = "0000180f-0000-1000-8000-00805f9b34fb";
+/// Nordic UART Service, used by prototype boards that stream raw samples (see `nus`).
+pub const NORDIC_UART_SERVICE: &str = "6e400001-b5a3-f393-e0a9-e50e24dcca9e";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceCapabilities {
+    pub channel_count: u16,
+    pub sample_rates: Vec<u32>,
+    pub battery_level: Option<u8>,
+    pub firmware_version: String,
+}
+
+/// A single advertisement report as seen by the adapter.
+#[derive(Debug, Clone)]
+pub struct Advertisement {
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: i16,
+    pub services: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredDevice {
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: i16,
+    pub services: Vec<String>,
+    pub capabilities: Option<DeviceCapabilities>,
+}
+
+#[async_trait]
+pub trait BleAdapter: Send + Sync {
+    async fn scan(&self, duration: Duration) -> DeviceResult<Vec<Advertisement>>;
+    async fn read_capabilities(&self, address: &str) -> DeviceResult<DeviceCapabilities>;
+}
+
+/// Restricts discovery to known addresses, name prefixes and/or advertised services. An empty
+/// allowlist allows nothing.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceAllowlist {
+    addresses: HashSet<String>,
+    name_prefixes: Vec<String>,
+    services: Vec<String>,
+}
+
+impl DeviceAllowlist {
+    pub fn new() -> Self {
+        DeviceAllowlist::default()
+    }
+
+    pub fn allow_address(mut self, address: &str) -> Self {
+        self.addresses.insert(address.to_uppercase());
+        self
+    }
+
+    pub fn allow_name_prefix(mut self, prefix: &str) -> Self {
+        self.name_prefixes.push(prefix.to_string());
+        self
+    }
+
+    pub fn allow_service(mut self, uuid: &str) -> Self {
+        self.services.push(uuid.to_lowercase());
+        self
+    }
+
+    pub fn allows(&self, advertisement: &Advertisement) -> bool {
+        if self.addresses.contains(&advertisement.address.to_uppercase()) {
+            return true;
+        }
+        if advertisement.services.iter().any(|service| self.services.contains(&service.to_lowercase())) {
+            return true;
+        }
+        match &advertisement.name {
+            Some(name) => self.name_prefixes.iter().any(|prefix| name.starts_with(prefix.as_str())),
+            None => false,
+        }
+    }
+}
+
+pub struct Scanner<A: BleAdapter> {
+    adapter: A,
+    allowlist: Option<DeviceAllowlist>,
+}
+
+impl<A: BleAdapter> Scanner<A> {
+    pub fn new(adapter: A) -> Self {
+        Scanner {
+            adapter,
+            allowlist: None,
+        }
+    }
+
+    pub fn with_allowlist(mut self, allowlist: DeviceAllowlist) -> Self {
+        self.allowlist = Some(allowlist);
+        self
+    }
+
+    /// Scans for `duration`, merging repeated reports per address (strongest RSSI wins),
+    /// and reads a capability descriptor from every device that passes the allowlist.
+    /// Addresses are reported in upper case, however the adapter spelled them.
+    pub async fn scan(&self, duration: Duration) -> DeviceResult<Vec<DiscoveredDevice>> {
+        let mut seen: HashMap<String, Advertisement> = HashMap::new();
+        for mut advertisement in self.adapter.scan(duration).await? {
+            advertisement.address = advertisement.address.to_uppercase();
+            if let Some(allowlist) = &self.allowlist {
+                if !allowlist.allows(&advertisement) {
+                    continue;
+                }
+            }
+            match seen.get_mut(&advertisement.address) {
+                Some(existing) => {
+                    if advertisement.rssi > existing.rssi {
+                        existing.rssi = advertisement.rssi;
+                    }
+                    if existing.name.is_none() {
+                        existing.name = advertisement.name;
+                    }
+                    for service in advertisement.services {
+                        if !existing.services.contains(&service) {
+                            existing.services.push(service);
+                        }
+                    }
+                }
+                None => {
+                    seen.insert(advertisement.address.clone(), advertisement);
+                }
+            }
+        }
+
+        let mut devices = Vec::with_capacity(seen.len());
+        for (address, advertisement) in seen {
+            let capabilities = match self.adapter.read_capabilities(&address).await {
+                Ok(capabilities) => Some(capabilities),
+                Err(e) => {
+                    eprintln!("Could not read capabilities of {}: {}", address, e);
+                    None
+                }
+            };
+            devices.push(DiscoveredDevice {
+                address,
+                name: advertisement.name,
+                rssi: advertisement.rssi,
+                services: advertisement.services,
+                capabilities,
+            });
+        }
+        devices.sort_by_key(|device| std::cmp::Reverse(device.rssi));
+        Ok(devices)
+    }
+}
+
+/// In-memory adapter that "sees" a fixed set of peripherals, with jittered RSSI.
+#[derive(Debug, Default)]
+pub struct MockAdapter {
+    peripherals: Vec<(Advertisement, Option<DeviceCapabilities>)>,
+}
+
+impl MockAdapter {
+    pub fn new() -> Self {
+        MockAdapter::default()
+    }
+
+    pub fn with_peripheral(mut self, advertisement: Advertisement, capabilities: Option<DeviceCapabilities>) -> Self {
+        self.peripherals.push((advertisement, capabilities));
+        self
+    }
+
+    /// Two BlueRAIN headsets and an unrelated peripheral.
+    pub fn with_demo_peripherals() -> Self {
+        let headset = |address: &str, name: &str, channels: u16, battery: u8| {
+            (
+                Advertisement {
+                    address: address.to_string(),
+                    name: Some(name.to_string()),
+                    rssi: -60,
+                    services: vec![DEVICE_INFORMATION_SERVICE.to_string(), BATTERY_SERVICE.to_string()],
+                },
+                Some(DeviceCapabilities {
+                    channel_count: channels,
+                    sample_rates: vec![250, 500, 1000],
+                    battery_level: Some(battery),
+                    firmware_version: "1.0.3".to_string(),
+                }),
+            )
+        };
+        let (a, a_caps) = headset("00:1A:7D:DA:71:13", "BlueRAIN-8", 8, 87);
+        let (b, b_caps) = headset("00:1A:7D:DA:71:14", "BlueRAIN-16", 16, 64);
+        MockAdapter::new()
+            .with_peripheral(a, a_caps)
+            .with_peripheral(b, b_caps)
+            .with_peripheral(
+                Advertisement {
+                    address: "4C:87:5D:10:22:01".to_string(),
+                    name: Some("Fitness Band".to_string()),
+                    rssi: -80,
+                    services: vec![BATTERY_SERVICE.to_string()],
+                },
+                None,
+            )
+            .with_peripheral(
+                Advertisement {
+                    address: "D2:41:9C:3E:50:07".to_string(),
+                    name: Some("Proto-UART".to_string()),
+                    rssi: -70,
+                    services: vec![NORDIC_UART_SERVICE.to_string()],
+                },
+                None,
+            )
+    }
+}
+
+#[async_trait]
+impl BleAdapter for MockAdapter {
+    async fn scan(&self, duration: Duration) -> DeviceResult<Vec<Advertisement>> {
+        time::sleep(duration).await;
+        let mut rng = rand::thread_rng();
+        let mut reports = Vec::new();
+        for (advertisement, _) in &self.peripherals {
+            // Each peripheral advertises a few times during the scan window.
+            for _ in 0..3 {
+                let mut report = advertisement.clone();
+                report.rssi += rng.gen_range(-6..=6);
+                reports.push(report);
+            }
+        }
+        Ok(reports)
+    }
+
+    async fn read_capabilities(&self, address: &str) -> DeviceResult<DeviceCapabilities> {
+        self.peripherals
+            .iter()
+            .find(|(advertisement, _)| advertisement.address.eq_ignore_ascii_case(address))
+            .and_then(|(_, capabilities)| capabilities.clone())
+            .ok_or_else(|| format!("Device {} does not expose a capability descriptor", address).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(devices: &[DiscoveredDevice]) -> Vec<&str> {
+        devices.iter().map(|device| device.address.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn scan_merges_reports_and_reads_capabilities() {
+        let devices = Scanner::new(MockAdapter::with_demo_peripherals()).scan(Duration::ZERO).await.unwrap();
+        assert_eq!(devices.len(), 4);
+        assert!(devices.windows(2).all(|pair| pair[0].rssi >= pair[1].rssi));
+        let headset = devices.iter().find(|device| device.address == "00:1A:7D:DA:71:14").unwrap();
+        assert_eq!(headset.capabilities.as_ref().map(|capabilities| capabilities.channel_count), Some(16));
+        assert!(devices.iter().find(|device| device.address == "D2:41:9C:3E:50:07").unwrap().capabilities.is_none());
+    }
+
+    #[tokio::test]
+    async fn allowlist_filters_by_address_prefix_and_service() {
+        let scan = |allowlist: DeviceAllowlist| async move {
+            let mut devices = Scanner::new(MockAdapter::with_demo_peripherals())
+                .with_allowlist(allowlist)
+                .scan(Duration::ZERO)
+                .await
+                .unwrap();
+            devices.sort_by(|a, b| a.address.cmp(&b.address));
+            devices
+        };
+        assert!(scan(DeviceAllowlist::new()).await.is_empty());
+        let by_prefix = scan(DeviceAllowlist::new().allow_name_prefix("BlueRAIN")).await;
+        assert_eq!(addresses(&by_prefix), ["00:1A:7D:DA:71:13", "00:1A:7D:DA:71:14"]);
+        let by_address = scan(DeviceAllowlist::new().allow_address("4c:87:5d:10:22:01")).await;
+        assert_eq!(addresses(&by_address), ["4C:87:5D:10:22:01"]);
+        let by_service = scan(DeviceAllowlist::new().allow_service(&NORDIC_UART_SERVICE.to_uppercase())).await;
+        assert_eq!(addresses(&by_service), ["D2:41:9C:3E:50:07"]);
+    }
+
+    #[tokio::test]
+    async fn addresses_are_normalised_on_arrival() {
+        let advertisement = |address: &str, rssi| Advertisement {
+            address: address.to_string(),
+            name: Some("BlueRAIN-8".to_string()),
+            rssi,
+            services: vec![],
+        };
+        let capabilities = DeviceCapabilities {
+            channel_count: 8,
+            sample_rates: vec![250],
+            battery_level: None,
+            firmware_version: "1.0.3".to_string(),
+        };
+        let adapter = MockAdapter::new()
+            .with_peripheral(advertisement("aa:bb:cc:dd:ee:ff", -70), Some(capabilities.clone()))
+            .with_peripheral(advertisement("AA:BB:CC:DD:EE:FF", -50), None);
+        let devices = Scanner::new(adapter)
+            .with_allowlist(DeviceAllowlist::new().allow_address("Aa:Bb:Cc:Dd:Ee:Ff"))
+            .scan(Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(addresses(&devices), ["AA:BB:CC:DD:EE:FF"]);
+        assert!(devices[0].rssi > -70 + 6);
+        assert_eq!(devices[0].capabilities, Some(capabilities));
+    }
+}
@@ -0,0 +1,364 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::session_store::{Segment, SessionEvent, StoredSession};
+
+const ANNOTATION_LABEL: &str = "EDF Annotations";
+/// Marks where a segment's last record stops holding real samples; the rest of the record is filler.
+const PADDING_LABEL: &str = "Record padding";
+
+#[derive(Debug, Clone)]
+struct SignalHeader {
+    label: String,
+    physical_min: f64,
+    physical_max: f64,
+    digital_min: f64,
+    digital_max: f64,
+    samples_per_record: usize,
+}
+
+impl SignalHeader {
+    fn is_annotation(&self) -> bool {
+        self.label == ANNOTATION_LABEL
+    }
+
+    fn to_physical(&self, digital: i16) -> f64 {
+        let scale = (self.physical_max - self.physical_min) / (self.digital_max - self.digital_min);
+        self.physical_min + (digital as f64 - self.digital_min) * scale
+    }
+}
+
+/// Reads fixed-width ASCII header fields.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn text(&mut self, width: usize) -> Result<String, String> {
+        let field = self
+            .bytes
+            .get(self.offset..self.offset + width)
+            .ok_or_else(|| "EDF header is truncated".to_string())?;
+        self.offset += width;
+        Ok(String::from_utf8_lossy(field).trim().to_string())
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, width: usize, what: &str) -> Result<T, String> {
+        let text = self.text(width)?;
+        text.parse().map_err(|_| format!("EDF header has invalid {}: '{}'", what, text))
+    }
+}
+
+/// Onsets and annotations from one record's TALs (time-stamped annotation lists).
+/// The first TAL of each EDF+ record has no text and gives the record's start time.
+fn parse_annotations(bytes: &[u8]) -> (Option<f64>, Vec<SessionEvent>) {
+    let mut record_onset = None;
+    let mut events = vec![];
+    for tal in bytes.split(|byte| *byte == 0).filter(|tal| !tal.is_empty()) {
+        let mut parts = tal.split(|byte| *byte == 0x14);
+        let timing = String::from_utf8_lossy(parts.next().unwrap_or_default()).to_string();
+        let onset = match timing.split('\u{15}').next().and_then(|onset| onset.parse::<f64>().ok()) {
+            Some(onset) => onset,
+            None => continue,
+        };
+        let texts: Vec<String> = parts
+            .map(|text| String::from_utf8_lossy(text).to_string())
+            .filter(|text| !text.is_empty())
+            .collect();
+        if texts.is_empty() {
+            record_onset.get_or_insert(onset);
+        }
+        events.extend(texts.into_iter().map(|label| SessionEvent { time_secs: onset, label }));
+    }
+    (record_onset, events)
+}
+
+/// Reads an EDF or EDF+ file into a session. All ordinary signals must share one sample rate.
+/// Discontinuous EDF+ recordings become one segment per run of back-to-back records, and
+/// records carrying a padding annotation are cut back to the samples before it.
+pub fn read_edf(path: &Path) -> Result<StoredSession, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+    let mut header = Fields { bytes: &bytes, offset: 0 };
+
+    let _version = header.text(8)?;
+    let _patient = header.text(80)?;
+    let recording = header.text(80)?;
+    let _start_date = header.text(8)?;
+    let _start_time = header.text(8)?;
+    let header_bytes: usize = header.number(8, "header size")?;
+    let _reserved = header.text(44)?;
+    let record_count: i64 = header.number(8, "record count")?;
+    let record_duration: f64 = header.number(8, "record duration")?;
+    let signal_count: usize = header.number(4, "signal count")?;
+
+    let mut read_all = |width: usize| (0..signal_count).map(|_| header.text(width)).collect::<Result<Vec<_>, _>>();
+    let labels = read_all(16)?;
+    let _transducers = read_all(80)?;
+    let _dimensions = read_all(8)?;
+    let numbers = |values: Vec<String>, what: &str| {
+        values
+            .into_iter()
+            .map(|value| value.parse::<f64>().map_err(|_| format!("EDF header has invalid {}: '{}'", what, value)))
+            .collect::<Result<Vec<f64>, String>>()
+    };
+    let physical_min = numbers(read_all(8)?, "physical minimum")?;
+    let physical_max = numbers(read_all(8)?, "physical maximum")?;
+    let digital_min = numbers(read_all(8)?, "digital minimum")?;
+    let digital_max = numbers(read_all(8)?, "digital maximum")?;
+    let _prefilters = read_all(80)?;
+    let samples_per_record = numbers(read_all(8)?, "samples per record")?;
+
+    let signals: Vec<SignalHeader> = (0..signal_count)
+        .map(|i| SignalHeader {
+            label: labels[i].clone(),
+            physical_min: physical_min[i],
+            physical_max: physical_max[i],
+            digital_min: digital_min[i],
+            digital_max: digital_max[i],
+            samples_per_record: samples_per_record[i] as usize,
+        })
+        .collect();
+    if signals.iter().any(|signal| !signal.is_annotation() && signal.digital_max <= signal.digital_min) {
+        return Err(format!("{} has a signal with an empty digital range", path.display()));
+    }
+
+    let ordinary: Vec<&SignalHeader> = signals.iter().filter(|signal| !signal.is_annotation()).collect();
+    let frames_per_record = ordinary.first().map_or(0, |signal| signal.samples_per_record);
+    if ordinary.iter().any(|signal| signal.samples_per_record != frames_per_record) {
+        return Err(format!("{} mixes sample rates, which sessions cannot hold", path.display()));
+    }
+    if record_duration <= 0.0 || frames_per_record == 0 {
+        return Err(format!("{} has no samples", path.display()));
+    }
+
+    let record_bytes: usize = signals.iter().map(|signal| signal.samples_per_record * 2).sum();
+    let available = bytes.len().saturating_sub(header_bytes) / record_bytes;
+    // -1 means the writer never filled in the count.
+    let record_count = if record_count < 0 { available } else { (record_count as usize).min(available) };
+
+    // EDF+ puts the equipment last in "Startdate <date> <admin code> <technician> <equipment>".
+    let device_id = match recording.strip_prefix("Startdate ") {
+        Some(fields) => fields.split_whitespace().nth(3).unwrap_or("edf").to_string(),
+        None if recording.is_empty() => "edf".to_string(),
+        None => recording,
+    };
+    let channels = ordinary.iter().map(|signal| signal.label.clone()).collect();
+    let mut session = StoredSession::new(&device_id, frames_per_record as f64 / record_duration, channels);
+
+    let mut expected_onset: Option<f64> = None;
+    for index in 0..record_count {
+        let mut offset = header_bytes + index * record_bytes;
+        let mut columns: Vec<Vec<f64>> = vec![];
+        let mut record_onset = None;
+        let mut padding_from = None;
+        for signal in &signals {
+            let data = &bytes[offset..offset + signal.samples_per_record * 2];
+            offset += data.len();
+            if signal.is_annotation() {
+                let (onset, events) = parse_annotations(data);
+                record_onset = record_onset.or(onset);
+                for event in events {
+                    if event.label == PADDING_LABEL {
+                        padding_from = Some(event.time_secs);
+                    } else {
+                        session.events.push(event);
+                    }
+                }
+            } else {
+                columns.push(
+                    data.chunks_exact(2)
+                        .map(|pair| signal.to_physical(i16::from_le_bytes([pair[0], pair[1]])))
+                        .collect(),
+                );
+            }
+        }
+
+        let onset = record_onset.unwrap_or(index as f64 * record_duration);
+        let frames = padding_from.map_or(frames_per_record, |from| {
+            let kept = ((from - onset) / record_duration * frames_per_record as f64).round();
+            (kept.max(0.0) as usize).min(frames_per_record)
+        });
+        let contiguous = expected_onset.is_some_and(|expected| (onset - expected).abs() < 1e-6);
+        if !contiguous {
+            session.segments.push(Segment {
+                start_secs: onset,
+                frames: vec![],
+            });
+        }
+        if let Some(segment) = session.segments.last_mut() {
+            segment
+                .frames
+                .extend((0..frames).map(|i| columns.iter().map(|column| column[i]).collect()));
+        }
+        expected_onset = Some(onset + record_duration);
+    }
+    Ok(session)
+}
+
+fn field(out: &mut Vec<u8>, text: &str, width: usize) {
+    let mut bytes: Vec<u8> = text.bytes().filter(u8::is_ascii).take(width).collect();
+    bytes.resize(width, b' ');
+    out.extend_from_slice(&bytes);
+}
+
+/// Shortest decimal that fits an 8-character header field.
+fn number_field(value: f64) -> Result<String, String> {
+    (0..=6)
+        .rev()
+        .map(|decimals| format!("{:.*}", decimals, value))
+        .find(|text| text.len() <= 8)
+        .ok_or_else(|| format!("{} does not fit an 8-character EDF header field", value))
+}
+
+fn tal(onset: f64, annotation: &str) -> Vec<u8> {
+    format!("{:+}\u{14}{}\u{14}\0", onset, annotation).into_bytes()
+}
+
+/// Writes a session as EDF+D with one-second records and an annotation signal carrying
+/// record onsets and events. Each segment's last record is padded by repeating its final frame,
+/// and a padding annotation records where the real samples end so `read_edf` can drop the filler.
+pub fn write_edf(session: &StoredSession, path: &Path) -> Result<(), String> {
+    let frames_per_record = session.sample_rate.round() as usize;
+    if frames_per_record == 0 || (session.sample_rate - frames_per_record as f64).abs() > 1e-9 {
+        return Err(format!("EDF export needs a whole-number sample rate, got {}", session.sample_rate));
+    }
+    if session.channels.is_empty() {
+        return Err("Session has no channels".to_string());
+    }
+
+    // (onset, frames) per record, and how many of those frames are real.
+    let mut records: Vec<(f64, Vec<&Vec<f64>>)> = vec![];
+    let mut real_frames: Vec<usize> = vec![];
+    for segment in &session.segments {
+        for (i, chunk) in segment.frames.chunks(frames_per_record).enumerate() {
+            let mut frames: Vec<&Vec<f64>> = chunk.iter().collect();
+            real_frames.push(frames.len());
+            if let Some(last) = frames.last().copied() {
+                frames.resize(frames_per_record, last);
+            }
+            records.push((segment.start_secs + i as f64, frames));
+        }
+    }
+    let annotations: Vec<Vec<u8>> = records
+        .iter()
+        .enumerate()
+        .map(|(index, (onset, _))| {
+            let mut bytes = tal(*onset, "");
+            let real = real_frames[index];
+            if real < frames_per_record {
+                let from = onset + real as f64 / session.sample_rate;
+                let length = (frames_per_record - real) as f64 / session.sample_rate;
+                bytes.extend(format!("{:+}\u{15}{}\u{14}{}\u{14}\0", from, length, PADDING_LABEL).into_bytes());
+            }
+            let end = records.get(index + 1).map_or(f64::INFINITY, |(next, _)| *next);
+            for event in &session.events {
+                // Events before the first record go with it; otherwise each lands in the record it falls in.
+                if (event.time_secs >= *onset || index == 0) && event.time_secs < end {
+                    bytes.extend(tal(event.time_secs, &event.label));
+                }
+            }
+            bytes
+        })
+        .collect();
+    let annotation_samples = annotations.iter().map(Vec::len).max().unwrap_or(0).div_ceil(2).max(30);
+
+    let ranges: Vec<(f64, f64)> = (0..session.channels.len())
+        .map(|channel| {
+            let values = session.segments.iter().flat_map(|segment| segment.frames.iter().map(move |frame| frame[channel]));
+            let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), x| (min.min(x), max.max(x)));
+            if min.is_finite() && max > min { (min, max) } else { (min.min(0.0) - 1.0, max.max(0.0) + 1.0) }
+        })
+        .collect();
+    // Header limits as written; samples are scaled against these rounded values.
+    let limits: Vec<(String, String)> = ranges
+        .iter()
+        .map(|(min, max)| Ok((number_field(*min)?, number_field(*max)?)))
+        .collect::<Result<_, String>>()?;
+
+    let signal_count = session.channels.len() + 1;
+    let mut header = vec![];
+    field(&mut header, "0", 8);
+    field(&mut header, "X X X X", 80);
+    field(&mut header, &format!("Startdate X X X {}", session.device_id.replace(' ', "_")), 80);
+    field(&mut header, "01.01.00", 8);
+    field(&mut header, "00.00.00", 8);
+    field(&mut header, &(256 * (signal_count + 1)).to_string(), 8);
+    field(&mut header, "EDF+D", 44);
+    field(&mut header, &records.len().to_string(), 8);
+    field(&mut header, "1", 8);
+    field(&mut header, &signal_count.to_string(), 4);
+    let per_signal = |header: &mut Vec<u8>, width: usize, channel: &dyn Fn(usize) -> String, annotation: &str| {
+        for i in 0..session.channels.len() {
+            field(header, &channel(i), width);
+        }
+        field(header, annotation, width);
+    };
+    per_signal(&mut header, 16, &|i| session.channels[i].clone(), ANNOTATION_LABEL);
+    per_signal(&mut header, 80, &|_| String::new(), "");
+    per_signal(&mut header, 8, &|_| "uV".to_string(), "");
+    per_signal(&mut header, 8, &|i| limits[i].0.clone(), "-1");
+    per_signal(&mut header, 8, &|i| limits[i].1.clone(), "1");
+    per_signal(&mut header, 8, &|_| "-32768".to_string(), "-32768");
+    per_signal(&mut header, 8, &|_| "32767".to_string(), "32767");
+    per_signal(&mut header, 80, &|_| String::new(), "");
+    per_signal(&mut header, 8, &|_| frames_per_record.to_string(), &annotation_samples.to_string());
+    per_signal(&mut header, 32, &|_| String::new(), "");
+
+    let file = File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+    let mut out = BufWriter::new(file);
+    let io = |e: std::io::Error| e.to_string();
+    out.write_all(&header).map_err(io)?;
+    for ((_, frames), mut annotation) in records.iter().zip(annotations) {
+        for (channel, ((min, max), (min_field, max_field))) in ranges.iter().zip(&limits).enumerate() {
+            // The header range is rounded to fit its field, so scale against the rounded values.
+            let (min, max): (f64, f64) = (min_field.parse().unwrap_or(*min), max_field.parse().unwrap_or(*max));
+            for frame in frames {
+                let digital = -32768.0 + (frame[channel] - min) / (max - min) * 65535.0;
+                out.write_all(&(digital.round().clamp(-32768.0, 32767.0) as i16).to_le_bytes()).map_err(io)?;
+            }
+        }
+        annotation.resize(annotation_samples * 2, 0);
+        out.write_all(&annotation).map_err(io)?;
+    }
+    out.flush().map_err(io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(session: &StoredSession) -> Result<StoredSession, String> {
+        let path = std::env::temp_dir().join(format!("bluerain-edf-test-{}-{}.edf", std::process::id(), session.device_id));
+        let written = write_edf(session, &path);
+        let read = written.and_then(|_| read_edf(&path));
+        let _ = fs::remove_file(&path);
+        read
+    }
+
+    #[test]
+    fn partial_records_are_trimmed_on_read() {
+        let mut session = StoredSession::new("PAD1", 4.0, vec!["C3".to_string()]);
+        session.segments.push(Segment { start_secs: 0.0, frames: (0..6).map(|i| vec![i as f64]).collect() });
+        session.segments.push(Segment { start_secs: 10.0, frames: (0..3).map(|i| vec![i as f64]).collect() });
+        session.events.push(SessionEvent { time_secs: 1.25, label: "blink".to_string() });
+
+        let read = round_trip(&session).unwrap();
+        let lengths: Vec<usize> = read.segments.iter().map(|segment| segment.frames.len()).collect();
+        assert_eq!(lengths, vec![6, 3]);
+        assert_eq!(read.segments[1].start_secs, 10.0);
+        assert!((read.segments[0].frames[5][0] - 5.0).abs() < 1e-3);
+        assert_eq!(read.events, session.events);
+    }
+
+    #[test]
+    fn values_too_wide_for_the_header_are_rejected() {
+        assert_eq!(number_field(-12.345678).unwrap(), "-12.3457");
+        assert!(number_field(1e9).is_err());
+
+        let mut session = StoredSession::new("WIDE", 4.0, vec!["C3".to_string()]);
+        session.segments.push(Segment { start_secs: 0.0, frames: vec![vec![0.0], vec![3e8]] });
+        assert!(round_trip(&session).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::f64::consts::PI;
+
+use super::streaming_stats::SlidingWindow;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Band {
+    Delta,
+    Theta,
+    Alpha,
+    Beta,
+    Gamma,
+}
+
+impl Band {
+    pub const ALL: [Band; 5] = [Band::Delta, Band::Theta, Band::Alpha, Band::Beta, Band::Gamma];
+
+    /// Frequency range in Hz, lower bound inclusive.
+    pub fn range(&self) -> (f64, f64) {
+        match self {
+            Band::Delta => (0.5, 4.0),
+            Band::Theta => (4.0, 8.0),
+            Band::Alpha => (8.0, 13.0),
+            Band::Beta => (13.0, 30.0),
+            Band::Gamma => (30.0, 45.0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Band::Delta => "delta",
+            Band::Theta => "theta",
+            Band::Alpha => "alpha",
+            Band::Beta => "beta",
+            Band::Gamma => "gamma",
+        }
+    }
+
+    /// Inverse of `name`.
+    pub fn from_name(name: &str) -> Option<Band> {
+        Band::ALL.into_iter().find(|band| band.name() == name)
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Band::Delta => "δ",
+            Band::Theta => "θ",
+            Band::Alpha => "α",
+            Band::Beta => "β",
+            Band::Gamma => "γ",
+        }
+    }
+}
+
+/// Absolute power per band in squared input units (µV² for EEG), in `Band::ALL` order.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BandPowers {
+    powers: [f64; 5],
+}
+
+impl From<[f64; 5]> for BandPowers {
+    fn from(powers: [f64; 5]) -> Self {
+        BandPowers { powers }
+    }
+}
+
+impl BandPowers {
+    pub fn get(&self, band: Band) -> f64 {
+        self.powers[band as usize]
+    }
+
+    pub fn total(&self) -> f64 {
+        self.powers.iter().sum()
+    }
+
+    /// Share of the total across the five bands, or 0 when there is no power at all.
+    pub fn relative(&self, band: Band) -> f64 {
+        let total = self.total();
+        if total > 0.0 { self.get(band) / total } else { 0.0 }
+    }
+
+    /// Element-wise mean, e.g. across channels.
+    pub fn average<'a>(all: impl IntoIterator<Item = &'a BandPowers>) -> BandPowers {
+        let mut sum = [0.0; 5];
+        let mut count = 0;
+        for powers in all {
+            for (total, power) in sum.iter_mut().zip(powers.powers) {
+                *total += power;
+            }
+            count += 1;
+        }
+        if count > 0 {
+            sum.iter_mut().for_each(|total| *total /= count as f64);
+        }
+        BandPowers { powers: sum }
+    }
+}
+
+/// Band powers of one window from a Hann-windowed periodogram. Bands above Nyquist are zero.
+pub fn band_powers(window: &[f64], sample_rate: f64) -> BandPowers {
+    let n = window.len();
+    let mut powers = BandPowers::default();
+    if n < 2 || sample_rate <= 0.0 {
+        return powers;
+    }
+    let mean = window.iter().sum::<f64>() / n as f64;
+    let tapered: Vec<f64> = window
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x - mean) * (0.5 - 0.5 * (2.0 * PI * i as f64 / (n - 1) as f64).cos()))
+        .collect();
+    // Hann power gain, so a sine of amplitude A reports A²/2 in its band.
+    let gain: f64 = (0..n).map(|i| (0.5 - 0.5 * (2.0 * PI * i as f64 / (n - 1) as f64).cos()).powi(2)).sum();
+    let resolution = sample_rate / n as f64;
+
+    for band in Band::ALL {
+        let (low, high) = band.range();
+        let first = (low / resolution).ceil() as usize;
+        let last = ((high / resolution).ceil() as usize).min((n - 1) / 2 + 1);
+        powers.powers[band as usize] = (first.max(1)..last)
+            .map(|k| {
+                let omega = 2.0 * PI * k as f64 / n as f64;
+                let (re, im) = tapered
+                    .iter()
+                    .enumerate()
+                    .fold((0.0, 0.0), |(re, im), (i, x)| (re + x * (omega * i as f64).cos(), im - x * (omega * i as f64).sin()));
+                // One-sided: each positive-frequency bin also stands for its negative twin.
+                2.0 * (re * re + im * im) / (n as f64 * gain)
+            })
+            .sum();
+    }
+    powers
+}
+
+/// Band powers over the most recent `window_secs` of a live channel.
+#[derive(Debug, Clone)]
+pub struct BandPowerTracker {
+    sample_rate: f64,
+    window: SlidingWindow,
+}
+
+impl BandPowerTracker {
+    pub fn new(sample_rate: f64, window_secs: f64) -> Self {
+        BandPowerTracker {
+            sample_rate,
+            window: SlidingWindow::new((sample_rate * window_secs).round() as usize),
+        }
+    }
+
+    pub fn push(&mut self, x: f64) {
+        if x.is_finite() {
+            self.window.push(x);
+        }
+    }
+
+    /// `None` until the window has filled once.
+    pub fn powers(&self) -> Option<BandPowers> {
+        if !self.window.is_full() {
+            return None;
+        }
+        let samples: Vec<f64> = self.window.iter().copied().collect();
+        Some(band_powers(&samples, self.sample_rate))
+    }
+
+    pub fn reset(&mut self) {
+        self.window.clear();
+    }
+}
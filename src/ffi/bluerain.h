@@ -0,0 +1,179 @@
+#ifndef BLUERAIN_H
+#define BLUERAIN_H
+
+/* Generated by cbindgen from src/bluerain_ffi.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Bumped whenever a function signature or struct layout in this file changes.
+#define BR_ABI_VERSION 1
+
+#define BR_OK 0
+
+// A null pointer, invalid UTF-8 or an out-of-range value was passed.
+#define BR_ERR_INVALID_ARGUMENT -1
+
+// No matching headset, or the recording does not exist.
+#define BR_ERR_NOT_FOUND -2
+
+// The recording could not be read.
+#define BR_ERR_IO -3
+
+// The device or replay failed while streaming.
+#define BR_ERR_DEVICE -4
+
+// A bug inside the library; close the session.
+#define BR_ERR_INTERNAL -5
+
+#define BR_STATE_STREAMING 1
+
+// The recording ended or the session was stopped; queued data can still be polled.
+#define BR_STATE_FINISHED 2
+
+#define BR_STATE_FAILED 3
+
+#define BR_BAND_COUNT 5
+
+// Index into `BrBandPowers` arrays, and the `band` of reward rules and rewards.
+typedef enum BrBand {
+  BR_BAND_DELTA = 0,
+  BR_BAND_THETA = 1,
+  BR_BAND_ALPHA = 2,
+  BR_BAND_BETA = 3,
+  BR_BAND_GAMMA = 4,
+} BrBand;
+
+// An open device or replay. Opaque to C.
+typedef struct BrSession BrSession;
+
+typedef struct BrSessionInfo {
+  double sample_rate;
+  uint32_t channel_count;
+  // One of `BR_STATE_*`.
+  int32_t state;
+  uint64_t frames_received;
+  // Frames discarded because the host did not poll them in time.
+  uint64_t frames_dropped;
+} BrSessionInfo;
+
+typedef struct BrFrame {
+  // Seconds since the start of the stream (recording time for replays).
+  double time_secs;
+  uint32_t channel_count;
+} BrFrame;
+
+// Band powers averaged across channels, indexed by `BrBand`.
+typedef struct BrBandPowers {
+  double time_secs;
+  // Absolute power in µV².
+  double power[BR_BAND_COUNT];
+  // Share of the total, 0 to 1.
+  double relative[BR_BAND_COUNT];
+} BrBandPowers;
+
+typedef struct BrRewardRule {
+  // A `BrBand`.
+  uint32_t band;
+  // Relative band power, 0 to 1, that must be exceeded.
+  double threshold;
+  // Seconds above threshold per reward.
+  double hold_secs;
+} BrRewardRule;
+
+typedef struct BrReward {
+  double time_secs;
+  // A `BrBand`.
+  uint32_t band;
+  // Relative band power when the reward was given.
+  double score;
+  // 1 for the first reward of a run above threshold, then 2, 3...
+  uint32_t streak;
+} BrReward;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// `BR_ABI_VERSION` of the loaded library; hosts should refuse to run on a mismatch.
+uint32_t bluerain_abi_version(void);
+
+// Describes the last failure on this thread. Valid until the next failing call on the thread.
+const char *bluerain_last_error(void);
+
+// Finds a BlueRAIN headset (blocking for about a second) and starts streaming from it.
+// Only simulated headsets are opened: the scan runs against the demo adapter and the stream
+// comes from a simulated peripheral, so no radio is used. Use `bluerain_open_replay` for real data.
+//
+// # Safety
+// `address` is null (strongest headset) or a NUL-terminated string; `out` points to writable memory.
+int32_t bluerain_open_device(const char *address,
+                             struct BrSession **out);
+
+// Replays a session file (.brs), EDF or XDF recording. `speed` is a multiple of real time;
+// 0 plays as fast as possible.
+//
+// # Safety
+// `path` is a NUL-terminated string; `out` points to writable memory.
+int32_t bluerain_open_replay(const char *path, double speed, struct BrSession **out);
+
+// # Safety
+// `session` comes from `bluerain_open_*` and is not closed; `out` points to writable memory.
+int32_t bluerain_session_info(const struct BrSession *session, struct BrSessionInfo *out);
+
+// Label of channel `index`, or null if out of range. Valid until the session is closed.
+//
+// # Safety
+// `session` comes from `bluerain_open_*` and is not closed.
+const char *bluerain_channel_label(const struct BrSession *session, uint32_t index);
+
+// Takes the oldest queued frame: writes its header to `frame` and `channel_count` values (µV)
+// to `values`. Returns 1 if a frame was taken, 0 if none is queued.
+//
+// # Safety
+// `session` comes from `bluerain_open_*` and is not closed; `frame` points to writable memory;
+// `values` has room for `capacity` doubles.
+int32_t bluerain_poll_frame(const struct BrSession *session,
+                            struct BrFrame *frame,
+                            double *values,
+                            size_t capacity);
+
+// Takes the oldest queued band powers (computed 4 times a second over a 2-second window).
+// Returns 1 if taken, 0 if none is queued.
+//
+// # Safety
+// `session` comes from `bluerain_open_*` and is not closed; `out` points to writable memory.
+int32_t bluerain_poll_band_powers(const struct BrSession *session, struct BrBandPowers *out);
+
+// Replaces the neurofeedback reward rule (alpha above 0.4 for 0.5 s by default).
+//
+// # Safety
+// `session` comes from `bluerain_open_*` and is not closed; `rule` points to a valid rule.
+int32_t bluerain_set_reward_rule(const struct BrSession *session, const struct BrRewardRule *rule);
+
+// Takes the oldest neurofeedback reward. Returns 1 if taken, 0 if none is queued.
+//
+// # Safety
+// `session` comes from `bluerain_open_*` and is not closed; `out` points to writable memory.
+int32_t bluerain_poll_reward(const struct BrSession *session, struct BrReward *out);
+
+// Stops streaming and waits for the session thread. Queued data can still be polled.
+//
+// # Safety
+// `session` comes from `bluerain_open_*` and is not closed.
+int32_t bluerain_stop(const struct BrSession *session);
+
+// Stops the session if needed and frees it. Null is ignored.
+//
+// # Safety
+// `session` comes from `bluerain_open_*` and is not used afterwards.
+void bluerain_close(struct BrSession *session);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BLUERAIN_H */
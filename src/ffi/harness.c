@@ -0,0 +1,158 @@
+/*
+ * Exercises the BlueRAIN C ABI the way a game engine would: open, poll every frame, stop.
+ *
+ *   cc -I src/ffi src/ffi/harness.c -L target/debug -lbluerain_ffi -o bluerain_harness
+ *   bluerain record session.brs --duration 10
+ *   LD_LIBRARY_PATH=target/debug ./bluerain_harness session.brs
+ *
+ * Stops with exit status 1 at the first failed check.
+ */
+#include <stdio.h>
+#include <string.h>
+#include <time.h>
+
+#include "bluerain.h"
+
+#define MAX_VALUES 32
+
+#define CHECK(condition, ...)                                           \
+    do {                                                                \
+        if (!(condition)) {                                             \
+            fprintf(stderr, "FAIL %s:%d: ", __FILE__, __LINE__);        \
+            fprintf(stderr, __VA_ARGS__);                               \
+            fprintf(stderr, " (last error: %s)\n", bluerain_last_error()); \
+            return 1;                                                   \
+        }                                                               \
+    } while (0)
+
+static void sleep_ms(long ms) {
+    struct timespec delay = { ms / 1000, (ms % 1000) * 1000000L };
+    nanosleep(&delay, NULL);
+}
+
+struct Totals {
+    unsigned long frames;
+    unsigned long band_powers;
+    unsigned long rewards;
+};
+
+/* One "game frame": drain everything that arrived since the last one. */
+static int poll_all(BrSession *session, struct Totals *totals) {
+    BrFrame frame;
+    double values[MAX_VALUES];
+    BrBandPowers powers;
+    BrReward reward;
+    int status;
+
+    while ((status = bluerain_poll_frame(session, &frame, values, MAX_VALUES)) == 1) {
+        totals->frames++;
+    }
+    CHECK(status == 0, "poll_frame returned %d", status);
+    while ((status = bluerain_poll_band_powers(session, &powers)) == 1) {
+        totals->band_powers++;
+    }
+    CHECK(status == 0, "poll_band_powers returned %d", status);
+    while ((status = bluerain_poll_reward(session, &reward)) == 1) {
+        if (totals->rewards++ < 3) {
+            printf("  reward #%u at %.2fs: band %u at %.0f%%\n", reward.streak, reward.time_secs, reward.band, reward.score * 100.0);
+        }
+    }
+    CHECK(status == 0, "poll_reward returned %d", status);
+    return 0;
+}
+
+static int check_errors(void) {
+    BrSession *session = NULL;
+    BrRewardRule bad_rule = { 99, 0.5, 1.0 };
+
+    CHECK(bluerain_abi_version() == BR_ABI_VERSION, "ABI version %u, header has %d", bluerain_abi_version(), BR_ABI_VERSION);
+    CHECK(bluerain_open_replay("/nonexistent.brs", 1.0, &session) == BR_ERR_NOT_FOUND, "missing file not reported");
+    CHECK(strlen(bluerain_last_error()) > 0, "no error message");
+    CHECK(session == NULL, "session set on failure");
+    CHECK(bluerain_open_replay(NULL, 1.0, &session) == BR_ERR_INVALID_ARGUMENT, "null path accepted");
+    CHECK(bluerain_set_reward_rule(NULL, &bad_rule) == BR_ERR_INVALID_ARGUMENT, "null session accepted");
+    bluerain_close(NULL);
+    printf("error handling: ok\n");
+    return 0;
+}
+
+static int check_replay(const char *path) {
+    BrSession *session = NULL;
+    BrSessionInfo info;
+    struct Totals totals = { 0, 0, 0 };
+    /* Easy enough that the alpha-heavy demo recordings earn rewards. */
+    BrRewardRule rule = { BR_BAND_ALPHA, 0.3, 0.25 };
+    BrRewardRule bad_rule = { BR_BAND_ALPHA, 1.5, 0.25 };
+
+    CHECK(bluerain_open_replay(path, 20.0, &session) == BR_OK, "cannot open %s", path);
+    CHECK(bluerain_set_reward_rule(session, &bad_rule) == BR_ERR_INVALID_ARGUMENT, "threshold 1.5 accepted");
+    CHECK(bluerain_set_reward_rule(session, &rule) == BR_OK, "reward rule rejected");
+    CHECK(bluerain_session_info(session, &info) == BR_OK, "no session info");
+    CHECK(info.channel_count > 0 && info.channel_count <= MAX_VALUES, "%u channels", info.channel_count);
+    CHECK(bluerain_channel_label(session, 0) != NULL, "no label for channel 0");
+    CHECK(bluerain_channel_label(session, info.channel_count) == NULL, "label past the last channel");
+    printf("replay %s: %u channels at %.0f Hz, first %s\n", path, info.channel_count, info.sample_rate, bluerain_channel_label(session, 0));
+
+    do {
+        sleep_ms(16);
+        CHECK(bluerain_session_info(session, &info) == BR_OK, "no session info");
+        if (poll_all(session, &totals) != 0) {
+            return 1;
+        }
+    } while (info.state == BR_STATE_STREAMING);
+    /* Anything that arrived between the last poll and the end. */
+    if (poll_all(session, &totals) != 0) {
+        return 1;
+    }
+
+    CHECK(info.state == BR_STATE_FINISHED, "replay ended in state %d", info.state);
+    CHECK(totals.frames + info.frames_dropped == info.frames_received,
+          "%lu polled + %llu dropped != %llu received", totals.frames,
+          (unsigned long long)info.frames_dropped, (unsigned long long)info.frames_received);
+    CHECK(totals.band_powers > 0, "no band powers");
+    printf("replay: %lu frames, %lu band power updates, %lu rewards\n", totals.frames, totals.band_powers, totals.rewards);
+    bluerain_close(session);
+    return 0;
+}
+
+static int check_device(void) {
+    BrSession *session = NULL;
+    BrSessionInfo info;
+    BrBandPowers powers;
+    struct Totals totals = { 0, 0, 0 };
+    int i;
+
+    CHECK(bluerain_open_device(NULL, &session) == BR_OK, "no headset");
+    for (i = 0; i < 150; i++) { /* about 2.5 s at 60 fps */
+        sleep_ms(16);
+        if (poll_all(session, &totals) != 0) {
+            return 1;
+        }
+    }
+    CHECK(bluerain_stop(session) == BR_OK, "stop failed");
+    CHECK(bluerain_session_info(session, &info) == BR_OK, "no session info");
+    CHECK(info.state == BR_STATE_FINISHED, "stopped session in state %d", info.state);
+    CHECK(totals.frames > 0, "no frames from the headset");
+    CHECK(totals.band_powers > 0, "no band powers from the headset");
+    /* Stopping keeps what was queued; after draining, polls report nothing. */
+    if (poll_all(session, &totals) != 0) {
+        return 1;
+    }
+    CHECK(bluerain_poll_band_powers(session, &powers) == 0, "data after drain");
+    printf("device: %u channels, %lu frames, %lu band power updates, %lu rewards\n",
+           info.channel_count, totals.frames, totals.band_powers, totals.rewards);
+    bluerain_close(session);
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <recording.brs|recording.edf>\n", argv[0]);
+        return 2;
+    }
+    if (check_errors() != 0 || check_replay(argv[1]) != 0 || check_device() != 0) {
+        return 1;
+    }
+    printf("all checks passed\n");
+    return 0;
+}
@@ -0,0 +1,495 @@
+use std::f64::consts::PI;
+
+/// Second-order IIR section in transposed direct form II (RBJ cookbook coefficients).
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn normalized(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn notch(sample_rate: f64, frequency: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        Biquad::normalized(1.0, -2.0 * cos_w0, 1.0, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+    }
+
+    pub fn lowpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        let b1 = 1.0 - cos_w0;
+        Biquad::normalized(b1 / 2.0, b1, b1 / 2.0, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+    }
+
+    pub fn highpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        let b1 = 1.0 + cos_w0;
+        Biquad::normalized(b1 / 2.0, -b1, b1 / 2.0, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+    }
+
+    /// First-order low-pass (bilinear transform) for odd Butterworth orders.
+    pub fn first_order_lowpass(sample_rate: f64, cutoff: f64) -> Self {
+        let k = (PI * cutoff / sample_rate).tan();
+        Biquad::normalized(k, k, 0.0, 1.0 + k, k - 1.0, 0.0)
+    }
+
+    pub fn first_order_highpass(sample_rate: f64, cutoff: f64) -> Self {
+        let k = (PI * cutoff / sample_rate).tan();
+        Biquad::normalized(1.0, -1.0, 0.0, 1.0 + k, k - 1.0, 0.0)
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    /// Sets the state a constant input `x` settles to and returns the settled output. A section
+    /// with a pole at DC has no steady state and is reset instead.
+    fn settle(&mut self, x: f64) -> f64 {
+        let denominator = 1.0 + self.a1 + self.a2;
+        if denominator.abs() < 1e-12 {
+            self.reset();
+            return x;
+        }
+        let y = x * (self.b0 + self.b1 + self.b2) / denominator;
+        self.z1 = y - self.b0 * x;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    /// Largest pole magnitude: how slowly the section forgets its past.
+    fn pole_radius(&self) -> f64 {
+        let discriminant = self.a1 * self.a1 - 4.0 * self.a2;
+        if discriminant < 0.0 {
+            self.a2.sqrt()
+        } else {
+            (self.a1.abs() + discriminant.sqrt()) / 2.0
+        }
+    }
+}
+
+/// One-pole DC blocker: `y[n] = x[n] - x[n-1] + pole * y[n-1]`.
+#[derive(Debug, Clone, Copy)]
+pub struct DcBlocker {
+    pole: f64,
+    x1: f64,
+    y1: f64,
+}
+
+impl DcBlocker {
+    pub fn new(pole: f64) -> Self {
+        DcBlocker { pole, x1: 0.0, y1: 0.0 }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = x - self.x1 + self.pole * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.y1 = 0.0;
+    }
+
+    /// A constant input settles to zero output.
+    fn settle(&mut self, x: f64) -> f64 {
+        self.x1 = x;
+        self.y1 = 0.0;
+        0.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterSpec {
+    /// Notch at the mains frequency and its harmonics below Nyquist.
+    LineNoise { mains_hz: f64, harmonics: usize, q: f64 },
+    HighPass { cutoff: f64, order: usize },
+    LowPass { cutoff: f64, order: usize },
+    BandPass { low: f64, high: f64, order: usize },
+    DcRemoval { pole: f64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Section {
+    Biquad(Biquad),
+    Dc(DcBlocker),
+}
+
+/// Q of each second-order section of an `order`-pole Butterworth filter.
+fn butterworth_qs(order: usize) -> Vec<f64> {
+    (0..order / 2)
+        .map(|k| 1.0 / (2.0 * ((2 * k + 1) as f64 * PI / (2 * order) as f64).sin()))
+        .collect()
+}
+
+fn butterworth(sample_rate: f64, cutoff: f64, order: usize, highpass: bool) -> Result<Vec<Section>, String> {
+    if order == 0 {
+        return Err("Butterworth filter order must be at least 1".to_string());
+    }
+    let mut sections: Vec<Section> = butterworth_qs(order)
+        .into_iter()
+        .map(|q| {
+            Section::Biquad(if highpass {
+                Biquad::highpass(sample_rate, cutoff, q)
+            } else {
+                Biquad::lowpass(sample_rate, cutoff, q)
+            })
+        })
+        .collect();
+    if order % 2 == 1 {
+        sections.push(Section::Biquad(if highpass {
+            Biquad::first_order_highpass(sample_rate, cutoff)
+        } else {
+            Biquad::first_order_lowpass(sample_rate, cutoff)
+        }));
+    }
+    Ok(sections)
+}
+
+/// A cascade of filter sections for a single channel. State persists between calls.
+#[derive(Debug, Clone)]
+pub struct FilterChain {
+    sections: Vec<Section>,
+}
+
+impl FilterChain {
+    pub fn new(specs: &[FilterSpec], sample_rate: f64) -> Result<Self, String> {
+        let nyquist = sample_rate / 2.0;
+        let check = |frequency: f64| {
+            if frequency > 0.0 && frequency < nyquist {
+                Ok(())
+            } else {
+                Err(format!("Cutoff {} Hz is outside (0, {}) Hz", frequency, nyquist))
+            }
+        };
+
+        let mut sections = vec![];
+        for spec in specs {
+            match *spec {
+                FilterSpec::LineNoise { mains_hz, harmonics, q } => {
+                    check(mains_hz)?;
+                    for harmonic in 1..=harmonics.max(1) {
+                        let frequency = mains_hz * harmonic as f64;
+                        if frequency < nyquist {
+                            sections.push(Section::Biquad(Biquad::notch(sample_rate, frequency, q * harmonic as f64)));
+                        }
+                    }
+                }
+                FilterSpec::HighPass { cutoff, order } => {
+                    check(cutoff)?;
+                    sections.extend(butterworth(sample_rate, cutoff, order, true)?);
+                }
+                FilterSpec::LowPass { cutoff, order } => {
+                    check(cutoff)?;
+                    sections.extend(butterworth(sample_rate, cutoff, order, false)?);
+                }
+                FilterSpec::BandPass { low, high, order } => {
+                    check(low)?;
+                    check(high)?;
+                    if low >= high {
+                        return Err(format!("Band-pass low edge {} Hz must be below high edge {} Hz", low, high));
+                    }
+                    sections.extend(butterworth(sample_rate, low, order, true)?);
+                    sections.extend(butterworth(sample_rate, high, order, false)?);
+                }
+                FilterSpec::DcRemoval { pole } => sections.push(Section::Dc(DcBlocker::new(pole))),
+            }
+        }
+        Ok(FilterChain { sections })
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        self.sections.iter_mut().fold(x, |value, section| match section {
+            Section::Biquad(biquad) => biquad.process(value),
+            Section::Dc(dc) => dc.process(value),
+        })
+    }
+
+    pub fn process_in_place(&mut self, samples: &mut [f64]) {
+        for sample in samples.iter_mut() {
+            *sample = self.process(*sample);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for section in &mut self.sections {
+            match section {
+                Section::Biquad(biquad) => biquad.reset(),
+                Section::Dc(dc) => dc.reset(),
+            }
+        }
+    }
+
+    /// Puts every section in the state a constant input `x` settles to, so a signal starting
+    /// at `x` does not ring as if it had jumped from zero.
+    fn settle(&mut self, x: f64) {
+        self.sections.iter_mut().fold(x, |value, section| match section {
+            Section::Biquad(biquad) => biquad.settle(value),
+            Section::Dc(dc) => dc.settle(value),
+        });
+    }
+
+    /// Samples the slowest section takes to decay by a factor of e.
+    fn longest_time_constant(&self) -> f64 {
+        self.sections
+            .iter()
+            .map(|section| match section {
+                Section::Biquad(biquad) => biquad.pole_radius(),
+                Section::Dc(dc) => dc.pole.abs(),
+            })
+            .map(|radius| if radius > 0.0 && radius < 1.0 { -1.0 / radius.ln() } else { 0.0 })
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Per-channel streaming filters for live acquisition.
+#[derive(Debug, Clone)]
+pub struct FilterBank {
+    channels: Vec<FilterChain>,
+}
+
+impl FilterBank {
+    pub fn new(specs: &[FilterSpec], sample_rate: f64, channel_count: usize) -> Result<Self, String> {
+        let chain = FilterChain::new(specs, sample_rate)?;
+        Ok(FilterBank {
+            channels: vec![chain; channel_count],
+        })
+    }
+
+    /// DC removal, mains notch with harmonics and a 1-40 Hz 4th-order band-pass.
+    pub fn eeg_default(sample_rate: f64, mains_hz: f64, channel_count: usize) -> Result<Self, String> {
+        FilterBank::new(
+            &[
+                FilterSpec::DcRemoval { pole: 0.995 },
+                FilterSpec::LineNoise { mains_hz, harmonics: 3, q: 30.0 },
+                FilterSpec::BandPass { low: 1.0, high: 40.0, order: 4 },
+            ],
+            sample_rate,
+            channel_count,
+        )
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Filters one multichannel frame in place.
+    pub fn process_frame(&mut self, frame: &mut [f64]) {
+        for (chain, sample) in self.channels.iter_mut().zip(frame.iter_mut()) {
+            *sample = chain.process(*sample);
+        }
+    }
+
+    /// Filters a chunk of consecutive samples from one channel in place.
+    pub fn process_channel(&mut self, channel: usize, samples: &mut [f64]) {
+        if let Some(chain) = self.channels.get_mut(channel) {
+            chain.process_in_place(samples);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for chain in &mut self.channels {
+            chain.reset();
+        }
+    }
+}
+
+/// Zero-phase forward-backward filtering of a stored signal, for offline re-analysis.
+/// To keep edge transients out of the result, the signal is padded at both ends with an odd
+/// reflection three times the filter's longest time constant (or the whole signal, if
+/// shorter), and each pass starts from the state its first sample settles to.
+pub fn filtfilt(specs: &[FilterSpec], sample_rate: f64, signal: &[f64]) -> Result<Vec<f64>, String> {
+    let mut chain = FilterChain::new(specs, sample_rate)?;
+    if signal.len() < 2 {
+        return Ok(signal.to_vec());
+    }
+
+    let pad = ((3.0 * chain.longest_time_constant()).ceil() as usize).clamp(1, signal.len() - 1);
+    let first = signal[0];
+    let last = signal[signal.len() - 1];
+    let mut padded: Vec<f64> = Vec::with_capacity(signal.len() + 2 * pad);
+    padded.extend((1..=pad).rev().map(|i| 2.0 * first - signal[i]));
+    padded.extend_from_slice(signal);
+    padded.extend((1..=pad).map(|i| 2.0 * last - signal[signal.len() - 1 - i]));
+
+    chain.settle(padded[0]);
+    chain.process_in_place(&mut padded);
+    padded.reverse();
+    chain.settle(padded[0]);
+    chain.process_in_place(&mut padded);
+    padded.reverse();
+
+    Ok(padded[pad..pad + signal.len()].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 250.0;
+
+    fn sine(frequency: f64, seconds: f64, phase: f64) -> Vec<f64> {
+        (0..(RATE * seconds) as usize)
+            .map(|n| (2.0 * PI * frequency * n as f64 / RATE + phase).sin())
+            .collect()
+    }
+
+    fn rms(samples: &[f64]) -> f64 {
+        (samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    /// RMS gain of a chain on a steady sine, skipping the first two seconds of settling.
+    fn steady_gain(specs: &[FilterSpec], sample_rate: f64, frequency: f64) -> f64 {
+        let mut chain = FilterChain::new(specs, sample_rate).unwrap();
+        let mut samples: Vec<f64> =
+            (0..(sample_rate * 6.0) as usize).map(|n| (2.0 * PI * frequency * n as f64 / sample_rate).sin()).collect();
+        let settled = (sample_rate * 2.0) as usize;
+        let input = rms(&samples[settled..]);
+        chain.process_in_place(&mut samples);
+        rms(&samples[settled..]) / input
+    }
+
+    #[test]
+    fn order_zero_is_rejected() {
+        for spec in [
+            FilterSpec::HighPass { cutoff: 1.0, order: 0 },
+            FilterSpec::LowPass { cutoff: 40.0, order: 0 },
+            FilterSpec::BandPass { low: 1.0, high: 40.0, order: 0 },
+        ] {
+            let error = FilterChain::new(&[spec], RATE).unwrap_err();
+            assert!(error.contains("order"), "{}", error);
+        }
+        assert!(FilterChain::new(&[FilterSpec::LowPass { cutoff: 40.0, order: 1 }], RATE).is_ok());
+    }
+
+    #[test]
+    fn chunked_filtering_equals_one_shot() {
+        let mut signal = sine(10.0, 4.0, 0.3);
+        for (n, (a, b)) in sine(50.0, 4.0, 0.0).iter().zip(sine(0.5, 4.0, 1.0)).enumerate() {
+            signal[n] += 0.5 * a + 2.0 * b + if n % 97 == 0 { 5.0 } else { 0.0 };
+        }
+
+        let mut one_shot = FilterBank::eeg_default(RATE, 50.0, 1).unwrap();
+        let mut expected = signal.clone();
+        one_shot.process_channel(0, &mut expected);
+
+        let mut chunked = FilterBank::eeg_default(RATE, 50.0, 2).unwrap();
+        let mut actual = signal.clone();
+        let mut offset = 0;
+        for size in [1, 7, 64, 3, 250, 13].iter().cycle() {
+            let end = (offset + size).min(actual.len());
+            chunked.process_channel(1, &mut actual[offset..end]);
+            offset = end;
+            if offset == actual.len() {
+                break;
+            }
+        }
+        assert_eq!(actual, expected);
+
+        let mut framed = FilterBank::eeg_default(RATE, 50.0, 1).unwrap();
+        let by_frame: Vec<f64> = signal
+            .iter()
+            .map(|&x| {
+                let mut frame = [x];
+                framed.process_frame(&mut frame);
+                frame[0]
+            })
+            .collect();
+        assert_eq!(by_frame, expected);
+    }
+
+    #[test]
+    fn notch_attenuates_mains_and_its_harmonics() {
+        for mains in [50.0, 60.0] {
+            let specs = [FilterSpec::LineNoise { mains_hz: mains, harmonics: 2, q: 30.0 }];
+            assert!(steady_gain(&specs, 500.0, mains) < 0.05, "{} Hz passed", mains);
+            assert!(steady_gain(&specs, 500.0, 2.0 * mains) < 0.05, "{} Hz passed", 2.0 * mains);
+            assert!(steady_gain(&specs, 500.0, 10.0) > 0.95, "10 Hz attenuated by the {} Hz notch", mains);
+            assert!(steady_gain(&specs, RATE, mains) < 0.05, "{} Hz passed at {} Hz", mains, RATE);
+        }
+    }
+
+    #[test]
+    fn filtfilt_is_zero_phase() {
+        let specs = [FilterSpec::BandPass { low: 1.0, high: 40.0, order: 4 }];
+        let signal = sine(5.0, 4.0, 0.7);
+        let filtered = filtfilt(&specs, RATE, &signal).unwrap();
+        assert_eq!(filtered.len(), signal.len());
+
+        let middle = 250..750;
+        let error = rms(&middle.clone().map(|n| filtered[n] - signal[n]).collect::<Vec<_>>());
+        assert!(error < 0.02, "residual {}", error);
+
+        let lag = (-5i64..=5)
+            .max_by(|&a, &b| {
+                let correlation = |lag: i64| -> f64 {
+                    middle.clone().map(|n| filtered[n] * signal[(n as i64 + lag) as usize]).sum()
+                };
+                correlation(a).total_cmp(&correlation(b))
+            })
+            .unwrap();
+        assert_eq!(lag, 0);
+
+        let mut one_way = signal.clone();
+        FilterChain::new(&specs, RATE).unwrap().process_in_place(&mut one_way);
+        let one_way_error = rms(&middle.clone().map(|n| one_way[n] - signal[n]).collect::<Vec<_>>());
+        assert!(one_way_error > 10.0 * error, "single pass residual {}", one_way_error);
+    }
+
+    #[test]
+    fn filtfilt_has_no_edge_transients_on_constant_input() {
+        let signal = vec![3.0; 500];
+
+        let low = filtfilt(&[FilterSpec::LowPass { cutoff: 5.0, order: 4 }], RATE, &signal).unwrap();
+        assert!(low.iter().all(|x| (x - 3.0).abs() < 1e-6), "{:?}", &low[..5]);
+
+        let high = filtfilt(&[FilterSpec::HighPass { cutoff: 1.0, order: 2 }], RATE, &signal).unwrap();
+        assert!(high.iter().all(|x| x.abs() < 1e-6), "{:?}", &high[..5]);
+
+        let dc = filtfilt(&[FilterSpec::DcRemoval { pole: 0.995 }], RATE, &signal).unwrap();
+        assert!(dc.iter().all(|x| x.abs() < 1e-6), "{:?}", &dc[..5]);
+    }
+
+    #[test]
+    fn filtfilt_pads_by_the_slowest_section() {
+        let fast = FilterChain::new(&[FilterSpec::LowPass { cutoff: 40.0, order: 2 }], RATE).unwrap();
+        let slow = FilterChain::new(&[FilterSpec::HighPass { cutoff: 0.5, order: 2 }], RATE).unwrap();
+        assert!(slow.longest_time_constant() > 10.0 * fast.longest_time_constant());
+        assert!(fast.longest_time_constant() > 0.0);
+
+        assert_eq!(filtfilt(&[FilterSpec::LowPass { cutoff: 5.0, order: 2 }], RATE, &[]).unwrap(), Vec::<f64>::new());
+        assert_eq!(filtfilt(&[FilterSpec::LowPass { cutoff: 5.0, order: 2 }], RATE, &[1.5]).unwrap(), vec![1.5]);
+        let short = filtfilt(&[FilterSpec::HighPass { cutoff: 0.5, order: 2 }], RATE, &[1.0, 2.0, 3.0]).unwrap();
+        assert!(short.iter().all(|x| x.is_finite()));
+    }
+}
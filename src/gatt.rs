@@ -0,0 +1,531 @@
+//! BlueRAIN GATT service and wire format.
+//!
+//! | Characteristic | UUID                                   | Properties   | Payload            |
+//! |----------------|----------------------------------------|--------------|--------------------|
+//! | Service        | `b1ea0001-5c3d-4f0e-9a7b-2d6e8f1c0a00` |              |                    |
+//! | Data           | `b1ea0002-5c3d-4f0e-9a7b-2d6e8f1c0a00` | notify       | sample packet      |
+//! | Control        | `b1ea0003-5c3d-4f0e-9a7b-2d6e8f1c0a00` | write        | control command    |
+//! | Status         | `b1ea0004-5c3d-4f0e-9a7b-2d6e8f1c0a00` | read, notify | status packet      |
+//!
+//! Every packet ends in a CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF) of the preceding
+//! bytes, little-endian. Multi-byte integers are little-endian except the 24-bit samples,
+//! which are big-endian two's complement as produced by the ADS1299 front end.
+//!
+//! Sample packet: `0x01 | seq:u16 | channels:u8 | frames:u8 | frames*channels*i24 | crc:u16`,
+//! frames interleaved (all channels of frame 0, then frame 1, ...).
+//!
+//! Control command: `opcode:u8 | args | crc:u16`, see [`ControlCommand`].
+//!
+//! Status packet: `0x02 | streaming:u8 | sample_rate:u16 | channel_mask:u32 | gain:u8 | battery:u8 | error:u8 | crc:u16`.
+
+use std::fmt;
+use rand::Rng;
+
+pub const SERVICE_UUID: &str = "b1ea0001-5c3d-4f0e-9a7b-2d6e8f1c0a00";
+pub const DATA_CHARACTERISTIC_UUID: &str = "b1ea0002-5c3d-4f0e-9a7b-2d6e8f1c0a00";
+pub const CONTROL_CHARACTERISTIC_UUID: &str = "b1ea0003-5c3d-4f0e-9a7b-2d6e8f1c0a00";
+pub const STATUS_CHARACTERISTIC_UUID: &str = "b1ea0004-5c3d-4f0e-9a7b-2d6e8f1c0a00";
+
+const SAMPLE_PACKET_TYPE: u8 = 0x01;
+const STATUS_PACKET_TYPE: u8 = 0x02;
+const SAMPLE_HEADER_LEN: usize = 5;
+const STATUS_PACKET_LEN: usize = 11 + 2;
+pub const MAX_CHANNELS: u8 = 32;
+/// Largest notification payload with the negotiated 247-byte ATT MTU.
+pub const MAX_PAYLOAD: usize = 244;
+
+/// PGA gain a headset powers up with.
+pub const DEFAULT_GAIN: u8 = 24;
+/// Microvolts per LSB at the default PGA gain of 24 (4.5 V reference, 24-bit ADC).
+pub const DEFAULT_LSB_MICROVOLTS: f32 = 4.5 / 24.0 / 8_388_607.0 * 1_000_000.0;
+
+/// Microvolts per LSB at a PGA gain; a gain of 0 is applied as 1, as the headset does.
+pub fn lsb_microvolts(gain: u8) -> f32 {
+    4.5 / gain.max(1) as f32 / 8_388_607.0 * 1_000_000.0
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodecError {
+    TooShort(usize),
+    UnexpectedType(u8),
+    BadCrc { expected: u16, found: u16 },
+    BadLength { expected: usize, found: usize },
+    InvalidChannelCount(u8),
+    TooManyFrames { max: usize, found: usize },
+    UnknownOpcode(u8),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::TooShort(len) => write!(f, "packet too short ({} bytes)", len),
+            CodecError::UnexpectedType(kind) => write!(f, "unexpected packet type 0x{:02x}", kind),
+            CodecError::BadCrc { expected, found } => write!(f, "CRC mismatch: expected 0x{:04x}, found 0x{:04x}", expected, found),
+            CodecError::BadLength { expected, found } => write!(f, "expected {} bytes, found {}", expected, found),
+            CodecError::InvalidChannelCount(count) => write!(f, "invalid channel count {}", count),
+            CodecError::TooManyFrames { max, found } => write!(f, "{} frames do not fit in one packet (max {})", found, max),
+            CodecError::UnknownOpcode(opcode) => write!(f, "unknown control opcode 0x{:02x}", opcode),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn append_crc(mut packet: Vec<u8>) -> Vec<u8> {
+    let crc = crc16(&packet);
+    packet.extend_from_slice(&crc.to_le_bytes());
+    packet
+}
+
+/// Checks the trailing CRC and returns the packet body without it.
+fn check_crc(packet: &[u8]) -> Result<&[u8], CodecError> {
+    if packet.len() < 3 {
+        return Err(CodecError::TooShort(packet.len()));
+    }
+    let (body, crc) = packet.split_at(packet.len() - 2);
+    let found = u16::from_le_bytes([crc[0], crc[1]]);
+    let expected = crc16(body);
+    if expected != found {
+        return Err(CodecError::BadCrc { expected, found });
+    }
+    Ok(body)
+}
+
+pub fn pack_i24(value: i32, out: &mut Vec<u8>) {
+    let clamped = value.clamp(-(1 << 23), (1 << 23) - 1);
+    out.extend_from_slice(&clamped.to_be_bytes()[1..]);
+}
+
+pub fn unpack_i24(bytes: &[u8]) -> i32 {
+    // Place the three bytes in the top of an i32 and shift back down to sign-extend.
+    i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplePacket {
+    pub sequence: u16,
+    pub channel_count: u8,
+    /// Raw ADC counts, one `Vec` per frame with `channel_count` entries each.
+    pub frames: Vec<Vec<i32>>,
+}
+
+impl SamplePacket {
+    pub fn max_frames(channel_count: u8) -> usize {
+        (MAX_PAYLOAD - SAMPLE_HEADER_LEN - 2) / (channel_count.max(1) as usize * 3)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        if self.channel_count == 0 || self.channel_count > MAX_CHANNELS {
+            return Err(CodecError::InvalidChannelCount(self.channel_count));
+        }
+        // Also keeps the frame count within its single header byte.
+        let max = Self::max_frames(self.channel_count);
+        if self.frames.len() > max {
+            return Err(CodecError::TooManyFrames { max, found: self.frames.len() });
+        }
+        let mut packet = Vec::with_capacity(SAMPLE_HEADER_LEN + self.frames.len() * self.channel_count as usize * 3 + 2);
+        packet.push(SAMPLE_PACKET_TYPE);
+        packet.extend_from_slice(&self.sequence.to_le_bytes());
+        packet.push(self.channel_count);
+        packet.push(self.frames.len() as u8);
+        for frame in &self.frames {
+            if frame.len() != self.channel_count as usize {
+                return Err(CodecError::BadLength { expected: self.channel_count as usize, found: frame.len() });
+            }
+            for sample in frame {
+                pack_i24(*sample, &mut packet);
+            }
+        }
+        Ok(append_crc(packet))
+    }
+
+    pub fn decode(packet: &[u8]) -> Result<Self, CodecError> {
+        let body = check_crc(packet)?;
+        if body.len() < SAMPLE_HEADER_LEN {
+            return Err(CodecError::TooShort(packet.len()));
+        }
+        if body[0] != SAMPLE_PACKET_TYPE {
+            return Err(CodecError::UnexpectedType(body[0]));
+        }
+        let sequence = u16::from_le_bytes([body[1], body[2]]);
+        let channel_count = body[3];
+        if channel_count == 0 || channel_count > MAX_CHANNELS {
+            return Err(CodecError::InvalidChannelCount(channel_count));
+        }
+        let frame_count = body[4] as usize;
+        let expected = SAMPLE_HEADER_LEN + frame_count * channel_count as usize * 3;
+        if body.len() != expected {
+            return Err(CodecError::BadLength { expected, found: body.len() });
+        }
+
+        let frames = body[SAMPLE_HEADER_LEN..]
+            .chunks(channel_count as usize * 3)
+            .map(|frame| frame.chunks(3).map(unpack_i24).collect())
+            .collect();
+        Ok(SamplePacket { sequence, channel_count, frames })
+    }
+
+    pub fn channel_microvolts(&self, channel: usize, lsb_microvolts: f32) -> Vec<f32> {
+        self.frames.iter().map(|frame| frame[channel] as f32 * lsb_microvolts).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlCommand {
+    StartStream,
+    StopStream,
+    SetSampleRate(u16),
+    SetChannelMask(u32),
+    SetGain(u8),
+    RequestStatus,
+}
+
+impl ControlCommand {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = vec![];
+        match self {
+            ControlCommand::StartStream => packet.push(0x01),
+            ControlCommand::StopStream => packet.push(0x02),
+            ControlCommand::SetSampleRate(rate) => {
+                packet.push(0x03);
+                packet.extend_from_slice(&rate.to_le_bytes());
+            }
+            ControlCommand::SetChannelMask(mask) => {
+                packet.push(0x04);
+                packet.extend_from_slice(&mask.to_le_bytes());
+            }
+            ControlCommand::SetGain(gain) => packet.extend_from_slice(&[0x05, *gain]),
+            ControlCommand::RequestStatus => packet.push(0x06),
+        }
+        append_crc(packet)
+    }
+
+    pub fn decode(packet: &[u8]) -> Result<Self, CodecError> {
+        let body = check_crc(packet)?;
+        let args = &body[1..];
+        let expect_args = |len: usize| {
+            if args.len() == len {
+                Ok(())
+            } else {
+                Err(CodecError::BadLength { expected: len + 3, found: packet.len() })
+            }
+        };
+        match body[0] {
+            0x01 => expect_args(0).map(|_| ControlCommand::StartStream),
+            0x02 => expect_args(0).map(|_| ControlCommand::StopStream),
+            0x03 => expect_args(2).map(|_| ControlCommand::SetSampleRate(u16::from_le_bytes([args[0], args[1]]))),
+            0x04 => expect_args(4).map(|_| ControlCommand::SetChannelMask(u32::from_le_bytes([args[0], args[1], args[2], args[3]]))),
+            0x05 => expect_args(1).map(|_| ControlCommand::SetGain(args[0])),
+            0x06 => expect_args(0).map(|_| ControlCommand::RequestStatus),
+            opcode => Err(CodecError::UnknownOpcode(opcode)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusPacket {
+    pub streaming: bool,
+    pub sample_rate: u16,
+    pub channel_mask: u32,
+    pub gain: u8,
+    pub battery_level: u8,
+    pub error_code: u8,
+}
+
+impl StatusPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = vec![STATUS_PACKET_TYPE, self.streaming as u8];
+        packet.extend_from_slice(&self.sample_rate.to_le_bytes());
+        packet.extend_from_slice(&self.channel_mask.to_le_bytes());
+        packet.extend_from_slice(&[self.gain, self.battery_level, self.error_code]);
+        append_crc(packet)
+    }
+
+    pub fn decode(packet: &[u8]) -> Result<Self, CodecError> {
+        if packet.len() != STATUS_PACKET_LEN {
+            return Err(CodecError::BadLength { expected: STATUS_PACKET_LEN, found: packet.len() });
+        }
+        let body = check_crc(packet)?;
+        if body[0] != STATUS_PACKET_TYPE {
+            return Err(CodecError::UnexpectedType(body[0]));
+        }
+        Ok(StatusPacket {
+            streaming: body[1] != 0,
+            sample_rate: u16::from_le_bytes([body[2], body[3]]),
+            channel_mask: u32::from_le_bytes([body[4], body[5], body[6], body[7]]),
+            gain: body[8],
+            battery_level: body[9],
+            error_code: body[10],
+        })
+    }
+}
+
+/// Tracks the wrapping sequence counter of incoming sample packets and counts the gaps.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    last: Option<u16>,
+    pub received: u64,
+    pub lost: u64,
+}
+
+impl SequenceTracker {
+    /// Returns how many packets were missed just before this one.
+    pub fn observe(&mut self, sequence: u16) -> u16 {
+        let missed = match self.last {
+            Some(last) => sequence.wrapping_sub(last).wrapping_sub(1),
+            None => 0,
+        };
+        self.last = Some(sequence);
+        self.received += 1;
+        self.lost += missed as u64;
+        missed
+    }
+}
+
+/// A peripheral that speaks the BlueRAIN GATT protocol, emitting alpha-band sine waves plus noise.
+#[derive(Debug)]
+pub struct SimulatedPeripheral {
+    streaming: bool,
+    sample_rate: u16,
+    channel_mask: u32,
+    gain: u8,
+    sequence: u16,
+    sample_clock: u64,
+}
+
+impl SimulatedPeripheral {
+    /// `channel_count` is clamped to `1..=MAX_CHANNELS`, like a channel mask written later.
+    pub fn new(channel_count: u8) -> Self {
+        let channel_count = channel_count.clamp(1, MAX_CHANNELS);
+        SimulatedPeripheral {
+            streaming: false,
+            sample_rate: 250,
+            channel_mask: if channel_count >= 32 { u32::MAX } else { (1u32 << channel_count) - 1 },
+            gain: DEFAULT_GAIN,
+            sequence: 0,
+            sample_clock: 0,
+        }
+    }
+
+    pub fn channel_count(&self) -> u8 {
+        self.channel_mask.count_ones() as u8
+    }
+
+    pub fn sample_rate(&self) -> u16 {
+        self.sample_rate
+    }
+
+    /// Handles a write to the control characteristic; returns a status notification if requested.
+    pub fn write_control(&mut self, packet: &[u8]) -> Result<Option<Vec<u8>>, CodecError> {
+        match ControlCommand::decode(packet)? {
+            ControlCommand::StartStream => self.streaming = true,
+            ControlCommand::StopStream => self.streaming = false,
+            ControlCommand::SetSampleRate(rate) => self.sample_rate = rate.max(1),
+            ControlCommand::SetChannelMask(mask) => self.channel_mask = mask.max(1),
+            ControlCommand::SetGain(gain) => self.gain = gain.max(1),
+            ControlCommand::RequestStatus => return Ok(Some(self.read_status())),
+        }
+        Ok(None)
+    }
+
+    pub fn read_status(&self) -> Vec<u8> {
+        StatusPacket {
+            streaming: self.streaming,
+            sample_rate: self.sample_rate,
+            channel_mask: self.channel_mask,
+            gain: self.gain,
+            battery_level: 100,
+            error_code: 0,
+        }
+        .encode()
+    }
+
+    /// Next data notification, or `None` while the stream is stopped.
+    pub fn next_notification(&mut self) -> Option<Vec<u8>> {
+        if !self.streaming {
+            return None;
+        }
+        let channel_count = self.channel_count();
+        let lsb = lsb_microvolts(self.gain);
+        let mut rng = rand::thread_rng();
+        let frames = (0..SamplePacket::max_frames(channel_count))
+            .map(|_| {
+                let t = self.sample_clock as f32 / self.sample_rate as f32;
+                self.sample_clock += 1;
+                (0..channel_count)
+                    .map(|channel| {
+                        let microvolts = 20.0 * (2.0 * std::f32::consts::PI * (10.0 + channel as f32 * 0.5) * t).sin()
+                            + rng.gen_range(-5.0..5.0);
+                        (microvolts / lsb) as i32
+                    })
+                    .collect()
+            })
+            .collect();
+        let packet = SamplePacket {
+            sequence: self.sequence,
+            channel_count,
+            frames,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        packet.encode().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_packet(channel_count: u8, frame_count: usize) -> SamplePacket {
+        SamplePacket {
+            sequence: 0xBEEF,
+            channel_count,
+            frames: (0..frame_count)
+                .map(|frame| (0..channel_count as i32).map(|channel| (frame as i32 - 3) * 100_000 + channel).collect())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn i24_round_trip_and_clamp() {
+        for value in [0, 1, -1, 123_456, -123_456, (1 << 23) - 1, -(1 << 23)] {
+            let mut bytes = vec![];
+            pack_i24(value, &mut bytes);
+            assert_eq!(bytes.len(), 3);
+            assert_eq!(unpack_i24(&bytes), value);
+        }
+        let mut bytes = vec![];
+        pack_i24(i32::MAX, &mut bytes);
+        assert_eq!(unpack_i24(&bytes), (1 << 23) - 1);
+    }
+
+    #[test]
+    fn sample_packet_round_trip() {
+        for channel_count in [1, 8, MAX_CHANNELS] {
+            let packet = sample_packet(channel_count, SamplePacket::max_frames(channel_count));
+            let bytes = packet.encode().unwrap();
+            assert!(bytes.len() <= MAX_PAYLOAD);
+            assert_eq!(SamplePacket::decode(&bytes), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn sample_packet_rejects_too_many_frames() {
+        let max = SamplePacket::max_frames(1);
+        assert_eq!(sample_packet(1, max + 1).encode(), Err(CodecError::TooManyFrames { max, found: max + 1 }));
+        // Would wrap the frame count byte to 0 if unchecked.
+        assert!(sample_packet(1, 256).encode().is_err());
+    }
+
+    #[test]
+    fn sample_packet_rejects_bad_input() {
+        assert_eq!(sample_packet(0, 1).encode(), Err(CodecError::InvalidChannelCount(0)));
+        let mut ragged = sample_packet(4, 2);
+        ragged.frames[1].pop();
+        assert_eq!(ragged.encode(), Err(CodecError::BadLength { expected: 4, found: 3 }));
+
+        let mut bytes = sample_packet(4, 2).encode().unwrap();
+        bytes[6] ^= 0xFF;
+        assert!(matches!(SamplePacket::decode(&bytes), Err(CodecError::BadCrc { .. })));
+        let status = SimulatedPeripheral::new(1).read_status();
+        assert_eq!(SamplePacket::decode(&status), Err(CodecError::UnexpectedType(STATUS_PACKET_TYPE)));
+    }
+
+    #[test]
+    fn control_command_round_trip() {
+        for command in [
+            ControlCommand::StartStream,
+            ControlCommand::StopStream,
+            ControlCommand::SetSampleRate(500),
+            ControlCommand::SetChannelMask(0x0000_00FF),
+            ControlCommand::SetGain(12),
+            ControlCommand::RequestStatus,
+        ] {
+            assert_eq!(ControlCommand::decode(&command.encode()), Ok(command));
+        }
+        assert_eq!(ControlCommand::decode(&append_crc(vec![0x7F])), Err(CodecError::UnknownOpcode(0x7F)));
+        assert!(matches!(ControlCommand::decode(&append_crc(vec![0x03, 1])), Err(CodecError::BadLength { .. })));
+    }
+
+    #[test]
+    fn status_packet_round_trip() {
+        let status = StatusPacket {
+            streaming: true,
+            sample_rate: 250,
+            channel_mask: 0xFF,
+            gain: 24,
+            battery_level: 87,
+            error_code: 0,
+        };
+        let bytes = status.encode();
+        assert_eq!(bytes.len(), STATUS_PACKET_LEN);
+        assert_eq!(StatusPacket::decode(&bytes), Ok(status));
+    }
+
+    #[test]
+    fn sequence_tracker_counts_gaps_across_wrap() {
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.observe(u16::MAX - 1), 0);
+        assert_eq!(tracker.observe(u16::MAX), 0);
+        assert_eq!(tracker.observe(2), 2);
+        assert_eq!((tracker.received, tracker.lost), (3, 2));
+    }
+
+    #[test]
+    fn simulated_peripheral_streams_decodable_packets() {
+        let mut peripheral = SimulatedPeripheral::new(8);
+        assert_eq!(peripheral.next_notification(), None);
+        peripheral.write_control(&ControlCommand::StartStream.encode()).unwrap();
+        let mut tracker = SequenceTracker::default();
+        for _ in 0..3 {
+            let packet = SamplePacket::decode(&peripheral.next_notification().unwrap()).unwrap();
+            assert_eq!(packet.channel_count, 8);
+            assert_eq!(packet.frames.len(), SamplePacket::max_frames(8));
+            tracker.observe(packet.sequence);
+        }
+        assert_eq!(tracker.lost, 0);
+        let status = peripheral.write_control(&ControlCommand::RequestStatus.encode()).unwrap().unwrap();
+        assert!(StatusPacket::decode(&status).unwrap().streaming);
+    }
+
+    #[test]
+    fn simulated_peripheral_needs_at_least_one_channel() {
+        let mut peripheral = SimulatedPeripheral::new(0);
+        assert_eq!(peripheral.channel_count(), 1);
+        peripheral.write_control(&ControlCommand::StartStream.encode()).unwrap();
+        let packet = SamplePacket::decode(&peripheral.next_notification().unwrap()).unwrap();
+        assert_eq!((packet.channel_count, packet.frames.len()), (1, SamplePacket::max_frames(1)));
+        assert_eq!(SamplePacket::max_frames(0), SamplePacket::max_frames(1));
+        assert_eq!(SimulatedPeripheral::new(200).channel_count(), MAX_CHANNELS);
+    }
+
+    #[test]
+    fn samples_scale_with_the_gain() {
+        assert_eq!(lsb_microvolts(DEFAULT_GAIN), DEFAULT_LSB_MICROVOLTS);
+        assert_eq!(lsb_microvolts(0), lsb_microvolts(1));
+
+        let mut peripheral = SimulatedPeripheral::new(1);
+        peripheral.write_control(&ControlCommand::SetGain(6).encode()).unwrap();
+        peripheral.write_control(&ControlCommand::StartStream.encode()).unwrap();
+        let status = StatusPacket::decode(&peripheral.write_control(&ControlCommand::RequestStatus.encode()).unwrap().unwrap()).unwrap();
+        assert_eq!(status.gain, 6);
+
+        // The simulated signal stays within 25 µV, but only when read at the gain it was set to.
+        let packet = SamplePacket::decode(&peripheral.next_notification().unwrap()).unwrap();
+        let peak = |lsb| packet.channel_microvolts(0, lsb).iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        assert!(peak(lsb_microvolts(status.gain)) <= 25.0);
+        assert!(peak(lsb_microvolts(status.gain)) > 10.0);
+        // Read at the power-up gain it would come out four times too small.
+        assert!((peak(lsb_microvolts(status.gain)) / peak(DEFAULT_LSB_MICROVOLTS) - 4.0).abs() < 1e-3);
+    }
+}
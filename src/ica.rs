@@ -0,0 +1,295 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::filters::{filtfilt, FilterSpec};
+
+/// An ICA decomposition of channel-major data: `data = mixing * sources + means`.
+#[derive(Debug, Clone)]
+pub struct IcaDecomposition {
+    pub means: Vec<f64>,
+    /// channels x components
+    pub mixing: Vec<Vec<f64>>,
+    /// components x samples
+    pub sources: Vec<Vec<f64>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComponentLabel {
+    Brain,
+    Ocular,
+    Muscle,
+}
+
+#[derive(Debug, Clone)]
+pub struct ComponentReport {
+    pub index: usize,
+    pub label: ComponentLabel,
+    pub low_frequency_ratio: f64,
+    pub high_frequency_ratio: f64,
+    pub frontal_weight: f64,
+}
+
+/// Symmetric eigen-decomposition by cyclic Jacobi rotations. Returns (eigenvalues, eigenvectors as columns).
+fn jacobi_eigen(matrix: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = matrix.len();
+    let mut a = matrix.to_vec();
+    let mut v = vec![vec![0.0; n]; n];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |j| *j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        if off_diagonal < 1e-18 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (upper, lower) = a.split_at_mut(q);
+                for (apk, aqk) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+                    let (x, y) = (*apk, *aqk);
+                    *apk = c * x - s * y;
+                    *aqk = s * x + c * y;
+                }
+                for row in v.iter_mut() {
+                    let (vkp, vkq) = (row[p], row[q]);
+                    row[p] = c * vkp - s * vkq;
+                    row[q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// FastICA (deflation, tanh non-linearity) over channel-major data. The starting weights come
+/// from `seed`, so the same seed and data give the same components.
+pub fn fast_ica(data: &[Vec<f64>], max_iterations: usize, tolerance: f64, seed: u64) -> Result<IcaDecomposition, String> {
+    let channels = data.len();
+    let samples = data.first().map_or(0, |channel| channel.len());
+    if channels < 2 || samples < channels * 10 {
+        return Err(format!("ICA needs at least 2 channels and {} samples", channels * 10));
+    }
+    if data.iter().any(|channel| channel.len() != samples) {
+        return Err("All channels must have the same length".to_string());
+    }
+
+    let means: Vec<f64> = data.iter().map(|channel| channel.iter().sum::<f64>() / samples as f64).collect();
+    let centered: Vec<Vec<f64>> = data
+        .iter()
+        .zip(&means)
+        .map(|(channel, mean)| channel.iter().map(|x| x - mean).collect())
+        .collect();
+
+    let covariance: Vec<Vec<f64>> = (0..channels)
+        .map(|i| (0..channels).map(|j| dot(&centered[i], &centered[j]) / samples as f64).collect())
+        .collect();
+    let (eigenvalues, eigenvectors) = jacobi_eigen(&covariance);
+
+    // Whitening keeps only directions with non-negligible variance (rank-deficient montages).
+    let largest = eigenvalues.iter().cloned().fold(0.0_f64, f64::max);
+    let kept: Vec<usize> = (0..channels).filter(|i| eigenvalues[*i] > largest * 1e-10).collect();
+    let whitening: Vec<Vec<f64>> = kept
+        .iter()
+        .map(|&k| (0..channels).map(|c| eigenvectors[c][k] / eigenvalues[k].sqrt()).collect())
+        .collect();
+    let dewhitening: Vec<Vec<f64>> = (0..channels)
+        .map(|c| kept.iter().map(|&k| eigenvectors[c][k] * eigenvalues[k].sqrt()).collect())
+        .collect();
+    let whitened: Vec<Vec<f64>> = whitening
+        .iter()
+        .map(|row| (0..samples).map(|t| (0..channels).map(|c| row[c] * centered[c][t]).sum()).collect())
+        .collect();
+
+    let components = kept.len();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut unmixing: Vec<Vec<f64>> = vec![];
+    for _ in 0..components {
+        let mut w: Vec<f64> = (0..components).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        for iteration in 0..max_iterations {
+            let projection: Vec<f64> = (0..samples)
+                .map(|t| (0..components).map(|i| w[i] * whitened[i][t]).sum())
+                .collect();
+            let mut next: Vec<f64> = (0..components)
+                .map(|i| (0..samples).map(|t| whitened[i][t] * projection[t].tanh()).sum::<f64>() / samples as f64)
+                .collect();
+            let mean_derivative = projection.iter().map(|u| 1.0 - u.tanh().powi(2)).sum::<f64>() / samples as f64;
+            for (value, weight) in next.iter_mut().zip(&w) {
+                *value -= mean_derivative * weight;
+            }
+            for previous in &unmixing {
+                let overlap = dot(&next, previous);
+                for (value, p) in next.iter_mut().zip(previous) {
+                    *value -= overlap * p;
+                }
+            }
+            let norm = dot(&next, &next).sqrt();
+            if norm < 1e-12 {
+                return Err("FastICA collapsed to a zero vector".to_string());
+            }
+            next.iter_mut().for_each(|value| *value /= norm);
+            let converged = (dot(&next, &w).abs() - 1.0).abs() < tolerance;
+            w = next;
+            if converged {
+                break;
+            }
+            if iteration + 1 == max_iterations {
+                eprintln!("FastICA component {} did not converge after {} iterations", unmixing.len(), max_iterations);
+            }
+        }
+        unmixing.push(w);
+    }
+
+    let sources: Vec<Vec<f64>> = unmixing
+        .iter()
+        .map(|w| (0..samples).map(|t| (0..components).map(|i| w[i] * whitened[i][t]).sum()).collect())
+        .collect();
+    // The ICA rotation is orthonormal, so the mixing matrix is dewhitening * unmixing^T.
+    let mixing: Vec<Vec<f64>> = dewhitening
+        .iter()
+        .map(|row| unmixing.iter().map(|w| dot(row, w)).collect())
+        .collect();
+
+    Ok(IcaDecomposition { means, mixing, sources })
+}
+
+fn power_ratio(signal: &[f64], spec: FilterSpec, sample_rate: f64) -> f64 {
+    let total: f64 = signal.iter().map(|x| x * x).sum();
+    if total == 0.0 {
+        return 0.0;
+    }
+    match filtfilt(&[spec], sample_rate, signal) {
+        Ok(filtered) => filtered.iter().map(|x| x * x).sum::<f64>() / total,
+        Err(_) => 0.0,
+    }
+}
+
+fn is_frontal(label: &str) -> bool {
+    let label = label.to_uppercase();
+    label.starts_with("FP") || label.starts_with("AF") || label == "F7" || label == "F8"
+}
+
+/// Flags ocular components (slow, frontally weighted) and muscle components (broadband high-frequency).
+pub fn classify_components(ica: &IcaDecomposition, sample_rate: f64, channel_labels: &[String]) -> Vec<ComponentReport> {
+    let high_cutoff = 20.0_f64.min(sample_rate / 2.0 * 0.8);
+    (0..ica.sources.len())
+        .map(|index| {
+            let source = &ica.sources[index];
+            let low_frequency_ratio = power_ratio(source, FilterSpec::LowPass { cutoff: 4.0, order: 4 }, sample_rate);
+            let high_frequency_ratio = power_ratio(source, FilterSpec::HighPass { cutoff: high_cutoff, order: 4 }, sample_rate);
+
+            let weights: Vec<f64> = ica.mixing.iter().map(|row| row[index] * row[index]).collect();
+            let total_weight: f64 = weights.iter().sum();
+            let frontal: f64 = weights
+                .iter()
+                .zip(channel_labels)
+                .filter(|(_, label)| is_frontal(label))
+                .map(|(weight, _)| weight)
+                .sum();
+            let frontal_weight = if total_weight > 0.0 { frontal / total_weight } else { 0.0 };
+
+            let label = if low_frequency_ratio > 0.6 && frontal_weight > 0.5 {
+                ComponentLabel::Ocular
+            } else if high_frequency_ratio > 0.5 {
+                ComponentLabel::Muscle
+            } else {
+                ComponentLabel::Brain
+            };
+            ComponentReport {
+                index,
+                label,
+                low_frequency_ratio,
+                high_frequency_ratio,
+                frontal_weight,
+            }
+        })
+        .collect()
+}
+
+/// Back-projects all components except `excluded` into channel space.
+pub fn reconstruct(ica: &IcaDecomposition, excluded: &[usize]) -> Vec<Vec<f64>> {
+    let samples = ica.sources.first().map_or(0, |source| source.len());
+    ica.mixing
+        .iter()
+        .zip(&ica.means)
+        .map(|(row, mean)| {
+            (0..samples)
+                .map(|t| {
+                    mean + row
+                        .iter()
+                        .enumerate()
+                        .filter(|(component, _)| !excluded.contains(component))
+                        .map(|(component, weight)| weight * ica.sources[component][t])
+                        .sum::<f64>()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 10 Hz sine and a 1.3 Hz square wave, mixed into three channels.
+    fn mixed() -> Vec<Vec<f64>> {
+        let sources: Vec<[f64; 2]> = (0..2000)
+            .map(|t| {
+                let secs = t as f64 / 250.0;
+                [(2.0 * std::f64::consts::PI * 10.0 * secs).sin(), (2.0 * std::f64::consts::PI * 1.3 * secs).sin().signum()]
+            })
+            .collect();
+        [[1.0, 0.5], [0.3, 1.0], [0.7, 0.7]]
+            .iter()
+            .map(|weights| sources.iter().map(|s| weights[0] * s[0] + weights[1] * s[1] + 2.0).collect())
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_components() {
+        let data = mixed();
+        let a = fast_ica(&data, 200, 1e-8, 42).unwrap();
+        let b = fast_ica(&data, 200, 1e-8, 42).unwrap();
+        assert_eq!(a.mixing, b.mixing);
+        assert_eq!(a.sources, b.sources);
+    }
+
+    #[test]
+    fn reconstructs_the_input_and_drops_excluded_components() {
+        let data = mixed();
+        let ica = fast_ica(&data, 200, 1e-8, 7).unwrap();
+        // Three channels of two sources: the rank-deficient direction is dropped.
+        assert_eq!(ica.sources.len(), 2);
+        for (original, rebuilt) in data.iter().zip(reconstruct(&ica, &[])) {
+            assert!(original.iter().zip(&rebuilt).all(|(x, y)| (x - y).abs() < 1e-6));
+        }
+        let without_first = reconstruct(&ica, &[0]);
+        assert!(data[0].iter().zip(&without_first[0]).any(|(x, y)| (x - y).abs() > 0.1));
+    }
+
+    #[test]
+    fn rejects_unusable_input() {
+        assert!(fast_ica(&[vec![0.0; 100]], 200, 1e-6, 0).is_err());
+        assert!(fast_ica(&[vec![0.0; 100], vec![0.0; 99]], 200, 1e-6, 0).is_err());
+    }
+}
@@ -1,66 +1,180 @@
 use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
 use rand::Rng;
 use tokio::task;
 use std::sync::{Arc, Mutex};
 
+#[allow(dead_code)]
+mod dashboard;
+#[allow(dead_code)]
+mod features;
+#[allow(dead_code)]
+mod filters;
+#[allow(dead_code)]
+mod neurofeedback;
+#[allow(dead_code)]
+mod osc;
+#[allow(dead_code)]
+mod pipeline;
+#[allow(dead_code)]
+mod ring_buffer;
+#[allow(dead_code)]
+mod session_store;
+#[allow(dead_code)]
+mod streaming_stats;
+
+use dashboard::{Dashboard, DeviceMonitor};
+use osc::OscSender;
+use ring_buffer::{sample_buffer, Consumer, OverflowPolicy, Producer};
+use streaming_stats::StreamingStats;
+
 #[derive(Debug)]
 struct LobotomySideEffectsRemediation {
     patient_id: String,
-    symptom_data: Arc<Mutex<Vec<f64>>>,
-    data_sender: mpsc::Sender<f64>,
+    symptom_data: Arc<Mutex<StreamingStats>>,
+    data_sender: Producer<f64>,
+    data_receiver: Mutex<Consumer<f64>>,
     remediation_status: Arc<Mutex<bool>>,
+    monitor: Option<DeviceMonitor>,
+    osc: Option<Arc<OscSender>>,
 }
 
+/// Mean symptom severity above which remediation is applied.
+const SEVERITY_THRESHOLD: f64 = 0.7;
+
 impl LobotomySideEffectsRemediation {
     fn new(patient_id: &str) -> Self {
-        let (tx, _) = mpsc::channel(100);
+        let (tx, rx) = sample_buffer(1024, OverflowPolicy::DropOldest);
         LobotomySideEffectsRemediation {
             patient_id: patient_id.to_string(),
-            symptom_data: Arc::new(Mutex::new(vec![])),
+            symptom_data: Arc::new(Mutex::new(StreamingStats::new(50))),
             data_sender: tx,
+            data_receiver: Mutex::new(rx),
             remediation_status: Arc::new(Mutex::new(false)),
+            monitor: None,
+            osc: None,
+        }
+    }
+
+    /// Reports to a dashboard panel instead of stdout, and honours its emergency stop.
+    fn with_monitor(mut self, monitor: DeviceMonitor) -> Self {
+        monitor.set_channels(&["severity".to_string()], None);
+        monitor.set_threshold("mean severity", None, SEVERITY_THRESHOLD);
+        self.monitor = Some(monitor);
+        self
+    }
+
+    /// Publishes phase changes over OSC, e.g. to drive feedback visuals.
+    fn with_osc(mut self, sender: Arc<OscSender>) -> Self {
+        self.osc = Some(sender);
+        self
+    }
+
+    fn report(&self, message: String) {
+        match &self.monitor {
+            Some(monitor) => monitor.log(message),
+            None => println!("{}", message),
+        }
+    }
+
+    fn set_phase(&self, phase: &str) {
+        if let Some(monitor) = &self.monitor {
+            monitor.set_phase(phase);
+        }
+        if let Some(osc) = &self.osc {
+            osc.send_phase(phase);
+        }
+    }
+
+    /// Sleeps for `duration`, cut short by the emergency stop.
+    async fn sleep_unless_stopped(&self, duration: Duration) -> Result<(), String> {
+        let Some(monitor) = &self.monitor else {
+            tokio::time::sleep(duration).await;
+            return Ok(());
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => Ok(()),
+            _ = monitor.dashboard().stopped() => {
+                monitor.set_state("ABORTED (emergency stop)");
+                self.set_phase("aborted");
+                Err(format!("Remediation for patient {} aborted by emergency stop", self.patient_id))
+            }
         }
     }
 
     async fn initialize(&self) -> Result<(), String> {
-        println!("Initializing remediation process for patient {}", self.patient_id);
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        Ok(())
+        self.set_phase("initializing");
+        if let Some(monitor) = &self.monitor {
+            monitor.set_state("Connected");
+        }
+        self.report(format!("Initializing remediation process for patient {}", self.patient_id));
+        self.sleep_unless_stopped(Duration::from_secs(1)).await
     }
 
     async fn collect_symptoms(&self, duration: Duration) -> Result<(), String> {
+        self.set_phase("collecting symptoms");
         let start = Instant::now();
-        let mut rng = rand::thread_rng();
         while start.elapsed() < duration {
-            let symptom_severity = rng.gen_range(0.0..1.0);
-            self.data_sender.send(symptom_severity).await.unwrap();
-            tokio::time::sleep(Duration::from_millis(150)).await;
+            let symptom_severity = rand::thread_rng().gen_range(0.0..1.0);
+            self.data_sender.send(symptom_severity).await.map_err(|_| "Sample buffer closed".to_string())?;
+            if let Some(monitor) = &self.monitor {
+                monitor.push_frame(&[symptom_severity]);
+                self.drain_samples();
+                monitor.set_threshold("mean severity", self.symptom_data.lock().unwrap().mean(), SEVERITY_THRESHOLD);
+            }
+            self.sleep_unless_stopped(Duration::from_millis(150)).await?;
         }
         Ok(())
     }
 
-    fn analyze_symptoms(&self) -> f64 {
-        let data = self.symptom_data.lock().unwrap();
-        let sum: f64 = data.iter().sum();
-        sum / data.len() as f64
+    /// Moves buffered readings into the running statistics.
+    fn drain_samples(&self) {
+        let mut stats = self.symptom_data.lock().unwrap();
+        let mut receiver = self.data_receiver.lock().unwrap();
+        receiver.drain(|batch| stats.extend(batch.iter().copied()));
+        let buffer = receiver.stats();
+        if let Some(monitor) = &self.monitor {
+            monitor.set_dropped(buffer.dropped);
+        } else if buffer.dropped > 0 {
+            println!("Patient {} overran the symptom buffer: {} of {} readings dropped", self.patient_id, buffer.dropped, buffer.accepted + buffer.dropped);
+        }
+    }
+
+    fn analyze_symptoms(&self) -> Option<f64> {
+        self.drain_samples();
+        self.symptom_data.lock().unwrap().mean()
     }
 
     async fn apply_remediation(&self) -> Result<(), String> {
-        let severity = self.analyze_symptoms();
-        if severity > 0.7 {
-            println!("Remediation applied to patient {}. High symptom severity detected: {}", self.patient_id, severity);
-            tokio::time::sleep(Duration::from_secs(2)).await;
+        self.set_phase("analyzing");
+        let severity = match self.analyze_symptoms() {
+            Some(severity) => severity,
+            None => {
+                self.report(format!("No symptom data collected for patient {}", self.patient_id));
+                self.set_phase("complete");
+                return Ok(());
+            }
+        };
+        if let Some(monitor) = &self.monitor {
+            monitor.set_threshold("mean severity", Some(severity), SEVERITY_THRESHOLD);
+        }
+        if severity > SEVERITY_THRESHOLD {
+            self.set_phase("remediating");
+            self.report(format!("Remediation applied to patient {}. High symptom severity detected: {}", self.patient_id, severity));
+            self.sleep_unless_stopped(Duration::from_secs(2)).await?;
             *self.remediation_status.lock().unwrap() = true;
         } else {
-            println!("No remediation required for patient {}. Symptom severity: {}", self.patient_id, severity);
+            self.report(format!("No remediation required for patient {}. Symptom severity: {}", self.patient_id, severity));
+        }
+        self.set_phase("complete");
+        if let Some(monitor) = &self.monitor {
+            monitor.set_state("Finished");
         }
         Ok(())
     }
 
     fn reset_data(&self) {
-        let mut data = self.symptom_data.lock().unwrap();
-        data.clear();
+        self.data_receiver.lock().unwrap().drain(|_| {});
+        self.symptom_data.lock().unwrap().reset();
     }
 }
 
@@ -72,11 +186,32 @@ async fn process_remediation_for_patient(device: &LobotomySideEffectsRemediation
     Ok(())
 }
 
+/// `--dashboard` shows the session live in the terminal, with `x` or Esc as the emergency stop.
+/// `--osc host:port[,host:port...]` publishes phase changes over OSC.
 #[tokio::main]
 async fn main() -> Result<(), String> {
-    let device = LobotomySideEffectsRemediation::new("P12345");
-    let device_handler = task::spawn(process_remediation_for_patient(&device));
+    let patient_id = "P12345";
+    let mut device = LobotomySideEffectsRemediation::new(patient_id);
+    if let Some(targets) = std::env::args().skip_while(|arg| arg != "--osc").nth(1) {
+        let targets: Vec<String> = targets.split(',').map(|target| target.trim().to_string()).collect();
+        device = device.with_osc(Arc::new(OscSender::new(patient_id, &targets)?));
+    }
+    if !std::env::args().any(|arg| arg == "--dashboard") {
+        return task::spawn(async move { process_remediation_for_patient(&device).await })
+            .await
+            .map_err(|e| e.to_string())?;
+    }
 
-    device_handler.await??;
-    Ok(())
+    let (dashboard, handle) = Dashboard::new("side-effect remediation");
+    let device = device.with_monitor(handle.add_device(patient_id));
+    let ui = task::spawn(dashboard.run());
+    let result = task::spawn(async move { process_remediation_for_patient(&device).await })
+        .await
+        .map_err(|e| e.to_string())?;
+    handle.finish();
+    ui.await.map_err(|e| e.to_string())??;
+    if result.is_ok() {
+        println!("Remediation for patient {} completed", patient_id);
+    }
+    result
 }
@@ -0,0 +1,845 @@
+//! Lab Streaming Layer (LSL) outlets and inlets, speaking the liblsl wire protocol (1.10) so
+//! streams interoperate with other LSL software on the network.
+//!
+//! - Discovery: `LSL:shortinfo` queries over UDP (multicast, broadcast and the unicast service
+//!   ports on this machine); outlets answer with their stream info as XML.
+//! - Data: inlets connect over TCP with `LSL:streamfeed/110` and receive timestamped samples.
+//! - Clocks: `LSL:timedata` probes over UDP give the offset between an outlet's clock and ours.
+//!
+//! Only numeric streams (`float32`, `double64`) are supported.
+
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use rand::Rng;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+use super::pipeline::{Frame, Source};
+use super::xml::{element, elements, escape, unescape};
+
+/// Port outlets listen on for multicast and broadcast queries.
+pub const MULTICAST_PORT: u16 = 16571;
+/// Data and service ports are taken from `BASE_PORT..BASE_PORT + PORT_RANGE`.
+const BASE_PORT: u16 = 16572;
+const PORT_RANGE: u16 = 32;
+const MULTICAST_GROUPS: [Ipv4Addr; 2] = [Ipv4Addr::new(224, 0, 0, 183), Ipv4Addr::new(239, 255, 172, 215)];
+const PROTOCOL_VERSION: u32 = 110;
+const TAG_DEDUCED_TIMESTAMP: u8 = 1;
+const TAG_TRANSMITTED_TIMESTAMP: u8 = 2;
+/// Timestamp of the two test-pattern samples sent before the data.
+const TEST_PATTERN_TIMESTAMP: f64 = 123456.789;
+/// Samples a slow consumer may fall behind by before it starts losing them.
+const OUTLET_BACKLOG: usize = 4096;
+const TIME_PROBES: usize = 8;
+const MAX_DATAGRAM: usize = 65536;
+
+/// Seconds on this process's monotonic clock, the time base of every timestamp we send.
+pub fn local_clock() -> f64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelFormat {
+    Float32,
+    Double64,
+}
+
+impl ChannelFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelFormat::Float32 => "float32",
+            ChannelFormat::Double64 => "double64",
+        }
+    }
+
+    fn value_size(&self) -> usize {
+        match self {
+            ChannelFormat::Float32 => 4,
+            ChannelFormat::Double64 => 8,
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "float32" => Ok(ChannelFormat::Float32),
+            "double64" => Ok(ChannelFormat::Double64),
+            other => Err(format!("LSL channel format '{}' is not supported", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelInfo {
+    pub label: String,
+    pub unit: String,
+    pub kind: String,
+}
+
+/// Stream metadata, as exchanged in the `<info>` XML documents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    pub name: String,
+    pub stream_type: String,
+    pub channel_count: usize,
+    /// Samples per second; 0 for irregular streams.
+    pub nominal_srate: f64,
+    pub channel_format: ChannelFormat,
+    /// Identifies the device, so inlets can find it again after a restart.
+    pub source_id: String,
+    /// Unique per outlet instance.
+    pub uid: String,
+    pub session_id: String,
+    pub hostname: String,
+    /// Outlet clock time when the stream was created.
+    pub created_at: f64,
+    /// Where the outlet answered from; `None` until resolved.
+    pub address: Option<IpAddr>,
+    pub data_port: u16,
+    pub service_port: u16,
+    /// From `<desc><channels>`; may be empty.
+    pub channels: Vec<ChannelInfo>,
+    pub manufacturer: Option<String>,
+}
+
+impl StreamInfo {
+    pub fn new(name: &str, stream_type: &str, channel_count: usize, nominal_srate: f64, source_id: &str) -> Self {
+        let mut rng = rand::thread_rng();
+        let uid = format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            rng.gen::<u32>(),
+            rng.gen::<u16>(),
+            rng.gen::<u16>(),
+            rng.gen::<u16>(),
+            rng.gen::<u64>() & 0xffff_ffff_ffff
+        );
+        StreamInfo {
+            name: name.to_string(),
+            stream_type: stream_type.to_string(),
+            channel_count,
+            nominal_srate,
+            channel_format: ChannelFormat::Float32,
+            source_id: source_id.to_string(),
+            uid,
+            session_id: "default".to_string(),
+            hostname: hostname(),
+            created_at: local_clock(),
+            address: None,
+            data_port: 0,
+            service_port: 0,
+            channels: vec![],
+            manufacturer: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: ChannelFormat) -> Self {
+        self.channel_format = format;
+        self
+    }
+
+    /// Channel metadata; `labels` must match the channel count.
+    pub fn with_channels(mut self, labels: &[String], unit: &str, kind: &str) -> Self {
+        self.channels = labels
+            .iter()
+            .map(|label| ChannelInfo {
+                label: label.clone(),
+                unit: unit.to_string(),
+                kind: kind.to_string(),
+            })
+            .collect();
+        self
+    }
+
+    pub fn with_manufacturer(mut self, manufacturer: &str) -> Self {
+        self.manufacturer = Some(manufacturer.to_string());
+        self
+    }
+
+    /// Channel labels, numbered when the stream has no channel metadata.
+    pub fn labels(&self) -> Vec<String> {
+        if self.channels.len() == self.channel_count {
+            return self.channels.iter().map(|channel| channel.label.clone()).collect();
+        }
+        (1..=self.channel_count).map(|n| format!("Ch{}", n)).collect()
+    }
+
+    /// The short form answers queries; the full form adds `<desc>` metadata.
+    fn to_xml(&self, full: bool) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\"?>\n<info>\n");
+        let mut field = |tag: &str, value: &str| xml.push_str(&format!("\t<{0}>{1}</{0}>\n", tag, escape(value)));
+        field("name", &self.name);
+        field("type", &self.stream_type);
+        field("channel_count", &self.channel_count.to_string());
+        field("channel_format", self.channel_format.name());
+        field("source_id", &self.source_id);
+        field("nominal_srate", &format!("{:.15}", self.nominal_srate));
+        field("version", "1.100000000000000");
+        field("created_at", &format!("{:.15}", self.created_at));
+        field("uid", &self.uid);
+        field("session_id", &self.session_id);
+        field("hostname", &self.hostname);
+        field("v4address", "");
+        field("v4data_port", &self.data_port.to_string());
+        field("v4service_port", &self.service_port.to_string());
+        field("v6address", "");
+        field("v6data_port", "0");
+        field("v6service_port", "0");
+        if !full || (self.channels.is_empty() && self.manufacturer.is_none()) {
+            xml.push_str("\t<desc />\n</info>\n");
+            return xml;
+        }
+        xml.push_str("\t<desc>\n");
+        if let Some(manufacturer) = &self.manufacturer {
+            xml.push_str(&format!("\t\t<manufacturer>{}</manufacturer>\n", escape(manufacturer)));
+        }
+        if !self.channels.is_empty() {
+            xml.push_str("\t\t<channels>\n");
+            for channel in &self.channels {
+                xml.push_str(&format!(
+                    "\t\t\t<channel>\n\t\t\t\t<label>{}</label>\n\t\t\t\t<unit>{}</unit>\n\t\t\t\t<type>{}</type>\n\t\t\t</channel>\n",
+                    escape(&channel.label),
+                    escape(&channel.unit),
+                    escape(&channel.kind)
+                ));
+            }
+            xml.push_str("\t\t</channels>\n");
+        }
+        xml.push_str("\t</desc>\n</info>\n");
+        xml
+    }
+
+    pub fn from_xml(xml: &str) -> Result<Self, String> {
+        let info = element(xml, "info").ok_or("Not an LSL stream info document")?;
+        // Header fields come before <desc>, whose free-form content may reuse their names.
+        let header = info.split("<desc").next().unwrap_or(info);
+        let text = |tag: &str| element(header, tag).map(unescape).unwrap_or_default();
+        let number = |tag: &str| -> Result<f64, String> {
+            text(tag).trim().parse().map_err(|_| format!("LSL stream info has an invalid <{}>", tag))
+        };
+        let desc = element(info, "desc").unwrap_or("");
+        let channels = element(desc, "channels")
+            .map(|channels| {
+                elements(channels, "channel")
+                    .into_iter()
+                    .map(|channel| {
+                        let text = |tag: &str| element(channel, tag).map(unescape).unwrap_or_default();
+                        ChannelInfo {
+                            label: text("label"),
+                            unit: text("unit"),
+                            kind: text("type"),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(StreamInfo {
+            name: text("name"),
+            stream_type: text("type"),
+            channel_count: number("channel_count")? as usize,
+            nominal_srate: number("nominal_srate")?,
+            channel_format: ChannelFormat::parse(&text("channel_format"))?,
+            source_id: text("source_id"),
+            uid: text("uid"),
+            session_id: text("session_id"),
+            hostname: text("hostname"),
+            created_at: number("created_at").unwrap_or(0.0),
+            address: text("v4address").parse().ok(),
+            data_port: number("v4data_port")? as u16,
+            service_port: number("v4service_port")? as u16,
+            channels,
+            manufacturer: element(desc, "manufacturer").map(unescape),
+        })
+    }
+
+    /// Evaluates the subset of liblsl's XPath queries we support: `key='value'` terms joined by
+    /// `and`, e.g. `session_id='default' and type='EEG'`. Anything else matches nothing.
+    fn matches(&self, query: &str) -> bool {
+        query.split(" and ").all(|term| {
+            let Some((key, value)) = term.split_once('=') else {
+                return term.trim().is_empty();
+            };
+            let value = value.trim().trim_matches(|c| c == '\'' || c == '"');
+            match key.trim() {
+                "name" => self.name == value,
+                "type" => self.stream_type == value,
+                "source_id" => self.source_id == value,
+                "session_id" => self.session_id == value,
+                "hostname" => self.hostname == value,
+                "uid" => self.uid == value,
+                "channel_format" => self.channel_format.name() == value,
+                "channel_count" => value.parse() == Ok(self.channel_count),
+                _ => false,
+            }
+        })
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Values of the two samples liblsl exchanges before the data, to check both ends agree on the format.
+fn test_pattern(format: ChannelFormat, channel_count: usize, offset: usize) -> Vec<f64> {
+    (0..channel_count)
+        .map(|k| {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+            match format {
+                ChannelFormat::Float32 => (2 * k + offset) as f64 * sign,
+                ChannelFormat::Double64 => (2 * k + offset + 16777217) as f64 * sign,
+            }
+        })
+        .collect()
+}
+
+fn encode_sample(format: ChannelFormat, timestamp: f64, values: &[f64], buffer: &mut Vec<u8>) {
+    buffer.push(TAG_TRANSMITTED_TIMESTAMP);
+    buffer.extend_from_slice(&timestamp.to_le_bytes());
+    for value in values {
+        match format {
+            ChannelFormat::Float32 => buffer.extend_from_slice(&(*value as f32).to_le_bytes()),
+            ChannelFormat::Double64 => buffer.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+}
+
+/// Binds a TCP listener and a UDP socket in the LSL port range, falling back to any free port.
+async fn bind_ports() -> Result<(TcpListener, UdpSocket), String> {
+    let unspecified = Ipv4Addr::UNSPECIFIED;
+    let mut tcp = None;
+    for port in BASE_PORT..BASE_PORT + PORT_RANGE {
+        if let Ok(listener) = TcpListener::bind((unspecified, port)).await {
+            tcp = Some(listener);
+            break;
+        }
+    }
+    let tcp = match tcp {
+        Some(listener) => listener,
+        None => TcpListener::bind((unspecified, 0)).await.map_err(|e| format!("Cannot open LSL data port: {}", e))?,
+    };
+    let mut udp = None;
+    for port in BASE_PORT..BASE_PORT + PORT_RANGE {
+        if let Ok(socket) = UdpSocket::bind((unspecified, port)).await {
+            udp = Some(socket);
+            break;
+        }
+    }
+    let udp = match udp {
+        Some(socket) => socket,
+        None => UdpSocket::bind((unspecified, 0)).await.map_err(|e| format!("Cannot open LSL service port: {}", e))?,
+    };
+    Ok((tcp, udp))
+}
+
+/// Shared multicast/broadcast query port. Other outlets on this machine bind it too, so it is
+/// opened with address reuse; groups that cannot be joined (no multicast route) are skipped.
+fn multicast_socket() -> Result<UdpSocket, String> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).map_err(|e| e.to_string())?;
+    socket.set_reuse_address(true).map_err(|e| e.to_string())?;
+    socket
+        .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MULTICAST_PORT)).into())
+        .map_err(|e| format!("Cannot listen on LSL port {}: {}", MULTICAST_PORT, e))?;
+    for group in MULTICAST_GROUPS {
+        let _ = socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED);
+    }
+    socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+    UdpSocket::from_std(socket.into()).map_err(|e| e.to_string())
+}
+
+/// Answers shortinfo queries and time probes arriving on `socket`.
+async fn answer_udp(socket: UdpSocket, info: Arc<StreamInfo>) {
+    let shortinfo = info.to_xml(false);
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let Ok((len, from)) = socket.recv_from(&mut buffer).await else {
+            continue;
+        };
+        let received = local_clock();
+        let request = String::from_utf8_lossy(&buffer[..len]);
+        let mut lines = request.lines();
+        match lines.next().map(str::trim) {
+            Some("LSL:timedata") => {
+                let mut fields = lines.next().unwrap_or("").split_whitespace();
+                let (Some(wave_id), Some(t0)) = (fields.next(), fields.next()) else {
+                    continue;
+                };
+                let reply = format!(" {} {} {:.15} {:.15}", wave_id, t0, received, local_clock());
+                let _ = socket.send_to(reply.as_bytes(), from).await;
+            }
+            Some("LSL:shortinfo") => {
+                let query = lines.next().unwrap_or("").trim();
+                let mut fields = lines.next().unwrap_or("").split_whitespace();
+                let (Some(Ok(return_port)), Some(query_id)) = (fields.next().map(str::parse::<u16>), fields.next()) else {
+                    continue;
+                };
+                if info.matches(query) {
+                    let reply = format!("{}\r\n{}", query_id, shortinfo);
+                    let _ = socket.send_to(reply.as_bytes(), SocketAddr::new(from.ip(), return_port)).await;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn read_line(reader: &mut BufReader<impl tokio::io::AsyncRead + Unpin>) -> Result<String, String> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.map_err(|e| e.to_string())?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Serves one TCP client: full or short info, or a sample feed.
+async fn serve_client(stream: TcpStream, info: Arc<StreamInfo>, samples: broadcast::Receiver<Arc<(f64, Vec<f64>)>>) -> Result<(), String> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let request = read_line(&mut reader).await?;
+    if request == "LSL:fullinfo" {
+        writer.write_all(info.to_xml(true).as_bytes()).await.map_err(|e| e.to_string())?;
+        return writer.flush().await.map_err(|e| e.to_string());
+    }
+    if request == "LSL:shortinfo" {
+        if info.matches(&read_line(&mut reader).await?) {
+            writer.write_all(info.to_xml(false).as_bytes()).await.map_err(|e| e.to_string())?;
+        }
+        return writer.flush().await.map_err(|e| e.to_string());
+    }
+    let Some(uid) = request.strip_prefix("LSL:streamfeed/").and_then(|rest| rest.split_once(' ')).map(|(_, uid)| uid.trim().to_string()) else {
+        return Err(format!("Unsupported LSL request '{}'", request));
+    };
+    // Request headers; we always answer in little-endian, version 1.10.
+    while !read_line(&mut reader).await?.is_empty() {}
+    if uid != info.uid {
+        writer.write_all(b"LSL/110 404 Not found\r\n\r\n").await.map_err(|e| e.to_string())?;
+        return writer.flush().await.map_err(|e| e.to_string());
+    }
+    let header = format!(
+        "LSL/{0} 200 OK\r\nUID: {1}\r\nByte-Order: 1234\r\nSuppress-Subnormals: 0\r\nData-Protocol-Version: {0}\r\n\r\n",
+        PROTOCOL_VERSION, info.uid
+    );
+    let mut buffer = header.into_bytes();
+    for offset in [4, 2] {
+        encode_sample(info.channel_format, TEST_PATTERN_TIMESTAMP, &test_pattern(info.channel_format, info.channel_count, offset), &mut buffer);
+    }
+    writer.write_all(&buffer).await.map_err(|e| e.to_string())?;
+    writer.flush().await.map_err(|e| e.to_string())?;
+
+    let mut samples = samples;
+    loop {
+        let sample = match samples.recv().await {
+            Ok(sample) => sample,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                eprintln!("LSL consumer of {} fell behind; {} samples skipped", info.name, missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        buffer.clear();
+        encode_sample(info.channel_format, sample.0, &sample.1, &mut buffer);
+        writer.write_all(&buffer).await.map_err(|e| e.to_string())?;
+        writer.flush().await.map_err(|e| e.to_string())?;
+    }
+}
+
+/// Publishes one stream on the network until dropped.
+#[derive(Debug)]
+pub struct LslOutlet {
+    info: Arc<StreamInfo>,
+    samples: broadcast::Sender<Arc<(f64, Vec<f64>)>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl LslOutlet {
+    pub async fn open(info: StreamInfo) -> Result<Self, String> {
+        if info.channel_count == 0 {
+            return Err("An LSL stream needs at least one channel".to_string());
+        }
+        let (listener, service) = bind_ports().await?;
+        let mut info = info;
+        info.data_port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        info.service_port = service.local_addr().map_err(|e| e.to_string())?.port();
+        let info = Arc::new(info);
+        let (samples, _) = broadcast::channel(OUTLET_BACKLOG);
+
+        let mut tasks = vec![tokio::spawn(answer_udp(service, info.clone()))];
+        match multicast_socket() {
+            Ok(socket) => tasks.push(tokio::spawn(answer_udp(socket, info.clone()))),
+            // Still reachable through its service port, e.g. from this machine.
+            Err(e) => eprintln!("LSL outlet {} is not discoverable by multicast: {}", info.name, e),
+        }
+        let (server_info, server_samples) = (info.clone(), samples.clone());
+        tasks.push(tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let _ = stream.set_nodelay(true);
+                let (info, samples) = (server_info.clone(), server_samples.subscribe());
+                tokio::spawn(async move {
+                    if let Err(e) = serve_client(stream, info.clone(), samples).await {
+                        eprintln!("LSL client of {}: {}", info.name, e);
+                    }
+                });
+            }
+        }));
+        Ok(LslOutlet { info, samples, tasks })
+    }
+
+    pub fn info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    /// Connected inlets.
+    pub fn consumer_count(&self) -> usize {
+        self.samples.receiver_count()
+    }
+
+    /// Sends one sample to every connected inlet, stamped `timestamp` or now.
+    pub fn push_sample(&self, values: &[f64], timestamp: Option<f64>) -> Result<(), String> {
+        if values.len() != self.info.channel_count {
+            return Err(format!("Stream {} has {} channels, got {} values", self.info.name, self.info.channel_count, values.len()));
+        }
+        // No inlets is fine; nobody is listening.
+        let _ = self.samples.send(Arc::new((timestamp.unwrap_or_else(local_clock), values.to_vec())));
+        Ok(())
+    }
+}
+
+impl Drop for LslOutlet {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Finds streams matching `query` (see `StreamInfo::matches`; empty for all) within `timeout`.
+pub async fn resolve(query: &str, timeout: Duration) -> Result<Vec<StreamInfo>, String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.map_err(|e| format!("Cannot open LSL resolver: {}", e))?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+    let return_port = socket.local_addr().map_err(|e| e.to_string())?.port();
+    let query_id = rand::thread_rng().gen::<u32>().to_string();
+    let query = match query.trim() {
+        "" => "session_id='default'".to_string(),
+        query => format!("session_id='default' and {}", query),
+    };
+    let request = format!("LSL:shortinfo\r\n{}\r\n{} {}\r\n", query, return_port, query_id);
+
+    let mut targets: Vec<SocketAddr> = MULTICAST_GROUPS
+        .iter()
+        .chain(&[Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST])
+        .map(|ip| SocketAddr::V4(SocketAddrV4::new(*ip, MULTICAST_PORT)))
+        .collect();
+    // Outlets on this machine also answer on their service ports, which works without multicast.
+    targets.extend((BASE_PORT..BASE_PORT + PORT_RANGE).map(|port| SocketAddr::from((Ipv4Addr::LOCALHOST, port))));
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut found: Vec<StreamInfo> = vec![];
+    let mut seen = HashSet::new();
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+    let mut next_wave = tokio::time::Instant::now();
+    loop {
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            break;
+        }
+        if now >= next_wave {
+            for target in &targets {
+                // Unreachable targets are normal (no route for multicast, port not in use).
+                let _ = socket.send_to(request.as_bytes(), target).await;
+            }
+            next_wave = now + Duration::from_millis(500);
+        }
+        let wait = deadline.min(next_wave) - now;
+        let Ok(Ok((len, from))) = tokio::time::timeout(wait, socket.recv_from(&mut buffer)).await else {
+            continue;
+        };
+        let reply = String::from_utf8_lossy(&buffer[..len]);
+        let Some((id, xml)) = reply.split_once("\r\n") else {
+            continue;
+        };
+        if id.trim() != query_id {
+            continue;
+        }
+        let Ok(mut info) = StreamInfo::from_xml(xml) else {
+            continue;
+        };
+        if seen.insert(info.uid.clone()) {
+            info.address = info.address.or(Some(from.ip()));
+            found.push(info);
+        }
+    }
+    Ok(found)
+}
+
+/// Receives samples from one outlet.
+pub struct LslInlet {
+    info: StreamInfo,
+    reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+    /// Dropping the write half would end the feed.
+    _writer: tokio::net::tcp::OwnedWriteHalf,
+    big_endian: bool,
+    last_timestamp: f64,
+}
+
+impl LslInlet {
+    /// Connects to a resolved stream, fetching its full metadata first.
+    pub async fn open(resolved: &StreamInfo) -> Result<Self, String> {
+        let address = SocketAddr::new(resolved.address.ok_or("Stream has no address; resolve it first")?, resolved.data_port);
+        let mut info = resolved.clone();
+        if let Ok(full) = Self::fetch_fullinfo(address).await {
+            info.channels = full.channels;
+            info.manufacturer = full.manufacturer;
+        }
+
+        let stream = TcpStream::connect(address).await.map_err(|e| format!("Cannot connect to LSL stream {}: {}", info.name, e))?;
+        let _ = stream.set_nodelay(true);
+        let (reader, mut writer) = stream.into_split();
+        let request = format!(
+            "LSL:streamfeed/{0} {1}\r\nNative-Byte-Order: 1234\r\nEndian-Performance: 0\r\nHas-IEEE754-Floats: 1\r\n\
+             Supports-Subnormals: 1\r\nValue-Size: {2}\r\nData-Protocol-Version: {0}\r\nMax-Buffer-Length: 360\r\n\
+             Max-Chunk-Length: 0\r\nHostname: {3}\r\nSource-Id: {4}\r\nSession-Id: {5}\r\n\r\n",
+            PROTOCOL_VERSION,
+            info.uid,
+            info.channel_format.value_size(),
+            info.hostname,
+            info.source_id,
+            info.session_id
+        );
+        writer.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+
+        let mut reader = BufReader::new(reader);
+        let status = read_line(&mut reader).await?;
+        if !status.contains(" 200 ") {
+            return Err(format!("LSL stream {} refused the connection: {}", info.name, status));
+        }
+        let mut big_endian = false;
+        loop {
+            let line = read_line(&mut reader).await?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                if key.trim().eq_ignore_ascii_case("Byte-Order") {
+                    big_endian = value.trim() == "4321";
+                }
+            }
+        }
+
+        let mut inlet = LslInlet {
+            info,
+            reader,
+            _writer: writer,
+            big_endian,
+            last_timestamp: 0.0,
+        };
+        for offset in [4, 2] {
+            let expected = test_pattern(inlet.info.channel_format, inlet.info.channel_count, offset);
+            match inlet.read_sample().await? {
+                Some((timestamp, values)) if timestamp == TEST_PATTERN_TIMESTAMP && values == expected => {}
+                _ => return Err(format!("LSL stream {} failed the test-pattern check", inlet.info.name)),
+            }
+        }
+        Ok(inlet)
+    }
+
+    async fn fetch_fullinfo(address: SocketAddr) -> Result<StreamInfo, String> {
+        let mut stream = TcpStream::connect(address).await.map_err(|e| e.to_string())?;
+        stream.write_all(b"LSL:fullinfo\r\n").await.map_err(|e| e.to_string())?;
+        let mut xml = String::new();
+        tokio::time::timeout(Duration::from_secs(2), stream.read_to_string(&mut xml))
+            .await
+            .map_err(|_| "Timed out reading stream info".to_string())?
+            .map_err(|e| e.to_string())?;
+        StreamInfo::from_xml(&xml)
+    }
+
+    pub fn info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    async fn read_exact<const N: usize>(&mut self) -> Result<Option<[u8; N]>, String> {
+        let mut bytes = [0u8; N];
+        match self.reader.read_exact(&mut bytes).await {
+            Ok(_) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(format!("LSL stream {}: {}", self.info.name, e)),
+        }
+    }
+
+    async fn read_f64(&mut self) -> Result<Option<f64>, String> {
+        Ok(self
+            .read_exact::<8>()
+            .await?
+            .map(|bytes| if self.big_endian { f64::from_be_bytes(bytes) } else { f64::from_le_bytes(bytes) }))
+    }
+
+    async fn read_sample(&mut self) -> Result<Option<(f64, Vec<f64>)>, String> {
+        let Some([tag]) = self.read_exact::<1>().await? else {
+            return Ok(None);
+        };
+        let timestamp = match tag {
+            TAG_TRANSMITTED_TIMESTAMP => match self.read_f64().await? {
+                Some(timestamp) => timestamp,
+                None => return Ok(None),
+            },
+            TAG_DEDUCED_TIMESTAMP if self.info.nominal_srate > 0.0 => self.last_timestamp + 1.0 / self.info.nominal_srate,
+            TAG_DEDUCED_TIMESTAMP => self.last_timestamp,
+            other => return Err(format!("LSL stream {} sent an unknown sample tag {}", self.info.name, other)),
+        };
+        let mut values = Vec::with_capacity(self.info.channel_count);
+        for _ in 0..self.info.channel_count {
+            let value = match self.info.channel_format {
+                ChannelFormat::Float32 => match self.read_exact::<4>().await? {
+                    Some(bytes) => (if self.big_endian { f32::from_be_bytes(bytes) } else { f32::from_le_bytes(bytes) }) as f64,
+                    None => return Ok(None),
+                },
+                ChannelFormat::Double64 => match self.read_f64().await? {
+                    Some(value) => value,
+                    None => return Ok(None),
+                },
+            };
+            values.push(value);
+        }
+        self.last_timestamp = timestamp;
+        Ok(Some((timestamp, values)))
+    }
+
+    /// The next sample and its timestamp on the outlet's clock; `None` once the outlet is gone.
+    pub async fn pull_sample(&mut self) -> Result<Option<(f64, Vec<f64>)>, String> {
+        self.read_sample().await
+    }
+
+    /// Seconds to add to this stream's timestamps to get `local_clock` time, from the probe
+    /// with the shortest round trip.
+    pub async fn time_correction(&self) -> Result<f64, String> {
+        let address = SocketAddr::new(self.info.address.ok_or("Stream has no address")?, self.info.service_port);
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.map_err(|e| e.to_string())?;
+        let mut best: Option<(f64, f64)> = None;
+        let mut buffer = [0u8; 512];
+        for wave_id in 0..TIME_PROBES {
+            let t0 = local_clock();
+            let probe = format!("LSL:timedata\r\n{} {}\r\n", wave_id, t0);
+            socket.send_to(probe.as_bytes(), address).await.map_err(|e| e.to_string())?;
+            let Ok(Ok((len, _))) = tokio::time::timeout(Duration::from_millis(200), socket.recv_from(&mut buffer)).await else {
+                continue;
+            };
+            let t3 = local_clock();
+            let reply = String::from_utf8_lossy(&buffer[..len]);
+            let fields: Vec<f64> = reply.split_whitespace().filter_map(|field| field.parse().ok()).collect();
+            // Outlets may reformat the echoed send time, so replies are matched by wave id alone.
+            let [id, _, t1, t2] = fields[..] else {
+                continue;
+            };
+            if id as usize != wave_id {
+                continue;
+            }
+            let round_trip = (t3 - t0) - (t2 - t1);
+            let correction = (t0 + t3) / 2.0 - (t1 + t2) / 2.0;
+            if best.is_none_or(|(best_trip, _)| round_trip < best_trip) {
+                best = Some((round_trip, correction));
+            }
+        }
+        best.map(|(_, correction)| correction)
+            .ok_or_else(|| format!("No clock reply from LSL stream {}", self.info.name))
+    }
+}
+
+/// Lets any LSL stream drive a pipeline: frames carry the samples, timed on our clock.
+pub struct LslSource {
+    inlet: LslInlet,
+    correction: f64,
+}
+
+impl LslSource {
+    /// Measures the clock offset once up front.
+    pub async fn new(inlet: LslInlet) -> Result<Self, String> {
+        let correction = inlet.time_correction().await?;
+        Ok(LslSource { inlet, correction })
+    }
+
+    pub fn correction(&self) -> f64 {
+        self.correction
+    }
+}
+
+#[async_trait]
+impl Source for LslSource {
+    async fn next_frame(&mut self) -> Result<Option<Frame>, String> {
+        Ok(self
+            .inlet
+            .pull_sample()
+            .await?
+            .map(|(timestamp, values)| Frame::new(timestamp + self.correction, values)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opens an inlet on `outlet` over loopback, as if it had been resolved on this machine.
+    async fn local_inlet(outlet: &LslOutlet) -> LslInlet {
+        let mut resolved = outlet.info().clone();
+        resolved.address = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        LslInlet::open(&resolved).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn inlet_receives_samples_after_the_test_pattern() {
+        for format in [ChannelFormat::Float32, ChannelFormat::Double64] {
+            let labels = vec!["C3".to_string(), "C4".to_string(), "Cz".to_string()];
+            let info = StreamInfo::new("Test EEG", "EEG", 3, 250.0, "test-source")
+                .with_format(format)
+                .with_channels(&labels, "microvolts", "EEG");
+            let outlet = LslOutlet::open(info).await.unwrap();
+            let mut inlet = local_inlet(&outlet).await;
+            assert_eq!(inlet.info().labels(), labels);
+            assert_eq!(inlet.info().channel_format, format);
+            assert_eq!(outlet.consumer_count(), 1);
+
+            let samples: Vec<(f64, Vec<f64>)> = (0..20).map(|i| (100.0 + i as f64 / 250.0, vec![i as f64, -0.5, 1e3])).collect();
+            for (timestamp, values) in &samples {
+                outlet.push_sample(values, Some(*timestamp)).unwrap();
+            }
+            assert!(outlet.push_sample(&[1.0], None).is_err());
+            for expected in &samples {
+                let received = tokio::time::timeout(Duration::from_secs(2), inlet.pull_sample()).await.unwrap().unwrap();
+                assert_eq!(received.as_ref(), Some(expected));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn clock_correction_is_near_zero_within_one_process() {
+        let outlet = LslOutlet::open(StreamInfo::new("Clock", "EEG", 1, 100.0, "clock-source")).await.unwrap();
+        let inlet = local_inlet(&outlet).await;
+        let correction = inlet.time_correction().await.unwrap();
+        assert!(correction.abs() < 0.01, "correction {}", correction);
+
+        let source = LslSource::new(inlet).await.unwrap();
+        assert!(source.correction().abs() < 0.01);
+    }
+
+    #[tokio::test]
+    async fn inlet_sees_the_end_of_the_feed() {
+        let outlet = LslOutlet::open(StreamInfo::new("Short", "EEG", 1, 10.0, "short-source")).await.unwrap();
+        let mut inlet = local_inlet(&outlet).await;
+        outlet.push_sample(&[7.0], Some(1.0)).unwrap();
+        assert_eq!(inlet.pull_sample().await.unwrap(), Some((1.0, vec![7.0])));
+        drop(outlet);
+        let end = tokio::time::timeout(Duration::from_secs(2), inlet.pull_sample()).await.unwrap().unwrap();
+        assert_eq!(end, None);
+    }
+}
@@ -0,0 +1,340 @@
+/// Idealised spherical positions (azimuth, elevation in degrees; azimuth 0 = nose,
+/// positive to the right) for the 10-20 system and the common 10-10 additions.
+/// The Fpz-T7-Oz-T8 ring lies on the equator and Cz at the vertex.
+const STANDARD_POSITIONS: &[(&str, f64, f64)] = &[
+    ("Fpz", 0.0, 0.0),
+    ("Fp1", -18.0, 0.0),
+    ("Fp2", 18.0, 0.0),
+    ("AF7", -36.0, 0.0),
+    ("AF8", 36.0, 0.0),
+    ("AF3", -23.0, 23.0),
+    ("AF4", 23.0, 23.0),
+    ("AFz", 0.0, 23.0),
+    ("F7", -54.0, 0.0),
+    ("F8", 54.0, 0.0),
+    ("F5", -50.0, 16.0),
+    ("F6", 50.0, 16.0),
+    ("F3", -42.0, 30.0),
+    ("F4", 42.0, 30.0),
+    ("F1", -25.0, 40.0),
+    ("F2", 25.0, 40.0),
+    ("Fz", 0.0, 45.0),
+    ("FT7", -72.0, 0.0),
+    ("FT8", 72.0, 0.0),
+    ("FC5", -69.0, 21.0),
+    ("FC6", 69.0, 21.0),
+    ("FC3", -62.0, 41.0),
+    ("FC4", 62.0, 41.0),
+    ("FC1", -45.0, 60.0),
+    ("FC2", 45.0, 60.0),
+    ("FCz", 0.0, 67.5),
+    ("T7", -90.0, 0.0),
+    ("T8", 90.0, 0.0),
+    ("C5", -90.0, 22.5),
+    ("C6", 90.0, 22.5),
+    ("C3", -90.0, 45.0),
+    ("C4", 90.0, 45.0),
+    ("C1", -90.0, 67.5),
+    ("C2", 90.0, 67.5),
+    ("Cz", 0.0, 90.0),
+    ("TP7", -108.0, 0.0),
+    ("TP8", 108.0, 0.0),
+    ("CP5", -111.0, 21.0),
+    ("CP6", 111.0, 21.0),
+    ("CP3", -118.0, 41.0),
+    ("CP4", 118.0, 41.0),
+    ("CP1", -135.0, 60.0),
+    ("CP2", 135.0, 60.0),
+    ("CPz", 180.0, 67.5),
+    ("P7", -126.0, 0.0),
+    ("P8", 126.0, 0.0),
+    ("P5", -130.0, 16.0),
+    ("P6", 130.0, 16.0),
+    ("P3", -138.0, 30.0),
+    ("P4", 138.0, 30.0),
+    ("P1", -155.0, 40.0),
+    ("P2", 155.0, 40.0),
+    ("Pz", 180.0, 45.0),
+    ("PO7", -144.0, 0.0),
+    ("PO8", 144.0, 0.0),
+    ("PO3", -157.0, 23.0),
+    ("PO4", 157.0, 23.0),
+    ("POz", 180.0, 23.0),
+    ("O1", -162.0, 0.0),
+    ("O2", 162.0, 0.0),
+    ("Oz", 180.0, 0.0),
+    ("M1", -100.0, -30.0),
+    ("M2", 100.0, -30.0),
+];
+
+/// Pre-2005 names that map onto the current nomenclature.
+const ALIASES: &[(&str, &str)] = &[("T3", "T7"), ("T4", "T8"), ("T5", "P7"), ("T6", "P8"), ("A1", "M1"), ("A2", "M2")];
+
+/// Unit-sphere coordinates: x towards the right ear, y towards the nose, z towards the vertex.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Position {
+    fn from_spherical(azimuth: f64, elevation: f64) -> Self {
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        Position {
+            x: elevation.cos() * azimuth.sin(),
+            y: elevation.cos() * azimuth.cos(),
+            z: elevation.sin(),
+        }
+    }
+
+    pub fn distance(&self, other: &Position) -> f64 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)).sqrt()
+    }
+}
+
+pub fn standard_position(label: &str) -> Option<(String, Position)> {
+    let canonical = ALIASES
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(label))
+        .map_or(label, |(_, name)| *name);
+    STANDARD_POSITIONS
+        .iter()
+        .find(|(name, _, _)| name.eq_ignore_ascii_case(canonical))
+        .map(|(name, azimuth, elevation)| (name.to_string(), Position::from_spherical(*azimuth, *elevation)))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelInfo {
+    pub label: String,
+    pub position: Option<Position>,
+}
+
+/// Which electrode each recorded channel came from, in recording order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelLayout {
+    pub channels: Vec<ChannelInfo>,
+}
+
+impl ChannelLayout {
+    /// Looks up standard positions; labels outside the 10-10 system are kept without a position.
+    pub fn from_labels(labels: &[&str]) -> Self {
+        ChannelLayout {
+            channels: labels
+                .iter()
+                .map(|label| match standard_position(label) {
+                    Some((name, position)) => ChannelInfo { label: name, position: Some(position) },
+                    None => ChannelInfo { label: label.to_string(), position: None },
+                })
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    pub fn index_of(&self, label: &str) -> Option<usize> {
+        let canonical = standard_position(label).map_or(label.to_string(), |(name, _)| name);
+        self.channels.iter().position(|channel| channel.label.eq_ignore_ascii_case(&canonical))
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        self.channels.iter().map(|channel| channel.label.clone()).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Montage {
+    CommonAverage,
+    LinkedMastoids { left: String, right: String },
+    /// Each output is `first - second`.
+    Bipolar(Vec<(String, String)>),
+    /// Hjorth surface Laplacian against the `neighbours` closest electrodes.
+    Laplacian { neighbours: usize },
+}
+
+impl Montage {
+    pub fn linked_mastoids() -> Self {
+        Montage::LinkedMastoids { left: "M1".to_string(), right: "M2".to_string() }
+    }
+
+    /// Consecutive pairs along a chain, e.g. `Fp1-F3, F3-C3, C3-P3, P3-O1`.
+    pub fn bipolar_chain(chain: &[&str]) -> Self {
+        Montage::Bipolar(chain.windows(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect())
+    }
+
+    /// The longitudinal bipolar ("double banana") montage.
+    pub fn double_banana() -> Self {
+        let chains: [&[&str]; 4] = [
+            &["Fp1", "F7", "T7", "P7", "O1"],
+            &["Fp1", "F3", "C3", "P3", "O1"],
+            &["Fp2", "F4", "C4", "P4", "O2"],
+            &["Fp2", "F8", "T8", "P8", "O2"],
+        ];
+        let mut pairs = vec![];
+        for chain in chains {
+            if let Montage::Bipolar(chain_pairs) = Montage::bipolar_chain(chain) {
+                pairs.extend(chain_pairs);
+            }
+        }
+        Montage::Bipolar(pairs)
+    }
+
+    /// Resolves the montage against a layout into a fixed re-referencing matrix.
+    pub fn compile(&self, layout: &ChannelLayout) -> Result<CompiledMontage, String> {
+        let n = layout.len();
+        let find = |label: &str| layout.index_of(label).ok_or_else(|| format!("Montage needs electrode {}", label));
+        let identity_row = |i: usize| {
+            let mut row = vec![0.0; n];
+            row[i] = 1.0;
+            row
+        };
+
+        let (labels, weights) = match self {
+            Montage::CommonAverage => {
+                let weights = (0..n)
+                    .map(|i| {
+                        let mut row = vec![-1.0 / n as f64; n];
+                        row[i] += 1.0;
+                        row
+                    })
+                    .collect();
+                (layout.labels(), weights)
+            }
+            Montage::LinkedMastoids { left, right } => {
+                let (left, right) = (find(left)?, find(right)?);
+                let mut labels = vec![];
+                let mut weights = vec![];
+                for i in (0..n).filter(|i| *i != left && *i != right) {
+                    let mut row = identity_row(i);
+                    row[left] -= 0.5;
+                    row[right] -= 0.5;
+                    labels.push(layout.channels[i].label.clone());
+                    weights.push(row);
+                }
+                (labels, weights)
+            }
+            Montage::Bipolar(pairs) => {
+                let mut labels = vec![];
+                let mut weights = vec![];
+                for (first, second) in pairs {
+                    let (a, b) = (find(first)?, find(second)?);
+                    let mut row = identity_row(a);
+                    row[b] -= 1.0;
+                    labels.push(format!("{}-{}", layout.channels[a].label, layout.channels[b].label));
+                    weights.push(row);
+                }
+                (labels, weights)
+            }
+            Montage::Laplacian { neighbours } => {
+                let mut weights = vec![];
+                for (i, channel) in layout.channels.iter().enumerate() {
+                    let position = channel
+                        .position
+                        .ok_or_else(|| format!("Laplacian needs a position for {}", channel.label))?;
+                    let mut nearest: Vec<(usize, f64)> = layout
+                        .channels
+                        .iter()
+                        .enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .filter_map(|(j, other)| other.position.map(|p| (j, position.distance(&p))))
+                        .collect();
+                    nearest.sort_by(|a, b| a.1.total_cmp(&b.1));
+                    nearest.truncate(*neighbours);
+                    if nearest.is_empty() {
+                        return Err(format!("No neighbours for {}", channel.label));
+                    }
+                    let mut row = identity_row(i);
+                    for (j, _) in &nearest {
+                        row[*j] -= 1.0 / nearest.len() as f64;
+                    }
+                    weights.push(row);
+                }
+                (layout.labels(), weights)
+            }
+        };
+        Ok(CompiledMontage { labels, weights })
+    }
+}
+
+/// A montage resolved to `outputs x inputs` weights; usable per frame (live) or per channel (offline).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledMontage {
+    pub labels: Vec<String>,
+    weights: Vec<Vec<f64>>,
+}
+
+impl CompiledMontage {
+    pub fn apply_frame(&self, frame: &[f64]) -> Vec<f64> {
+        self.weights
+            .iter()
+            .map(|row| row.iter().zip(frame).map(|(weight, value)| weight * value).sum())
+            .collect()
+    }
+
+    /// Where each output sits on the scalp: the electrode itself, or the midpoint of a bipolar
+    /// pair. `None` if an electrode in the label has no position in `layout`.
+    pub fn output_positions(&self, layout: &ChannelLayout) -> Vec<Option<Position>> {
+        self.labels
+            .iter()
+            .map(|label| {
+                let positions = label
+                    .split('-')
+                    .map(|electrode| layout.index_of(electrode).and_then(|i| layout.channels[i].position))
+                    .collect::<Option<Vec<Position>>>()?;
+                let n = positions.len() as f64;
+                Some(Position {
+                    x: positions.iter().map(|p| p.x).sum::<f64>() / n,
+                    y: positions.iter().map(|p| p.y).sum::<f64>() / n,
+                    z: positions.iter().map(|p| p.z).sum::<f64>() / n,
+                })
+            })
+            .collect()
+    }
+
+    /// Re-references channel-major data.
+    pub fn apply(&self, channels: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        let samples = channels.first().map_or(0, |channel| channel.len());
+        self.weights
+            .iter()
+            .map(|row| {
+                (0..samples)
+                    .map(|t| row.iter().zip(channels).map(|(weight, channel)| weight * channel[t]).sum())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_positions_follow_the_montage_labels() {
+        let layout = ChannelLayout::from_labels(&["Fp1", "F3", "C3", "C4", "M1", "M2"]);
+        let bipolar = Montage::bipolar_chain(&["Fp1", "F3", "C3"]).compile(&layout).unwrap();
+        let positions = bipolar.output_positions(&layout);
+        assert_eq!(positions.len(), bipolar.labels.len());
+        let (fp1, f3) = (layout.channels[0].position.unwrap(), layout.channels[1].position.unwrap());
+        assert!((positions[0].unwrap().y - (fp1.y + f3.y) / 2.0).abs() < 1e-12);
+
+        // Linked mastoids drops the references, so outputs no longer line up with the layout.
+        let mastoids = Montage::linked_mastoids().compile(&layout).unwrap();
+        let positions = mastoids.output_positions(&layout);
+        assert_eq!(mastoids.labels, vec!["Fp1", "F3", "C3", "C4"]);
+        assert!(positions[2].unwrap().x < 0.0 && positions[3].unwrap().x > 0.0);
+    }
+
+    #[test]
+    fn unknown_electrodes_have_no_position() {
+        let layout = ChannelLayout::from_labels(&["C3", "EMG"]);
+        let positions = Montage::bipolar_chain(&["C3", "EMG"]).compile(&layout).unwrap().output_positions(&layout);
+        assert_eq!(positions, vec![None]);
+    }
+}
@@ -1,69 +1,130 @@
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
 use tokio::task;
-use std::time::Duration;
-use futures::stream::StreamExt;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
 use rand::Rng;
 
+#[allow(dead_code)]
+mod device_operations;
+#[allow(dead_code)]
+mod ring_buffer;
+
+use device_operations::{DeviceOperations, HealthCheck, Lifecycle};
+use ring_buffer::{sample_buffer, Consumer, OverflowPolicy, Producer};
+
 #[derive(Debug)]
 struct NeuralInterface {
     id: String,
-    signal_channel: mpsc::Sender<f32>,
-    signal_receiver: Arc<Mutex<mpsc::Receiver<f32>>>,
+    signal_channel: Producer<f32>,
+    signal_receiver: Arc<Mutex<Consumer<f32>>>,
+    /// Running sum and count of every signal drained so far.
+    signal_totals: Mutex<(f32, usize)>,
+    lifecycle: Lifecycle,
 }
 
 impl NeuralInterface {
     fn new(id: &str) -> Self {
-        let (tx, rx) = mpsc::channel(100);
+        // Every signal counts towards the average, so producers wait rather than drop;
+        // collection drains as it goes so the buffer never fills on long streams.
+        let (tx, rx) = sample_buffer(128, OverflowPolicy::Block);
         NeuralInterface {
             id: id.to_string(),
             signal_channel: tx,
             signal_receiver: Arc::new(Mutex::new(rx)),
+            signal_totals: Mutex::new((0.0, 0)),
+            lifecycle: Lifecycle::new(),
+        }
+    }
+
+    async fn collect_signals(&self, duration: Duration) -> Result<(), String> {
+        let start = Instant::now();
+        let mut paused_for = Duration::ZERO;
+        while start.elapsed().saturating_sub(paused_for) < duration {
+            paused_for += self.lifecycle.wait_while_paused().await;
+            if !self.lifecycle.is_connected() {
+                return Err(format!("Device {} disconnected while streaming", self.id));
+            }
+            let signal_value = rand::thread_rng().gen_range(0.0..1.0);
+            self.signal_channel.send(signal_value).await.map_err(|_| "Signal buffer closed".to_string())?;
+            self.lifecycle.record_samples(1);
+            self.drain_signals();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+
+    /// Moves buffered signals into the running totals.
+    fn drain_signals(&self) {
+        let mut totals = self.signal_totals.lock().unwrap();
+        let mut total_signal = 0.0;
+        let count = self.signal_receiver.lock().unwrap().drain(|batch| total_signal += batch.iter().sum::<f32>());
+        totals.0 += total_signal;
+        totals.1 += count;
+    }
+
+    async fn process_signals(&self) -> Result<f32, String> {
+        self.drain_signals();
+        let (total_signal, count) = *self.signal_totals.lock().unwrap();
+        if count == 0 {
+            return Err(format!("No signals received from device {}", self.id));
         }
+
+        let average_signal = total_signal / count as f32;
+        Ok(average_signal)
     }
+}
 
-    async fn connect(&self) -> Result<(), String> {
+#[async_trait]
+impl DeviceOperations for NeuralInterface {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
         // Simulate connection establishment
         tokio::time::sleep(Duration::from_secs(1)).await;
+        self.lifecycle.connected()?;
         println!("Device {} connected", self.id);
         Ok(())
     }
 
-    async fn disconnect(&self) -> Result<(), String> {
+    async fn stream(&mut self, duration: Duration) -> Result<(), String> {
+        self.lifecycle.begin_stream()?;
+        let result = self.collect_signals(duration).await;
+        if let Err(e) = &result {
+            self.lifecycle.record_error(e);
+        }
+        self.lifecycle.end_stream();
+        result
+    }
+
+    async fn disconnect(&mut self) -> Result<(), String> {
+        self.lifecycle.check_can_disconnect()?;
         // Simulate disconnection
         tokio::time::sleep(Duration::from_secs(1)).await;
+        self.lifecycle.disconnected();
         println!("Device {} disconnected", self.id);
         Ok(())
     }
 
-    async fn collect_signals(&self) -> Result<(), String> {
-        let mut rng = rand::thread_rng();
-        for _ in 0..100 {
-            let signal_value = rng.gen_range(0.0..1.0);
-            self.signal_channel.send(signal_value).await.unwrap();
-            tokio::time::sleep(Duration::from_millis(100)).await;
-        }
-        Ok(())
-    }
-
-    async fn process_signals(&self) -> Result<f32, String> {
-        let mut total_signal = 0.0;
-        let receiver_lock = self.signal_receiver.clone();
-        let receiver = receiver_lock.lock().unwrap();
-
-        let mut signal_stream = receiver.clone().into_stream();
-        while let Some(signal) = signal_stream.next().await {
-            total_signal += signal;
-        }
-
-        let average_signal = total_signal / 100.0;
-        Ok(average_signal)
+    async fn device_checks(&mut self) -> Vec<HealthCheck> {
+        let buffer = self.signal_receiver.lock().unwrap().stats();
+        vec![
+            HealthCheck::new("dropped signals", buffer.dropped == 0, buffer.dropped.to_string()),
+            HealthCheck::new("buffer", buffer.len < buffer.capacity, format!("{}/{} queued", buffer.len, buffer.capacity)),
+        ]
     }
 }
 
-async fn handle_neuro_device_operations(device: &NeuralInterface) -> Result<(), String> {
+async fn handle_neuro_device_operations(device: &mut NeuralInterface) -> Result<(), String> {
     device.connect().await?;
-    device.collect_signals().await?;
+    device.stream(Duration::from_secs(10)).await?;
+    let health = device.health_check().await;
+    println!("Health of device {}: {}", device.id, health);
     let avg_signal = device.process_signals().await?;
     println!("Processed average signal: {}", avg_signal);
     device.disconnect().await?;
@@ -72,9 +133,9 @@ async fn handle_neuro_device_operations(device: &NeuralInterface) -> Result<(),
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
-    let device = NeuralInterface::new("N123");
-    let device_handler = task::spawn(handle_neuro_device_operations(&device));
+    let mut device = NeuralInterface::new("N123");
+    let device_handler = task::spawn(async move { handle_neuro_device_operations(&mut device).await });
 
-    device_handler.await??;
+    device_handler.await.map_err(|e| e.to_string())??;
     Ok(())
 }
@@ -1,58 +1,157 @@
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
 use tokio::task;
 use std::time::{Duration, Instant};
 use rand::Rng;
 
+#[allow(dead_code)]
+mod montage;
+#[allow(dead_code)]
+mod ring_buffer;
+#[allow(dead_code)]
+mod signal_quality;
+#[allow(dead_code)]
+mod streaming_stats;
+
+use montage::{ChannelLayout, CompiledMontage, Montage, Position};
+use ring_buffer::{sample_buffer, Consumer, OverflowPolicy, Producer};
+use signal_quality::{simulate_channel, QualityCriteria, QualityGate};
+use streaming_stats::{StreamingStats, Welford};
+
+const ELECTRODES: &[&str] = &["Fp1", "Fp2", "F3", "F4", "C3", "C4", "P3", "P4", "O1", "O2", "Fz", "Cz", "Pz"];
+
 #[derive(Debug)]
 struct NeuralSpaceDistortion {
     device_id: String,
-    signal_data: Arc<Mutex<Vec<f64>>>,
-    data_sender: mpsc::Sender<f64>,
+    signal_data: Arc<Mutex<StreamingStats>>,
+    data_sender: Producer<f64>,
+    data_receiver: Mutex<Consumer<f64>>,
     distortion_factor: Arc<Mutex<f64>>,
+    layout: ChannelLayout,
+    montage: CompiledMontage,
+    /// Scalp position of each montage output, in the montage's output order.
+    output_positions: Vec<Option<Position>>,
+    /// Running mean of the absolute Laplacian per montage output.
+    spatial_data: Arc<Mutex<Vec<Welford>>>,
+    quality_gate: QualityGate,
 }
 
 impl NeuralSpaceDistortion {
     fn new(device_id: &str) -> Self {
-        let (tx, _) = mpsc::channel(100);
+        let (tx, rx) = sample_buffer(1024, OverflowPolicy::DropOldest);
+        let layout = ChannelLayout::from_labels(ELECTRODES);
+        let montage = Montage::Laplacian { neighbours: 4 }
+            .compile(&layout)
+            .expect("all electrodes have standard positions");
+        let output_positions = montage.output_positions(&layout);
+        let spatial_data = vec![Welford::default(); montage.labels.len()];
         NeuralSpaceDistortion {
             device_id: device_id.to_string(),
-            signal_data: Arc::new(Mutex::new(vec![])),
+            signal_data: Arc::new(Mutex::new(StreamingStats::new(50))),
             data_sender: tx,
+            data_receiver: Mutex::new(rx),
             distortion_factor: Arc::new(Mutex::new(1.0)),
+            layout,
+            montage,
+            output_positions,
+            spatial_data: Arc::new(Mutex::new(spatial_data)),
+            quality_gate: QualityGate::new(QualityCriteria::default()),
         }
     }
 
     async fn initialize(&self) -> Result<(), String> {
         println!("Initializing Neural Space Distortion for device {}", self.device_id);
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        let channel_count = self.layout.len();
+        self.quality_gate
+            .run(&self.device_id, ELECTRODES, 250.0, |attempt| async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                // Impedance drops as the gel soaks in; the frontal electrodes take longest.
+                let settle = 0.5_f64.powi(attempt as i32 - 1);
+                let impedances: Vec<f64> = (0..channel_count).map(|i| 5.0 + settle * if i < 2 { 40.0 } else { 10.0 }).collect();
+                let data = impedances
+                    .iter()
+                    .map(|kohm| simulate_channel(250.0, 1.0, kohm / 4.0, kohm / 8.0, 50.0))
+                    .collect();
+                Ok((data, Some(impedances)))
+            })
+            .await?;
         Ok(())
     }
 
     async fn generate_distortion_signals(&self, duration: Duration) -> Result<(), String> {
         let start = Instant::now();
-        let mut rng = rand::thread_rng();
         while start.elapsed() < duration {
-            let signal = rng.gen_range(0.0..1.0);
-            self.data_sender.send(signal).await.unwrap();
+            let frame: Vec<f64> = {
+                let mut rng = rand::thread_rng();
+                (0..self.layout.len()).map(|_| rng.gen_range(0.0..1.0)).collect()
+            };
+            let signal = frame.iter().sum::<f64>() / frame.len() as f64;
+            {
+                let mut spatial = self.spatial_data.lock().unwrap();
+                for (electrode, value) in spatial.iter_mut().zip(self.montage.apply_frame(&frame)) {
+                    electrode.push(value.abs());
+                }
+            }
+            self.data_sender.send(signal).await.map_err(|_| "Sample buffer closed".to_string())?;
+            self.drain_samples();
             tokio::time::sleep(Duration::from_millis(150)).await;
         }
         Ok(())
     }
 
-    fn evaluate_distortion(&self) -> f64 {
-        let data = self.signal_data.lock().unwrap();
-        let total: f64 = data.iter().sum();
-        total / data.len() as f64
+    /// Moves buffered samples into the running statistics.
+    fn drain_samples(&self) {
+        let mut stats = self.signal_data.lock().unwrap();
+        let mut receiver = self.data_receiver.lock().unwrap();
+        receiver.drain(|batch| stats.extend(batch.iter().copied()));
+        let buffer = receiver.stats();
+        if buffer.dropped > 0 {
+            println!("Device {} overran its sample buffer: {} of {} samples dropped", self.device_id, buffer.dropped, buffer.accepted + buffer.dropped);
+        }
     }
 
-    async fn apply_space_distortion(&self) -> Result<(), String> {
-        let distortion_level = self.evaluate_distortion();
-        let mut factor = self.distortion_factor.lock().unwrap();
+    fn evaluate_distortion(&self) -> Option<f64> {
+        self.drain_samples();
+        self.signal_data.lock().unwrap().mean()
+    }
+
+    /// Electrode with the strongest local (Laplacian) activity, and the left-right asymmetry
+    /// of that activity (positive when the right hemisphere dominates).
+    fn evaluate_spatial_distortion(&self) -> Option<(String, f64)> {
+        let mean_abs = self
+            .spatial_data
+            .lock()
+            .unwrap()
+            .iter()
+            .map(Welford::mean)
+            .collect::<Option<Vec<f64>>>()?;
+        let focus = (0..mean_abs.len()).max_by(|a, b| mean_abs[*a].total_cmp(&mean_abs[*b]))?;
 
+        let (mut left, mut right) = (0.0, 0.0);
+        for (position, activity) in self.output_positions.iter().zip(&mean_abs) {
+            match position {
+                Some(position) if position.x < -1e-6 => left += activity,
+                Some(position) if position.x > 1e-6 => right += activity,
+                _ => {}
+            }
+        }
+        let asymmetry = if left + right > 0.0 { (right - left) / (right + left) } else { 0.0 };
+        Some((self.montage.labels[focus].clone(), asymmetry))
+    }
+
+    async fn apply_space_distortion(&self) -> Result<(), String> {
+        if let Some((focus, asymmetry)) = self.evaluate_spatial_distortion() {
+            println!("Distortion focus for device {} at {} (hemispheric asymmetry {:.2})", self.device_id, focus, asymmetry);
+        }
+        let distortion_level = match self.evaluate_distortion() {
+            Some(level) => level,
+            None => {
+                println!("No distortion signals recorded for device {}", self.device_id);
+                return Ok(());
+            }
+        };
         if distortion_level > 0.8 {
             println!("Severe neural space distortion detected for device {}: {}", self.device_id, distortion_level);
-            *factor *= 1.5;  // Increase distortion factor
+            *self.distortion_factor.lock().unwrap() *= 1.5;  // Increase distortion factor
             tokio::time::sleep(Duration::from_secs(2)).await;
         } else {
             println!("Minimal distortion detected for device {}: {}", self.device_id, distortion_level);
@@ -62,8 +161,11 @@ impl NeuralSpaceDistortion {
     }
 
     fn reset_distortion_data(&self) {
-        let mut data = self.signal_data.lock().unwrap();
-        data.clear();
+        self.data_receiver.lock().unwrap().drain(|_| {});
+        self.signal_data.lock().unwrap().reset();
+        for electrode in self.spatial_data.lock().unwrap().iter_mut() {
+            *electrode = Welford::default();
+        }
     }
 }
 
@@ -78,8 +180,8 @@ async fn execute_neural_space_distortion(device: &NeuralSpaceDistortion) -> Resu
 #[tokio::main]
 async fn main() -> Result<(), String> {
     let device = NeuralSpaceDistortion::new("NSD123");
-    let device_task = task::spawn(execute_neural_space_distortion(&device));
+    let device_task = task::spawn(async move { execute_neural_space_distortion(&device).await });
 
-    device_task.await??;
+    device_task.await.map_err(|e| e.to_string())??;
     Ok(())
 }
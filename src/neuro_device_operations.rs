@@ -1,102 +1,418 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use rand::Rng;
 use tokio::time::sleep;
 use async_trait::async_trait;
 
+#[allow(dead_code)]
+mod artifacts;
+#[allow(dead_code)]
+mod device_operations;
+#[allow(dead_code)]
+mod edf;
+#[allow(dead_code)]
+mod features;
+#[allow(dead_code)]
+mod filters;
+#[allow(dead_code)]
+mod lsl;
+#[allow(dead_code)]
+mod pipeline;
+#[allow(dead_code)]
+mod replay;
+#[allow(dead_code)]
+mod ring_buffer;
+#[allow(dead_code)]
+mod session_store;
+#[allow(dead_code)]
+mod streaming_stats;
+#[allow(dead_code)]
+mod telemetry;
+#[allow(dead_code)]
+mod xdf;
+#[allow(dead_code)]
+mod xml;
+
+use artifacts::{ArtifactDetector, ArtifactThresholds, SessionReport, StreamingScreener};
+use device_operations::{DeviceOperations, HealthCheck, Lifecycle};
+use lsl::{LslOutlet, StreamInfo};
+use replay::{ReplayDevice, ReplayItem, ReplaySpeed};
+use ring_buffer::{sample_buffer, Consumer, OverflowPolicy, Producer};
+use session_store::SessionWriter;
+use streaming_stats::StreamingStats;
+use telemetry::{SimulatedTelemetry, TelemetryMonitor, TelemetrySource, TelemetryThresholds};
+
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(2);
+const SAMPLE_RATE_HZ: f64 = 2.0;
+
 #[derive(Debug)]
 struct NeuroDevice {
     id: String,
-    connection_status: bool,
-    brainwave_data: Arc<Mutex<Vec<f32>>>,
+    lifecycle: Lifecycle,
+    brainwave_data: Arc<Mutex<StreamingStats>>,
+    /// Samples drained so far.
+    recorded: AtomicUsize,
+    /// Drained samples are written here as they arrive, once `start_recording` is called.
+    recorder: Mutex<Option<SessionWriter>>,
+    recording_path: Option<PathBuf>,
+    sample_sender: Producer<f64>,
+    sample_receiver: Mutex<Consumer<f64>>,
+    screener: Mutex<StreamingScreener>,
+    telemetry: TelemetryMonitor,
+    telemetry_source: tokio::sync::Mutex<Box<dyn TelemetrySource>>,
+    outlet: Option<LslOutlet>,
 }
 
 impl NeuroDevice {
     fn new(id: &str) -> Self {
+        let (tx, rx) = sample_buffer(1024, OverflowPolicy::DropOldest);
         NeuroDevice {
             id: id.to_string(),
-            connection_status: false,
-            brainwave_data: Arc::new(Mutex::new(vec![])),
+            lifecycle: Lifecycle::new(),
+            brainwave_data: Arc::new(Mutex::new(StreamingStats::new(20))),
+            recorded: AtomicUsize::new(0),
+            recorder: Mutex::new(None),
+            recording_path: None,
+            sample_sender: tx,
+            sample_receiver: Mutex::new(rx),
+            // Samples arrive at 2 Hz; screen them in 5-second windows.
+            screener: Mutex::new(StreamingScreener::new(ArtifactDetector::new(ArtifactThresholds::normalized(), SAMPLE_RATE_HZ, 10))),
+            telemetry: TelemetryMonitor::new(TelemetryThresholds::default()),
+            telemetry_source: tokio::sync::Mutex::new(Box::new(SimulatedTelemetry::new(100, 0.5, 8))),
+            outlet: None,
         }
     }
 
-    async fn establish_connection(&mut self) -> Result<(), String> {
-        if self.connection_status {
-            return Err("Connection already established".to_string());
-        }
-
-        sleep(Duration::from_secs(1)).await;
-        self.connection_status = true;
-        println!("Device {} connected", self.id);
-        Ok(())
-    }
-
-    async fn disconnect(&mut self) -> Result<(), String> {
-        if !self.connection_status {
-            return Err("Device not connected".to_string());
-        }
-
-        sleep(Duration::from_secs(1)).await;
-        self.connection_status = false;
-        println!("Device {} disconnected", self.id);
+    /// Publishes the brainwave signal (one channel, 2 Hz) as an LSL stream named after the device.
+    async fn open_lsl_outlet(&mut self) -> Result<(), String> {
+        let info = StreamInfo::new(&format!("NeuroDevice {}", self.id), "EEG", 1, SAMPLE_RATE_HZ, &self.id)
+            .with_channels(&["signal".to_string()], "normalized", "EEG")
+            .with_manufacturer("BlueRAIN");
+        let outlet = LslOutlet::open(info).await?;
+        println!("Device {} streaming to LSL as '{}' (port {})", self.id, outlet.info().name, outlet.info().data_port);
+        self.outlet = Some(outlet);
         Ok(())
     }
 
     async fn collect_brainwave_data(&self, duration: Duration) -> Result<(), String> {
         let start = Instant::now();
-        let mut data_lock = self.brainwave_data.lock().unwrap();
+        let mut paused_for = Duration::ZERO;
+        let mut last_telemetry: Option<Instant> = None;
+
+        while start.elapsed().saturating_sub(paused_for) < duration {
+            paused_for += self.lifecycle.wait_while_paused().await;
+            if !self.lifecycle.is_connected() {
+                return Err(format!("Device {} disconnected while streaming", self.id));
+            }
+            if last_telemetry.is_none_or(|at| at.elapsed() >= TELEMETRY_INTERVAL) {
+                let recorded = self.drain_samples()?;
+                let mut source = self.telemetry_source.lock().await;
+                self.telemetry.poll(&self.id, source.as_mut(), recorded).await;
+                last_telemetry = Some(Instant::now());
+            }
+            if self.telemetry.safe_stop_requested() {
+                println!("Data collection stopped early for device {} to protect the session", self.id);
+                return Ok(());
+            }
 
-        while start.elapsed() < duration {
             let simulated_data = rand::thread_rng().gen_range(0.0..1.0);
-            data_lock.push(simulated_data);
-            sleep(Duration::from_millis(500)).await;
+            self.sample_sender.send(simulated_data).await.map_err(|_| "Sample buffer closed".to_string())?;
+            if let Some(outlet) = &self.outlet {
+                outlet.push_sample(&[simulated_data], None)?;
+            }
+            self.lifecycle.record_samples(1);
+            sleep(Duration::from_secs_f64(1.0 / SAMPLE_RATE_HZ)).await;
         }
         println!("Data collection completed for device {}", self.id);
         Ok(())
     }
 
-    fn analyze_data(&self) -> f32 {
-        let data_lock = self.brainwave_data.lock().unwrap();
-        let total: f32 = data_lock.iter().sum();
-        total / data_lock.len() as f32
+    /// Screens buffered samples into the running statistics and appends them to the
+    /// recording, if any; returns the total drained so far.
+    fn drain_samples(&self) -> Result<usize, String> {
+        let mut screener = self.screener.lock().unwrap();
+        let mut stats = self.brainwave_data.lock().unwrap();
+        let mut recorder = self.recorder.lock().unwrap();
+        let mut written = Ok(());
+        let mut drained = 0;
+        self.sample_receiver.lock().unwrap().drain(|batch| {
+            for sample in batch {
+                if let Some(clean) = screener.push(*sample) {
+                    stats.extend(clean);
+                }
+                if let (Some(writer), Ok(())) = (recorder.as_mut(), &written) {
+                    written = writer.write_frame(&[*sample]);
+                }
+            }
+            drained += batch.len();
+        });
+        if let Err(e) = written {
+            // Stop writing rather than leave a recording with holes in it.
+            *recorder = None;
+            return Err(format!("Recording for device {} failed: {}", self.id, e));
+        }
+        Ok(self.recorded.fetch_add(drained, Ordering::Relaxed) + drained)
+    }
+
+    /// Writes samples to `path` as a native session from now on, as they are drained.
+    fn start_recording(&mut self, path: &Path) -> Result<(), String> {
+        let writer = SessionWriter::create(path, &self.id, SAMPLE_RATE_HZ, &["signal".to_string()])?;
+        *self.recorder.lock().unwrap() = Some(writer);
+        self.recording_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Completes the recording, with its telemetry beside it in `<name>.telemetry.csv`.
+    /// Returns the telemetry file's path.
+    fn finish_recording(&self) -> Result<PathBuf, String> {
+        self.drain_samples()?;
+        let (Some(writer), Some(path)) = (self.recorder.lock().unwrap().take(), &self.recording_path) else {
+            return Err(format!("Device {} is not recording", self.id));
+        };
+        writer.finish()?;
+        let telemetry_path = path.with_extension("telemetry.csv");
+        self.telemetry.save_csv(&telemetry_path)?;
+        Ok(telemetry_path)
+    }
+
+    /// Mean of the clean windows and the share of windows rejected.
+    fn analyze_data(&self) -> SessionReport {
+        if let Err(e) = self.drain_samples() {
+            println!("{}", e);
+        }
+        let buffer = self.sample_receiver.lock().unwrap().stats();
+        if buffer.dropped > 0 {
+            println!("Device {} overran its sample buffer: {} of {} samples dropped", self.id, buffer.dropped, buffer.accepted + buffer.dropped);
+        }
+        let mut screener = self.screener.lock().unwrap();
+        if let Some(clean) = screener.finish() {
+            self.brainwave_data.lock().unwrap().extend(clean);
+        }
+        screener.report(self.brainwave_data.lock().unwrap().mean())
     }
 
     fn reset_data(&self) {
-        let mut data_lock = self.brainwave_data.lock().unwrap();
-        data_lock.clear();
+        self.sample_receiver.lock().unwrap().drain(|_| {});
+        self.brainwave_data.lock().unwrap().reset();
+        self.recorded.store(0, Ordering::Relaxed);
+        self.screener.lock().unwrap().reset();
+        self.telemetry.reset();
         println!("Data reset for device {}", self.id);
     }
 }
 
-#[async_trait]
-trait DeviceOperations {
-    async fn start(&mut self) -> Result<(), String>;
-    async fn stop(&mut self) -> Result<(), String>;
-}
-
 #[async_trait]
 impl DeviceOperations for NeuroDevice {
-    async fn start(&mut self) -> Result<(), String> {
-        self.establish_connection().await?;
-        self.collect_brainwave_data(Duration::from_secs(10)).await?;
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        if self.lifecycle.is_connected() {
+            return Err("Connection already established".to_string());
+        }
+
+        sleep(Duration::from_secs(1)).await;
+        self.lifecycle.connected()?;
+        println!("Device {} connected", self.id);
         Ok(())
     }
 
-    async fn stop(&mut self) -> Result<(), String> {
-        self.reset_data();
-        self.disconnect().await?;
+    async fn stream(&mut self, duration: Duration) -> Result<(), String> {
+        self.lifecycle.begin_stream()?;
+        let result = self.collect_brainwave_data(duration).await;
+        if let Err(e) = &result {
+            self.lifecycle.record_error(e);
+        }
+        self.lifecycle.end_stream();
+        result
+    }
+
+    async fn disconnect(&mut self) -> Result<(), String> {
+        self.lifecycle.check_can_disconnect()?;
+
+        sleep(Duration::from_secs(1)).await;
+        self.lifecycle.disconnected();
+        println!("Device {} disconnected", self.id);
         Ok(())
     }
+
+    async fn device_checks(&mut self) -> Vec<HealthCheck> {
+        let thresholds = self.telemetry.thresholds();
+        let latest = match self.telemetry.latest() {
+            Some(latest) => latest,
+            None => return vec![HealthCheck::new("telemetry", false, "no readings yet")],
+        };
+        vec![
+            HealthCheck::new(
+                "battery",
+                latest.battery_level.is_some_and(|level| level > thresholds.low_battery),
+                latest.battery_level.map_or("read failed".to_string(), |level| format!("{}%", level)),
+            ),
+            HealthCheck::new(
+                "signal",
+                latest.rssi.is_some_and(|rssi| rssi >= thresholds.min_rssi),
+                latest.rssi.map_or("read failed".to_string(), |rssi| format!("{} dBm", rssi)),
+            ),
+            HealthCheck::new(
+                "packet loss",
+                latest.packet_loss_rate <= thresholds.max_packet_loss,
+                format!("{:.1}%", latest.packet_loss_rate * 100.0),
+            ),
+            HealthCheck::new(
+                "electrodes",
+                latest.electrode_contact.iter().all(|in_contact| *in_contact),
+                format!("{}/{} in contact", latest.electrode_contact.iter().filter(|c| **c).count(), latest.electrode_contact.len()),
+            ),
+        ]
+    }
 }
 
+/// Plays a recorded session through `DeviceOperations`: `<session.brs|file.edf> [realtime|max|<n>x]`.
+async fn replay_recording(path: &Path, speed: ReplaySpeed) -> Result<(), String> {
+    let mut device = ReplayDevice::open(path)?.with_speed(speed);
+    let mut items = device.subscribe(1024);
+    let monitor = tokio::spawn(async move {
+        let mut frames = 0;
+        while let Some(item) = items.recv().await {
+            match item {
+                ReplayItem::Frame { .. } => frames += 1,
+                ReplayItem::Gap { from_secs, to_secs } => println!("Gap of {:.3}s at {:.3}s", to_secs - from_secs, from_secs),
+                ReplayItem::Event(event) => println!("Event at {:.3}s: {}", event.time_secs, event.label),
+            }
+        }
+        frames
+    });
+
+    device.connect().await?;
+    device.stream(Duration::MAX).await?;
+    match device.analyze_data() {
+        Some(avg_signal) => println!("Average brainwave signal: {}", avg_signal),
+        None => println!("No brainwave data in recording for device {}", device.id),
+    }
+    device.disconnect().await?;
+    let frames = monitor.await.map_err(|e| e.to_string())?;
+    println!("Monitor received {} frames", frames);
+    Ok(())
+}
+
+/// `--lsl` also publishes the device as an LSL stream; `--record <out.brs>` saves the session
+/// and its telemetry.
 #[tokio::main]
 async fn main() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let publish_lsl = args.iter().any(|arg| arg == "--lsl");
+    args.retain(|arg| arg != "--lsl");
+    let record_to = match args.iter().position(|arg| arg == "--record") {
+        Some(index) => {
+            let path = args.get(index + 1).cloned().ok_or_else(|| "--record needs an output path".to_string())?;
+            args.drain(index..=index + 1);
+            Some(PathBuf::from(path))
+        }
+        None => None,
+    };
+    let mut args = args.into_iter();
+    if let Some(path) = args.next() {
+        let speed = args.next().map(|speed| speed.parse()).transpose()?.unwrap_or(ReplaySpeed::Realtime);
+        return replay_recording(Path::new(&path), speed).await;
+    }
+
     let mut device = NeuroDevice::new("A123");
-    device.start().await?;
+    if let Some(path) = &record_to {
+        device.start_recording(path)?;
+    }
+    device.connect().await?;
+    if publish_lsl {
+        device.open_lsl_outlet().await?;
+    }
 
-    let avg_signal = device.analyze_data();
-    println!("Average brainwave signal: {}", avg_signal);
+    // An operator pauses the stream for a while from another task.
+    let control = device.lifecycle().clone();
+    let operator = tokio::spawn(async move {
+        sleep(Duration::from_secs(3)).await;
+        if control.pause().is_ok() {
+            println!("Stream paused by operator");
+            sleep(Duration::from_secs(2)).await;
+            if control.resume().is_ok() {
+                println!("Stream resumed by operator");
+            }
+        }
+    });
+    device.stream(Duration::from_secs(10)).await?;
+    operator.await.map_err(|e| e.to_string())?;
+
+    let status = device.status();
+    println!(
+        "Device {} {:?}: up {:?}, {} samples, {} errors",
+        device.id, status.state, status.uptime.unwrap_or_default(), status.samples_received, status.errors
+    );
+    let health = device.health_check().await;
+    println!("Health of device {}: {}", device.id, health);
+
+    let report = device.analyze_data();
+    println!("Brainwave data for device {}: {}", device.id, report.summary());
+    match report.mean {
+        Some(avg_signal) => println!("Average brainwave signal: {}", avg_signal),
+        None => println!("No clean brainwave data collected for device {}", device.id),
+    }
+    if let Some(latest) = device.telemetry.latest() {
+        let battery = latest.battery_level.map_or("unknown".to_string(), |level| format!("{}%", level));
+        let rssi = latest.rssi.map_or("unknown".to_string(), |rssi| format!("{} dBm", rssi));
+        println!("Battery {}, RSSI {}, packet loss {:.1}%", battery, rssi, latest.packet_loss_rate * 100.0);
+    }
+    if let Some(path) = &record_to {
+        let telemetry_path = device.finish_recording()?;
+        println!("Session saved to {} with telemetry in {}", path.display(), telemetry_path.display());
+    }
 
-    device.stop().await?;
+    device.reset_data();
+    device.disconnect().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use session_store::StoredSession;
+
+    #[tokio::test]
+    async fn recording_is_written_as_samples_are_drained() {
+        let path = std::env::temp_dir().join(format!("bluerain-ndo-test-{}.brs", std::process::id()));
+        let mut device = NeuroDevice::new("T1");
+        device.start_recording(&path).unwrap();
+        let samples: Vec<f64> = (0..600).map(|i| (i % 7) as f64 / 7.0).collect();
+        for chunk in samples.chunks(200) {
+            for sample in chunk {
+                device.sample_sender.send(*sample).await.unwrap();
+            }
+            device.drain_samples().unwrap();
+        }
+        assert_eq!(device.recorded.load(Ordering::Relaxed), 600);
+
+        let telemetry_path = device.finish_recording().unwrap();
+        let session = StoredSession::load(&path).unwrap();
+        assert_eq!(session.to_channels(), vec![samples]);
+        assert_eq!(session.sample_rate, SAMPLE_RATE_HZ);
+        assert!(device.finish_recording().is_err());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(telemetry_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn device_checks_use_the_latest_reading() {
+        let mut device = NeuroDevice::new("T2");
+        assert!(!device.device_checks().await[0].passed);
+        let mut source = SimulatedTelemetry::new(90, 0.0, 2);
+        device.telemetry.poll("T2", &mut source, 0).await;
+        let checks = device.device_checks().await;
+        let names: Vec<&str> = checks.iter().map(|check| check.name.as_str()).collect();
+        assert_eq!(names, vec!["battery", "signal", "packet loss", "electrodes"]);
+        assert!(checks[0].passed);
+    }
+}
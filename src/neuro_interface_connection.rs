@@ -1,108 +1,504 @@
-```rust
 use std::error::Error;
 use std::time::{Duration, Instant};
-use std::sync::{Arc, Mutex};
-use futures::executor::block_on;
-use tokio::{task, time};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use async_trait::async_trait;
+use ed25519_dalek::VerifyingKey;
+use tokio::time;
 
-mod bluez;
+#[allow(dead_code)]
+mod artifacts;
+#[allow(dead_code)]
 mod neurofeedback;
-mod signal_analysis;
-mod data_storage;
+#[allow(dead_code)]
+mod device_manager;
+#[allow(dead_code)]
+mod device_operations;
+#[allow(dead_code)]
+mod dfu;
+#[allow(dead_code)]
+mod discovery;
+#[allow(dead_code)]
+mod features;
+#[allow(dead_code)]
+mod filters;
+#[allow(dead_code)]
+mod gatt;
+#[allow(dead_code)]
+mod lsl;
+#[allow(dead_code)]
+mod nus;
+#[allow(dead_code)]
+mod pipeline;
+#[allow(dead_code)]
+mod session_store;
+#[allow(dead_code)]
+mod streaming_stats;
+#[allow(dead_code)]
+mod xml;
+
+use artifacts::{ArtifactDetector, ArtifactThresholds, SessionReport, StreamingScreener};
+use device_manager::{DeviceManager, RestartPolicy};
+use device_operations::{DeviceOperations, HealthCheck, Lifecycle};
+use dfu::{DfuUpdater, FirmwareImage, FirmwareVersion, MockDfuPeripheral};
+use discovery::{DeviceAllowlist, MockAdapter, Scanner, NORDIC_UART_SERVICE};
+use filters::FilterBank;
+use gatt::{ControlCommand, SamplePacket, SequenceTracker, SimulatedPeripheral, StatusPacket, DEFAULT_GAIN};
+use lsl::{local_clock, LslOutlet, StreamInfo};
+use nus::{NusFramer, NusProfile, SimulatedNusPeripheral};
+use streaming_stats::StreamingStats;
+
+type DeviceResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Set by `--lsl`: every connected device publishes its samples as an LSL stream.
+static PUBLISH_LSL: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--firmware`: every BlueRAIN headset is updated to this signed image once connected.
+static FIRMWARE_UPDATE: OnceLock<(DfuUpdater, Vec<u8>)> = OnceLock::new();
+
+/// Firmware the simulated headsets' bootloaders report before any update.
+const SIMULATED_FIRMWARE: FirmwareVersion = FirmwareVersion { major: 1, minor: 0, patch: 0 };
+
+/// How a device streams its samples.
+#[derive(Debug, Clone, PartialEq)]
+enum DeviceProfile {
+    /// The BlueRAIN GATT service (see `gatt`).
+    BlueRain,
+    /// A generic board on the Nordic UART Service (see `nus`).
+    Nus(NusProfile),
+}
+
+/// The simulated radio link behind a device, per profile.
+#[derive(Debug)]
+enum Link {
+    /// `gain` is the last gain written to the headset, which sets the scale of its samples.
+    BlueRain { peripheral: SimulatedPeripheral, sequence: SequenceTracker, gain: u8 },
+    Nus { peripheral: Box<SimulatedNusPeripheral>, framer: Box<NusFramer> },
+}
+
+impl Link {
+    fn open(profile: DeviceProfile) -> Self {
+        match profile {
+            DeviceProfile::BlueRain => Link::BlueRain {
+                peripheral: SimulatedPeripheral::new(8),
+                sequence: SequenceTracker::default(),
+                gain: DEFAULT_GAIN,
+            },
+            DeviceProfile::Nus(profile) => Link::Nus {
+                framer: Box::new(NusFramer::new(profile.clone())),
+                peripheral: Box::new(SimulatedNusPeripheral::new(profile)),
+            },
+        }
+    }
+
+    fn channel_count(&self) -> usize {
+        match self {
+            Link::BlueRain { peripheral, .. } => peripheral.channel_count() as usize,
+            Link::Nus { peripheral, .. } => peripheral.profile().channel_count,
+        }
+    }
+
+    fn sample_rate(&self) -> f64 {
+        match self {
+            Link::BlueRain { peripheral, .. } => peripheral.sample_rate() as f64,
+            Link::Nus { peripheral, .. } => peripheral.profile().sample_rate,
+        }
+    }
+}
 
 #[derive(Debug)]
 struct BluetoothDevice {
     address: String,
-    connection_state: bool,
-    data_stream: Arc<Mutex<Vec<f32>>>,
+    lifecycle: Lifecycle,
+    data_stream: Arc<Mutex<StreamingStats>>,
+    link: Mutex<Link>,
+    filters: Mutex<FilterBank>,
+    screener: Mutex<StreamingScreener>,
+    outlet: Option<LslOutlet>,
+    /// The headset's DFU bootloader; NUS boards have none.
+    bootloader: Option<MockDfuPeripheral>,
 }
 
 impl BluetoothDevice {
-    fn new(address: String) -> Self {
-        BluetoothDevice {
+    /// Fails if the filters cannot run at the profile's sample rate.
+    fn with_profile(address: String, profile: DeviceProfile) -> Result<Self, String> {
+        let bootloader = (profile == DeviceProfile::BlueRain).then(|| MockDfuPeripheral::new(SIMULATED_FIRMWARE));
+        let link = Link::open(profile);
+        let (channel_count, sample_rate) = (link.channel_count(), link.sample_rate());
+        let window_len = sample_rate.round().max(1.0) as usize;
+        Ok(BluetoothDevice {
             address,
-            connection_state: false,
-            data_stream: Arc::new(Mutex::new(vec![])),
-        }
+            lifecycle: Lifecycle::new(),
+            data_stream: Arc::new(Mutex::new(StreamingStats::new(window_len))),
+            link: Mutex::new(link),
+            filters: Mutex::new(FilterBank::eeg_default(sample_rate, 50.0, channel_count)?),
+            screener: Mutex::new(StreamingScreener::new(ArtifactDetector::new(
+                ArtifactThresholds::eeg_microvolts(),
+                sample_rate,
+                window_len,
+            ))),
+            outlet: None,
+            bootloader,
+        })
     }
 
-    async fn connect(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.connection_state {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "Device already connected")));
-        }
-        // Simulate Bluetooth™ connection
-        time::sleep(Duration::from_secs(2)).await;
-        self.connection_state = true;
-        println!("Device {} connected successfully", self.address);
+    /// Publishes the raw µV samples as an LSL `EEG` stream named after the device.
+    async fn open_lsl_outlet(&mut self) -> DeviceResult<()> {
+        let (channel_count, sample_rate) = {
+            let link = self.link.lock().unwrap();
+            (link.channel_count(), link.sample_rate())
+        };
+        let labels: Vec<String> = (1..=channel_count).map(|n| format!("Ch{}", n)).collect();
+        let info = StreamInfo::new(&format!("BlueRAIN {}", self.address), "EEG", channel_count, sample_rate, &self.address)
+            .with_channels(&labels, "microvolts", "EEG")
+            .with_manufacturer("BlueRAIN");
+        let outlet = LslOutlet::open(info).await?;
+        println!("Device {} streaming to LSL as '{}' (port {})", self.address, outlet.info().name, outlet.info().data_port);
+        self.outlet = Some(outlet);
         Ok(())
     }
 
-    async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
-        if !self.connection_state {
+    async fn update_firmware(&mut self, updater: &DfuUpdater, image: &[u8]) -> DeviceResult<()> {
+        if !self.lifecycle.is_connected() {
             return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotConnected, "Device not connected")));
         }
-        // Simulate Bluetooth™ disconnection
-        time::sleep(Duration::from_secs(1)).await;
-        self.connection_state = false;
-        println!("Device {} disconnected", self.address);
+        let bootloader = self
+            .bootloader
+            .as_mut()
+            .ok_or_else(|| format!("Device {} has no BlueRAIN bootloader", self.address))?;
+        let version = updater.update(bootloader, image).await?;
+        println!("Device {} now running firmware {}", self.address, version);
         Ok(())
     }
 
-    async fn read_signal(&self) -> Result<f32, Box<dyn Error>> {
-        let random_signal = rand::random::<f32>();
-        time::sleep(Duration::from_millis(100)).await;
-        Ok(random_signal)
+    /// Waits for the next notification and returns the frames it completed, in µV.
+    async fn read_signal(&self) -> DeviceResult<Vec<Vec<f64>>> {
+        let not_streaming = || std::io::Error::new(std::io::ErrorKind::NotConnected, "Device is not streaming");
+        let (frames, sample_rate) = {
+            let mut link = self.link.lock().unwrap();
+            let sample_rate = link.sample_rate();
+            let frames = match &mut *link {
+                Link::BlueRain { peripheral, sequence, gain } => {
+                    let packet = SamplePacket::decode(&peripheral.next_notification().ok_or_else(not_streaming)?)?;
+                    let missed = sequence.observe(packet.sequence);
+                    if missed > 0 {
+                        println!("Device {} dropped {} packets before #{}", self.address, missed, packet.sequence);
+                    }
+                    let lsb = gatt::lsb_microvolts(*gain) as f64;
+                    packet.frames.iter().map(|frame| frame.iter().map(|count| *count as f64 * lsb).collect()).collect()
+                }
+                // A NUS notification may end mid-frame; the framer keeps the remainder.
+                Link::Nus { peripheral, framer } => framer.push(&peripheral.next_notification().ok_or_else(not_streaming)?),
+            };
+            (frames, sample_rate)
+        };
+
+        // Notifications arrive at the rate the device fills them.
+        time::sleep(Duration::from_secs_f64(frames.len() as f64 / sample_rate)).await;
+        Ok(frames)
+    }
+
+    fn send_command(&self, command: ControlCommand) -> DeviceResult<Option<Vec<u8>>> {
+        match &mut *self.link.lock().unwrap() {
+            Link::BlueRain { peripheral, gain, .. } => {
+                let reply = peripheral.write_control(&command.encode())?;
+                if let ControlCommand::SetGain(new_gain) = command {
+                    *gain = new_gain;
+                }
+                Ok(reply)
+            }
+            // NUS boards only know start and stop, and have no status to report.
+            Link::Nus { peripheral, .. } => {
+                let rx = match command {
+                    ControlCommand::StartStream => peripheral.profile().start_command.clone(),
+                    ControlCommand::StopStream => peripheral.profile().stop_command.clone(),
+                    _ => return Ok(None),
+                };
+                peripheral.write_rx(&rx)?;
+                Ok(None)
+            }
+        }
     }
 
-    async fn collect_data(&self, duration: Duration) -> Result<(), Box<dyn Error>> {
+    async fn collect_data(&self, duration: Duration) -> DeviceResult<()> {
         let start_time = Instant::now();
-        let mut data_lock = self.data_stream.lock().unwrap();
-        
-        while start_time.elapsed() < duration {
-            let signal = self.read_signal().await?;
-            data_lock.push(signal);
+        let mut paused_for = Duration::ZERO;
+
+        while start_time.elapsed().saturating_sub(paused_for) < duration {
+            if self.lifecycle.is_paused() {
+                // Stop the radio traffic for the length of the pause.
+                self.send_command(ControlCommand::StopStream)?;
+                paused_for += self.lifecycle.wait_while_paused().await;
+                if !self.lifecycle.is_connected() {
+                    return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotConnected, "Device disconnected")));
+                }
+                self.send_command(ControlCommand::StartStream)?;
+            }
+            let frames = self.read_signal().await?;
+            let frame_count = frames.len();
+            self.lifecycle.record_samples(frame_count as u64);
+            let mut filters = self.filters.lock().unwrap();
+            let mut screener = self.screener.lock().unwrap();
+            // The notification just arrived, so its last frame was sampled about now.
+            let (received, sample_rate) = (local_clock(), self.link.lock().unwrap().sample_rate());
+            for (index, mut microvolts) in frames.into_iter().enumerate() {
+                if let Some(outlet) = &self.outlet {
+                    let age = (frame_count - 1 - index) as f64 / sample_rate;
+                    outlet.push_sample(&microvolts, Some(received - age))?;
+                }
+                filters.process_frame(&mut microvolts);
+                if let Some(clean) = screener.push(microvolts[0]) {
+                    self.data_stream.lock().unwrap().extend(clean);
+                }
+            }
         }
-        
+
         Ok(())
     }
 
-    fn analyze_data(&self) -> Result<f32, Box<dyn Error>> {
-        let data_lock = self.data_stream.lock().unwrap();
-        let sum: f32 = data_lock.iter().sum();
-        let average = sum / data_lock.len() as f32;
-        Ok(average)
+    /// Mean of the clean windows collected so far and the share of windows rejected.
+    fn analyze_data(&self) -> DeviceResult<SessionReport> {
+        let mut screener = self.screener.lock().unwrap();
+        if let Some(clean) = screener.finish() {
+            self.data_stream.lock().unwrap().extend(clean);
+        }
+        Ok(screener.report(self.data_stream.lock().unwrap().mean()))
     }
 
-    async fn sync_with_cloud(&self) -> Result<(), Box<dyn Error>> {
-        let data_lock = self.data_stream.lock().unwrap();
+    async fn sync_with_cloud(&self) -> DeviceResult<()> {
+        let summary = self.data_stream.lock().unwrap().summary();
         // Simulate cloud sync
         time::sleep(Duration::from_secs(1)).await;
-        println!("Synced data to cloud: {:?}", *data_lock);
+        println!("Synced data to cloud: {:?}", summary);
         Ok(())
     }
 }
 
-async fn handle_device_operations(device: &mut BluetoothDevice) -> Result<(), Box<dyn Error>> {
+#[async_trait]
+impl DeviceOperations for BluetoothDevice {
+    fn id(&self) -> &str {
+        &self.address
+    }
+
+    fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        if self.lifecycle.is_connected() {
+            return Err("Device already connected".to_string());
+        }
+        // Simulate Bluetooth™ connection
+        time::sleep(Duration::from_secs(2)).await;
+        self.send_command(ControlCommand::StartStream).map_err(|e| e.to_string())?;
+        self.lifecycle.connected()?;
+        println!("Device {} connected successfully", self.address);
+        Ok(())
+    }
+
+    async fn stream(&mut self, duration: Duration) -> Result<(), String> {
+        self.lifecycle.begin_stream()?;
+        let result = self.collect_data(duration).await.map_err(|e| e.to_string());
+        if let Err(e) = &result {
+            self.lifecycle.record_error(e);
+        }
+        self.lifecycle.end_stream();
+        result
+    }
+
+    async fn disconnect(&mut self) -> Result<(), String> {
+        self.lifecycle.check_can_disconnect()?;
+        // Simulate Bluetooth™ disconnection
+        self.send_command(ControlCommand::StopStream).map_err(|e| e.to_string())?;
+        time::sleep(Duration::from_secs(1)).await;
+        self.lifecycle.disconnected();
+        println!("Device {} disconnected", self.address);
+        Ok(())
+    }
+
+    async fn device_checks(&mut self) -> Vec<HealthCheck> {
+        if let Link::Nus { framer, .. } = &*self.link.lock().unwrap() {
+            let expected = framer.frames + framer.lost_frames();
+            let loss = if expected == 0 { 0.0 } else { framer.lost_frames() as f64 / expected as f64 };
+            return vec![HealthCheck::new(
+                "framing",
+                loss <= 0.05,
+                format!("{} frames, {} bad lines, {} bytes skipped", framer.frames, framer.bad_lines, framer.skipped_bytes),
+            )];
+        }
+        let mut checks = vec![];
+        match self.send_command(ControlCommand::RequestStatus) {
+            Ok(Some(status)) => match StatusPacket::decode(&status) {
+                Ok(status) => {
+                    checks.push(HealthCheck::new("firmware", status.error_code == 0, format!("error code {}", status.error_code)));
+                    checks.push(HealthCheck::new("battery", status.battery_level > 20, format!("{}%", status.battery_level)));
+                }
+                Err(e) => checks.push(HealthCheck::new("status", false, e.to_string())),
+            },
+            Ok(None) => checks.push(HealthCheck::new("status", false, "no status reply")),
+            Err(e) => checks.push(HealthCheck::new("status", false, e.to_string())),
+        }
+        if let Link::BlueRain { sequence, .. } = &*self.link.lock().unwrap() {
+            let expected = sequence.received + sequence.lost;
+            let loss = if expected == 0 { 0.0 } else { sequence.lost as f64 / expected as f64 };
+            checks.push(HealthCheck::new("packet loss", loss <= 0.05, format!("{} of {} packets lost", sequence.lost, expected)));
+        }
+        checks
+    }
+}
+
+async fn handle_device_operations(device: &mut BluetoothDevice) -> DeviceResult<()> {
     device.connect().await?;
-    device.collect_data(Duration::from_secs(5)).await?;
-    let avg_signal = device.analyze_data()?;
-    println!("Average signal: {}", avg_signal);
+    if let Some((updater, image)) = FIRMWARE_UPDATE.get() {
+        if device.bootloader.is_some() {
+            device.update_firmware(updater, image).await?;
+        }
+    }
+    if PUBLISH_LSL.load(Ordering::Relaxed) {
+        device.open_lsl_outlet().await?;
+    }
+    device.stream(Duration::from_secs(5)).await?;
+    let health = device.health_check().await;
+    println!("Health of device {}: {}", device.address, health);
+    let report = device.analyze_data()?;
+    println!("Device {}: {}", device.address, report.summary());
+    match report.mean {
+        Some(avg_signal) => println!("Average signal: {}", avg_signal),
+        None => println!("No clean signal recorded for device {}", device.address),
+    }
     device.sync_with_cloud().await?;
     device.disconnect().await?;
     Ok(())
 }
 
+/// Parses an Ed25519 public key written as 64 hex digits.
+fn parse_public_key(hex: &str) -> Result<VerifyingKey, String> {
+    let invalid = || format!("Firmware key '{}' must be 64 hex digits", hex);
+    if !hex.is_ascii() || hex.len() != 64 {
+        return Err(invalid());
+    }
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("Firmware key is not a valid Ed25519 key: {}", e))
+}
+
+/// Reads and checks the image for `--firmware <image> --firmware-key <hex>` before any device is touched.
+fn firmware_update(image_path: &str, key: Option<&String>) -> Result<(DfuUpdater, Vec<u8>), String> {
+    let key = parse_public_key(key.ok_or("--firmware needs --firmware-key <hex public key>")?)?;
+    let image = std::fs::read(image_path).map_err(|e| format!("Cannot read firmware image {}: {}", image_path, e))?;
+    let version = FirmwareImage::verify(&image, &key)?.version;
+    println!("Updating BlueRAIN headsets to firmware {}", version);
+    Ok((DfuUpdater::new(key), image))
+}
+
+/// `--lsl` publishes each device as an LSL stream while it records. `--nus <profile>` also
+/// connects boards advertising the Nordic UART Service, framing their data with the given
+/// profile, e.g. `format=i16le,channels=8,scale=0.195,sync=a0` (see `nus::NusProfile`).
+/// `--firmware <image> --firmware-key <hex>` updates each BlueRAIN headset over DFU after it
+/// connects, if the image is signed by that key.
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let mut device = BluetoothDevice::new("00:1A:7D:DA:71:13".to_string());
-    let task1 = task::spawn(handle_device_operations(&mut device));
+async fn main() -> DeviceResult<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let value_of = |flag: &str| args.iter().position(|arg| arg == flag).map(|index| args.get(index + 1));
+    PUBLISH_LSL.store(args.iter().any(|arg| arg == "--lsl"), Ordering::Relaxed);
+    let nus_profile = match value_of("--nus") {
+        Some(profile) => Some(profile.ok_or("--nus needs a profile")?.parse::<NusProfile>()?),
+        None => None,
+    };
+    if let Some(image) = value_of("--firmware") {
+        let update = firmware_update(image.ok_or("--firmware needs an image path")?, value_of("--firmware-key").flatten())?;
+        let _ = FIRMWARE_UPDATE.set(update);
+    }
+    let mut manager = DeviceManager::new(RestartPolicy::OnFailure {
+        max_restarts: 3,
+        backoff: Duration::from_secs(2),
+    });
 
-    let mut device2 = BluetoothDevice::new("00:1A:7D:DA:71:14".to_string());
-    let task2 = task::spawn(handle_device_operations(&mut device2));
+    let mut allowlist = DeviceAllowlist::new().allow_name_prefix("BlueRAIN");
+    if nus_profile.is_some() {
+        allowlist = allowlist.allow_service(NORDIC_UART_SERVICE);
+    }
+    let scanner = Scanner::new(MockAdapter::with_demo_peripherals()).with_allowlist(allowlist);
+    for found in scanner.scan(Duration::from_secs(3)).await? {
+        println!("Found {} ({:?}) at {} dBm: {:?}", found.address, found.name, found.rssi, found.capabilities);
+        match &nus_profile {
+            Some(profile) if found.services.iter().any(|service| service == NORDIC_UART_SERVICE) => {
+                manager.add_device_with_profile(&found.address, DeviceProfile::Nus(profile.clone()))?
+            }
+            _ => manager.add_device(&found.address)?,
+        }
+    }
 
-    task1.await??;
-    task2.await??;
+    manager.wait_all().await;
+    for (address, status) in manager.statuses() {
+        println!("Device {}: {:?} after {} restarts", address, status.state, status.restarts);
+    }
 
     Ok(())
 }
-```
\ No newline at end of file
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dfu::DfuTarget;
+    use ed25519_dalek::SigningKey;
+
+    const V2: FirmwareVersion = FirmwareVersion { major: 1, minor: 1, patch: 0 };
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn key_hex(key: &SigningKey) -> String {
+        key.verifying_key().to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn public_keys_are_parsed_from_hex() {
+        let key = signing_key();
+        assert_eq!(parse_public_key(&key_hex(&key)), Ok(key.verifying_key()));
+        assert_eq!(parse_public_key(&key_hex(&key).to_uppercase()), Ok(key.verifying_key()));
+        for bad in ["", "abcd", &"zz".repeat(32), &"ab".repeat(33)] {
+            assert!(parse_public_key(bad).unwrap_err().contains("64 hex digits"), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn firmware_images_are_checked_before_any_device_is_touched() {
+        let path = std::env::temp_dir().join(format!("bluerain-nic-test-{}.brfw", std::process::id()));
+        std::fs::write(&path, FirmwareImage::sign(V2, b"firmware", &signing_key())).unwrap();
+        let image_path = path.to_str().unwrap();
+
+        let (_, image) = firmware_update(image_path, Some(&key_hex(&signing_key()))).unwrap();
+        assert_eq!(image, std::fs::read(&path).unwrap());
+
+        assert!(firmware_update(image_path, None).unwrap_err().contains("--firmware-key"));
+        let other_key = key_hex(&SigningKey::from_bytes(&[9; 32]));
+        assert_eq!(firmware_update(image_path, Some(&other_key)).unwrap_err(), "Firmware image signature is invalid");
+        std::fs::remove_file(&path).unwrap();
+        assert!(firmware_update(image_path, Some(&key_hex(&signing_key()))).unwrap_err().starts_with("Cannot read"));
+    }
+
+    #[tokio::test]
+    async fn connected_headsets_update_through_their_bootloader() {
+        let updater = DfuUpdater::new(signing_key().verifying_key());
+        let image = FirmwareImage::sign(V2, &[0x5a; 600], &signing_key());
+
+        let mut device = BluetoothDevice::with_profile("00:1A:7D:DA:71:13".to_string(), DeviceProfile::BlueRain).unwrap();
+        let error = device.update_firmware(&updater, &image).await.unwrap_err();
+        assert_eq!(error.to_string(), "Device not connected");
+
+        device.lifecycle.connected().unwrap();
+        device.update_firmware(&updater, &image).await.unwrap();
+        assert_eq!(device.bootloader.as_mut().unwrap().running_version().await, Ok(V2));
+        let error = device.update_firmware(&updater, &image).await.unwrap_err();
+        assert_eq!(error.to_string(), "Firmware 1.1.0 is already installed");
+
+        let mut board = BluetoothDevice::with_profile("Proto-UART".to_string(), DeviceProfile::Nus(NusProfile::default())).unwrap();
+        board.lifecycle.connected().unwrap();
+        let error = board.update_firmware(&updater, &image).await.unwrap_err();
+        assert_eq!(error.to_string(), "Device Proto-UART has no BlueRAIN bootloader");
+    }
+}
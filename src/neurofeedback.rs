@@ -0,0 +1,154 @@
+use super::features::{Band, BandPowers};
+
+/// When to reward: the target band's share of total power held above `threshold` for
+/// `hold_secs`. While it stays above, a further reward follows every `hold_secs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewardRule {
+    pub band: Band,
+    /// Relative power, 0 to 1.
+    pub threshold: f64,
+    pub hold_secs: f64,
+}
+
+impl Default for RewardRule {
+    /// Alpha up-training: reward half a second of alpha above 40% of total power.
+    fn default() -> Self {
+        RewardRule {
+            band: Band::Alpha,
+            threshold: 0.4,
+            hold_secs: 0.5,
+        }
+    }
+}
+
+impl RewardRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.threshold) {
+            return Err(format!("Reward threshold {} is outside 0 to 1", self.threshold));
+        }
+        if !(self.hold_secs > 0.0 && self.hold_secs.is_finite()) {
+            return Err(format!("Reward hold time {} must be positive", self.hold_secs));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewardEvent {
+    pub time_secs: f64,
+    pub band: Band,
+    /// Relative power of the band when the reward was given.
+    pub score: f64,
+    /// 1 for the first reward after the band rose above threshold, then 2, 3...
+    pub streak: u32,
+}
+
+/// Turns a stream of band powers into reward events according to a `RewardRule`.
+#[derive(Debug, Clone)]
+pub struct RewardTracker {
+    rule: RewardRule,
+    /// Start of the current stretch above threshold, or of the current hold after a reward.
+    above_since: Option<f64>,
+    streak: u32,
+}
+
+impl RewardTracker {
+    pub fn new(rule: RewardRule) -> Result<Self, String> {
+        rule.validate()?;
+        Ok(RewardTracker {
+            rule,
+            above_since: None,
+            streak: 0,
+        })
+    }
+
+    pub fn rule(&self) -> &RewardRule {
+        &self.rule
+    }
+
+    /// Feeds the band powers measured at `time_secs`; returns a reward if one is due.
+    pub fn update(&mut self, time_secs: f64, powers: &BandPowers) -> Option<RewardEvent> {
+        let score = powers.relative(self.rule.band);
+        if score <= self.rule.threshold {
+            self.reset();
+            return None;
+        }
+        let since = *self.above_since.get_or_insert(time_secs);
+        if time_secs - since < self.rule.hold_secs {
+            return None;
+        }
+        self.above_since = Some(time_secs);
+        self.streak += 1;
+        Some(RewardEvent {
+            time_secs,
+            band: self.rule.band,
+            score,
+            streak: self.streak,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.above_since = None;
+        self.streak = 0;
+    }
+}
+
+/// When to withhold rewards: the band's share of total power above `threshold`, e.g. theta
+/// for drowsiness or gamma for muscle tension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InhibitRule {
+    pub band: Band,
+    /// Relative power, 0 to 1.
+    pub threshold: f64,
+}
+
+impl InhibitRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.threshold) {
+            return Err(format!("Inhibit threshold {} is outside 0 to 1", self.threshold));
+        }
+        Ok(())
+    }
+}
+
+/// The inhibit band crossed its threshold: `active` when it went above, not when it fell back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InhibitEvent {
+    pub time_secs: f64,
+    pub band: Band,
+    pub score: f64,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct InhibitTracker {
+    rule: InhibitRule,
+    active: bool,
+}
+
+impl InhibitTracker {
+    pub fn new(rule: InhibitRule) -> Result<Self, String> {
+        rule.validate()?;
+        Ok(InhibitTracker { rule, active: false })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Feeds the band powers measured at `time_secs`; returns an event when the inhibit starts or ends.
+    pub fn update(&mut self, time_secs: f64, powers: &BandPowers) -> Option<InhibitEvent> {
+        let score = powers.relative(self.rule.band);
+        let active = score > self.rule.threshold;
+        if active == self.active {
+            return None;
+        }
+        self.active = active;
+        Some(InhibitEvent {
+            time_secs,
+            band: self.rule.band,
+            score,
+            active,
+        })
+    }
+}
@@ -0,0 +1,483 @@
+use std::str::FromStr;
+use rand::Rng;
+
+use super::gatt::{pack_i24, unpack_i24};
+
+/// Characteristics of the Nordic UART Service (`discovery::NORDIC_UART_SERVICE`): the host
+/// writes commands to RX and the board notifies samples on TX.
+pub const NUS_RX_CHARACTERISTIC: &str = "6e400002-b5a3-f393-e0a9-e50e24dcca9e";
+pub const NUS_TX_CHARACTERISTIC: &str = "6e400003-b5a3-f393-e0a9-e50e24dcca9e";
+
+/// ATT payload of a notification before any MTU exchange (23-byte MTU less the 3-byte header).
+pub const DEFAULT_NOTIFICATION_PAYLOAD: usize = 20;
+
+/// Lines longer than this without a newline are dropped rather than buffered forever.
+const MAX_LINE_LENGTH: usize = 1024;
+
+/// Encoding of one value in a binary frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NusValue {
+    I16Le,
+    I16Be,
+    I24Le,
+    I24Be,
+    I32Le,
+    F32Le,
+}
+
+impl NusValue {
+    pub fn size(self) -> usize {
+        match self {
+            NusValue::I16Le | NusValue::I16Be => 2,
+            NusValue::I24Le | NusValue::I24Be => 3,
+            NusValue::I32Le | NusValue::F32Le => 4,
+        }
+    }
+
+    /// Decodes one value from the first `size()` bytes.
+    pub fn read(self, bytes: &[u8]) -> f64 {
+        match self {
+            NusValue::I16Le => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            NusValue::I16Be => i16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+            NusValue::I24Le => unpack_i24(&[bytes[2], bytes[1], bytes[0]]) as f64,
+            NusValue::I24Be => unpack_i24(bytes) as f64,
+            NusValue::I32Le => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            NusValue::F32Le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        }
+    }
+
+    /// Encodes `counts`, saturating at the range of the integer formats.
+    pub fn write(self, counts: f64, out: &mut Vec<u8>) {
+        match self {
+            NusValue::I16Le => out.extend_from_slice(&(counts.round() as i16).to_le_bytes()),
+            NusValue::I16Be => out.extend_from_slice(&(counts.round() as i16).to_be_bytes()),
+            NusValue::I24Le => {
+                let mut be = Vec::with_capacity(3);
+                pack_i24(counts.round() as i32, &mut be);
+                out.extend(be.iter().rev());
+            }
+            NusValue::I24Be => pack_i24(counts.round() as i32, out),
+            NusValue::I32Le => out.extend_from_slice(&(counts.round() as i32).to_le_bytes()),
+            NusValue::F32Le => out.extend_from_slice(&(counts as f32).to_le_bytes()),
+        }
+    }
+}
+
+impl FromStr for NusValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "i16le" => Ok(NusValue::I16Le),
+            "i16be" => Ok(NusValue::I16Be),
+            "i24le" => Ok(NusValue::I24Le),
+            "i24be" => Ok(NusValue::I24Be),
+            "i32le" => Ok(NusValue::I32Le),
+            "f32le" => Ok(NusValue::F32Le),
+            other => Err(format!("Unknown sample format '{}'", other)),
+        }
+    }
+}
+
+/// How samples are laid out in the TX byte stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NusFormat {
+    /// One text line per frame, values separated by commas, semicolons, tabs or spaces.
+    Lines,
+    /// Fixed-size frames: the `sync` bytes followed by one value per channel.
+    Binary { value: NusValue, sync: Vec<u8> },
+}
+
+/// What a board streaming over NUS sends, and how to turn it into µV.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NusProfile {
+    pub format: NusFormat,
+    pub channel_count: usize,
+    pub sample_rate: f64,
+    /// µV per count (or per unit on a text line).
+    pub scale: f64,
+    /// Written to RX to start and stop the stream.
+    pub start_command: Vec<u8>,
+    pub stop_command: Vec<u8>,
+}
+
+impl Default for NusProfile {
+    fn default() -> Self {
+        NusProfile {
+            format: NusFormat::Lines,
+            channel_count: 4,
+            sample_rate: 250.0,
+            scale: 1.0,
+            start_command: b"b".to_vec(),
+            stop_command: b"s".to_vec(),
+        }
+    }
+}
+
+impl NusProfile {
+    pub fn with_format(mut self, format: NusFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_channels(mut self, channel_count: usize) -> Self {
+        self.channel_count = channel_count;
+        self
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_commands(mut self, start: &[u8], stop: &[u8]) -> Self {
+        self.start_command = start.to_vec();
+        self.stop_command = stop.to_vec();
+        self
+    }
+
+    /// Bytes per binary frame, or `None` for line-based formats.
+    pub fn frame_len(&self) -> Option<usize> {
+        match &self.format {
+            NusFormat::Lines => None,
+            NusFormat::Binary { value, sync } => Some(sync.len() + self.channel_count * value.size()),
+        }
+    }
+
+    fn validate(self) -> Result<Self, String> {
+        if self.channel_count == 0 || self.channel_count > 64 {
+            return Err(format!("Channel count must be 1-64, got {}", self.channel_count));
+        }
+        if !(self.sample_rate > 0.0 && self.sample_rate.is_finite()) {
+            return Err(format!("Sample rate must be positive, got {}", self.sample_rate));
+        }
+        if !(self.scale.is_finite() && self.scale != 0.0) {
+            return Err(format!("Scale must be non-zero, got {}", self.scale));
+        }
+        Ok(self)
+    }
+}
+
+/// Parses `key=value` pairs separated by commas, e.g. `format=i16le,channels=8,scale=0.195,sync=a0`.
+/// Keys: `format` (`lines` or a binary value type), `channels`, `rate`, `scale`, `sync` (hex),
+/// `start` and `stop` (RX commands). Unset keys keep the defaults.
+impl FromStr for NusProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut profile = NusProfile::default();
+        let mut value = None;
+        let mut sync = Vec::new();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, setting) = pair
+                .split_once('=')
+                .map(|(key, setting)| (key.trim(), setting.trim()))
+                .ok_or_else(|| format!("Expected key=value, got '{}'", pair))?;
+            let number = |what: &str| setting.parse::<f64>().map_err(|_| format!("Invalid {} '{}'", what, setting));
+            match key {
+                "format" if setting.eq_ignore_ascii_case("lines") => value = None,
+                "format" => value = Some(setting.parse::<NusValue>()?),
+                "channels" => {
+                    profile.channel_count = setting.parse().map_err(|_| format!("Invalid channel count '{}'", setting))?
+                }
+                "rate" => profile.sample_rate = number("sample rate")?,
+                "scale" => profile.scale = number("scale")?,
+                "sync" => sync = parse_hex(setting)?,
+                "start" => profile.start_command = setting.as_bytes().to_vec(),
+                "stop" => profile.stop_command = setting.as_bytes().to_vec(),
+                other => return Err(format!("Unknown NUS profile key '{}'", other)),
+            }
+        }
+        profile.format = match value {
+            Some(value) => NusFormat::Binary { value, sync },
+            None if sync.is_empty() => NusFormat::Lines,
+            None => return Err("Sync bytes only apply to binary formats".to_string()),
+        };
+        profile.validate()
+    }
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.is_ascii() || !s.len().is_multiple_of(2) {
+        return Err(format!("Sync bytes '{}' must be an even number of hex digits", s));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("Invalid hex in sync bytes '{}'", s)))
+        .collect()
+}
+
+/// Reassembles frames from TX notifications, which split the byte stream wherever the MTU falls.
+#[derive(Debug)]
+pub struct NusFramer {
+    profile: NusProfile,
+    buffer: Vec<u8>,
+    pub frames: u64,
+    /// Lines that did not hold `channel_count` numbers.
+    pub bad_lines: u64,
+    /// Bytes discarded while hunting for the next sync pattern.
+    pub skipped_bytes: u64,
+}
+
+impl NusFramer {
+    pub fn new(profile: NusProfile) -> Self {
+        NusFramer {
+            profile,
+            buffer: Vec::new(),
+            frames: 0,
+            bad_lines: 0,
+            skipped_bytes: 0,
+        }
+    }
+
+    /// Frames lost to bad lines or skipped bytes, counting a partial frame of skipped bytes as one.
+    pub fn lost_frames(&self) -> u64 {
+        let frame_len = self.profile.frame_len().unwrap_or(1) as u64;
+        self.bad_lines + self.skipped_bytes.div_ceil(frame_len)
+    }
+
+    /// Appends a notification and returns every frame it completed, scaled to µV.
+    pub fn push(&mut self, notification: &[u8]) -> Vec<Vec<f64>> {
+        self.buffer.extend_from_slice(notification);
+        let frames = match self.profile.format.clone() {
+            NusFormat::Lines => self.take_lines(),
+            NusFormat::Binary { value, sync } => self.take_binary(value, &sync),
+        };
+        self.frames += frames.len() as u64;
+        frames
+    }
+
+    fn take_lines(&mut self) -> Vec<Vec<f64>> {
+        let mut frames = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values: Result<Vec<f64>, _> = line
+                .split([',', ';', '\t', ' '])
+                .filter(|field| !field.is_empty())
+                .map(str::parse::<f64>)
+                .collect();
+            match values {
+                Ok(values) if values.len() == self.profile.channel_count => {
+                    frames.push(values.iter().map(|value| value * self.profile.scale).collect())
+                }
+                _ => self.bad_lines += 1,
+            }
+        }
+        if self.buffer.len() > MAX_LINE_LENGTH {
+            self.buffer.clear();
+            self.bad_lines += 1;
+        }
+        frames
+    }
+
+    fn take_binary(&mut self, value: NusValue, sync: &[u8]) -> Vec<Vec<f64>> {
+        let frame_len = sync.len() + self.profile.channel_count * value.size();
+        let mut frames = Vec::new();
+        let mut start = 0;
+        while self.buffer.len() - start >= frame_len {
+            if !self.buffer[start..].starts_with(sync) {
+                start += 1;
+                self.skipped_bytes += 1;
+                continue;
+            }
+            let body = &self.buffer[start + sync.len()..start + frame_len];
+            frames.push(
+                body.chunks(value.size())
+                    .map(|bytes| value.read(bytes) * self.profile.scale)
+                    .collect(),
+            );
+            start += frame_len;
+        }
+        self.buffer.drain(..start);
+        frames
+    }
+}
+
+/// A prototype board streaming over NUS: alpha-band sine waves plus noise in the profile's
+/// format, sent in notifications of `payload` bytes with no regard for frame boundaries.
+#[derive(Debug)]
+pub struct SimulatedNusPeripheral {
+    profile: NusProfile,
+    payload: usize,
+    streaming: bool,
+    sample_clock: u64,
+    pending: Vec<u8>,
+}
+
+impl SimulatedNusPeripheral {
+    pub fn new(profile: NusProfile) -> Self {
+        SimulatedNusPeripheral {
+            profile,
+            payload: DEFAULT_NOTIFICATION_PAYLOAD,
+            streaming: false,
+            sample_clock: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Notification payload size, as negotiated by an MTU exchange.
+    pub fn with_payload(mut self, payload: usize) -> Self {
+        self.payload = payload.max(1);
+        self
+    }
+
+    pub fn profile(&self) -> &NusProfile {
+        &self.profile
+    }
+
+    /// Handles a write to RX. Stopping discards any partly sent frame, as firmware clearing
+    /// its UART buffer would.
+    pub fn write_rx(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes == self.profile.start_command.as_slice() {
+            self.streaming = true;
+        } else if bytes == self.profile.stop_command.as_slice() {
+            self.streaming = false;
+            self.pending.clear();
+        } else {
+            return Err(format!("Unknown NUS command {:?}", String::from_utf8_lossy(bytes)));
+        }
+        Ok(())
+    }
+
+    /// Next TX notification, or `None` while the stream is stopped.
+    pub fn next_notification(&mut self) -> Option<Vec<u8>> {
+        if !self.streaming {
+            return None;
+        }
+        while self.pending.len() < self.payload {
+            self.encode_frame();
+        }
+        Some(self.pending.drain(..self.payload).collect())
+    }
+
+    fn encode_frame(&mut self) {
+        let mut rng = rand::thread_rng();
+        let t = self.sample_clock as f64 / self.profile.sample_rate;
+        self.sample_clock += 1;
+        let counts: Vec<f64> = (0..self.profile.channel_count)
+            .map(|channel| {
+                let microvolts = 20.0 * (2.0 * std::f64::consts::PI * (10.0 + channel as f64 * 0.5) * t).sin()
+                    + rng.gen_range(-5.0..5.0);
+                microvolts / self.profile.scale
+            })
+            .collect();
+        match &self.profile.format {
+            NusFormat::Lines => {
+                let line: Vec<String> = counts.iter().map(|count| format!("{:.3}", count)).collect();
+                self.pending.extend_from_slice(line.join(",").as_bytes());
+                self.pending.extend_from_slice(b"\r\n");
+            }
+            NusFormat::Binary { value, sync } => {
+                self.pending.extend_from_slice(sync);
+                for count in counts {
+                    value.write(count, &mut self.pending);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary_profile() -> NusProfile {
+        "format=i16le,channels=2,scale=0.5,sync=a55a".parse().unwrap()
+    }
+
+    #[test]
+    fn values_round_trip() {
+        for (value, counts) in [
+            (NusValue::I16Le, -1234.0),
+            (NusValue::I16Be, 1234.0),
+            (NusValue::I24Le, -1_234_567.0),
+            (NusValue::I24Be, 1_234_567.0),
+            (NusValue::I32Le, -123_456_789.0),
+            (NusValue::F32Le, 0.25),
+        ] {
+            let mut bytes = vec![];
+            value.write(counts, &mut bytes);
+            assert_eq!(bytes.len(), value.size());
+            assert_eq!(value.read(&bytes), counts, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn profile_parsing() {
+        let profile = binary_profile();
+        assert_eq!(profile.format, NusFormat::Binary { value: NusValue::I16Le, sync: vec![0xA5, 0x5A] });
+        assert_eq!(profile.frame_len(), Some(6));
+        assert_eq!("channels=8,rate=500".parse::<NusProfile>().unwrap().frame_len(), None);
+        assert!("sync=a5".parse::<NusProfile>().is_err());
+        assert!("format=i16le,sync=a".parse::<NusProfile>().is_err());
+        assert!("channels=0".parse::<NusProfile>().is_err());
+        assert!("colour=blue".parse::<NusProfile>().is_err());
+    }
+
+    #[test]
+    fn binary_frames_split_across_notifications() {
+        let mut framer = NusFramer::new(binary_profile());
+        // Two frames (100, -200) and (3, 4) behind two bytes of noise, split mid-frame.
+        let stream = [0x00, 0xFF, 0xA5, 0x5A, 100, 0, 0x38, 0xFF, 0xA5, 0x5A, 3, 0, 4, 0];
+        assert_eq!(framer.push(&stream[..5]), Vec::<Vec<f64>>::new());
+        assert_eq!(framer.push(&stream[5..11]), vec![vec![50.0, -100.0]]);
+        assert_eq!(framer.push(&stream[11..]), vec![vec![1.5, 2.0]]);
+        assert_eq!((framer.frames, framer.skipped_bytes, framer.lost_frames()), (2, 2, 1));
+    }
+
+    #[test]
+    fn line_frames_split_across_notifications() {
+        let mut framer = NusFramer::new(NusProfile::default().with_channels(3).with_scale(2.0));
+        assert!(framer.push(b"1,2").is_empty());
+        assert_eq!(framer.push(b",3\r\n4;5\t6\n"), vec![vec![2.0, 4.0, 6.0], vec![8.0, 10.0, 12.0]]);
+        assert!(framer.push(b"\nnot,a,number\n1,2\n").is_empty());
+        assert_eq!((framer.frames, framer.bad_lines), (2, 2));
+        framer.push(&[b'7'; MAX_LINE_LENGTH + 1]);
+        assert_eq!(framer.bad_lines, 3);
+    }
+
+    /// Streams from the simulated board through a framer; the signal stays within ±25 µV.
+    fn simulated_round_trip(profile: NusProfile, payload: usize) {
+        let mut peripheral = SimulatedNusPeripheral::new(profile.clone()).with_payload(payload);
+        let mut framer = NusFramer::new(profile.clone());
+        assert_eq!(peripheral.next_notification(), None);
+        peripheral.write_rx(&profile.start_command).unwrap();
+        let mut frames = vec![];
+        for _ in 0..50 {
+            let notification = peripheral.next_notification().unwrap();
+            assert_eq!(notification.len(), payload);
+            frames.extend(framer.push(&notification));
+        }
+        peripheral.write_rx(&profile.stop_command).unwrap();
+        assert_eq!(peripheral.next_notification(), None);
+        assert!(peripheral.write_rx(b"?").is_err());
+
+        assert!(frames.len() >= 30, "only {} frames", frames.len());
+        assert_eq!(framer.lost_frames(), 0);
+        for frame in frames {
+            assert_eq!(frame.len(), profile.channel_count);
+            assert!(frame.iter().all(|microvolts| microvolts.abs() <= 25.0 + profile.scale), "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn simulated_binary_board() {
+        simulated_round_trip(binary_profile(), DEFAULT_NOTIFICATION_PAYLOAD);
+        simulated_round_trip("format=i24be,channels=8,scale=0.02235,sync=a0".parse().unwrap(), 244);
+    }
+
+    #[test]
+    fn simulated_line_board() {
+        simulated_round_trip(NusProfile::default(), DEFAULT_NOTIFICATION_PAYLOAD);
+    }
+}
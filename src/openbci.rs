@@ -0,0 +1,1276 @@
+//! OpenBCI Cyton boards over a serial port (the USB dongle at 115200 baud).
+//!
+//! Data packets are 33 bytes:
+//!
+//! `0xA0 | sample:u8 | 8 x i24 channels | 6 bytes aux | 0xCn`
+//!
+//! Channels are big-endian two's complement ADC counts. With stop byte `0xC0` the aux bytes
+//! are the accelerometer as three big-endian `i16`; the firmware sends zeros between readings.
+//! With a Daisy module the Cyton alternates packets: odd sample numbers carry channels 1-8 and
+//! the following even one channels 9-16, so 16-channel samples arrive at half the rate.
+//!
+//! Commands are single ASCII bytes: `v` resets and prints a banner ending in `$$$`, `b` starts
+//! and `s` stops streaming, `C` and `c` switch the Daisy's channels on and off.
+//!
+//! The 4-channel Ganglion sends 20-byte BLE packets instead, read here as a BLE-to-serial
+//! bridge relays them back to back. The first byte is the packet ID:
+//!
+//! - `0`: one uncompressed sample, 4 x i24, which the deltas that follow build on.
+//! - `1`-`100`: two samples as 8 x 18-bit deltas, then one accelerometer axis as an `i8`
+//!   (x, y and z in packets whose ID ends in 1, 2 and 3).
+//! - `101`-`200`: two samples as 8 x 19-bit deltas.
+//! - `201`-`207`: impedance readings and text.
+//!
+//! Deltas are packed big-endian, channel 1 to 4 of the first sample and then of the second.
+//! Each is subtracted from the sample before it. A delta is negative when its lowest bit is
+//! set, and is then sign-extended from its width.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use super::device_operations::{DeviceOperations, HealthCheck, Lifecycle};
+use super::gatt::{pack_i24, unpack_i24, DEFAULT_LSB_MICROVOLTS};
+use super::streaming_stats::StreamingStats;
+
+pub const PACKET_LEN: usize = 33;
+const START_BYTE: u8 = 0xA0;
+/// Stop bytes are `0xC0`-`0xCF`; the low nibble says what the aux bytes hold.
+const STOP_BYTE_ACCELEROMETER: u8 = 0xC0;
+pub const BAUD_RATE: u32 = 115_200;
+const BANNER_END: &[u8] = b"$$$";
+const BANNER_TIMEOUT: Duration = Duration::from_secs(3);
+/// g per accelerometer count (LIS3DH at ±4 g on the Cyton).
+const CYTON_ACCEL_SCALE: f64 = 0.002 / 16.0;
+pub const GANGLION_PACKET_LEN: usize = 20;
+/// Microvolts per count on the Ganglion's MCP3912 (1.2 V reference, gain 1.5 x 51).
+const GANGLION_LSB_MICROVOLTS: f64 = 1.2 / (8_388_607.0 * 1.5 * 51.0) * 1_000_000.0;
+/// g per accelerometer count (LIS2DH at ±4 g on the Ganglion).
+const GANGLION_ACCEL_SCALE: f64 = 0.032;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenBciBoard {
+    Cyton,
+    /// Cyton with a Daisy module, 16 channels.
+    CytonDaisy,
+    /// 4 channels in compressed BLE packets.
+    Ganglion,
+}
+
+impl std::str::FromStr for OpenBciBoard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "cyton" => Ok(OpenBciBoard::Cyton),
+            "cyton-daisy" | "daisy" => Ok(OpenBciBoard::CytonDaisy),
+            "ganglion" => Ok(OpenBciBoard::Ganglion),
+            _ => Err(format!("Unknown OpenBCI board '{}'; use cyton, cyton-daisy or ganglion", s)),
+        }
+    }
+}
+
+impl OpenBciBoard {
+    pub fn channel_count(&self) -> usize {
+        match self {
+            OpenBciBoard::Cyton => 8,
+            OpenBciBoard::CytonDaisy => 16,
+            OpenBciBoard::Ganglion => 4,
+        }
+    }
+
+    /// Complete samples per second; the Daisy halves the packet rate per sample.
+    pub fn sample_rate(&self) -> f64 {
+        match self {
+            OpenBciBoard::Cyton => 250.0,
+            OpenBciBoard::CytonDaisy => 125.0,
+            OpenBciBoard::Ganglion => 200.0,
+        }
+    }
+
+    pub fn channel_labels(&self) -> Vec<String> {
+        (1..=self.channel_count()).map(|n| format!("Ch{}", n)).collect()
+    }
+
+    fn lsb_microvolts(&self) -> f64 {
+        match self {
+            OpenBciBoard::Ganglion => GANGLION_LSB_MICROVOLTS,
+            _ => DEFAULT_LSB_MICROVOLTS as f64,
+        }
+    }
+
+    fn accel_scale(&self) -> f64 {
+        match self {
+            OpenBciBoard::Ganglion => GANGLION_ACCEL_SCALE,
+            _ => CYTON_ACCEL_SCALE,
+        }
+    }
+
+    /// Packets per second on the wire.
+    fn packet_rate(&self) -> f64 {
+        match self {
+            OpenBciBoard::CytonDaisy => 250.0,
+            // Two samples per compressed packet.
+            OpenBciBoard::Ganglion => 100.0,
+            board => board.sample_rate(),
+        }
+    }
+
+    /// Reads the board from the banner `v` prints; `daisy` picks 16 channels when one is fitted.
+    fn from_banner(banner: &str, daisy: bool) -> Result<Self, String> {
+        if banner.contains("Ganglion") {
+            Ok(OpenBciBoard::Ganglion)
+        } else if banner.contains("OpenBCI") {
+            Ok(if daisy && banner.contains("Daisy") { OpenBciBoard::CytonDaisy } else { OpenBciBoard::Cyton })
+        } else {
+            Err(format!("did not answer like an OpenBCI board: {:?}", banner))
+        }
+    }
+}
+
+/// One 33-byte packet, undecoded apart from the framing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawPacket {
+    pub sample_number: u8,
+    /// ADC counts.
+    pub channels: [i32; 8],
+    pub aux: [u8; 6],
+    pub stop_byte: u8,
+}
+
+impl RawPacket {
+    /// `None` unless `bytes` is exactly one packet with valid start and stop bytes.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != PACKET_LEN || bytes[0] != START_BYTE || bytes[32] & 0xF0 != STOP_BYTE_ACCELEROMETER {
+            return None;
+        }
+        let mut channels = [0; 8];
+        for (channel, counts) in bytes[2..26].chunks(3).zip(channels.iter_mut()) {
+            *counts = unpack_i24(channel);
+        }
+        let mut aux = [0; 6];
+        aux.copy_from_slice(&bytes[26..32]);
+        Some(RawPacket {
+            sample_number: bytes[1],
+            channels,
+            aux,
+            stop_byte: bytes[32],
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKET_LEN);
+        bytes.push(START_BYTE);
+        bytes.push(self.sample_number);
+        for counts in self.channels {
+            pack_i24(counts, &mut bytes);
+        }
+        bytes.extend_from_slice(&self.aux);
+        bytes.push(self.stop_byte);
+        bytes
+    }
+
+    /// Accelerometer counts (x, y, z), when this packet carries a reading.
+    fn accelerometer(&self) -> Option<[i16; 3]> {
+        if self.stop_byte != STOP_BYTE_ACCELEROMETER || self.aux == [0; 6] {
+            return None;
+        }
+        Some([0, 2, 4].map(|i| i16::from_be_bytes([self.aux[i], self.aux[i + 1]])))
+    }
+}
+
+/// Finds packets in a byte stream, skipping anything between them such as text the board
+/// prints or bytes lost on the line.
+#[derive(Debug, Default)]
+pub struct PacketParser {
+    buffer: Vec<u8>,
+    /// Bytes discarded while looking for the next packet.
+    pub skipped_bytes: u64,
+}
+
+impl PacketParser {
+    pub fn new() -> Self {
+        PacketParser::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<RawPacket> {
+        self.buffer.extend_from_slice(bytes);
+        let mut packets = vec![];
+        let mut offset = 0;
+        while self.buffer.len() - offset >= PACKET_LEN {
+            match RawPacket::decode(&self.buffer[offset..offset + PACKET_LEN]) {
+                Some(packet) => {
+                    packets.push(packet);
+                    offset += PACKET_LEN;
+                }
+                None => {
+                    self.skipped_bytes += 1;
+                    offset += 1;
+                }
+            }
+        }
+        self.buffer.drain(..offset);
+        packets
+    }
+}
+
+/// A decoded sample: all channels of the board, in microvolts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenBciSample {
+    /// Samples since streaming started, counting lost ones, so `index / sample_rate` is its time.
+    pub index: u64,
+    pub microvolts: Vec<f64>,
+    /// In g, when the board sent a reading with this sample.
+    pub accelerometer: Option<[f64; 3]>,
+}
+
+/// Turns packets into samples: scales counts, pairs Daisy packets and counts lost packets
+/// from gaps in the sample numbers.
+#[derive(Debug)]
+pub struct SampleAssembler {
+    board: OpenBciBoard,
+    expected: Option<u8>,
+    /// Packets on the wire since the first one, lost ones included.
+    packet_index: u64,
+    /// The board half of a Daisy sample, waiting for its daisy half.
+    pending: Option<(u64, RawPacket)>,
+    pub lost_packets: u64,
+}
+
+impl SampleAssembler {
+    pub fn new(board: OpenBciBoard) -> Self {
+        SampleAssembler {
+            board,
+            expected: None,
+            packet_index: 0,
+            pending: None,
+            lost_packets: 0,
+        }
+    }
+
+    pub fn push(&mut self, packet: RawPacket) -> Option<OpenBciSample> {
+        if let Some(expected) = self.expected {
+            let lost = packet.sample_number.wrapping_sub(expected) as u64;
+            self.lost_packets += lost;
+            self.packet_index += lost + 1;
+        }
+        self.expected = Some(packet.sample_number.wrapping_add(1));
+        let scale = self.board.lsb_microvolts();
+        let accel_scale = self.board.accel_scale();
+        let accelerometer = |packet: &RawPacket| packet.accelerometer().map(|counts| counts.map(|c| c as f64 * accel_scale));
+
+        if self.board != OpenBciBoard::CytonDaisy {
+            return Some(OpenBciSample {
+                index: self.packet_index,
+                microvolts: packet.channels[..self.board.channel_count()].iter().map(|c| *c as f64 * scale).collect(),
+                accelerometer: accelerometer(&packet),
+            });
+        }
+        if packet.sample_number % 2 == 1 {
+            self.pending = Some((self.packet_index, packet));
+            return None;
+        }
+        // A daisy packet is only usable straight after its board packet.
+        let (board_index, board) = self.pending.take().filter(|(index, _)| index + 1 == self.packet_index)?;
+        Some(OpenBciSample {
+            index: board_index / 2,
+            microvolts: board.channels.iter().chain(&packet.channels).map(|c| *c as f64 * scale).collect(),
+            accelerometer: accelerometer(&board).or_else(|| accelerometer(&packet)),
+        })
+    }
+}
+
+/// One Ganglion packet. Apart from the reference sample, samples arrive as deltas from the
+/// sample before them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GanglionPacket {
+    /// ID 0: one uncompressed sample, sent when streaming starts.
+    Raw([i32; 4]),
+    /// IDs 1-100 carry 18-bit deltas and `aux`, one accelerometer axis; IDs 101-200 carry
+    /// 19-bit deltas. Deltas must have their lowest bit set exactly when negative, as the
+    /// firmware sends them.
+    Compressed { id: u8, deltas: [[i32; 4]; 2], aux: Option<i8> },
+    /// IDs 201-207: impedance readings and text, which are not decoded.
+    Other(u8),
+}
+
+impl GanglionPacket {
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != GANGLION_PACKET_LEN {
+            return None;
+        }
+        match bytes[0] {
+            0 => Some(GanglionPacket::Raw([1, 4, 7, 10].map(|i| unpack_i24(&bytes[i..i + 3])))),
+            id @ 1..=100 => Some(GanglionPacket::Compressed {
+                id,
+                deltas: unpack_deltas(&bytes[1..19], 18),
+                aux: Some(bytes[19] as i8),
+            }),
+            id @ 101..=200 => Some(GanglionPacket::Compressed {
+                id,
+                deltas: unpack_deltas(&bytes[1..20], 19),
+                aux: None,
+            }),
+            id @ 201..=207 => Some(GanglionPacket::Other(id)),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(GANGLION_PACKET_LEN);
+        match self {
+            GanglionPacket::Raw(channels) => {
+                bytes.push(0);
+                for counts in channels {
+                    pack_i24(*counts, &mut bytes);
+                }
+            }
+            GanglionPacket::Compressed { id, deltas, aux } => {
+                bytes.push(*id);
+                let bits = if *id <= 100 { 18 } else { 19 };
+                pack_deltas(deltas, bits, &mut bytes);
+                if bits == 18 {
+                    bytes.push(aux.unwrap_or(0) as u8);
+                }
+            }
+            GanglionPacket::Other(id) => bytes.push(*id),
+        }
+        bytes.resize(GANGLION_PACKET_LEN, 0);
+        bytes
+    }
+}
+
+/// Reads eight `bits`-wide values from a big-endian bit stream: two samples of four channels.
+/// The lowest bit, not the highest, says whether a value is negative.
+fn unpack_deltas(bytes: &[u8], bits: usize) -> [[i32; 4]; 2] {
+    let mut values = [0; 8];
+    for (n, value) in values.iter_mut().enumerate() {
+        let raw = (n * bits..(n + 1) * bits).fold(0u32, |raw, bit| (raw << 1) | ((bytes[bit / 8] >> (7 - bit % 8)) & 1) as u32);
+        *value = if raw & 1 == 1 { (raw | !((1u32 << bits) - 1)) as i32 } else { raw as i32 };
+    }
+    [[values[0], values[1], values[2], values[3]], [values[4], values[5], values[6], values[7]]]
+}
+
+fn pack_deltas(deltas: &[[i32; 4]; 2], bits: usize, out: &mut Vec<u8>) {
+    let start = out.len();
+    out.resize(start + bits, 0);
+    for (n, value) in deltas.iter().flatten().enumerate() {
+        for bit in 0..bits {
+            if ((*value as u32) >> (bits - 1 - bit)) & 1 == 1 {
+                let position = n * bits + bit;
+                out[start + position / 8] |= 0x80 >> (position % 8);
+            }
+        }
+    }
+}
+
+/// Splits a byte stream into Ganglion packets. Packets have no start byte, so a byte that
+/// cannot begin one is skipped.
+#[derive(Debug, Default)]
+pub struct GanglionParser {
+    buffer: Vec<u8>,
+    /// Bytes discarded while looking for the next packet.
+    pub skipped_bytes: u64,
+}
+
+impl GanglionParser {
+    pub fn new() -> Self {
+        GanglionParser::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<GanglionPacket> {
+        self.buffer.extend_from_slice(bytes);
+        let mut packets = vec![];
+        let mut offset = 0;
+        while self.buffer.len() - offset >= GANGLION_PACKET_LEN {
+            match GanglionPacket::decode(&self.buffer[offset..offset + GANGLION_PACKET_LEN]) {
+                Some(packet) => {
+                    packets.push(packet);
+                    offset += GANGLION_PACKET_LEN;
+                }
+                None => {
+                    self.skipped_bytes += 1;
+                    offset += 1;
+                }
+            }
+        }
+        self.buffer.drain(..offset);
+        packets
+    }
+}
+
+/// Turns Ganglion packets into samples by subtracting each delta from the sample before it.
+/// Packets before the first reference sample cannot be decoded and are dropped. After a lost
+/// packet the following samples are offset by the deltas it carried, as with the OpenBCI
+/// software; a high-pass filter removes the step.
+#[derive(Debug, Default)]
+pub struct GanglionAssembler {
+    last: Option<[i32; 4]>,
+    expected: Option<u8>,
+    /// Index of the next sample, lost ones included.
+    next_index: u64,
+    /// Accelerometer axes seen so far in the current group of ten packets.
+    axes: [Option<i8>; 3],
+    pub lost_packets: u64,
+}
+
+impl GanglionAssembler {
+    pub fn new() -> Self {
+        GanglionAssembler::default()
+    }
+
+    pub fn push(&mut self, packet: GanglionPacket) -> Vec<OpenBciSample> {
+        let scale = OpenBciBoard::Ganglion.lsb_microvolts();
+        let sample = |index: u64, counts: &[i32; 4]| OpenBciSample {
+            index,
+            microvolts: counts.iter().map(|c| *c as f64 * scale).collect(),
+            accelerometer: None,
+        };
+        match packet {
+            GanglionPacket::Raw(counts) => {
+                if self.last.is_some() {
+                    self.next_index += 1;
+                }
+                self.last = Some(counts);
+                self.expected = None;
+                vec![sample(self.next_index, &counts)]
+            }
+            GanglionPacket::Compressed { id, deltas, aux } => {
+                let Some(mut counts) = self.last else {
+                    return vec![];
+                };
+                // IDs count 1-100 or 101-200 and wrap within their range.
+                let first = if id <= 100 { 1 } else { 101 };
+                if let Some(expected) = self.expected.filter(|expected| (first..first + 100).contains(expected)) {
+                    let lost = (id as u64 + 100 - expected as u64) % 100;
+                    if lost > 0 {
+                        self.lost_packets += lost;
+                        self.next_index += 2 * lost;
+                        self.axes = [None; 3];
+                    }
+                }
+                self.expected = Some(if id == first + 99 { first } else { id + 1 });
+
+                let mut samples = Vec::with_capacity(2);
+                for delta in &deltas {
+                    for (value, delta) in counts.iter_mut().zip(delta) {
+                        *value -= delta;
+                    }
+                    self.next_index += 1;
+                    samples.push(sample(self.next_index, &counts));
+                }
+                self.last = Some(counts);
+
+                // Packets ending in 1, 2 and 3 carry x, y and z.
+                if let (Some(value), axis @ 1..=3) = (aux, id % 10) {
+                    self.axes[axis as usize - 1] = Some(value);
+                    if let [Some(x), Some(y), Some(z)] = self.axes {
+                        let accel_scale = OpenBciBoard::Ganglion.accel_scale();
+                        samples[1].accelerometer = Some([x, y, z].map(|counts| counts as f64 * accel_scale));
+                        self.axes = [None; 3];
+                    }
+                }
+                samples
+            }
+            GanglionPacket::Other(_) => vec![],
+        }
+    }
+}
+
+/// Frames and assembles samples for whichever board is streaming.
+#[derive(Debug)]
+pub enum OpenBciDecoder {
+    Cyton(PacketParser, SampleAssembler),
+    Ganglion(GanglionParser, GanglionAssembler),
+}
+
+impl OpenBciDecoder {
+    pub fn new(board: OpenBciBoard) -> Self {
+        match board {
+            OpenBciBoard::Ganglion => OpenBciDecoder::Ganglion(GanglionParser::new(), GanglionAssembler::new()),
+            board => OpenBciDecoder::Cyton(PacketParser::new(), SampleAssembler::new(board)),
+        }
+    }
+
+    /// Returns the samples completed by `bytes` and how many packets they held.
+    pub fn push(&mut self, bytes: &[u8]) -> (Vec<OpenBciSample>, usize) {
+        match self {
+            OpenBciDecoder::Cyton(parser, assembler) => {
+                let packets = parser.push(bytes);
+                let count = packets.len();
+                (packets.into_iter().filter_map(|packet| assembler.push(packet)).collect(), count)
+            }
+            OpenBciDecoder::Ganglion(parser, assembler) => {
+                let packets = parser.push(bytes);
+                let count = packets.len();
+                (packets.into_iter().flat_map(|packet| assembler.push(packet)).collect(), count)
+            }
+        }
+    }
+
+    pub fn lost_packets(&self) -> u64 {
+        match self {
+            OpenBciDecoder::Cyton(_, assembler) => assembler.lost_packets,
+            OpenBciDecoder::Ganglion(_, assembler) => assembler.lost_packets,
+        }
+    }
+
+    pub fn skipped_bytes(&self) -> u64 {
+        match self {
+            OpenBciDecoder::Cyton(parser, _) => parser.skipped_bytes,
+            OpenBciDecoder::Ganglion(parser, _) => parser.skipped_bytes,
+        }
+    }
+}
+
+/// A tty put in raw mode and read through the tokio reactor.
+#[derive(Debug)]
+pub struct SerialPort {
+    file: AsyncFd<File>,
+    name: String,
+}
+
+/// Raw 8N1 at `baud`. With VMIN 1 a non-blocking read with nothing to read fails with
+/// `WouldBlock` rather than returning 0, which would look like a hangup.
+fn configure_raw(file: &File, baud: u32) -> Result<(), String> {
+    let speed = match baud {
+        9600 => libc::B9600,
+        57_600 => libc::B57600,
+        115_200 => libc::B115200,
+        230_400 => libc::B230400,
+        other => return Err(format!("Unsupported baud rate {}", other)),
+    };
+    let fd = file.as_raw_fd();
+    // SAFETY: `fd` is an open descriptor for the lifetime of `file`, and `termios` is plain data.
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(format!("Not a serial port: {}", std::io::Error::last_os_error()));
+        }
+        libc::cfmakeraw(&mut termios);
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        libc::cfsetispeed(&mut termios, speed);
+        libc::cfsetospeed(&mut termios, speed);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(format!("Cannot configure serial port: {}", std::io::Error::last_os_error()));
+        }
+        libc::tcflush(fd, libc::TCIOFLUSH);
+    }
+    Ok(())
+}
+
+impl SerialPort {
+    pub fn open(path: &Path, baud: u32) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)
+            .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        configure_raw(&file, baud).map_err(|e| format!("{}: {}", path.display(), e))?;
+        SerialPort::from_file(file, &path.display().to_string())
+    }
+
+    /// Wraps an already-configured, non-blocking descriptor.
+    fn from_file(file: File, name: &str) -> Result<Self, String> {
+        Ok(SerialPort {
+            file: AsyncFd::new(file).map_err(|e| e.to_string())?,
+            name: name.to_string(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Waits for at least one byte; 0 means the other end has gone.
+    pub async fn read(&self, buffer: &mut [u8]) -> Result<usize, String> {
+        loop {
+            let mut ready = self.file.readable().await.map_err(|e| e.to_string())?;
+            match ready.try_io(|file| file.get_ref().read(buffer)) {
+                Ok(Ok(len)) => return Ok(len),
+                // A pty reports the other side closing as EIO.
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Ok(0),
+                Ok(Err(e)) => return Err(format!("{}: {}", self.name, e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    pub async fn write_all(&self, mut bytes: &[u8]) -> Result<(), String> {
+        while !bytes.is_empty() {
+            let mut ready = self.file.writable().await.map_err(|e| e.to_string())?;
+            match ready.try_io(|file| file.get_ref().write(bytes)) {
+                Ok(Ok(written)) => bytes = &bytes[written..],
+                Ok(Err(e)) => return Err(format!("{}: {}", self.name, e)),
+                Err(_would_block) => continue,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A Cyton, optionally with Daisy, or a bridged Ganglion on a serial port.
+#[derive(Debug)]
+pub struct OpenBciDevice {
+    pub id: String,
+    path: PathBuf,
+    /// What the caller asked for; `None` takes whatever the banner reports.
+    requested: Option<OpenBciBoard>,
+    board: Option<OpenBciBoard>,
+    port: Option<SerialPort>,
+    /// Set up for the board when a stream starts.
+    decoder: Option<OpenBciDecoder>,
+    brainwave_data: Arc<Mutex<StreamingStats>>,
+    output: Option<mpsc::Sender<OpenBciSample>>,
+    /// Receives every byte read while streaming.
+    capture: Option<BufWriter<File>>,
+    lifecycle: Lifecycle,
+    samples: u64,
+}
+
+impl OpenBciDevice {
+    pub fn new(path: &Path) -> Self {
+        OpenBciDevice {
+            id: format!("openbci:{}", path.display()),
+            path: path.to_path_buf(),
+            requested: None,
+            board: None,
+            port: None,
+            decoder: None,
+            brainwave_data: Arc::new(Mutex::new(StreamingStats::new(20))),
+            output: None,
+            capture: None,
+            lifecycle: Lifecycle::new(),
+            samples: 0,
+        }
+    }
+
+    /// Fails the connection unless the board is this one; `CytonDaisy` turns the Daisy on.
+    pub fn with_board(mut self, board: OpenBciBoard) -> Self {
+        self.requested = Some(board);
+        self
+    }
+
+    /// Saves the raw bytes read while streaming, for decoding later or as a fixture.
+    pub fn with_capture(mut self, path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+        self.capture = Some(BufWriter::new(file));
+        Ok(self)
+    }
+
+    /// The board found on connecting.
+    pub fn board(&self) -> Option<OpenBciBoard> {
+        self.board
+    }
+
+    /// Receives every sample as it is decoded.
+    pub fn subscribe(&mut self, capacity: usize) -> mpsc::Receiver<OpenBciSample> {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        self.output = Some(tx);
+        rx
+    }
+
+    pub fn analyze_data(&self) -> Option<f32> {
+        self.brainwave_data.lock().unwrap().mean().map(|mean| mean as f32)
+    }
+
+    /// Packets missing from the sample numbers in the current or last stream.
+    pub fn lost_packets(&self) -> u64 {
+        self.decoder.as_ref().map_or(0, OpenBciDecoder::lost_packets)
+    }
+
+    /// Bytes skipped while framing packets in the current or last stream.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.decoder.as_ref().map_or(0, OpenBciDecoder::skipped_bytes)
+    }
+
+    fn port(&self) -> Result<&SerialPort, String> {
+        self.port.as_ref().ok_or_else(|| format!("OpenBCI board {} not connected", self.id))
+    }
+
+    /// Sends a command and returns what the board prints up to its `$$$`.
+    async fn command(&self, command: u8) -> Result<String, String> {
+        let port = self.port()?;
+        port.write_all(&[command]).await?;
+        let mut reply = vec![];
+        let mut buffer = [0u8; 256];
+        let deadline = Instant::now() + BANNER_TIMEOUT;
+        while !reply.ends_with(BANNER_END) {
+            let len = tokio::time::timeout_at(deadline, port.read(&mut buffer))
+                .await
+                .map_err(|_| format!("No reply to '{}' from {}", command as char, port.name()))??;
+            if len == 0 {
+                return Err(format!("{} closed", port.name()));
+            }
+            reply.extend_from_slice(&buffer[..len]);
+        }
+        Ok(String::from_utf8_lossy(&reply[..reply.len() - BANNER_END.len()]).trim().to_string())
+    }
+
+    /// Reads and decodes until `duration` of unpaused time has passed or the port closes.
+    async fn read_samples(&mut self, duration: Duration) -> Result<(), String> {
+        let mut buffer = [0u8; 1024];
+        let mut deadline = Instant::now() + duration;
+        loop {
+            if self.lifecycle.is_paused() {
+                self.port()?.write_all(b"s").await?;
+                deadline += self.lifecycle.wait_while_paused().await;
+                if !self.lifecycle.is_connected() {
+                    return Err(format!("OpenBCI board {} disconnected while streaming", self.id));
+                }
+                self.port()?.write_all(b"b").await?;
+            }
+            let read = match tokio::time::timeout_at(deadline, self.port()?.read(&mut buffer)).await {
+                Err(_) => return Ok(()),
+                Ok(read) => read?,
+            };
+            if read == 0 {
+                return Err(format!("{} closed while streaming", self.path.display()));
+            }
+            if let Some(capture) = &mut self.capture {
+                capture.write_all(&buffer[..read]).map_err(|e| format!("Cannot write capture: {}", e))?;
+            }
+            let samples = match &mut self.decoder {
+                Some(decoder) => decoder.push(&buffer[..read]).0,
+                None => vec![],
+            };
+            for sample in samples {
+                self.brainwave_data.lock().unwrap().push(sample.microvolts[0]);
+                self.lifecycle.record_samples(1);
+                self.samples += 1;
+                if let Some(output) = &self.output {
+                    if output.send(sample).await.is_err() {
+                        self.output = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl DeviceOperations for OpenBciDevice {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    /// Opens the port, resets the board and reads its banner to tell which board it is.
+    async fn connect(&mut self) -> Result<(), String> {
+        if self.lifecycle.is_connected() {
+            return Err(format!("OpenBCI board {} already connected", self.id));
+        }
+        self.port = Some(SerialPort::open(&self.path, BAUD_RATE)?);
+        let result = async {
+            // Stop a stream left running by an earlier session before asking for the banner.
+            self.port()?.write_all(b"s").await?;
+            let banner = self.command(b'v').await?;
+            let want_daisy = self.requested.is_none_or(|board| board == OpenBciBoard::CytonDaisy);
+            let found = OpenBciBoard::from_banner(&banner, want_daisy).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+            if let Some(requested) = self.requested.filter(|requested| *requested != found) {
+                return Err(format!("Expected an OpenBCI {:?} on {}, found {:?}", requested, self.path.display(), found));
+            }
+            match found {
+                OpenBciBoard::CytonDaisy => self.command(b'C').await.map(|_| ())?,
+                OpenBciBoard::Cyton if banner.contains("Daisy") => self.command(b'c').await.map(|_| ())?,
+                _ => {}
+            }
+            Ok(found)
+        }
+        .await;
+        let board = match result {
+            Ok(board) => board,
+            Err(e) => {
+                self.port = None;
+                return Err(e);
+            }
+        };
+        self.board = Some(board);
+        self.lifecycle.connected()?;
+        eprintln!(
+            "OpenBCI {:?} connected on {} ({} channels at {} Hz)",
+            board,
+            self.path.display(),
+            board.channel_count(),
+            board.sample_rate()
+        );
+        Ok(())
+    }
+
+    async fn stream(&mut self, duration: Duration) -> Result<(), String> {
+        let board = self.board.ok_or_else(|| format!("OpenBCI board {} not connected", self.id))?;
+        self.lifecycle.begin_stream()?;
+        self.decoder = Some(OpenBciDecoder::new(board));
+        let mut result = self.port()?.write_all(b"b").await;
+        if result.is_ok() {
+            result = self.read_samples(duration).await;
+        }
+        // Stop the board even if reading failed, so the next stream starts clean.
+        if let Ok(port) = self.port() {
+            let _ = port.write_all(b"s").await;
+        }
+        if let Some(Err(e)) = self.capture.as_mut().map(|capture| capture.flush()) {
+            result = result.and(Err(format!("Cannot write capture: {}", e)));
+        }
+        self.lifecycle.end_stream();
+        if let Err(e) = &result {
+            self.lifecycle.record_error(e);
+        }
+        result
+    }
+
+    async fn disconnect(&mut self) -> Result<(), String> {
+        self.lifecycle.check_can_disconnect()?;
+        if let Ok(port) = self.port() {
+            let _ = port.write_all(b"s").await;
+        }
+        self.port = None;
+        self.lifecycle.disconnected();
+        self.output = None;
+        eprintln!("OpenBCI board {} disconnected", self.id);
+        Ok(())
+    }
+
+    async fn device_checks(&mut self) -> Vec<HealthCheck> {
+        let lost = self.lost_packets();
+        vec![
+            HealthCheck::new("samples", self.samples > 0, format!("{} decoded", self.samples)),
+            HealthCheck::new("packet loss", lost == 0, format!("{} packets lost", lost)),
+            HealthCheck::new("framing", true, format!("{} bytes skipped", self.skipped_bytes())),
+        ]
+    }
+}
+
+/// Opens a pseudo-terminal pair: the non-blocking master, and the slave's path and descriptor.
+fn open_pty() -> Result<(File, PathBuf, File), String> {
+    let error = |what: &str| format!("Cannot {}: {}", what, std::io::Error::last_os_error());
+    // SAFETY: each call is checked before the descriptor is used, and ownership of the
+    // descriptor passes to the `File` exactly once.
+    let master = unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+        if fd < 0 {
+            return Err(error("open a pseudo-terminal"));
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(error("unlock the pseudo-terminal"));
+        }
+        master
+    };
+    let mut name = [0 as libc::c_char; 128];
+    // SAFETY: `name` is writable for its full length, which is passed along.
+    if unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) } != 0 {
+        return Err(error("name the pseudo-terminal"));
+    }
+    // SAFETY: ptsname_r wrote a NUL-terminated string into `name`.
+    let path = PathBuf::from(unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned());
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&path)
+        .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+    // Raw from the start, so nothing is echoed or translated before a client configures it.
+    configure_raw(&slave, BAUD_RATE)?;
+    Ok((master, path, slave))
+}
+
+/// A Cyton (with or without Daisy) or a bridged Ganglion served on a pseudo-terminal, for
+/// running the serial code without hardware. Channel n carries a 10 Hz sine of 10n µV, and
+/// the accelerometer reads (0, 0, 1 g) every tenth packet, so output is the same on every run.
+pub struct SimulatedBoard {
+    path: PathBuf,
+    task: JoinHandle<()>,
+    /// Held open so the master keeps working while clients come and go.
+    _slave: File,
+}
+
+impl SimulatedBoard {
+    /// `CytonDaisy` is a Cyton with a Daisy fitted, starting in 8-channel mode like the real one.
+    pub fn spawn(board: OpenBciBoard) -> Result<Self, String> {
+        let (master, path, slave) = open_pty()?;
+        let port = SerialPort::from_file(master, &path.display().to_string())?;
+        let task = tokio::spawn(async move {
+            if let Err(e) = run_simulated_board(port, board).await {
+                eprintln!("Simulated OpenBCI board stopped: {}", e);
+            }
+        });
+        Ok(SimulatedBoard { path, task, _slave: slave })
+    }
+
+    /// The serial port to open, e.g. `/dev/pts/3`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SimulatedBoard {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn banner(board: OpenBciBoard) -> String {
+    if board == OpenBciBoard::Ganglion {
+        return "OpenBCI Ganglion\nMCP3912 Device ID: 0x12\nLIS2DH Device ID: 0x33\nFirmware: v2.0.0\n$$$".to_string();
+    }
+    let daisy = if board == OpenBciBoard::CytonDaisy { "On Daisy ADS1299 Device ID: 0x3E\n" } else { "" };
+    format!("OpenBCI V3 8-16 channel\nOn Board ADS1299 Device ID: 0x3E\n{}LIS3DH Device ID: 0x33\nFirmware: v3.1.2\n$$$", daisy)
+}
+
+/// Channel `channel` (from 0) of a simulated board's `sample`-th sample: a 10 Hz sine of
+/// 10 µV times the channel number.
+fn simulated_counts(board: OpenBciBoard, channel: usize, sample: u64) -> i32 {
+    let time_secs = sample as f64 / board.sample_rate();
+    let microvolts = 10.0 * (channel + 1) as f64 * (2.0 * std::f64::consts::PI * 10.0 * time_secs).sin();
+    (microvolts / board.lsb_microvolts()).round() as i32
+}
+
+/// Compresses a simulated Ganglion's samples as its firmware does. Deltas are nudged by a
+/// count to follow the sign convention, so it tracks the sample the receiver will rebuild
+/// to keep those nudges from adding up.
+#[derive(Debug, Default)]
+struct GanglionEncoder {
+    sent: u64,
+    last: [i32; 4],
+}
+
+impl GanglionEncoder {
+    fn next_packet(&mut self) -> GanglionPacket {
+        let board = OpenBciBoard::Ganglion;
+        let counts = |sample: u64| [0, 1, 2, 3].map(|channel| simulated_counts(board, channel, sample));
+        let packet = if self.sent == 0 {
+            self.last = counts(0);
+            GanglionPacket::Raw(self.last)
+        } else {
+            let mut deltas = [[0; 4]; 2];
+            for (n, delta) in deltas.iter_mut().enumerate() {
+                let target = counts(2 * self.sent - 1 + n as u64);
+                for ((delta, last), target) in delta.iter_mut().zip(&mut self.last).zip(target) {
+                    let mut value = *last - target;
+                    if (value & 1 == 1) != (value < 0) {
+                        value -= 1;
+                    }
+                    *delta = value;
+                    *last -= value;
+                }
+            }
+            // The accelerometer reads (0, 0, 1 g) to the nearest count, once per ten packets.
+            let id = ((self.sent - 1) % 100 + 1) as u8;
+            let aux = if id % 10 == 3 { (1.0 / board.accel_scale()).round() as i8 } else { 0 };
+            GanglionPacket::Compressed { id, deltas, aux: Some(aux) }
+        };
+        self.sent += 1;
+        packet
+    }
+}
+
+/// The packet a simulated board sends as its `packet`-th since streaming started.
+pub fn simulated_packet(board: OpenBciBoard, daisy_on: bool, packet: u64) -> RawPacket {
+    let active = if daisy_on { OpenBciBoard::CytonDaisy } else { board };
+    // With the Daisy on, packets alternate board/daisy halves of one sample starting at number 1.
+    let (sample, first_channel) = match active {
+        OpenBciBoard::CytonDaisy => (packet / 2, if packet.is_multiple_of(2) { 0 } else { 8 }),
+        _ => (packet, 0),
+    };
+    let mut channels = [0; 8];
+    for (i, counts) in channels.iter_mut().enumerate().take(active.channel_count().min(8)) {
+        *counts = simulated_counts(active, first_channel + i, sample);
+    }
+    let mut aux = [0; 6];
+    if packet.is_multiple_of(10) {
+        let one_g = (1.0 / active.accel_scale()).round() as i16;
+        aux[4..].copy_from_slice(&one_g.to_be_bytes());
+    }
+    RawPacket {
+        sample_number: (packet + 1) as u8,
+        channels,
+        aux,
+        stop_byte: STOP_BYTE_ACCELEROMETER,
+    }
+}
+
+async fn run_simulated_board(port: SerialPort, board: OpenBciBoard) -> Result<(), String> {
+    let mut buffer = [0u8; 64];
+    let mut daisy_on = false;
+    // Set while streaming: when it started and how many packets have gone out.
+    let mut streaming: Option<(Instant, u64)> = None;
+    let mut ganglion = GanglionEncoder::default();
+    let mut ticker = tokio::time::interval(Duration::from_millis(20));
+    loop {
+        tokio::select! {
+            read = port.read(&mut buffer) => {
+                for command in &buffer[..read?] {
+                    let reply = match command {
+                        b'v' => {
+                            streaming = None;
+                            daisy_on = false;
+                            Some(banner(board))
+                        }
+                        b'b' => {
+                            if streaming.is_none() {
+                                streaming = Some((Instant::now(), 0));
+                                // A new stream starts from a reference sample.
+                                ganglion = GanglionEncoder::default();
+                            }
+                            None
+                        }
+                        b's' => {
+                            streaming = None;
+                            None
+                        }
+                        b'C' if board == OpenBciBoard::CytonDaisy => {
+                            daisy_on = true;
+                            Some("daisy attached16$$$".to_string())
+                        }
+                        b'C' => Some("no daisy to attach!8$$$".to_string()),
+                        b'c' => {
+                            daisy_on = false;
+                            Some("daisy removed8$$$".to_string())
+                        }
+                        _ => None,
+                    };
+                    if let Some(reply) = reply {
+                        port.write_all(reply.as_bytes()).await?;
+                    }
+                }
+            }
+            _ = ticker.tick() => {
+                let Some((started, sent)) = streaming.as_mut() else { continue };
+                let rate = if daisy_on { OpenBciBoard::CytonDaisy } else { board }.packet_rate();
+                let due = (started.elapsed().as_secs_f64() * rate) as u64;
+                let mut bytes = vec![];
+                while *sent < due {
+                    match board {
+                        OpenBciBoard::Ganglion => bytes.extend(ganglion.next_packet().encode()),
+                        _ => bytes.extend(simulated_packet(board, daisy_on, *sent).encode()),
+                    }
+                    *sent += 1;
+                }
+                port.write_all(&bytes).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Decoded {
+        samples: Vec<OpenBciSample>,
+        lost_packets: u64,
+        skipped_bytes: u64,
+    }
+
+    /// Feeds `bytes` through the parser in uneven chunks, as a serial port would deliver them.
+    fn decode(board: OpenBciBoard, bytes: &[u8]) -> Decoded {
+        let mut decoder = OpenBciDecoder::new(board);
+        let mut samples = vec![];
+        for chunk in bytes.chunks(50) {
+            samples.extend(decoder.push(chunk).0);
+        }
+        Decoded {
+            samples,
+            lost_packets: decoder.lost_packets(),
+            skipped_bytes: decoder.skipped_bytes(),
+        }
+    }
+
+    /// The first `packets` packets of a simulated Ganglion stream, back to back.
+    fn ganglion_stream(packets: usize) -> Vec<Vec<u8>> {
+        let mut encoder = GanglionEncoder::default();
+        (0..packets).map(|_| encoder.next_packet().encode()).collect()
+    }
+
+    fn accelerometer_readings(samples: &[OpenBciSample]) -> usize {
+        samples.iter().filter(|sample| sample.accelerometer.is_some()).count()
+    }
+
+    /// Runs of consecutive sample indices, as `bluerain` splits them into segments.
+    fn segments(samples: &[OpenBciSample]) -> usize {
+        1 + samples.windows(2).filter(|pair| pair[1].index != pair[0].index + 1).count()
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let packet = simulated_packet(OpenBciBoard::Cyton, false, 10);
+        let bytes = packet.encode();
+        assert_eq!(bytes.len(), PACKET_LEN);
+        assert_eq!(RawPacket::decode(&bytes), Some(packet));
+        let mut bad_stop = bytes.clone();
+        bad_stop[32] = 0xB0;
+        assert_eq!(RawPacket::decode(&bad_stop), None);
+    }
+
+    #[test]
+    fn cyton_fixture() {
+        let decoded = decode(OpenBciBoard::Cyton, include_bytes!("fixtures/openbci/cyton.bin"));
+        assert_eq!(decoded.samples.len(), 250);
+        assert!(decoded.samples.iter().all(|sample| sample.microvolts.len() == 8));
+        assert_eq!(accelerometer_readings(&decoded.samples), 25);
+        assert_eq!((decoded.lost_packets, decoded.skipped_bytes), (0, 0));
+        assert_eq!(segments(&decoded.samples), 1);
+    }
+
+    #[test]
+    fn cyton_daisy_fixture() {
+        let decoded = decode(OpenBciBoard::CytonDaisy, include_bytes!("fixtures/openbci/cyton_daisy.bin"));
+        assert_eq!(decoded.samples.len(), 125);
+        assert!(decoded.samples.iter().all(|sample| sample.microvolts.len() == 16));
+        assert_eq!(accelerometer_readings(&decoded.samples), 25);
+        assert_eq!((decoded.lost_packets, decoded.skipped_bytes), (0, 0));
+        assert_eq!(segments(&decoded.samples), 1);
+    }
+
+    #[test]
+    fn cyton_glitches_fixture() {
+        let decoded = decode(OpenBciBoard::Cyton, include_bytes!("fixtures/openbci/cyton_glitches.bin"));
+        assert_eq!(decoded.samples.len(), 246);
+        assert_eq!(segments(&decoded.samples), 3);
+        assert_eq!(decoded.lost_packets, 4);
+        assert_eq!(decoded.skipped_bytes, 122);
+    }
+
+    #[test]
+    fn boards_are_told_apart_by_name_and_banner() {
+        assert_eq!("ganglion".parse::<OpenBciBoard>(), Ok(OpenBciBoard::Ganglion));
+        assert!("ganglion-daisy".parse::<OpenBciBoard>().is_err());
+        assert_eq!(OpenBciBoard::from_banner(&banner(OpenBciBoard::Ganglion), true), Ok(OpenBciBoard::Ganglion));
+        assert_eq!(OpenBciBoard::from_banner(&banner(OpenBciBoard::CytonDaisy), true), Ok(OpenBciBoard::CytonDaisy));
+        assert_eq!(OpenBciBoard::from_banner(&banner(OpenBciBoard::CytonDaisy), false), Ok(OpenBciBoard::Cyton));
+        assert!(OpenBciBoard::from_banner("Ready", true).is_err());
+    }
+
+    #[test]
+    fn ganglion_deltas_are_packed_big_endian_with_the_sign_in_the_lowest_bit() {
+        let mut bytes = [0u8; GANGLION_PACKET_LEN];
+        bytes[0] = 150;
+        // The first 19-bit value ends at bit 18 of the payload, so 2 sets bit 17.
+        bytes[3] = 0x40;
+        // The last one is the payload's final 19 bits, all set: -1.
+        bytes[17] = 0x07;
+        bytes[18] = 0xFF;
+        bytes[19] = 0xFF;
+        let packet = GanglionPacket::decode(&bytes).unwrap();
+        assert_eq!(packet, GanglionPacket::Compressed { id: 150, deltas: [[2, 0, 0, 0], [0, 0, 0, -1]], aux: None });
+        assert_eq!(packet.encode(), bytes);
+
+        // An odd value is negative: 3 is sign-extended from 18 bits.
+        let mut bytes = [0u8; GANGLION_PACKET_LEN];
+        bytes[0] = 7;
+        bytes[3] = 0xC0;
+        bytes[19] = 0xFE;
+        let packet = GanglionPacket::decode(&bytes).unwrap();
+        assert_eq!(packet, GanglionPacket::Compressed { id: 7, deltas: [[3 - (1 << 18), 0, 0, 0], [0; 4]], aux: Some(-2) });
+        assert_eq!(packet.encode(), bytes);
+
+        let raw = GanglionPacket::Raw([1, -1, 8_388_607, -8_388_608]);
+        assert_eq!(GanglionPacket::decode(&raw.encode()), Some(raw));
+        assert_eq!(GanglionPacket::decode(&GanglionPacket::Other(201).encode()), Some(GanglionPacket::Other(201)));
+        assert_eq!(GanglionPacket::decode(&[208; GANGLION_PACKET_LEN]), None);
+        assert_eq!(GanglionPacket::decode(&[0; 19]), None);
+    }
+
+    #[test]
+    fn ganglion_samples_are_rebuilt_from_deltas() {
+        let bytes = ganglion_stream(101).concat();
+        let decoded = decode(OpenBciBoard::Ganglion, &bytes);
+        // A reference sample, then two per compressed packet.
+        assert_eq!(decoded.samples.len(), 201);
+        assert_eq!((decoded.lost_packets, decoded.skipped_bytes), (0, 0));
+        assert_eq!(segments(&decoded.samples), 1);
+        for sample in &decoded.samples {
+            assert_eq!(sample.microvolts.len(), 4);
+            for (channel, microvolts) in sample.microvolts.iter().enumerate() {
+                let expected = simulated_counts(OpenBciBoard::Ganglion, channel, sample.index) as f64 * GANGLION_LSB_MICROVOLTS;
+                assert!((microvolts - expected).abs() <= GANGLION_LSB_MICROVOLTS * 1.001, "{} vs {}", microvolts, expected);
+            }
+        }
+        // One reading per ten packets, from the packet carrying z.
+        let readings: Vec<_> = decoded.samples.iter().filter_map(|sample| sample.accelerometer).collect();
+        assert_eq!(readings.len(), 10);
+        assert!(readings.iter().all(|reading| reading[..2] == [0.0, 0.0] && (reading[2] - 1.0).abs() < GANGLION_ACCEL_SCALE));
+    }
+
+    #[test]
+    fn ganglion_gaps_count_lost_packets_across_the_id_wrap() {
+        let mut packets = ganglion_stream(106);
+        // Drop packets 99 and 100, and one more after the IDs wrap back to 1.
+        packets.remove(102);
+        packets.drain(99..101);
+        let mut bytes = b"\xff\xfe".to_vec();
+        bytes.extend(packets.concat());
+        let decoded = decode(OpenBciBoard::Ganglion, &bytes);
+        assert_eq!((decoded.lost_packets, decoded.skipped_bytes), (3, 2));
+        assert_eq!(decoded.samples.len(), 1 + 2 * 102);
+        assert_eq!(segments(&decoded.samples), 3);
+        assert_eq!(decoded.samples.last().unwrap().index, 2 * 105);
+
+        // Nothing can be rebuilt before a reference sample.
+        let decoded = decode(OpenBciBoard::Ganglion, &ganglion_stream(5)[1..].concat());
+        assert!(decoded.samples.is_empty());
+    }
+
+    async fn stream_simulated(board: OpenBciBoard) -> (Vec<OpenBciSample>, OpenBciDevice) {
+        let simulated = SimulatedBoard::spawn(board).unwrap();
+        let mut device = OpenBciDevice::new(simulated.path()).with_board(board);
+        let mut samples = device.subscribe(4096);
+        device.connect().await.unwrap();
+        assert_eq!(device.board(), Some(board));
+        device.stream(Duration::from_millis(500)).await.unwrap();
+        device.disconnect().await.unwrap();
+        let mut received = vec![];
+        while let Ok(sample) = samples.try_recv() {
+            received.push(sample);
+        }
+        (received, device)
+    }
+
+    #[tokio::test]
+    async fn cyton_over_pty() {
+        let (samples, device) = stream_simulated(OpenBciBoard::Cyton).await;
+        assert!(samples.len() >= 50, "only {} samples", samples.len());
+        assert_eq!((device.lost_packets(), device.skipped_bytes()), (0, 0));
+        // Channel n carries a sine of 10n µV.
+        let expected = simulated_packet(OpenBciBoard::Cyton, false, 1);
+        for (channel, microvolts) in samples[1].microvolts.iter().enumerate() {
+            assert!((microvolts - expected.channels[channel] as f64 * DEFAULT_LSB_MICROVOLTS as f64).abs() < 1e-9);
+        }
+        assert_eq!(samples[0].accelerometer, Some([0.0, 0.0, 1.0]));
+    }
+
+    #[tokio::test]
+    async fn ganglion_over_pty() {
+        let (samples, device) = stream_simulated(OpenBciBoard::Ganglion).await;
+        assert!(samples.len() >= 50, "only {} samples", samples.len());
+        assert!(samples.iter().all(|sample| sample.microvolts.len() == 4));
+        assert_eq!(segments(&samples), 1);
+        assert_eq!((device.lost_packets(), device.skipped_bytes()), (0, 0));
+        assert!(samples.iter().any(|sample| sample.accelerometer.is_some()));
+    }
+
+    #[tokio::test]
+    async fn cyton_daisy_over_pty() {
+        let (samples, device) = stream_simulated(OpenBciBoard::CytonDaisy).await;
+        assert!(samples.len() >= 25, "only {} samples", samples.len());
+        assert!(samples.iter().all(|sample| sample.microvolts.len() == 16));
+        assert_eq!(segments(&samples), 1);
+        assert_eq!(device.lost_packets(), 0);
+    }
+}
@@ -0,0 +1,569 @@
+//! Open Sound Control output over UDP, for audio and visual feedback tools (Max, Pure Data,
+//! TouchDesigner, SuperCollider...).
+//!
+//! Default addresses, where `{device}` is the device id:
+//!
+//! ```text
+//! /bluerain/{device}/band/{band}    f absolute (µV²), f relative
+//! /bluerain/{device}/ratio/{ratio}  f value            e.g. /bluerain/D987/ratio/theta/beta
+//! /bluerain/{device}/reward         s band, f score, i streak
+//! /bluerain/{device}/inhibit        s band, f score, i 1 on start / 0 on end
+//! /bluerain/{device}/phase          s phase
+//! /bluerain/{device}/event          s label
+//! ```
+//!
+//! Every message goes to every target; an empty address pattern turns that message off.
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+
+use super::features::{Band, BandPowers};
+use super::neurofeedback::{InhibitEvent, InhibitRule, InhibitTracker, RewardEvent, RewardRule, RewardTracker};
+use super::pipeline::{Frame, Sink};
+
+/// Larger than any message we send; datagrams beyond this are truncated and rejected.
+const MAX_PACKET: usize = 4096;
+/// Characters the OSC spec reserves for address pattern matching.
+const RESERVED: &[char] = &[' ', '#', '*', ',', '?', '[', ']', '{', '}'];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+impl OscArg {
+    fn tag(&self) -> char {
+        match self {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::Str(_) => 's',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(address: impl Into<String>, args: Vec<OscArg>) -> Self {
+        OscMessage { address: address.into(), args }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = vec![];
+        write_string(&mut packet, &self.address);
+        let tags: String = std::iter::once(',').chain(self.args.iter().map(OscArg::tag)).collect();
+        write_string(&mut packet, &tags);
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(value) => packet.extend_from_slice(&value.to_be_bytes()),
+                OscArg::Float(value) => packet.extend_from_slice(&value.to_be_bytes()),
+                OscArg::Str(value) => write_string(&mut packet, value),
+            }
+        }
+        packet
+    }
+}
+
+impl std::fmt::Display for OscMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.address)?;
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(value) => write!(f, " {}", value)?,
+                OscArg::Float(value) => write!(f, " {}", value)?,
+                OscArg::Str(value) => write!(f, " {:?}", value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A string, NUL-terminated and padded with NULs to a multiple of 4 bytes.
+fn write_string(packet: &mut Vec<u8>, text: &str) {
+    packet.extend_from_slice(text.as_bytes());
+    packet.resize(packet.len() + 4 - text.len() % 4, 0);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position + len;
+        let bytes = self.data.get(self.position..end).ok_or("OSC packet is truncated")?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn word(&mut self) -> Result<[u8; 4], String> {
+        Ok(self.take(4)?.try_into().unwrap())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.data[self.position..];
+        let len = rest.iter().position(|byte| *byte == 0).ok_or("OSC string is not terminated")?;
+        let text = std::str::from_utf8(&rest[..len]).map_err(|_| "OSC string is not UTF-8")?.to_string();
+        self.take((len / 4 + 1) * 4)?;
+        Ok(text)
+    }
+}
+
+/// Decodes a packet into its messages; bundles are flattened and their time tags ignored.
+pub fn decode_packet(packet: &[u8]) -> Result<Vec<OscMessage>, String> {
+    let mut reader = Reader { data: packet, position: 0 };
+    if packet.starts_with(b"#bundle\0") {
+        reader.take(16)?;
+        let mut messages = vec![];
+        while reader.position < packet.len() {
+            let size = i32::from_be_bytes(reader.word()?);
+            let size = usize::try_from(size).map_err(|_| "Negative OSC bundle element size")?;
+            messages.extend(decode_packet(reader.take(size)?)?);
+        }
+        return Ok(messages);
+    }
+
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(format!("OSC address '{}' does not start with /", address));
+    }
+    let tags = reader.string()?;
+    let tags = tags.strip_prefix(',').ok_or("OSC type tags are missing")?;
+    let mut args = vec![];
+    for tag in tags.chars() {
+        args.push(match tag {
+            'i' => OscArg::Int(i32::from_be_bytes(reader.word()?)),
+            'f' => OscArg::Float(f32::from_be_bytes(reader.word()?)),
+            's' => OscArg::Str(reader.string()?),
+            _ => return Err(format!("Unsupported OSC type tag '{}'", tag)),
+        });
+    }
+    Ok(vec![OscMessage::new(address, args)])
+}
+
+/// Address patterns for each kind of message. `{device}` works in all of them, `{band}` and
+/// `{ratio}` in their own. An empty pattern is not sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscAddresses {
+    pub band_power: String,
+    pub ratio: String,
+    pub reward: String,
+    pub inhibit: String,
+    pub phase: String,
+    pub event: String,
+}
+
+impl Default for OscAddresses {
+    fn default() -> Self {
+        OscAddresses {
+            band_power: "/bluerain/{device}/band/{band}".to_string(),
+            ratio: "/bluerain/{device}/ratio/{ratio}".to_string(),
+            reward: "/bluerain/{device}/reward".to_string(),
+            inhibit: "/bluerain/{device}/inhibit".to_string(),
+            phase: "/bluerain/{device}/phase".to_string(),
+            event: "/bluerain/{device}/event".to_string(),
+        }
+    }
+}
+
+impl OscAddresses {
+    /// Applies `kind=pattern`, e.g. `reward=/feedback/reward` or `band_power=` to turn band powers off.
+    pub fn set(&mut self, assignment: &str) -> Result<(), String> {
+        let (kind, pattern) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("Expected kind=pattern, got '{}'", assignment))?;
+        let (slot, placeholders): (&mut String, &[&str]) = match kind.trim() {
+            "band_power" => (&mut self.band_power, &["{device}", "{band}"]),
+            "ratio" => (&mut self.ratio, &["{device}", "{ratio}"]),
+            "reward" => (&mut self.reward, &["{device}"]),
+            "inhibit" => (&mut self.inhibit, &["{device}"]),
+            "phase" => (&mut self.phase, &["{device}"]),
+            "event" => (&mut self.event, &["{device}"]),
+            other => {
+                return Err(format!(
+                    "Unknown OSC message kind '{}' (band_power, ratio, reward, inhibit, phase or event)",
+                    other
+                ))
+            }
+        };
+        let pattern = pattern.trim();
+        validate_pattern(pattern, placeholders)?;
+        *slot = pattern.to_string();
+        Ok(())
+    }
+}
+
+fn validate_pattern(pattern: &str, placeholders: &[&str]) -> Result<(), String> {
+    if pattern.is_empty() {
+        return Ok(());
+    }
+    if !pattern.starts_with('/') {
+        return Err(format!("OSC address '{}' must start with /", pattern));
+    }
+    let bare = placeholders.iter().fold(pattern.to_string(), |bare, placeholder| bare.replace(placeholder, "x"));
+    match bare.chars().find(|c| RESERVED.contains(c) || c.is_whitespace()) {
+        Some(c) => Err(format!("OSC address '{}' contains '{}' (placeholders here: {})", pattern, c, placeholders.join(" "))),
+        None => Ok(()),
+    }
+}
+
+/// Replaces reserved characters so any device id or band name is a valid address part.
+fn address_part(text: &str) -> String {
+    text.chars()
+        .map(|c| if RESERVED.contains(&c) || c.is_whitespace() || c == '/' { '_' } else { c })
+        .collect()
+}
+
+/// A ratio of two bands' absolute powers, written `theta/beta`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandRatio {
+    pub numerator: Band,
+    pub denominator: Band,
+}
+
+impl BandRatio {
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.numerator.name(), self.denominator.name())
+    }
+
+    /// `None` when the denominator band has no power.
+    pub fn value(&self, powers: &BandPowers) -> Option<f64> {
+        let denominator = powers.get(self.denominator);
+        (denominator > 0.0).then(|| powers.get(self.numerator) / denominator)
+    }
+
+    /// Theta/beta (attention) and alpha/theta (relaxation).
+    pub fn defaults() -> Vec<BandRatio> {
+        vec![
+            BandRatio { numerator: Band::Theta, denominator: Band::Beta },
+            BandRatio { numerator: Band::Alpha, denominator: Band::Theta },
+        ]
+    }
+}
+
+impl FromStr for BandRatio {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let band = |name: &str| Band::from_name(name.trim()).ok_or_else(|| format!("Unknown band '{}'", name.trim()));
+        let (numerator, denominator) = text
+            .split_once('/')
+            .ok_or_else(|| format!("Expected a ratio such as theta/beta, got '{}'", text))?;
+        Ok(BandRatio {
+            numerator: band(numerator)?,
+            denominator: band(denominator)?,
+        })
+    }
+}
+
+/// Sends one device's messages to a fixed set of UDP targets. Share it between sinks with an `Arc`.
+#[derive(Debug)]
+pub struct OscSender {
+    socket: UdpSocket,
+    targets: Vec<SocketAddr>,
+    device: String,
+    addresses: OscAddresses,
+    sent: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl OscSender {
+    /// `targets` are `host:port` strings, resolved once here.
+    pub fn new(device: &str, targets: &[String]) -> Result<Self, String> {
+        let mut resolved = vec![];
+        for target in targets {
+            let addrs = target
+                .to_socket_addrs()
+                .map_err(|e| format!("Cannot resolve OSC target '{}': {}", target, e))?;
+            resolved.push(addrs.into_iter().next().ok_or_else(|| format!("OSC target '{}' has no address", target))?);
+        }
+        if resolved.is_empty() {
+            return Err("No OSC targets given".to_string());
+        }
+        let bind = if resolved.iter().all(SocketAddr::is_ipv6) { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(bind).map_err(|e| format!("Cannot open OSC socket: {}", e))?;
+        // A full send buffer must never stall the pipeline; such messages are counted as failed.
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(OscSender {
+            socket,
+            targets: resolved,
+            device: address_part(device),
+            addresses: OscAddresses::default(),
+            sent: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        })
+    }
+
+    pub fn with_addresses(mut self, addresses: OscAddresses) -> Self {
+        self.addresses = addresses;
+        self
+    }
+
+    pub fn targets(&self) -> &[SocketAddr] {
+        &self.targets
+    }
+
+    /// Messages sent and messages that could not be sent, counting each target separately.
+    pub fn counts(&self) -> (u64, u64) {
+        (self.sent.load(Ordering::Relaxed), self.failed.load(Ordering::Relaxed))
+    }
+
+    /// Fills in `{device}` and the given placeholder; `None` if the pattern is turned off.
+    fn address(&self, pattern: &str, placeholder: Option<(&str, &str)>) -> Option<String> {
+        if pattern.is_empty() {
+            return None;
+        }
+        let address = pattern.replace("{device}", &self.device);
+        Some(match placeholder {
+            Some((name, value)) => address.replace(name, value),
+            None => address,
+        })
+    }
+
+    /// Sends to every target. UDP has no delivery guarantee, so a failed target only shows in `counts`.
+    pub fn send(&self, message: &OscMessage) {
+        let packet = message.encode();
+        for target in &self.targets {
+            match self.socket.send_to(&packet, target) {
+                Ok(_) => self.sent.fetch_add(1, Ordering::Relaxed),
+                Err(_) => self.failed.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    pub fn send_band_powers(&self, powers: &BandPowers) {
+        for band in Band::ALL {
+            if let Some(address) = self.address(&self.addresses.band_power, Some(("{band}", band.name()))) {
+                let args = vec![OscArg::Float(powers.get(band) as f32), OscArg::Float(powers.relative(band) as f32)];
+                self.send(&OscMessage::new(address, args));
+            }
+        }
+    }
+
+    pub fn send_ratio(&self, ratio: &BandRatio, value: f64) {
+        if let Some(address) = self.address(&self.addresses.ratio, Some(("{ratio}", &ratio.name()))) {
+            self.send(&OscMessage::new(address, vec![OscArg::Float(value as f32)]));
+        }
+    }
+
+    pub fn send_reward(&self, reward: &RewardEvent) {
+        if let Some(address) = self.address(&self.addresses.reward, None) {
+            let args = vec![
+                OscArg::Str(reward.band.name().to_string()),
+                OscArg::Float(reward.score as f32),
+                OscArg::Int(reward.streak.min(i32::MAX as u32) as i32),
+            ];
+            self.send(&OscMessage::new(address, args));
+        }
+    }
+
+    pub fn send_inhibit(&self, inhibit: &InhibitEvent) {
+        if let Some(address) = self.address(&self.addresses.inhibit, None) {
+            let args = vec![
+                OscArg::Str(inhibit.band.name().to_string()),
+                OscArg::Float(inhibit.score as f32),
+                OscArg::Int(inhibit.active as i32),
+            ];
+            self.send(&OscMessage::new(address, args));
+        }
+    }
+
+    pub fn send_phase(&self, phase: &str) {
+        if let Some(address) = self.address(&self.addresses.phase, None) {
+            self.send(&OscMessage::new(address, vec![OscArg::Str(phase.to_string())]));
+        }
+    }
+
+    pub fn send_event(&self, label: &str) {
+        if let Some(address) = self.address(&self.addresses.event, None) {
+            self.send(&OscMessage::new(address, vec![OscArg::Str(label.to_string())]));
+        }
+    }
+}
+
+/// Publishes band-power frames (`Band::ALL` order, as from `BandPowerStage`) with their ratios
+/// and any reward or inhibit events. Frame events are forwarded from every frame, so the sink
+/// can also sit behind a decision stage, where frames of other widths only have their events sent.
+pub struct OscSink {
+    sender: Arc<OscSender>,
+    ratios: Vec<BandRatio>,
+    reward: Option<RewardTracker>,
+    inhibit: Option<InhibitTracker>,
+}
+
+impl OscSink {
+    pub fn new(sender: Arc<OscSender>) -> Self {
+        OscSink {
+            sender,
+            ratios: BandRatio::defaults(),
+            reward: None,
+            inhibit: None,
+        }
+    }
+
+    pub fn with_ratios(mut self, ratios: Vec<BandRatio>) -> Self {
+        self.ratios = ratios;
+        self
+    }
+
+    pub fn with_reward(mut self, rule: RewardRule) -> Result<Self, String> {
+        self.reward = Some(RewardTracker::new(rule)?);
+        Ok(self)
+    }
+
+    /// While the inhibit band is over its threshold no rewards are given and the hold restarts.
+    pub fn with_inhibit(mut self, rule: InhibitRule) -> Result<Self, String> {
+        self.inhibit = Some(InhibitTracker::new(rule)?);
+        Ok(self)
+    }
+}
+
+#[async_trait]
+impl Sink for OscSink {
+    async fn consume(&mut self, frame: &Frame) -> Result<(), String> {
+        for label in &frame.events {
+            self.sender.send_event(label);
+        }
+        let Ok(values) = <[f64; 5]>::try_from(frame.values.as_slice()) else {
+            return Ok(());
+        };
+        let powers = BandPowers::from(values);
+        self.sender.send_band_powers(&powers);
+        for ratio in &self.ratios {
+            if let Some(value) = ratio.value(&powers) {
+                self.sender.send_ratio(ratio, value);
+            }
+        }
+        if let Some(inhibit) = &mut self.inhibit {
+            if let Some(event) = inhibit.update(frame.time_secs, &powers) {
+                self.sender.send_inhibit(&event);
+            }
+        }
+        if let Some(reward) = &mut self.reward {
+            if self.inhibit.as_ref().is_some_and(InhibitTracker::is_active) {
+                reward.reset();
+            } else if let Some(event) = reward.update(frame.time_secs, &powers) {
+                self.sender.send_reward(&event);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Receives OSC packets, e.g. to check what a sender publishes (`bluerain osc-listen`).
+pub struct OscListener {
+    socket: tokio::net::UdpSocket,
+}
+
+impl OscListener {
+    pub async fn bind(addr: &str) -> Result<Self, String> {
+        let socket = tokio::net::UdpSocket::bind(addr)
+            .await
+            .map_err(|e| format!("Cannot listen for OSC on {}: {}", addr, e))?;
+        Ok(OscListener { socket })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.socket.local_addr().map_err(|e| e.to_string())
+    }
+
+    /// The messages of the next packet and who sent it; `None` if nothing arrives within `timeout`.
+    /// A malformed packet is an error, but the listener stays usable.
+    pub async fn recv(&self, timeout: Duration) -> Result<Option<(SocketAddr, Vec<OscMessage>)>, String> {
+        let mut buffer = [0u8; MAX_PACKET];
+        let (len, from) = match tokio::time::timeout(timeout, self.socket.recv_from(&mut buffer)).await {
+            Err(_) => return Ok(None),
+            Ok(result) => result.map_err(|e| format!("OSC receive failed: {}", e))?,
+        };
+        if len == MAX_PACKET {
+            return Err(format!("OSC packet from {} is too large", from));
+        }
+        let messages = decode_packet(&buffer[..len]).map_err(|e| format!("{} (from {})", e, from))?;
+        Ok(Some((from, messages)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn listener_and_sender(addresses: OscAddresses) -> (OscListener, Arc<OscSender>) {
+        let listener = OscListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let sender = OscSender::new("D 987", &[target]).unwrap().with_addresses(addresses);
+        (listener, Arc::new(sender))
+    }
+
+    async fn receive(listener: &OscListener) -> OscMessage {
+        let (_, mut messages) = listener.recv(Duration::from_secs(2)).await.unwrap().expect("a packet arrives");
+        assert_eq!(messages.len(), 1);
+        messages.remove(0)
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let message = OscMessage::new(
+            "/bluerain/D987/reward",
+            vec![OscArg::Str("alpha".to_string()), OscArg::Float(0.75), OscArg::Int(3)],
+        );
+        let packet = message.encode();
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(decode_packet(&packet), Ok(vec![message]));
+        assert!(decode_packet(b"nope\0\0\0\0,\0\0\0").is_err());
+    }
+
+    #[test]
+    fn address_overrides_are_validated() {
+        let mut addresses = OscAddresses::default();
+        addresses.set("reward=/feedback/{device}/reward").unwrap();
+        addresses.set("band_power=").unwrap();
+        assert_eq!(addresses.reward, "/feedback/{device}/reward");
+        assert!(addresses.band_power.is_empty());
+        assert!(addresses.set("phase=/x/{band}").is_err());
+        assert!(addresses.set("colour=/x").is_err());
+    }
+
+    #[tokio::test]
+    async fn listener_receives_what_the_sender_sends() {
+        let (listener, sender) = listener_and_sender(OscAddresses::default()).await;
+        sender.send_phase("baseline");
+        let message = receive(&listener).await;
+        // The space in the device id is not allowed in an address.
+        assert_eq!(message, OscMessage::new("/bluerain/D_987/phase", vec![OscArg::Str("baseline".to_string())]));
+        assert_eq!(sender.counts(), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn sink_sends_band_powers_ratios_and_events() {
+        let mut addresses = OscAddresses::default();
+        addresses.set("ratio=").unwrap();
+        let (listener, sender) = listener_and_sender(addresses).await;
+        let mut sink = OscSink::new(sender.clone());
+
+        let mut frame = Frame::new(0.5, vec![1.0, 2.0, 4.0, 2.0, 1.0]);
+        frame.events.push("high alpha".to_string());
+        sink.consume(&frame).await.unwrap();
+
+        assert_eq!(receive(&listener).await, OscMessage::new("/bluerain/D_987/event", vec![OscArg::Str("high alpha".to_string())]));
+        let mut bands = vec![];
+        for _ in Band::ALL {
+            bands.push(receive(&listener).await);
+        }
+        let alpha = OscMessage::new("/bluerain/D_987/band/alpha", vec![OscArg::Float(4.0), OscArg::Float(0.4)]);
+        assert!(bands.contains(&alpha), "{:?}", bands);
+        // Ratios are turned off, so nothing else follows.
+        assert_eq!(listener.recv(Duration::from_millis(100)).await, Ok(None));
+        assert_eq!(sender.counts(), (6, 0));
+    }
+}
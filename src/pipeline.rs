@@ -0,0 +1,981 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::f64::consts::PI;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use rand::Rng;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use super::features::{Band, BandPowerTracker, BandPowers};
+use super::filters::FilterBank;
+use super::session_store::{Segment, SessionEvent, StoredSession};
+
+/// One multichannel sample (or derived feature vector) flowing through the pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub time_secs: f64,
+    pub values: Vec<f64>,
+    /// Labels attached by decision stages.
+    pub events: Vec<String>,
+}
+
+impl Frame {
+    pub fn new(time_secs: f64, values: Vec<f64>) -> Self {
+        Frame { time_secs, values, events: vec![] }
+    }
+}
+
+#[async_trait]
+pub trait Source: Send {
+    /// `None` ends the stream.
+    async fn next_frame(&mut self) -> Result<Option<Frame>, String>;
+}
+
+pub trait Stage: Send {
+    /// Returning `None` swallows the frame (e.g. a feature stage between windows).
+    fn process(&mut self, frame: Frame) -> Result<Option<Frame>, String>;
+}
+
+#[async_trait]
+pub trait Sink: Send {
+    async fn consume(&mut self, frame: &Frame) -> Result<(), String>;
+
+    /// Called once the upstream ends or the sink is detached.
+    async fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StageMetrics {
+    pub frames_in: u64,
+    pub frames_out: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+    /// Time spent inside the stage itself.
+    pub busy: Duration,
+    /// Time spent waiting for downstream queues to accept frames (backpressure).
+    pub blocked: Duration,
+    /// Frames waiting in this node's input queue.
+    pub queued: usize,
+}
+
+enum Node {
+    Source(Box<dyn Source>),
+    Stage(Box<dyn Stage>),
+    Sink(Box<dyn Sink>),
+}
+
+impl Node {
+    fn kind(&self) -> &'static str {
+        match self {
+            Node::Source(_) => "source",
+            Node::Stage(_) => "stage",
+            Node::Sink(_) => "sink",
+        }
+    }
+}
+
+/// Downstream queues of a node; `None` once the node has finished.
+type Outputs = Arc<Mutex<Option<Vec<(String, mpsc::Sender<Frame>)>>>>;
+
+struct NodeSpec {
+    name: String,
+    upstream: Option<String>,
+    node: Node,
+}
+
+/// Describes a pipeline graph: sources feed stages, stages feed further stages or sinks.
+/// Every node except a source has exactly one upstream; any node but a sink may fan out.
+pub struct PipelineBuilder {
+    nodes: Vec<NodeSpec>,
+    queue_capacity: usize,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        PipelineBuilder::new()
+    }
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        PipelineBuilder {
+            nodes: vec![],
+            queue_capacity: 64,
+        }
+    }
+
+    /// Frames each queue holds before the upstream node has to wait.
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self
+    }
+
+    pub fn source(self, name: &str, source: impl Source + 'static) -> Self {
+        self.node(name, None, Node::Source(Box::new(source)))
+    }
+
+    pub fn stage(self, name: &str, upstream: &str, stage: impl Stage + 'static) -> Self {
+        self.node(name, Some(upstream), Node::Stage(Box::new(stage)))
+    }
+
+    pub fn sink(self, name: &str, upstream: &str, sink: impl Sink + 'static) -> Self {
+        self.node(name, Some(upstream), Node::Sink(Box::new(sink)))
+    }
+
+    fn node(mut self, name: &str, upstream: Option<&str>, node: Node) -> Self {
+        self.nodes.push(NodeSpec {
+            name: name.to_string(),
+            upstream: upstream.map(str::to_string),
+            node,
+        });
+        self
+    }
+
+    /// Builds the graph from a config, one node per line:
+    ///
+    /// ```text
+    /// # name = kind [<- upstream]
+    /// device = simulated
+    /// filters = eeg-filters <- device
+    /// console = console <- filters
+    /// ```
+    pub fn from_config(config: &str, registry: &Registry) -> Result<Self, String> {
+        let mut builder = PipelineBuilder::new();
+        for (number, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, definition) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Line {}: expected `name = kind [<- upstream]`", number + 1))?;
+            let (kind, upstream) = match definition.split_once("<-") {
+                Some((kind, upstream)) => (kind.trim(), Some(upstream.trim())),
+                None => (definition.trim(), None),
+            };
+            let node = registry
+                .create(kind)
+                .ok_or_else(|| format!("Line {}: unknown node kind '{}'", number + 1, kind))?;
+            builder = builder.node(name.trim(), upstream, node);
+        }
+        Ok(builder)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut kinds = HashMap::new();
+        for spec in &self.nodes {
+            if kinds.insert(spec.name.as_str(), spec.node.kind()).is_some() {
+                return Err(format!("Duplicate pipeline node '{}'", spec.name));
+            }
+        }
+        if !kinds.values().any(|kind| *kind == "source") {
+            return Err("Pipeline has no source".to_string());
+        }
+        for spec in &self.nodes {
+            match (&spec.node, &spec.upstream) {
+                (Node::Source(_), Some(_)) => return Err(format!("Source '{}' cannot have an upstream", spec.name)),
+                (Node::Source(_), None) => {}
+                (_, None) => return Err(format!("Node '{}' needs an upstream", spec.name)),
+                (_, Some(upstream)) => match kinds.get(upstream.as_str()) {
+                    None => return Err(format!("Node '{}' reads from unknown node '{}'", spec.name, upstream)),
+                    Some(&"sink") => return Err(format!("Node '{}' cannot read from sink '{}'", spec.name, upstream)),
+                    Some(_) => {}
+                },
+            }
+        }
+
+        // Topological order from the sources; anything never reached sits on (or behind) a cycle
+        // and would wait forever for frames.
+        let mut downstream: HashMap<&str, Vec<&str>> = HashMap::new();
+        for spec in &self.nodes {
+            if let Some(upstream) = &spec.upstream {
+                downstream.entry(upstream.as_str()).or_default().push(spec.name.as_str());
+            }
+        }
+        let mut ready: Vec<&str> = self.nodes.iter().filter(|spec| spec.upstream.is_none()).map(|spec| spec.name.as_str()).collect();
+        let mut ordered = HashSet::new();
+        while let Some(name) = ready.pop() {
+            ordered.insert(name);
+            ready.extend(downstream.get(name).into_iter().flatten());
+        }
+        let cyclic: Vec<&str> = self.nodes.iter().map(|spec| spec.name.as_str()).filter(|name| !ordered.contains(name)).collect();
+        if !cyclic.is_empty() {
+            return Err(format!("Pipeline has a cycle through {}", cyclic.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// Validates the graph and spawns one task per node.
+    pub fn start(self) -> Result<Pipeline, String> {
+        self.validate()?;
+        let (shutdown, _) = watch::channel(false);
+        let mut pipeline = Pipeline {
+            outputs: HashMap::new(),
+            inputs: HashMap::new(),
+            metrics: BTreeMap::new(),
+            tasks: vec![],
+            shutdown,
+            queue_capacity: self.queue_capacity,
+        };
+        for spec in &self.nodes {
+            if !matches!(spec.node, Node::Sink(_)) {
+                pipeline.outputs.insert(spec.name.clone(), Arc::new(Mutex::new(Some(vec![]))));
+            }
+        }
+        // Sources go last so that no frame is emitted before its consumers are connected.
+        let (sources, others): (Vec<_>, Vec<_>) = self.nodes.into_iter().partition(|spec| matches!(spec.node, Node::Source(_)));
+        for spec in others.into_iter().chain(sources) {
+            pipeline.spawn(spec)?;
+        }
+        Ok(pipeline)
+    }
+}
+
+/// A running pipeline. Sinks can be attached to or detached from any live node.
+pub struct Pipeline {
+    outputs: HashMap<String, Outputs>,
+    /// Input queue of each node, for reporting backlog. Weak, so queues still close when upstream ends.
+    inputs: HashMap<String, mpsc::WeakSender<Frame>>,
+    metrics: BTreeMap<String, Arc<Mutex<StageMetrics>>>,
+    tasks: Vec<JoinHandle<()>>,
+    shutdown: watch::Sender<bool>,
+    queue_capacity: usize,
+}
+
+impl Pipeline {
+    fn spawn(&mut self, spec: NodeSpec) -> Result<(), String> {
+        let input = match &spec.upstream {
+            Some(upstream) => {
+                let (tx, rx) = mpsc::channel(self.queue_capacity);
+                let outputs = self
+                    .outputs
+                    .get(upstream)
+                    .ok_or_else(|| format!("Unknown upstream node '{}'", upstream))?;
+                outputs
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .ok_or_else(|| format!("Node '{}' has already finished", upstream))?
+                    .push((spec.name.clone(), tx.clone()));
+                self.inputs.insert(spec.name.clone(), tx.downgrade());
+                Some(rx)
+            }
+            None => None,
+        };
+
+        let metrics = Arc::new(Mutex::new(StageMetrics::default()));
+        self.metrics.insert(spec.name.clone(), metrics.clone());
+        let name = spec.name;
+        let outputs = self.outputs.get(&name).cloned();
+        let handle = match (spec.node, input, outputs) {
+            (Node::Source(source), None, Some(outputs)) => {
+                tokio::spawn(run_source(name, source, outputs, metrics, self.shutdown.subscribe()))
+            }
+            (Node::Stage(stage), Some(input), Some(outputs)) => tokio::spawn(run_stage(name, stage, input, outputs, metrics)),
+            (Node::Sink(sink), Some(input), None) => tokio::spawn(run_sink(name, sink, input, metrics)),
+            _ => return Err(format!("Node '{}' is wired incorrectly", name)),
+        };
+        self.tasks.push(handle);
+        Ok(())
+    }
+
+    /// Starts a sink reading from `upstream` while the pipeline runs; it sees frames from now on.
+    pub fn attach_sink(&mut self, name: &str, upstream: &str, sink: impl Sink + 'static) -> Result<(), String> {
+        if self.metrics.contains_key(name) {
+            return Err(format!("Duplicate pipeline node '{}'", name));
+        }
+        self.spawn(NodeSpec {
+            name: name.to_string(),
+            upstream: Some(upstream.to_string()),
+            node: Node::Sink(Box::new(sink)),
+        })?;
+        println!("Attached sink '{}' to '{}'", name, upstream);
+        Ok(())
+    }
+
+    /// Disconnects a node from its upstream; it drains what is queued, then finishes.
+    pub fn detach(&mut self, name: &str) -> Result<(), String> {
+        self.inputs.remove(name).ok_or_else(|| format!("Unknown pipeline node '{}'", name))?;
+        for outputs in self.outputs.values() {
+            if let Some(outputs) = outputs.lock().unwrap().as_mut() {
+                outputs.retain(|(downstream, _)| downstream != name);
+            }
+        }
+        println!("Detached '{}'", name);
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> Vec<(String, StageMetrics)> {
+        self.metrics
+            .iter()
+            .map(|(name, metrics)| {
+                let mut snapshot = metrics.lock().unwrap().clone();
+                snapshot.queued = self
+                    .inputs
+                    .get(name)
+                    .and_then(|tx| tx.upgrade())
+                    .map_or(0, |tx| tx.max_capacity() - tx.capacity());
+                (name.clone(), snapshot)
+            })
+            .collect()
+    }
+
+    pub fn print_metrics(&self) {
+        for (name, metrics) in self.metrics() {
+            println!(
+                "  {:<10} in {:>6}  out {:>6}  errors {:>3}  busy {:>8.1?}  blocked {:>8.1?}  queued {:>3}",
+                name, metrics.frames_in, metrics.frames_out, metrics.errors, metrics.busy, metrics.blocked, metrics.queued
+            );
+        }
+    }
+
+    /// Asks the sources to stop; everything downstream drains and finishes.
+    pub fn stop(&self) {
+        let _ = self.shutdown.send(true);
+    }
+
+    /// True once every node has finished, e.g. because the source ran out.
+    pub fn is_finished(&self) -> bool {
+        self.tasks.iter().all(JoinHandle::is_finished)
+    }
+
+    /// Waits until every node has finished.
+    pub async fn wait(mut self) {
+        for task in self.tasks.drain(..) {
+            if let Err(e) = task.await {
+                println!("Pipeline task failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Sends a frame to every current downstream queue, waiting on full ones.
+async fn forward(outputs: &Outputs, frame: Frame, metrics: &Mutex<StageMetrics>) {
+    let targets = outputs.lock().unwrap().clone().unwrap_or_default();
+    let started = Instant::now();
+    for (downstream, tx) in targets {
+        if tx.send(frame.clone()).await.is_err() {
+            if let Some(outputs) = outputs.lock().unwrap().as_mut() {
+                outputs.retain(|(name, _)| *name != downstream);
+            }
+        }
+    }
+    let mut metrics = metrics.lock().unwrap();
+    metrics.frames_out += 1;
+    metrics.blocked += started.elapsed();
+}
+
+fn record_error(name: &str, metrics: &Mutex<StageMetrics>, error: String) {
+    println!("Pipeline node '{}': {}", name, error);
+    let mut metrics = metrics.lock().unwrap();
+    metrics.errors += 1;
+    metrics.last_error = Some(error);
+}
+
+async fn run_source(
+    name: String,
+    mut source: Box<dyn Source>,
+    outputs: Outputs,
+    metrics: Arc<Mutex<StageMetrics>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        let started = Instant::now();
+        let next = tokio::select! {
+            _ = shutdown.changed() => break,
+            next = source.next_frame() => next,
+        };
+        metrics.lock().unwrap().busy += started.elapsed();
+        match next {
+            Ok(Some(frame)) => {
+                metrics.lock().unwrap().frames_in += 1;
+                forward(&outputs, frame, &metrics).await;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                record_error(&name, &metrics, e);
+                break;
+            }
+        }
+    }
+    outputs.lock().unwrap().take();
+}
+
+async fn run_stage(
+    name: String,
+    mut stage: Box<dyn Stage>,
+    mut input: mpsc::Receiver<Frame>,
+    outputs: Outputs,
+    metrics: Arc<Mutex<StageMetrics>>,
+) {
+    while let Some(frame) = input.recv().await {
+        let started = Instant::now();
+        let result = stage.process(frame);
+        {
+            let mut metrics = metrics.lock().unwrap();
+            metrics.frames_in += 1;
+            metrics.busy += started.elapsed();
+        }
+        match result {
+            Ok(Some(frame)) => forward(&outputs, frame, &metrics).await,
+            Ok(None) => {}
+            Err(e) => record_error(&name, &metrics, e),
+        }
+    }
+    outputs.lock().unwrap().take();
+}
+
+async fn run_sink(name: String, mut sink: Box<dyn Sink>, mut input: mpsc::Receiver<Frame>, metrics: Arc<Mutex<StageMetrics>>) {
+    while let Some(frame) = input.recv().await {
+        let started = Instant::now();
+        let result = sink.consume(&frame).await;
+        {
+            let mut metrics = metrics.lock().unwrap();
+            metrics.frames_in += 1;
+            metrics.busy += started.elapsed();
+        }
+        if let Err(e) = result {
+            record_error(&name, &metrics, e);
+        }
+    }
+    if let Err(e) = sink.finish().await {
+        record_error(&name, &metrics, e);
+    }
+}
+
+enum Factory {
+    Source(Box<dyn Fn() -> Box<dyn Source> + Send + Sync>),
+    Stage(Box<dyn Fn() -> Box<dyn Stage> + Send + Sync>),
+    Sink(Box<dyn Fn() -> Box<dyn Sink> + Send + Sync>),
+}
+
+/// Node kinds that a config can refer to by name.
+#[derive(Default)]
+pub struct Registry {
+    factories: HashMap<String, Factory>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    pub fn register_source<S: Source + 'static>(mut self, kind: &str, create: impl Fn() -> S + Send + Sync + 'static) -> Self {
+        self.factories
+            .insert(kind.to_string(), Factory::Source(Box::new(move || Box::new(create()))));
+        self
+    }
+
+    pub fn register_stage<S: Stage + 'static>(mut self, kind: &str, create: impl Fn() -> S + Send + Sync + 'static) -> Self {
+        self.factories
+            .insert(kind.to_string(), Factory::Stage(Box::new(move || Box::new(create()))));
+        self
+    }
+
+    pub fn register_sink<S: Sink + 'static>(mut self, kind: &str, create: impl Fn() -> S + Send + Sync + 'static) -> Self {
+        self.factories
+            .insert(kind.to_string(), Factory::Sink(Box::new(move || Box::new(create()))));
+        self
+    }
+
+    fn create(&self, kind: &str) -> Option<Node> {
+        Some(match self.factories.get(kind)? {
+            Factory::Source(create) => Node::Source(create()),
+            Factory::Stage(create) => Node::Stage(create()),
+            Factory::Sink(create) => Node::Sink(create()),
+        })
+    }
+}
+
+/// Simulated multichannel EEG (10 Hz rhythm plus noise), paced in real time.
+pub struct SimulatedSource {
+    channel_count: usize,
+    sample_rate: f64,
+    total_frames: usize,
+    emitted: usize,
+    started: Option<Instant>,
+}
+
+impl SimulatedSource {
+    pub fn new(channel_count: usize, sample_rate: f64, duration: Duration) -> Self {
+        SimulatedSource {
+            channel_count,
+            sample_rate,
+            total_frames: (duration.as_secs_f64() * sample_rate) as usize,
+            emitted: 0,
+            started: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Source for SimulatedSource {
+    async fn next_frame(&mut self) -> Result<Option<Frame>, String> {
+        if self.emitted >= self.total_frames {
+            return Ok(None);
+        }
+        let time_secs = self.emitted as f64 / self.sample_rate;
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let due = Duration::from_secs_f64(time_secs);
+        if started.elapsed() < due {
+            tokio::time::sleep(due - started.elapsed()).await;
+        }
+        // Alpha waxes and wanes every few seconds.
+        let alpha = 10.0 + 20.0 * (2.0 * PI * 0.2 * time_secs).sin().max(0.0);
+        let values = {
+            let mut rng = rand::thread_rng();
+            (0..self.channel_count)
+                .map(|_| alpha * (2.0 * PI * 10.0 * time_secs).sin() + rng.gen_range(-5.0..5.0))
+                .collect()
+        };
+        self.emitted += 1;
+        Ok(Some(Frame::new(time_secs, values)))
+    }
+}
+
+pub struct FilterStage {
+    bank: FilterBank,
+}
+
+impl FilterStage {
+    pub fn new(bank: FilterBank) -> Self {
+        FilterStage { bank }
+    }
+}
+
+impl Stage for FilterStage {
+    fn process(&mut self, mut frame: Frame) -> Result<Option<Frame>, String> {
+        if frame.values.len() != self.bank.channel_count() {
+            return Err(format!(
+                "Frame has {} channels, filter bank expects {}",
+                frame.values.len(),
+                self.bank.channel_count()
+            ));
+        }
+        self.bank.process_frame(&mut frame.values);
+        Ok(Some(frame))
+    }
+}
+
+/// Per-channel RMS over fixed windows; emits one feature frame per window.
+pub struct RmsStage {
+    window_len: usize,
+    sums: Vec<f64>,
+    count: usize,
+}
+
+impl RmsStage {
+    pub fn new(window_len: usize) -> Self {
+        RmsStage {
+            window_len: window_len.max(1),
+            sums: vec![],
+            count: 0,
+        }
+    }
+}
+
+impl Stage for RmsStage {
+    fn process(&mut self, frame: Frame) -> Result<Option<Frame>, String> {
+        if self.sums.len() != frame.values.len() {
+            self.sums = vec![0.0; frame.values.len()];
+            self.count = 0;
+        }
+        for (sum, value) in self.sums.iter_mut().zip(&frame.values) {
+            *sum += value * value;
+        }
+        self.count += 1;
+        if self.count < self.window_len {
+            return Ok(None);
+        }
+        let rms = self.sums.iter().map(|sum| (sum / self.count as f64).sqrt()).collect();
+        self.sums.iter_mut().for_each(|sum| *sum = 0.0);
+        self.count = 0;
+        Ok(Some(Frame::new(frame.time_secs, rms)))
+    }
+}
+
+/// Channel-averaged band powers over a sliding window, emitted every `hop` frames in
+/// `Band::ALL` order (µV²).
+pub struct BandPowerStage {
+    sample_rate: f64,
+    window_secs: f64,
+    hop: usize,
+    trackers: Vec<BandPowerTracker>,
+    count: usize,
+}
+
+impl BandPowerStage {
+    pub fn new(sample_rate: f64, window_secs: f64, hop: usize) -> Self {
+        BandPowerStage {
+            sample_rate,
+            window_secs,
+            hop: hop.max(1),
+            trackers: vec![],
+            count: 0,
+        }
+    }
+
+    pub fn labels() -> Vec<String> {
+        Band::ALL.iter().map(|band| band.name().to_string()).collect()
+    }
+}
+
+impl Stage for BandPowerStage {
+    fn process(&mut self, frame: Frame) -> Result<Option<Frame>, String> {
+        if self.trackers.len() != frame.values.len() {
+            self.trackers = vec![BandPowerTracker::new(self.sample_rate, self.window_secs); frame.values.len()];
+            self.count = 0;
+        }
+        for (tracker, value) in self.trackers.iter_mut().zip(&frame.values) {
+            tracker.push(*value);
+        }
+        self.count += 1;
+        if self.count < self.hop {
+            return Ok(None);
+        }
+        self.count = 0;
+        let per_channel: Option<Vec<BandPowers>> = self.trackers.iter().map(BandPowerTracker::powers).collect();
+        let Some(per_channel) = per_channel else {
+            return Ok(None);
+        };
+        let powers = BandPowers::average(&per_channel);
+        Ok(Some(Frame::new(frame.time_secs, Band::ALL.iter().map(|band| powers.get(*band)).collect())))
+    }
+}
+
+/// Labels the frame where the mean of the values crosses the threshold, in either direction.
+pub struct ThresholdDecision {
+    threshold: f64,
+    label: String,
+    above: bool,
+}
+
+impl ThresholdDecision {
+    pub fn new(threshold: f64, label: &str) -> Self {
+        ThresholdDecision {
+            threshold,
+            label: label.to_string(),
+            above: false,
+        }
+    }
+}
+
+impl Stage for ThresholdDecision {
+    fn process(&mut self, mut frame: Frame) -> Result<Option<Frame>, String> {
+        if frame.values.is_empty() {
+            return Ok(Some(frame));
+        }
+        let level = frame.values.iter().sum::<f64>() / frame.values.len() as f64;
+        let above = level > self.threshold;
+        if above != self.above {
+            self.above = above;
+            frame
+                .events
+                .push(format!("{} {}", self.label, if above { "start" } else { "end" }));
+        }
+        Ok(Some(frame))
+    }
+}
+
+/// Records frames and events into a session file, written when the sink finishes.
+pub struct RecorderSink {
+    path: PathBuf,
+    session: StoredSession,
+    last_time: Option<f64>,
+}
+
+impl RecorderSink {
+    pub fn new(path: impl Into<PathBuf>, device_id: &str, sample_rate: f64, channels: Vec<String>) -> Self {
+        RecorderSink {
+            path: path.into(),
+            session: StoredSession::new(device_id, sample_rate, channels),
+            last_time: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for RecorderSink {
+    async fn consume(&mut self, frame: &Frame) -> Result<(), String> {
+        if frame.values.len() != self.session.channels.len() {
+            return Err(format!(
+                "Frame has {} channels, recording expects {}",
+                frame.values.len(),
+                self.session.channels.len()
+            ));
+        }
+        let gap = self
+            .last_time
+            .is_none_or(|last| frame.time_secs - last > 1.5 / self.session.sample_rate);
+        if gap {
+            self.session.segments.push(Segment {
+                start_secs: frame.time_secs,
+                frames: vec![],
+            });
+        }
+        if let Some(segment) = self.session.segments.last_mut() {
+            segment.frames.push(frame.values.clone());
+        }
+        for label in &frame.events {
+            self.session.events.push(SessionEvent {
+                time_secs: frame.time_secs,
+                label: label.clone(),
+            });
+        }
+        self.last_time = Some(frame.time_secs);
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), String> {
+        self.session.save(&self.path)?;
+        println!(
+            "Recorded {} frames to {}",
+            self.session.frame_count(),
+            self.path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Text visualizer: prints a bar per channel for every `every`-th frame, and every event.
+pub struct ConsoleSink {
+    every: u64,
+    seen: u64,
+}
+
+impl ConsoleSink {
+    pub fn new(every: u64) -> Self {
+        ConsoleSink { every: every.max(1), seen: 0 }
+    }
+}
+
+#[async_trait]
+impl Sink for ConsoleSink {
+    async fn consume(&mut self, frame: &Frame) -> Result<(), String> {
+        for event in &frame.events {
+            println!("[{:7.2}s] {}", frame.time_secs, event);
+        }
+        if self.seen.is_multiple_of(self.every) {
+            let bars: Vec<String> = frame
+                .values
+                .iter()
+                .map(|value| "#".repeat((value.abs() / 2.0).min(20.0) as usize))
+                .collect();
+            println!("[{:7.2}s] {}", frame.time_secs, bars.join(" | "));
+        }
+        self.seen += 1;
+        Ok(())
+    }
+}
+
+/// Batches frames and simulates uploading each batch with a fixed latency.
+pub struct UploaderSink {
+    batch_size: usize,
+    latency: Duration,
+    pending: Vec<Frame>,
+    uploaded: usize,
+}
+
+impl UploaderSink {
+    pub fn new(batch_size: usize, latency: Duration) -> Self {
+        UploaderSink {
+            batch_size: batch_size.max(1),
+            latency,
+            pending: vec![],
+            uploaded: 0,
+        }
+    }
+
+    async fn upload(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        tokio::time::sleep(self.latency).await;
+        self.uploaded += self.pending.len();
+        self.pending.clear();
+    }
+}
+
+#[async_trait]
+impl Sink for UploaderSink {
+    async fn consume(&mut self, frame: &Frame) -> Result<(), String> {
+        self.pending.push(frame.clone());
+        if self.pending.len() >= self.batch_size {
+            self.upload().await;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), String> {
+        self.upload().await;
+        println!("Uploaded {} frames", self.uploaded);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> SimulatedSource {
+        SimulatedSource::new(2, 250.0, Duration::from_millis(100))
+    }
+
+    /// Emits whatever the test sends it; ends when the sender is dropped.
+    struct ChannelSource(mpsc::UnboundedReceiver<f64>);
+
+    #[async_trait]
+    impl Source for ChannelSource {
+        async fn next_frame(&mut self) -> Result<Option<Frame>, String> {
+            Ok(self.0.recv().await.map(|time_secs| Frame::new(time_secs, vec![time_secs])))
+        }
+    }
+
+    /// Records frame times, taking `delay` over each one.
+    struct CollectingSink {
+        times: Arc<Mutex<Vec<f64>>>,
+        delay: Duration,
+    }
+
+    impl CollectingSink {
+        fn new(delay: Duration) -> (Self, Arc<Mutex<Vec<f64>>>) {
+            let times = Arc::new(Mutex::new(vec![]));
+            (CollectingSink { times: times.clone(), delay }, times)
+        }
+    }
+
+    #[async_trait]
+    impl Sink for CollectingSink {
+        async fn consume(&mut self, frame: &Frame) -> Result<(), String> {
+            tokio::time::sleep(self.delay).await;
+            self.times.lock().unwrap().push(frame.time_secs);
+            Ok(())
+        }
+    }
+
+    async fn wait_for(times: &Mutex<Vec<f64>>, count: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while times.lock().unwrap().len() < count {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("sink receives frames");
+    }
+
+    #[test]
+    fn validate_accepts_a_tree() {
+        let builder = PipelineBuilder::new()
+            .source("device", source())
+            .stage("rms", "device", RmsStage::new(5))
+            .sink("console", "rms", ConsoleSink::new(10));
+        assert_eq!(builder.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_cycles() {
+        let builder = PipelineBuilder::new()
+            .source("device", source())
+            .stage("a", "b", RmsStage::new(5))
+            .stage("b", "a", RmsStage::new(5))
+            .sink("console", "b", ConsoleSink::new(10));
+        let error = builder.validate().unwrap_err();
+        assert!(error.contains("cycle"), "{}", error);
+        assert!(error.contains("a, b, console"), "{}", error);
+    }
+
+    #[test]
+    fn validate_rejects_self_loops() {
+        let builder = PipelineBuilder::new().source("device", source()).stage("loop", "loop", RmsStage::new(5));
+        assert!(builder.validate().unwrap_err().contains("loop"));
+    }
+
+    #[tokio::test]
+    async fn pipeline_runs_to_completion() {
+        let pipeline = PipelineBuilder::new()
+            .source("device", source())
+            .stage("rms", "device", RmsStage::new(5))
+            .sink("console", "rms", ConsoleSink::new(1000))
+            .start()
+            .unwrap();
+        let metrics: BTreeMap<String, StageMetrics> = pipeline.metrics().into_iter().collect();
+        assert!(metrics.contains_key("rms"));
+        tokio::time::timeout(Duration::from_secs(5), pipeline.wait()).await.expect("pipeline finishes");
+    }
+    #[tokio::test]
+    async fn sinks_attached_while_running_see_frames_from_then_on() {
+        let (frames, rx) = mpsc::unbounded_channel();
+        let (first, first_times) = CollectingSink::new(Duration::ZERO);
+        let mut pipeline = PipelineBuilder::new()
+            .source("device", ChannelSource(rx))
+            .stage("rms", "device", RmsStage::new(1))
+            .sink("first", "device", first)
+            .start()
+            .unwrap();
+
+        for time_secs in [0.0, 1.0, 2.0] {
+            frames.send(time_secs).unwrap();
+        }
+        wait_for(&first_times, 3).await;
+
+        let (late, late_times) = CollectingSink::new(Duration::ZERO);
+        pipeline.attach_sink("late", "rms", late).unwrap();
+        assert!(pipeline.attach_sink("first", "device", CollectingSink::new(Duration::ZERO).0).is_err());
+        assert!(pipeline.attach_sink("orphan", "missing", CollectingSink::new(Duration::ZERO).0).is_err());
+        for time_secs in [3.0, 4.0] {
+            frames.send(time_secs).unwrap();
+        }
+        wait_for(&late_times, 2).await;
+
+        pipeline.detach("late").unwrap();
+        frames.send(5.0).unwrap();
+        drop(frames);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !pipeline.is_finished() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("pipeline finishes");
+
+        assert_eq!(*first_times.lock().unwrap(), vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(*late_times.lock().unwrap(), vec![3.0, 4.0]);
+        // The source has finished, so there is nothing left to attach to.
+        let error = pipeline.attach_sink("too-late", "device", CollectingSink::new(Duration::ZERO).0).unwrap_err();
+        assert!(error.contains("finished"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn a_slow_sink_holds_the_source_back_without_losing_frames() {
+        let (slow, times) = CollectingSink::new(Duration::from_millis(2));
+        let pipeline = PipelineBuilder::new()
+            .with_queue_capacity(2)
+            .source("device", SimulatedSource::new(1, 1000.0, Duration::from_millis(40)))
+            .sink("slow", "device", slow)
+            .start()
+            .unwrap();
+
+        let mut most_queued = 0;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !pipeline.is_finished() {
+                let metrics: BTreeMap<String, StageMetrics> = pipeline.metrics().into_iter().collect();
+                most_queued = most_queued.max(metrics["slow"].queued);
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("pipeline finishes");
+
+        let metrics: BTreeMap<String, StageMetrics> = pipeline.metrics().into_iter().collect();
+        assert!(most_queued <= 2, "queue grew to {}", most_queued);
+        assert!(metrics["device"].blocked >= Duration::from_millis(10), "{:?}", metrics["device"]);
+        assert_eq!(metrics["slow"].frames_in, metrics["device"].frames_out);
+        let times = times.lock().unwrap();
+        assert_eq!(times.len() as u64, metrics["device"].frames_out);
+        assert!(times.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
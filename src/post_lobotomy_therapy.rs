@@ -1,66 +1,202 @@
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
 use tokio::task;
 use rand::Rng;
 
+#[allow(dead_code)]
+mod artifacts;
+#[allow(dead_code)]
+mod dashboard;
+#[allow(dead_code)]
+mod features;
+#[allow(dead_code)]
+mod filters;
+#[allow(dead_code)]
+mod neurofeedback;
+#[allow(dead_code)]
+mod osc;
+#[allow(dead_code)]
+mod pipeline;
+#[allow(dead_code)]
+mod ring_buffer;
+#[allow(dead_code)]
+mod session_store;
+#[allow(dead_code)]
+mod streaming_stats;
+
+use artifacts::{ArtifactDetector, ArtifactThresholds, SessionReport, StreamingScreener};
+use dashboard::{Dashboard, DeviceMonitor};
+use osc::OscSender;
+use ring_buffer::{sample_buffer, Consumer, OverflowPolicy, Producer};
+use streaming_stats::StreamingStats;
+
 #[derive(Debug)]
 struct PostLobotomyTherapy {
     patient_id: String,
-    therapy_data: Arc<Mutex<Vec<f64>>>,
-    therapy_sender: mpsc::Sender<f64>,
+    therapy_data: Arc<Mutex<StreamingStats>>,
+    therapy_sender: Producer<f64>,
+    therapy_receiver: Mutex<Consumer<f64>>,
     therapy_effectiveness: Arc<Mutex<bool>>,
+    screener: Mutex<StreamingScreener>,
+    monitor: Option<DeviceMonitor>,
+    osc: Option<Arc<OscSender>>,
 }
 
+/// Mean symptom severity above which corrective measures are applied.
+const SEVERITY_THRESHOLD: f64 = 0.6;
+
 impl PostLobotomyTherapy {
     fn new(patient_id: &str) -> Self {
-        let (tx, _) = mpsc::channel(100);
+        let (tx, rx) = sample_buffer(1024, OverflowPolicy::DropOldest);
         PostLobotomyTherapy {
             patient_id: patient_id.to_string(),
-            therapy_data: Arc::new(Mutex::new(vec![])),
+            therapy_data: Arc::new(Mutex::new(StreamingStats::new(50))),
             therapy_sender: tx,
+            therapy_receiver: Mutex::new(rx),
             therapy_effectiveness: Arc::new(Mutex::new(false)),
+            // Symptoms are sampled at 5 Hz; screen them in 2-second windows.
+            screener: Mutex::new(StreamingScreener::new(ArtifactDetector::new(ArtifactThresholds::normalized(), 5.0, 10))),
+            monitor: None,
+            osc: None,
+        }
+    }
+
+    /// Reports to a dashboard panel instead of stdout, and honours its emergency stop.
+    fn with_monitor(mut self, monitor: DeviceMonitor) -> Self {
+        monitor.set_channels(&["symptom".to_string()], None);
+        monitor.set_threshold("severity", None, SEVERITY_THRESHOLD);
+        self.monitor = Some(monitor);
+        self
+    }
+
+    /// Publishes phase changes over OSC, e.g. to drive feedback visuals.
+    fn with_osc(mut self, sender: Arc<OscSender>) -> Self {
+        self.osc = Some(sender);
+        self
+    }
+
+    fn report(&self, message: String) {
+        match &self.monitor {
+            Some(monitor) => monitor.log(message),
+            None => println!("{}", message),
+        }
+    }
+
+    fn set_phase(&self, phase: &str) {
+        if let Some(monitor) = &self.monitor {
+            monitor.set_phase(phase);
+        }
+        if let Some(osc) = &self.osc {
+            osc.send_phase(phase);
+        }
+    }
+
+    /// Sleeps for `duration`, cut short by the emergency stop.
+    async fn sleep_unless_stopped(&self, duration: Duration) -> Result<(), String> {
+        let Some(monitor) = &self.monitor else {
+            tokio::time::sleep(duration).await;
+            return Ok(());
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => Ok(()),
+            _ = monitor.dashboard().stopped() => {
+                monitor.set_state("ABORTED (emergency stop)");
+                self.set_phase("aborted");
+                Err(format!("Therapy for patient {} aborted by emergency stop", self.patient_id))
+            }
         }
     }
 
     async fn begin_therapy(&self) -> Result<(), String> {
-        println!("Beginning post-lobotomy therapy for patient {}", self.patient_id);
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        Ok(())
+        self.set_phase("starting");
+        if let Some(monitor) = &self.monitor {
+            monitor.set_state("Connected");
+        }
+        self.report(format!("Beginning post-lobotomy therapy for patient {}", self.patient_id));
+        self.sleep_unless_stopped(Duration::from_secs(1)).await
     }
 
     async fn monitor_symptoms(&self, duration: Duration) -> Result<(), String> {
+        self.set_phase("monitoring symptoms");
         let start = Instant::now();
-        let mut rng = rand::thread_rng();
         while start.elapsed() < duration {
-            let symptom_intensity = rng.gen_range(0.0..1.0);
-            self.therapy_sender.send(symptom_intensity).await.unwrap();
-            tokio::time::sleep(Duration::from_millis(200)).await;
+            let symptom_intensity = rand::thread_rng().gen_range(0.0..1.0);
+            self.therapy_sender.send(symptom_intensity).await.map_err(|_| "Sample buffer closed".to_string())?;
+            if let Some(monitor) = &self.monitor {
+                monitor.push_frame(&[symptom_intensity]);
+                // Keep the severity readout live rather than waiting for the evaluation.
+                self.drain_samples();
+                monitor.set_threshold("severity", self.therapy_data.lock().unwrap().mean(), SEVERITY_THRESHOLD);
+            }
+            self.sleep_unless_stopped(Duration::from_millis(200)).await?;
         }
         Ok(())
     }
 
-    fn evaluate_symptoms(&self) -> f64 {
-        let data = self.therapy_data.lock().unwrap();
-        let total: f64 = data.iter().sum();
-        total / data.len() as f64
+    /// Screens buffered readings and moves the clean windows into the running statistics.
+    fn drain_samples(&self) {
+        let mut screener = self.screener.lock().unwrap();
+        let mut stats = self.therapy_data.lock().unwrap();
+        let mut receiver = self.therapy_receiver.lock().unwrap();
+        receiver.drain(|batch| {
+            for intensity in batch {
+                if let Some(clean) = screener.push(*intensity) {
+                    stats.extend(clean);
+                }
+            }
+        });
+        let buffer = receiver.stats();
+        if let Some(monitor) = &self.monitor {
+            monitor.set_dropped(buffer.dropped);
+        } else if buffer.dropped > 0 {
+            println!("Patient {} overran the symptom buffer: {} of {} readings dropped", self.patient_id, buffer.dropped, buffer.accepted + buffer.dropped);
+        }
+    }
+
+    /// Mean of the clean windows and the share of windows rejected.
+    fn evaluate_symptoms(&self) -> SessionReport {
+        self.drain_samples();
+        let mut screener = self.screener.lock().unwrap();
+        if let Some(clean) = screener.finish() {
+            self.therapy_data.lock().unwrap().extend(clean);
+        }
+        screener.report(self.therapy_data.lock().unwrap().mean())
     }
 
     async fn apply_therapy(&self) -> Result<(), String> {
-        let severity = self.evaluate_symptoms();
-        if severity > 0.6 {
-            println!("High severity detected for patient {}. Applying corrective measures.", self.patient_id);
-            tokio::time::sleep(Duration::from_secs(2)).await;
+        self.set_phase("evaluating");
+        let report = self.evaluate_symptoms();
+        self.report(format!("Symptom data for patient {}: {}", self.patient_id, report.summary()));
+        let severity = match report.mean {
+            Some(severity) => severity,
+            None => {
+                self.report(format!("No clean symptom readings for patient {}; therapy not adjusted.", self.patient_id));
+                self.set_phase("complete");
+                return Ok(());
+            }
+        };
+        if let Some(monitor) = &self.monitor {
+            monitor.set_threshold("severity", Some(severity), SEVERITY_THRESHOLD);
+        }
+        if severity > SEVERITY_THRESHOLD {
+            self.set_phase("applying corrective measures");
+            self.report(format!("High severity detected for patient {}. Applying corrective measures.", self.patient_id));
+            self.sleep_unless_stopped(Duration::from_secs(2)).await?;
             *self.therapy_effectiveness.lock().unwrap() = true;
         } else {
-            println!("Symptom severity for patient {} is low. Therapy completed successfully.", self.patient_id);
+            self.report(format!("Symptom severity for patient {} is low. Therapy completed successfully.", self.patient_id));
+        }
+        self.set_phase("complete");
+        if let Some(monitor) = &self.monitor {
+            monitor.set_state("Finished");
         }
         Ok(())
     }
 
     fn reset_therapy_data(&self) {
-        let mut data = self.therapy_data.lock().unwrap();
-        data.clear();
+        self.therapy_receiver.lock().unwrap().drain(|_| {});
+        self.therapy_data.lock().unwrap().reset();
+        self.screener.lock().unwrap().reset();
     }
 }
 
@@ -72,11 +208,32 @@ async fn execute_post_lobotomy_therapy(device: &PostLobotomyTherapy) -> Result<(
     Ok(())
 }
 
+/// `--dashboard` shows the session live in the terminal, with `x` or Esc as the emergency stop.
+/// `--osc host:port[,host:port...]` publishes phase changes over OSC.
 #[tokio::main]
 async fn main() -> Result<(), String> {
-    let therapy_device = PostLobotomyTherapy::new("P9876");
-    let therapy_task = task::spawn(execute_post_lobotomy_therapy(&therapy_device));
+    let patient_id = "P9876";
+    let mut therapy_device = PostLobotomyTherapy::new(patient_id);
+    if let Some(targets) = std::env::args().skip_while(|arg| arg != "--osc").nth(1) {
+        let targets: Vec<String> = targets.split(',').map(|target| target.trim().to_string()).collect();
+        therapy_device = therapy_device.with_osc(Arc::new(OscSender::new(patient_id, &targets)?));
+    }
+    if !std::env::args().any(|arg| arg == "--dashboard") {
+        return task::spawn(async move { execute_post_lobotomy_therapy(&therapy_device).await })
+            .await
+            .map_err(|e| e.to_string())?;
+    }
 
-    therapy_task.await??;
-    Ok(())
+    let (dashboard, handle) = Dashboard::new("post-lobotomy therapy");
+    let therapy_device = therapy_device.with_monitor(handle.add_device(patient_id));
+    let ui = task::spawn(dashboard.run());
+    let result = task::spawn(async move { execute_post_lobotomy_therapy(&therapy_device).await })
+        .await
+        .map_err(|e| e.to_string())?;
+    handle.finish();
+    ui.await.map_err(|e| e.to_string())??;
+    if result.is_ok() {
+        println!("Therapy for patient {} completed", patient_id);
+    }
+    result
 }
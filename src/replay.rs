@@ -0,0 +1,432 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use super::device_operations::{DeviceOperations, HealthCheck, Lifecycle};
+use super::edf::read_edf;
+use super::session_store::{SessionEvent, StoredSession};
+use super::streaming_stats::StreamingStats;
+use super::xdf::read_session;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    Realtime,
+    /// Plays `factor` times faster than recorded.
+    Accelerated(f64),
+    AsFastAsPossible,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    /// Accepts `realtime`, `max`, or a factor such as `4x`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "realtime" | "1x" => Ok(ReplaySpeed::Realtime),
+            "max" => Ok(ReplaySpeed::AsFastAsPossible),
+            _ => match s.strip_suffix('x').and_then(|factor| factor.parse::<f64>().ok()) {
+                Some(factor) if factor > 0.0 => Ok(ReplaySpeed::Accelerated(factor)),
+                _ => Err(format!("Invalid replay speed '{}'; use realtime, max or e.g. 4x", s)),
+            },
+        }
+    }
+}
+
+/// What a replay emits, stamped with the original recording times.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayItem {
+    Frame { time_secs: f64, values: Vec<f64> },
+    Gap { from_secs: f64, to_secs: f64 },
+    Event(SessionEvent),
+}
+
+/// Reads a native session file, EDF/EDF+ when the extension is `.edf`, or the EEG stream of
+/// an XDF file (see `xdf::read_session`) when it is `.xdf`.
+pub fn load_recording(path: &Path) -> Result<StoredSession, String> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    match extension.to_ascii_lowercase().as_str() {
+        "edf" => read_edf(path),
+        "xdf" => read_session(path, None),
+        _ => StoredSession::load(path),
+    }
+}
+
+/// Plays a stored session back as if it were a live device.
+#[derive(Debug)]
+pub struct ReplayDevice {
+    pub id: String,
+    session: StoredSession,
+    speed: ReplaySpeed,
+    channel: usize,
+    brainwave_data: Arc<Mutex<StreamingStats>>,
+    output: Option<mpsc::Sender<ReplayItem>>,
+    lifecycle: Lifecycle,
+    /// Index of the next frame to play, counted across segments.
+    position: usize,
+    /// Events are emitted in time order; this many have been sent.
+    events_sent: usize,
+}
+
+impl ReplayDevice {
+    pub fn new(session: StoredSession) -> Self {
+        ReplayDevice {
+            id: format!("replay:{}", session.device_id),
+            session,
+            speed: ReplaySpeed::Realtime,
+            channel: 0,
+            brainwave_data: Arc::new(Mutex::new(StreamingStats::new(20))),
+            output: None,
+            lifecycle: Lifecycle::new(),
+            position: 0,
+            events_sent: 0,
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, String> {
+        Ok(ReplayDevice::new(load_recording(path)?))
+    }
+
+    pub fn with_speed(mut self, speed: ReplaySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Channel fed into `analyze_data`; the first one by default.
+    pub fn with_channel(mut self, label: &str) -> Result<Self, String> {
+        self.channel = self
+            .session
+            .channels
+            .iter()
+            .position(|channel| channel == label)
+            .ok_or_else(|| format!("Recording has no channel {}", label))?;
+        Ok(self)
+    }
+
+    /// Receives every frame, gap and event as it is played.
+    pub fn subscribe(&mut self, capacity: usize) -> mpsc::Receiver<ReplayItem> {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        self.output = Some(tx);
+        rx
+    }
+
+    pub fn analyze_data(&self) -> Option<f32> {
+        self.brainwave_data.lock().unwrap().mean().map(|mean| mean as f32)
+    }
+
+    async fn emit(&mut self, item: ReplayItem) {
+        if let Some(output) = &self.output {
+            if output.send(item).await.is_err() {
+                self.output = None;
+            }
+        }
+    }
+
+    /// Waits until recording time `time_secs` (relative to the first sample) is due.
+    async fn pace(&self, started: Instant, time_secs: f64, frames_played: usize) {
+        let factor = match self.speed {
+            ReplaySpeed::Realtime => 1.0,
+            ReplaySpeed::Accelerated(factor) => factor,
+            ReplaySpeed::AsFastAsPossible => {
+                if frames_played.is_multiple_of(1024) {
+                    tokio::task::yield_now().await;
+                }
+                return;
+            }
+        };
+        let due = started + Duration::from_secs_f64(time_secs.max(0.0) / factor);
+        if due > Instant::now() {
+            tokio::time::sleep_until(due).await;
+        }
+    }
+
+    /// Plays from the current position for up to `duration` of recording time.
+    async fn play(&mut self, duration: Duration) -> Result<usize, String> {
+        let sample_rate = self.session.sample_rate;
+        if self.channel >= self.session.channels.len() {
+            return Err(format!("Recording for {} has no channels", self.id));
+        }
+        let segments = self.session.segments.clone();
+        let mut events = self.session.events.clone();
+        events.sort_by(|a, b| a.time_secs.total_cmp(&b.time_secs));
+
+        let mut started = Instant::now();
+        let mut origin: Option<f64> = None;
+        let mut previous_end: Option<f64> = None;
+        let mut index = 0;
+        let mut frames_played = 0;
+
+        for segment in &segments {
+            if index + segment.frames.len() <= self.position {
+                index += segment.frames.len();
+                previous_end = Some(segment.start_secs + segment.frames.len() as f64 / sample_rate);
+                continue;
+            }
+            for (i, frame) in segment.frames.iter().enumerate().skip(self.position - index) {
+                let time_secs = segment.start_secs + i as f64 / sample_rate;
+                let origin = *origin.get_or_insert(time_secs);
+                if time_secs - origin >= duration.as_secs_f64() {
+                    return Ok(frames_played);
+                }
+                if i == 0 {
+                    if let Some(end) = previous_end.filter(|end| segment.start_secs - end > 0.5 / sample_rate) {
+                        eprintln!("Recording gap on {}: {:.3}s to {:.3}s", self.id, end, segment.start_secs);
+                        self.emit(ReplayItem::Gap { from_secs: end, to_secs: segment.start_secs }).await;
+                    }
+                }
+                while let Some(event) = events.get(self.events_sent).filter(|event| event.time_secs <= time_secs) {
+                    self.emit(ReplayItem::Event(event.clone())).await;
+                    self.events_sent += 1;
+                }
+
+                started += self.lifecycle.wait_while_paused().await;
+                if !self.lifecycle.is_connected() {
+                    return Err(format!("Replay {} disconnected while streaming", self.id));
+                }
+                self.pace(started, time_secs - origin, frames_played).await;
+                self.brainwave_data.lock().unwrap().push(frame[self.channel]);
+                self.emit(ReplayItem::Frame { time_secs, values: frame.clone() }).await;
+                self.lifecycle.record_samples(1);
+                self.position += 1;
+                frames_played += 1;
+            }
+            index += segment.frames.len();
+            previous_end = Some(segment.start_secs + segment.frames.len() as f64 / sample_rate);
+        }
+        while let Some(event) = events.get(self.events_sent) {
+            self.emit(ReplayItem::Event(event.clone())).await;
+            self.events_sent += 1;
+        }
+        Ok(frames_played)
+    }
+}
+
+#[async_trait]
+impl DeviceOperations for ReplayDevice {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        self.lifecycle.connected()?;
+        eprintln!(
+            "Replay {} ready ({} frames, {:.1}s, {} events) at {:?}",
+            self.id,
+            self.session.frame_count(),
+            self.session.duration_secs(),
+            self.session.events.len(),
+            self.speed
+        );
+        Ok(())
+    }
+
+    async fn stream(&mut self, duration: Duration) -> Result<(), String> {
+        self.lifecycle.begin_stream()?;
+        let result = self.play(duration).await;
+        self.lifecycle.end_stream();
+        match result {
+            Ok(frames) => {
+                eprintln!("Replay {} played {} frames ({} of {})", self.id, frames, self.position, self.session.frame_count());
+                Ok(())
+            }
+            Err(e) => {
+                self.lifecycle.record_error(&e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Ends the replay; reconnecting starts again from the beginning.
+    async fn disconnect(&mut self) -> Result<(), String> {
+        self.lifecycle.check_can_disconnect()?;
+        self.lifecycle.disconnected();
+        self.position = 0;
+        self.events_sent = 0;
+        self.brainwave_data.lock().unwrap().reset();
+        self.output = None;
+        eprintln!("Replay {} stopped", self.id);
+        Ok(())
+    }
+
+    async fn device_checks(&mut self) -> Vec<HealthCheck> {
+        let total = self.session.frame_count();
+        vec![HealthCheck::new(
+            "recording",
+            total > 0,
+            format!("{} of {} frames played", self.position, total),
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::session_store::Segment;
+
+    fn session(sample_rate: f64, segments: Vec<(f64, usize)>) -> StoredSession {
+        let mut session = StoredSession::new("A123", sample_rate, vec!["signal".to_string()]);
+        let mut value = 0.0;
+        for (start_secs, count) in segments {
+            let frames = (0..count)
+                .map(|_| {
+                    value += 1.0;
+                    vec![value]
+                })
+                .collect();
+            session.segments.push(Segment { start_secs, frames });
+        }
+        session
+    }
+
+    fn event(time_secs: f64, label: &str) -> SessionEvent {
+        SessionEvent { time_secs, label: label.to_string() }
+    }
+
+    fn frame_times(items: &[ReplayItem]) -> Vec<f64> {
+        items
+            .iter()
+            .filter_map(|item| match item {
+                ReplayItem::Frame { time_secs, .. } => Some(*time_secs),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn drain(output: &mut mpsc::Receiver<ReplayItem>) -> Vec<ReplayItem> {
+        let mut items = vec![];
+        while let Ok(item) = output.try_recv() {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn speeds_parse() {
+        assert_eq!("realtime".parse(), Ok(ReplaySpeed::Realtime));
+        assert_eq!("max".parse(), Ok(ReplaySpeed::AsFastAsPossible));
+        assert_eq!("4x".parse(), Ok(ReplaySpeed::Accelerated(4.0)));
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[tokio::test]
+    async fn frames_gaps_and_events_come_out_in_order() {
+        let mut recording = session(10.0, vec![(0.0, 3), (1.0, 2)]);
+        recording.events = vec![event(5.0, "end"), event(0.15, "blink"), event(1.05, "eyes closed")];
+        let mut replay = ReplayDevice::new(recording).with_speed(ReplaySpeed::AsFastAsPossible);
+        let mut output = replay.subscribe(64);
+        replay.connect().await.unwrap();
+        replay.stream(Duration::from_secs(60)).await.unwrap();
+
+        let frame = |time_secs: f64, value: f64| ReplayItem::Frame { time_secs, values: vec![value] };
+        assert_eq!(
+            drain(&mut output),
+            vec![
+                frame(0.0, 1.0),
+                frame(0.1, 2.0),
+                ReplayItem::Event(event(0.15, "blink")),
+                frame(0.2, 3.0),
+                ReplayItem::Gap { from_secs: 0.3, to_secs: 1.0 },
+                frame(1.0, 4.0),
+                ReplayItem::Event(event(1.05, "eyes closed")),
+                frame(1.1, 5.0),
+                ReplayItem::Event(event(5.0, "end")),
+            ]
+        );
+        assert_eq!(replay.analyze_data(), Some(3.0));
+        assert_eq!(replay.lifecycle().status().samples_received, 5);
+    }
+
+    #[tokio::test]
+    async fn accelerated_replay_is_paced_by_the_factor() {
+        // 50 frames at 100 Hz span 0.49s of recording: 49ms at 10x.
+        let mut replay = ReplayDevice::new(session(100.0, vec![(0.0, 50)])).with_speed(ReplaySpeed::Accelerated(10.0));
+        replay.connect().await.unwrap();
+        let started = std::time::Instant::now();
+        replay.stream(Duration::from_secs(60)).await.unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(45), "finished too early: {:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(400), "finished too late: {:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn as_fast_as_possible_ignores_recording_time() {
+        // A thousand seconds of recording.
+        let mut replay = ReplayDevice::new(session(1.0, vec![(0.0, 1000)])).with_speed(ReplaySpeed::AsFastAsPossible);
+        let mut output = replay.subscribe(2048);
+        replay.connect().await.unwrap();
+        let started = std::time::Instant::now();
+        replay.stream(Duration::from_secs(10_000)).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(frame_times(&drain(&mut output)).len(), 1000);
+    }
+
+    #[tokio::test]
+    async fn consecutive_streams_continue_from_the_position() {
+        let mut recording = session(20.0, vec![(0.0, 6), (2.0, 6)]);
+        recording.events = vec![event(0.1, "first"), event(2.1, "second")];
+        let mut replay = ReplayDevice::new(recording).with_speed(ReplaySpeed::AsFastAsPossible);
+        let mut output = replay.subscribe(64);
+        replay.connect().await.unwrap();
+
+        replay.stream(Duration::from_millis(250)).await.unwrap();
+        let first = drain(&mut output);
+        assert_eq!(frame_times(&first), vec![0.0, 0.05, 0.1, 0.15, 0.2]);
+        assert_eq!(first.iter().filter(|item| matches!(item, ReplayItem::Event(_))).count(), 1);
+
+        replay.stream(Duration::from_secs(60)).await.unwrap();
+        let second = drain(&mut output);
+        assert_eq!(frame_times(&second), vec![0.25, 2.0, 2.05, 2.1, 2.15, 2.2, 2.25]);
+        assert!(second.contains(&ReplayItem::Gap { from_secs: 0.3, to_secs: 2.0 }));
+        assert_eq!(second.iter().filter(|item| matches!(item, ReplayItem::Event(_))).count(), 1);
+
+        // Disconnecting rewinds to the start.
+        replay.disconnect().await.unwrap();
+        let mut output = replay.subscribe(64);
+        replay.connect().await.unwrap();
+        replay.stream(Duration::from_millis(100)).await.unwrap();
+        assert_eq!(frame_times(&drain(&mut output)), vec![0.0, 0.05]);
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume_continue_without_skipping_frames() {
+        let mut replay = ReplayDevice::new(session(50.0, vec![(0.0, 20)]));
+        let mut output = replay.subscribe(64);
+        replay.connect().await.unwrap();
+        let lifecycle = replay.lifecycle().clone();
+        let streaming = tokio::spawn(async move {
+            let result = replay.stream(Duration::from_secs(60)).await;
+            (replay, result)
+        });
+
+        let mut items = vec![];
+        while frame_times(&items).len() < 5 {
+            items.push(output.recv().await.unwrap());
+        }
+        lifecycle.pause().unwrap();
+        // At most the frame already being paced gets through while paused.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let during_pause = drain(&mut output);
+        assert!(frame_times(&during_pause).len() <= 1);
+        items.extend(during_pause);
+        assert!(!streaming.is_finished());
+
+        lifecycle.resume().unwrap();
+        let resumed = std::time::Instant::now();
+        let (replay, result) = streaming.await.unwrap();
+        result.unwrap();
+        // The pause is left out of the pacing: the rest plays at the recorded rate, not all at once.
+        assert!(resumed.elapsed() >= Duration::from_millis(150));
+        items.extend(drain(&mut output));
+        let expected: Vec<f64> = (0..20).map(|i| i as f64 / 50.0).collect();
+        assert_eq!(frame_times(&items), expected);
+        assert_eq!(replay.lifecycle().status().samples_received, 20);
+    }
+}
@@ -0,0 +1,455 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// What a producer does when the buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// Wait until the consumer frees space. Nothing is lost.
+    Block,
+    /// Evict the oldest buffered sample to make room for the new one.
+    DropOldest,
+    /// Discard the incoming sample.
+    DropNewest,
+    /// While full, keep every `factor`-th incoming sample (evicting the oldest) and discard the rest.
+    Decimate { factor: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BufferStats {
+    pub accepted: u64,
+    pub dropped: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+/// Bounded lock-free queue (Vyukov's sequence-numbered ring). Values and sequence numbers live
+/// in separate arrays so that a run of published slots can be read as one contiguous slice.
+struct Ring<T> {
+    values: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    sequences: Box<[AtomicUsize]>,
+    mask: usize,
+    tail: AtomicUsize,
+    head: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T: Copy> Ring<T> {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        Ring {
+            values: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            sequences: (0..capacity).map(AtomicUsize::new).collect(),
+            mask: capacity - 1,
+            tail: AtomicUsize::new(0),
+            head: AtomicUsize::new(0),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.load(Ordering::Acquire).wrapping_sub(head).min(self.capacity())
+    }
+
+    fn try_push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let sequence = self.sequences[pos & self.mask].load(Ordering::Acquire);
+            let diff = sequence as isize - pos as isize;
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        unsafe { (*self.values[pos & self.mask].get()).write(value) };
+                        self.sequences[pos & self.mask].store(pos + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                return Err(value);
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let sequence = self.sequences[pos & self.mask].load(Ordering::Acquire);
+            let diff = sequence as isize - (pos + 1) as isize;
+            if diff == 0 {
+                match self.head.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        let value = unsafe { (*self.values[pos & self.mask].get()).assume_init() };
+                        self.sequences[pos & self.mask].store(pos + self.capacity(), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Claims up to `max` published slots that are contiguous in memory; returns (start, len).
+    fn claim(&self, max: usize) -> Option<(usize, usize)> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let until_wrap = self.capacity() - (pos & self.mask);
+            let len = (0..max.min(until_wrap))
+                .take_while(|i| self.sequences[(pos + i) & self.mask].load(Ordering::Acquire) == pos + i + 1)
+                .count();
+            if len == 0 {
+                return None;
+            }
+            match self.head.compare_exchange_weak(pos, pos + len, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return Some((pos, len)),
+                Err(current) => pos = current,
+            }
+        }
+    }
+
+    fn release(&self, start: usize, len: usize) {
+        for pos in start..start + len {
+            self.sequences[pos & self.mask].store(pos + self.capacity(), Ordering::Release);
+        }
+    }
+}
+
+struct Shared<T> {
+    ring: Ring<T>,
+    policy: OverflowPolicy,
+    accepted: AtomicU64,
+    dropped: AtomicU64,
+    /// Incoming samples seen while full, for `Decimate`.
+    pressure: AtomicUsize,
+    producers: AtomicUsize,
+    consumer_alive: AtomicBool,
+    space: Notify,
+    data: Notify,
+}
+
+impl<T: Copy> Shared<T> {
+    fn stats(&self) -> BufferStats {
+        BufferStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            len: self.ring.len(),
+            capacity: self.ring.capacity(),
+        }
+    }
+
+    fn accept(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        self.data.notify_one();
+    }
+
+    fn drop_one(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Makes room by evicting the oldest sample, then pushes. If the consumer holds every
+    /// buffered slot in a batch, nothing can be evicted and the new sample is dropped instead.
+    fn push_evicting(&self, mut value: T) {
+        loop {
+            match self.ring.try_push(value) {
+                Ok(()) => return self.accept(),
+                Err(rejected) => value = rejected,
+            }
+            if self.ring.try_pop().is_none() {
+                return self.drop_one();
+            }
+            self.drop_one();
+        }
+    }
+}
+
+/// Creates a sample buffer. The capacity is rounded up to a power of two.
+pub fn sample_buffer<T: Copy + Send>(capacity: usize, policy: OverflowPolicy) -> (Producer<T>, Consumer<T>) {
+    let shared = Arc::new(Shared {
+        ring: Ring::new(capacity),
+        policy,
+        accepted: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+        pressure: AtomicUsize::new(0),
+        producers: AtomicUsize::new(1),
+        consumer_alive: AtomicBool::new(true),
+        space: Notify::new(),
+        data: Notify::new(),
+    });
+    (Producer { shared: shared.clone() }, Consumer { shared })
+}
+
+/// Sending half; clone it for multiple producers.
+pub struct Producer<T: Copy> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Copy> Producer<T> {
+    /// Pushes a sample according to the overflow policy. Only `Block` ever waits.
+    /// Returns the sample back if the consumer is gone.
+    pub async fn send(&self, value: T) -> Result<(), T> {
+        let shared = &self.shared;
+        if !shared.consumer_alive.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        match shared.policy {
+            OverflowPolicy::Block => {
+                let mut value = value;
+                loop {
+                    let space = shared.space.notified();
+                    tokio::pin!(space);
+                    space.as_mut().enable();
+                    match shared.ring.try_push(value) {
+                        Ok(()) => {
+                            shared.accept();
+                            return Ok(());
+                        }
+                        Err(rejected) => value = rejected,
+                    }
+                    if !shared.consumer_alive.load(Ordering::Acquire) {
+                        return Err(value);
+                    }
+                    space.await;
+                }
+            }
+            OverflowPolicy::DropOldest => shared.push_evicting(value),
+            OverflowPolicy::DropNewest => match shared.ring.try_push(value) {
+                Ok(()) => shared.accept(),
+                Err(_) => shared.drop_one(),
+            },
+            OverflowPolicy::Decimate { factor } => match shared.ring.try_push(value) {
+                Ok(()) => {
+                    shared.pressure.store(0, Ordering::Relaxed);
+                    shared.accept();
+                }
+                Err(value) => {
+                    if shared.pressure.fetch_add(1, Ordering::Relaxed).is_multiple_of(factor.max(1)) {
+                        shared.push_evicting(value);
+                    } else {
+                        shared.drop_one();
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        self.shared.stats()
+    }
+}
+
+impl<T: Copy> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        self.shared.producers.fetch_add(1, Ordering::Relaxed);
+        Producer { shared: self.shared.clone() }
+    }
+}
+
+impl<T: Copy> Drop for Producer<T> {
+    fn drop(&mut self) {
+        if self.shared.producers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.data.notify_one();
+        }
+    }
+}
+
+impl<T: Copy> std::fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Producer").field("policy", &self.shared.policy).field("stats", &self.shared.stats()).finish()
+    }
+}
+
+/// Receiving half. There is exactly one consumer per buffer.
+pub struct Consumer<T: Copy> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Borrows up to `max` buffered samples in place, without waiting. A batch never spans
+    /// the end of the ring, so a full drain may take two calls.
+    pub fn try_batch(&mut self, max: usize) -> Option<Batch<'_, T>> {
+        let (start, len) = self.shared.ring.claim(max.max(1))?;
+        Some(Batch { shared: &self.shared, start, len })
+    }
+
+    /// Waits for at least one sample; `None` once every producer is gone and the buffer is empty.
+    pub async fn next_batch(&mut self, max: usize) -> Option<Batch<'_, T>> {
+        loop {
+            let data = self.shared.data.notified();
+            if let Some((start, len)) = self.shared.ring.claim(max.max(1)) {
+                return Some(Batch { shared: &self.shared, start, len });
+            }
+            if self.shared.producers.load(Ordering::Acquire) == 0 {
+                // The last producer may have pushed between the claim above and dropping.
+                let (start, len) = self.shared.ring.claim(max.max(1))?;
+                return Some(Batch { shared: &self.shared, start, len });
+            }
+            data.await;
+        }
+    }
+
+    /// Hands every buffered sample to `f`, batch by batch; returns how many were read.
+    pub fn drain(&mut self, mut f: impl FnMut(&[T])) -> usize {
+        let mut total = 0;
+        while let Some(batch) = self.try_batch(usize::MAX) {
+            f(&batch);
+            total += batch.len();
+        }
+        total
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        self.shared.stats()
+    }
+}
+
+impl<T: Copy> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.shared.consumer_alive.store(false, Ordering::Release);
+        self.shared.space.notify_waiters();
+    }
+}
+
+impl<T: Copy> std::fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Consumer").field("policy", &self.shared.policy).field("stats", &self.shared.stats()).finish()
+    }
+}
+
+/// Samples borrowed straight out of the ring; the slots are handed back to producers on drop.
+pub struct Batch<'a, T: Copy> {
+    shared: &'a Shared<T>,
+    start: usize,
+    len: usize,
+}
+
+impl<T: Copy> Deref for Batch<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        let ring = &self.shared.ring;
+        // The claimed slots are published, contiguous and owned by this batch until it drops.
+        // `UnsafeCell<MaybeUninit<T>>` has the same layout as `T`.
+        unsafe { std::slice::from_raw_parts(ring.values[self.start & ring.mask].get() as *const T, self.len) }
+    }
+}
+
+impl<T: Copy> Drop for Batch<'_, T> {
+    fn drop(&mut self) {
+        self.shared.ring.release(self.start, self.len);
+        self.shared.space.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    const CHANNELS: usize = 16;
+    const RATE_HZ: usize = 1000;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn sixteen_channels_at_1khz_does_not_stall() {
+        // Ten seconds of 16-channel frames through a buffer that holds a tenth of a second.
+        let frames = 10 * RATE_HZ;
+        let (tx, mut rx) = sample_buffer::<[f32; CHANNELS]>(RATE_HZ / 10, OverflowPolicy::Block);
+        let consumer = tokio::spawn(async move {
+            let mut expected = 0.0;
+            while let Some(batch) = rx.next_batch(64).await {
+                for frame in batch.iter() {
+                    assert_eq!(frame[0], expected);
+                    expected += 1.0;
+                }
+            }
+            (expected as usize, rx.stats())
+        });
+
+        let start = Instant::now();
+        for n in 0..frames {
+            tx.send([n as f32; CHANNELS]).await.unwrap();
+        }
+        drop(tx);
+        let (received, stats) = tokio::time::timeout(Duration::from_secs(5), consumer).await.expect("consumer stalled").unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(received, frames);
+        assert_eq!(stats.dropped, 0);
+        // Ten seconds of data must move in well under a second to keep up in real time.
+        assert!(elapsed < Duration::from_secs(1), "{} frames took {:?}", frames, elapsed);
+    }
+
+    #[tokio::test]
+    async fn drop_policies_count_what_they_discard() {
+        for policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            let (tx, mut rx) = sample_buffer(4, policy);
+            for n in 0..10u32 {
+                tx.send(n).await.unwrap();
+            }
+            let mut kept = Vec::new();
+            rx.drain(|batch| kept.extend_from_slice(batch));
+            let expected = if policy == OverflowPolicy::DropOldest { vec![6, 7, 8, 9] } else { vec![0, 1, 2, 3] };
+            assert_eq!(kept, expected);
+            assert_eq!(rx.stats().dropped, 6);
+        }
+    }
+
+    #[tokio::test]
+    async fn decimate_keeps_every_nth_sample_while_full() {
+        let (tx, mut rx) = sample_buffer(4, OverflowPolicy::Decimate { factor: 2 });
+        for n in 0..10u32 {
+            tx.send(n).await.unwrap();
+        }
+        let mut kept = Vec::new();
+        rx.drain(|batch| kept.extend_from_slice(batch));
+        assert_eq!(kept, vec![3, 4, 6, 8]);
+        let stats = rx.stats();
+        assert_eq!((stats.accepted, stats.dropped), (7, 6));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn final_sample_survives_the_producer_dropping() {
+        for round in 0..500u32 {
+            let (tx, mut rx) = sample_buffer(8, OverflowPolicy::Block);
+            let producer = tokio::spawn(async move {
+                tx.send(round).await.unwrap();
+                // `tx` drops here, right after its only push.
+            });
+            let mut received = Vec::new();
+            while let Some(batch) = rx.next_batch(8).await {
+                received.extend_from_slice(&batch);
+            }
+            producer.await.unwrap();
+            assert_eq!(received, vec![round], "round {}", round);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocked_producer_resumes_when_drained() {
+        let (tx, mut rx) = sample_buffer(2, OverflowPolicy::Block);
+        tx.send(1u8).await.unwrap();
+        tx.send(2).await.unwrap();
+        let producer = tokio::spawn(async move { tx.send(3).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!producer.is_finished());
+
+        assert_eq!(rx.drain(|_| {}), 2);
+        producer.await.unwrap().unwrap();
+        assert_eq!(rx.drain(|_| {}), 1);
+    }
+}
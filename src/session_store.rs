@@ -0,0 +1,313 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const FORMAT_HEADER: &str = "# bluerain-session 1";
+
+/// Event labels are stored one per line, so backslashes and line breaks are escaped.
+fn escape_label(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_label(escaped: &str) -> String {
+    let mut label = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            label.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => label.push('\n'),
+            Some('r') => label.push('\r'),
+            Some(other) => label.push(other),
+            None => label.push('\\'),
+        }
+    }
+    label
+}
+
+/// A contiguous run of frames; a recording gap starts a new segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub start_secs: f64,
+    /// One entry per frame, one value per channel.
+    pub frames: Vec<Vec<f64>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionEvent {
+    pub time_secs: f64,
+    pub label: String,
+}
+
+/// A recorded multichannel session in the native line-based format:
+///
+/// ```text
+/// # bluerain-session 1
+/// device A123
+/// sample_rate 250
+/// channels Fp1,Fp2,C3,C4
+/// segment 0
+/// 1.5,2.25,-0.5,3
+/// ...
+/// event 12.5 eyes closed
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredSession {
+    pub device_id: String,
+    pub sample_rate: f64,
+    pub channels: Vec<String>,
+    pub segments: Vec<Segment>,
+    pub events: Vec<SessionEvent>,
+}
+
+impl StoredSession {
+    pub fn new(device_id: &str, sample_rate: f64, channels: Vec<String>) -> Self {
+        StoredSession {
+            device_id: device_id.to_string(),
+            sample_rate,
+            channels,
+            segments: vec![],
+            events: vec![],
+        }
+    }
+
+    /// Builds a gap-free session from channel-major data, one equally long `Vec` per label.
+    pub fn from_channels(device_id: &str, sample_rate: f64, channels: Vec<String>, data: &[Vec<f64>]) -> Result<Self, String> {
+        if data.len() != channels.len() {
+            return Err(format!("{} channel labels for {} channels of data", channels.len(), data.len()));
+        }
+        let frame_count = data.first().map_or(0, |channel| channel.len());
+        if data.iter().any(|channel| channel.len() != frame_count) {
+            return Err("All channels must have the same length".to_string());
+        }
+        let mut session = StoredSession::new(device_id, sample_rate, channels);
+        let frames = (0..frame_count)
+            .map(|i| data.iter().map(|channel| channel[i]).collect())
+            .collect();
+        session.segments.push(Segment { start_secs: 0.0, frames });
+        Ok(session)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.segments.iter().map(|segment| segment.frames.len()).sum()
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        self.segments
+            .last()
+            .map_or(0.0, |segment| segment.start_secs + segment.frames.len() as f64 / self.sample_rate)
+    }
+
+    /// All segments concatenated, one `Vec` per channel.
+    pub fn to_channels(&self) -> Vec<Vec<f64>> {
+        let mut data = vec![Vec::with_capacity(self.frame_count()); self.channels.len()];
+        for frame in self.segments.iter().flat_map(|segment| segment.frames.iter()) {
+            for (channel, value) in data.iter_mut().zip(frame) {
+                channel.push(*value);
+            }
+        }
+        data
+    }
+
+    /// Replaces the samples with channel-major `data` of the same total length, keeping segment boundaries.
+    pub fn replace_channels(&mut self, data: &[Vec<f64>]) -> Result<(), String> {
+        if data.len() != self.channels.len() || data.iter().any(|channel| channel.len() != self.frame_count()) {
+            return Err("Replacement data does not match the session shape".to_string());
+        }
+        let mut index = 0;
+        for segment in &mut self.segments {
+            for frame in &mut segment.frames {
+                for (value, channel) in frame.iter_mut().zip(data) {
+                    *value = channel[index];
+                }
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut writer = SessionWriter::create(path, &self.device_id, self.sample_rate, &self.channels)?;
+        for segment in &self.segments {
+            writer.start_segment(segment.start_secs)?;
+            for frame in &segment.frames {
+                writer.write_frame(frame)?;
+            }
+        }
+        for event in &self.events {
+            writer.write_event(event)?;
+        }
+        writer.finish()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        let mut lines = BufReader::new(file).lines();
+        match lines.next() {
+            Some(Ok(header)) if header.trim() == FORMAT_HEADER => {}
+            _ => return Err(format!("{} is not a BlueRAIN session file", path.display())),
+        }
+
+        let mut session = StoredSession::new("", 0.0, vec![]);
+        for (number, line) in lines.enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim();
+            let bad = |what: &str| format!("{} line {}: {}", path.display(), number + 2, what);
+            if line.is_empty() {
+                continue;
+            }
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "device" => session.device_id = rest.to_string(),
+                "sample_rate" => session.sample_rate = rest.parse().map_err(|_| bad("invalid sample rate"))?,
+                "channels" => session.channels = rest.split(',').map(|label| label.trim().to_string()).collect(),
+                "segment" => session.segments.push(Segment {
+                    start_secs: rest.parse().map_err(|_| bad("invalid segment start"))?,
+                    frames: vec![],
+                }),
+                "event" => {
+                    let (time, label) = rest.split_once(' ').unwrap_or((rest, ""));
+                    session.events.push(SessionEvent {
+                        time_secs: time.parse().map_err(|_| bad("invalid event time"))?,
+                        label: unescape_label(label),
+                    });
+                }
+                _ => {
+                    let frame = line
+                        .split(',')
+                        .map(|value| value.trim().parse::<f64>())
+                        .collect::<Result<Vec<f64>, _>>()
+                        .map_err(|_| bad("invalid sample"))?;
+                    if frame.len() != session.channels.len() {
+                        return Err(bad("frame width does not match channel count"));
+                    }
+                    session
+                        .segments
+                        .last_mut()
+                        .ok_or_else(|| bad("sample before first segment"))?
+                        .frames
+                        .push(frame);
+                }
+            }
+        }
+
+        if session.sample_rate <= 0.0 {
+            return Err(format!("{} has no sample rate", path.display()));
+        }
+        Ok(session)
+    }
+}
+
+/// Writes a session in the native format frame by frame, so long recordings never sit in
+/// memory. Frames written before any `start_segment` go into a segment starting at 0.
+#[derive(Debug)]
+pub struct SessionWriter {
+    out: BufWriter<File>,
+    channel_count: usize,
+    in_segment: bool,
+}
+
+impl SessionWriter {
+    pub fn create(path: &Path, device_id: &str, sample_rate: f64, channels: &[String]) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+        let mut writer = SessionWriter {
+            out: BufWriter::new(file),
+            channel_count: channels.len(),
+            in_segment: false,
+        };
+        writer.line(format_args!("{}", FORMAT_HEADER))?;
+        writer.line(format_args!("device {}", device_id))?;
+        writer.line(format_args!("sample_rate {}", sample_rate))?;
+        writer.line(format_args!("channels {}", channels.join(",")))?;
+        Ok(writer)
+    }
+
+    fn line(&mut self, line: std::fmt::Arguments) -> Result<(), String> {
+        writeln!(self.out, "{}", line).map_err(|e| e.to_string())
+    }
+
+    pub fn start_segment(&mut self, start_secs: f64) -> Result<(), String> {
+        self.in_segment = true;
+        self.line(format_args!("segment {}", start_secs))
+    }
+
+    pub fn write_frame(&mut self, frame: &[f64]) -> Result<(), String> {
+        if frame.len() != self.channel_count {
+            return Err(format!("Session has {} channels, got a frame with {}", self.channel_count, frame.len()));
+        }
+        if !self.in_segment {
+            self.start_segment(0.0)?;
+        }
+        let values: Vec<String> = frame.iter().map(|value| value.to_string()).collect();
+        self.line(format_args!("{}", values.join(",")))
+    }
+
+    pub fn write_event(&mut self, event: &SessionEvent) -> Result<(), String> {
+        self.line(format_args!("event {} {}", event.time_secs, escape_label(&event.label)))
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.out.flush().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_channels_rejects_mismatched_data() {
+        let labels = vec!["C3".to_string(), "C4".to_string()];
+        let session = StoredSession::from_channels("A123", 250.0, labels.clone(), &[vec![1.0, 2.0], vec![3.0, 4.0]]).unwrap();
+        assert_eq!(session.segments[0].frames, vec![vec![1.0, 3.0], vec![2.0, 4.0]]);
+        assert!(StoredSession::from_channels("A123", 250.0, labels.clone(), &[vec![1.0, 2.0], vec![3.0]]).is_err());
+        assert!(StoredSession::from_channels("A123", 250.0, labels, &[vec![1.0]]).is_err());
+    }
+
+    #[test]
+    fn save_and_load_round_trip_with_awkward_labels() {
+        let labels = vec!["Fp1".to_string(), "Fp2".to_string()];
+        let mut session = StoredSession::from_channels("A123", 250.0, labels, &[vec![1.5, -0.25], vec![3.0, 4.0]]).unwrap();
+        session.segments.push(Segment { start_secs: 10.0, frames: vec![vec![5.0, 6.0]] });
+        for label in ["eyes closed", "two\nlines", "segment 3\r\n1,2", "back\\slash\\n"] {
+            session.events.push(SessionEvent { time_secs: 1.5, label: label.to_string() });
+        }
+
+        let path = std::env::temp_dir().join(format!("bluerain-session-test-{}.brs", std::process::id()));
+        session.save(&path).unwrap();
+        let loaded = StoredSession::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, Ok(session));
+    }
+
+    #[test]
+    fn writer_streams_frames_into_a_first_segment() {
+        let path = std::env::temp_dir().join(format!("bluerain-session-writer-test-{}.brs", std::process::id()));
+        let mut writer = SessionWriter::create(&path, "A123", 2.0, &["signal".to_string()]).unwrap();
+        writer.write_frame(&[0.25]).unwrap();
+        writer.write_event(&SessionEvent { time_secs: 0.5, label: "paused".to_string() }).unwrap();
+        writer.write_frame(&[0.75]).unwrap();
+        assert!(writer.write_frame(&[1.0, 2.0]).is_err());
+        writer.start_segment(5.0).unwrap();
+        writer.write_frame(&[1.0]).unwrap();
+        writer.finish().unwrap();
+
+        let loaded = StoredSession::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let starts: Vec<f64> = loaded.segments.iter().map(|segment| segment.start_secs).collect();
+        assert_eq!(starts, vec![0.0, 5.0]);
+        assert_eq!(loaded.to_channels(), vec![vec![0.25, 0.75, 1.0]]);
+        assert_eq!(loaded.events.len(), 1);
+    }
+}
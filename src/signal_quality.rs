@@ -0,0 +1,295 @@
+use std::f64::consts::PI;
+use std::future::Future;
+use std::time::Duration;
+use rand::Rng;
+
+#[derive(Debug, Clone)]
+pub struct QualityCriteria {
+    /// Applied only when the hardware can measure impedance.
+    pub max_impedance_kohm: f64,
+    /// Broadband noise estimated from the sample-to-sample differences.
+    pub max_noise_floor_uv: f64,
+    /// Below this the channel is flat: a disconnected electrode or a stuck amplifier.
+    pub min_noise_floor_uv: f64,
+    /// Amplitude of the mains component.
+    pub max_line_noise_uv: f64,
+    pub mains_hz: f64,
+}
+
+impl Default for QualityCriteria {
+    fn default() -> Self {
+        QualityCriteria {
+            max_impedance_kohm: 20.0,
+            max_noise_floor_uv: 10.0,
+            min_noise_floor_uv: 0.05,
+            max_line_noise_uv: 5.0,
+            mains_hz: 50.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelQuality {
+    pub label: String,
+    pub impedance_kohm: Option<f64>,
+    pub noise_floor_uv: f64,
+    pub line_noise_uv: f64,
+    pub failures: Vec<String>,
+}
+
+impl ChannelQuality {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QualityReport {
+    pub channels: Vec<ChannelQuality>,
+}
+
+impl QualityReport {
+    pub fn passed(&self) -> bool {
+        self.channels.iter().all(|channel| channel.passed())
+    }
+
+    pub fn print(&self, device_id: &str) {
+        println!("Signal quality for device {}:", device_id);
+        for channel in &self.channels {
+            let impedance = channel
+                .impedance_kohm
+                .map_or("     n/a".to_string(), |kohm| format!("{:5.1} kΩ", kohm));
+            println!(
+                "  {:<5} {}  noise {:5.1} µV  line {:5.1} µV  {}",
+                channel.label,
+                impedance,
+                channel.noise_floor_uv,
+                channel.line_noise_uv,
+                if channel.passed() { "PASS".to_string() } else { format!("FAIL ({})", channel.failures.join(", ")) }
+            );
+        }
+    }
+}
+
+/// Amplitude of the `frequency` component of a window, via the Goertzel recurrence.
+fn tone_amplitude(samples: &[f64], frequency: f64, sample_rate: f64) -> f64 {
+    if samples.is_empty() || frequency >= sample_rate / 2.0 {
+        return 0.0;
+    }
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    let coefficient = 2.0 * (2.0 * PI * frequency / sample_rate).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for x in samples {
+        let s0 = (x - mean) + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    let magnitude = (s1 * s1 + s2 * s2 - coefficient * s1 * s2).max(0.0).sqrt();
+    2.0 * magnitude / samples.len() as f64
+}
+
+/// White-noise level estimated from first differences, which ignores slow drift.
+fn noise_floor(samples: &[f64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let mean_square = samples.windows(2).map(|pair| (pair[1] - pair[0]).powi(2)).sum::<f64>() / (samples.len() - 1) as f64;
+    (mean_square / 2.0).sqrt()
+}
+
+/// Scores each channel of a short recording (in µV) against the criteria. Every label needs
+/// a data channel (and an impedance, if given); empty and flat channels fail.
+pub fn assess(
+    criteria: &QualityCriteria,
+    labels: &[&str],
+    sample_rate: f64,
+    data: &[Vec<f64>],
+    impedances_kohm: Option<&[f64]>,
+) -> Result<QualityReport, String> {
+    if data.len() != labels.len() {
+        return Err(format!("{} electrodes but {} data channels", labels.len(), data.len()));
+    }
+    if let Some(impedances) = impedances_kohm.filter(|impedances| impedances.len() != labels.len()) {
+        return Err(format!("{} electrodes but {} impedance readings", labels.len(), impedances.len()));
+    }
+    let channels = labels
+        .iter()
+        .zip(data)
+        .enumerate()
+        .map(|(i, (label, samples))| {
+            let impedance_kohm = impedances_kohm.and_then(|impedances| impedances.get(i).copied());
+            let noise_floor_uv = noise_floor(samples);
+            let line_noise_uv = tone_amplitude(samples, criteria.mains_hz, sample_rate);
+
+            let mut failures = vec![];
+            if let Some(kohm) = impedance_kohm {
+                if kohm > criteria.max_impedance_kohm {
+                    failures.push("impedance".to_string());
+                }
+            }
+            if samples.len() < 2 {
+                failures.push("no data".to_string());
+            } else if noise_floor_uv < criteria.min_noise_floor_uv {
+                failures.push("flat".to_string());
+            }
+            if noise_floor_uv > criteria.max_noise_floor_uv {
+                failures.push("noise".to_string());
+            }
+            if line_noise_uv > criteria.max_line_noise_uv {
+                failures.push("line noise".to_string());
+            }
+            ChannelQuality {
+                label: label.to_string(),
+                impedance_kohm,
+                noise_floor_uv,
+                line_noise_uv,
+                failures,
+            }
+        })
+        .collect();
+    Ok(QualityReport { channels })
+}
+
+/// A short measurement: per-channel samples in µV and, if supported, impedances in kΩ.
+pub type QualityMeasurement = (Vec<Vec<f64>>, Option<Vec<f64>>);
+
+/// Holds a session in initialisation until signal quality passes, or until an explicit,
+/// logged override once the attempts are used up.
+#[derive(Debug, Clone)]
+pub struct QualityGate {
+    pub criteria: QualityCriteria,
+    pub max_attempts: u32,
+    pub retry_interval: Duration,
+    pub override_reason: Option<String>,
+}
+
+impl QualityGate {
+    pub fn new(criteria: QualityCriteria) -> Self {
+        QualityGate {
+            criteria,
+            max_attempts: 5,
+            retry_interval: Duration::from_secs(2),
+            override_reason: None,
+        }
+    }
+
+    pub fn with_override(mut self, reason: &str) -> Self {
+        self.override_reason = Some(reason.to_string());
+        self
+    }
+
+    pub async fn run<F, Fut>(&self, device_id: &str, labels: &[&str], sample_rate: f64, mut measure: F) -> Result<QualityReport, String>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<QualityMeasurement, String>>,
+    {
+        if self.max_attempts == 0 {
+            return Err(format!("Signal quality gate for device {} allows no attempts", device_id));
+        }
+        for attempt in 1..=self.max_attempts {
+            let (data, impedances) = measure(attempt).await?;
+            let report = assess(&self.criteria, labels, sample_rate, &data, impedances.as_deref())?;
+            report.print(device_id);
+            if report.passed() {
+                return Ok(report);
+            }
+            if attempt < self.max_attempts {
+                println!("Adjust the failing electrodes; re-checking in {:?} ({}/{})", self.retry_interval, attempt, self.max_attempts);
+                tokio::time::sleep(self.retry_interval).await;
+            } else if let Some(reason) = &self.override_reason {
+                println!("Signal quality check OVERRIDDEN for device {}: {}", device_id, reason);
+                return Ok(report);
+            }
+        }
+        Err(format!(
+            "Signal quality criteria not met for device {} after {} attempts",
+            device_id, self.max_attempts
+        ))
+    }
+}
+
+/// Simulated electrode recording: 10 Hz rhythm plus white noise and mains pickup.
+pub fn simulate_channel(sample_rate: f64, seconds: f64, noise_uv: f64, line_uv: f64, mains_hz: f64) -> Vec<f64> {
+    let mut rng = rand::thread_rng();
+    (0..(sample_rate * seconds) as usize)
+        .map(|i| {
+            let t = i as f64 / sample_rate;
+            15.0 * (2.0 * PI * 10.0 * t).sin()
+                + line_uv * (2.0 * PI * mains_hz * t).sin()
+                + noise_uv * rng.gen_range(-1.0..1.0) * 3.0_f64.sqrt()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failures(report: &QualityReport) -> Vec<Vec<String>> {
+        report.channels.iter().map(|channel| channel.failures.clone()).collect()
+    }
+
+    /// Two seconds at 250 Hz: a 10 Hz rhythm, a 50 Hz tone and sample-to-sample jitter, in µV.
+    fn channel(line_uv: f64, jitter_uv: f64) -> Vec<f64> {
+        (0..500)
+            .map(|i| {
+                let t = i as f64 / 250.0;
+                let jitter = if i % 2 == 0 { jitter_uv } else { -jitter_uv };
+                15.0 * (2.0 * PI * 10.0 * t).sin() + line_uv * (2.0 * PI * 50.0 * t).sin() + jitter
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clean_channel_passes_and_noisy_ones_fail() {
+        let criteria = QualityCriteria::default();
+        let data = vec![channel(0.0, 1.0), channel(0.0, 10.0), channel(8.0, 1.0)];
+        let report = assess(&criteria, &["C3", "C4", "Cz"], 250.0, &data, Some(&[5.0, 5.0, 50.0])).unwrap();
+        assert_eq!(failures(&report), [vec![], vec!["noise".to_string()], vec!["impedance".to_string(), "line noise".to_string()]]);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn flat_and_empty_channels_fail() {
+        let report = assess(&QualityCriteria::default(), &["C3", "C4"], 250.0, &[vec![12.5; 500], vec![]], None).unwrap();
+        assert_eq!(failures(&report), [vec!["flat".to_string()], vec!["no data".to_string()]]);
+    }
+
+    #[test]
+    fn every_electrode_needs_a_channel() {
+        let data = vec![simulate_channel(250.0, 1.0, 2.0, 0.0, 50.0)];
+        assert!(assess(&QualityCriteria::default(), &["C3", "C4"], 250.0, &data, None).is_err());
+        assert!(assess(&QualityCriteria::default(), &["C3"], 250.0, &data, Some(&[])).is_err());
+    }
+
+    fn gate(max_attempts: u32) -> QualityGate {
+        QualityGate {
+            max_attempts,
+            retry_interval: Duration::ZERO,
+            ..QualityGate::new(QualityCriteria::default())
+        }
+    }
+
+    /// Noisy until the given attempt, then clean.
+    async fn run(gate: &QualityGate, clean_from: u32) -> Result<QualityReport, String> {
+        gate.run("A123", &["Cz"], 250.0, |attempt| async move {
+            let noise = if attempt >= clean_from { 2.0 } else { 25.0 };
+            Ok((vec![simulate_channel(250.0, 1.0, noise, 0.0, 50.0)], None))
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn gate_retries_until_quality_passes() {
+        assert!(run(&gate(3), 3).await.unwrap().passed());
+        assert!(run(&gate(3), 4).await.is_err());
+        let overridden = run(&gate(3).with_override("electrode cap damaged"), 4).await.unwrap();
+        assert!(!overridden.passed());
+    }
+
+    #[tokio::test]
+    async fn gate_needs_at_least_one_attempt() {
+        let error = run(&gate(0).with_override("skip"), 1).await.unwrap_err();
+        assert!(error.contains("no attempts"), "{}", error);
+    }
+}
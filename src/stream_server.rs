@@ -0,0 +1,542 @@
+//! Streams pipeline frames, features and events to browsers over WebSockets.
+//!
+//! A client connects to `ws://<addr>/` and first receives a catalog of devices, channels and
+//! features. It may then send a subscription at any time; omitted fields mean "everything":
+//!
+//! ```text
+//! {"devices": ["D987"], "channels": ["O1", "O2"], "features": ["alpha-rms"], "events": true, "max_rate": 20}
+//! ```
+//!
+//! `max_rate` caps messages per second for each frame or feature stream, dropping the ones in
+//! between; events are not rate-limited. A client that falls `CLIENT_BACKLOG` messages behind
+//! loses messages of every kind, events included, and is sent `{"type": "dropped", "count": n}`.
+//! Plain HTTP requests to `/` get a small plotting page.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use super::pipeline::{Frame, Sink};
+
+const VIEWER_PAGE: &str = include_str!("stream_viewer.html");
+/// Messages a slow client may fall behind by before it starts losing them.
+const CLIENT_BACKLOG: usize = 1024;
+const MAX_REQUEST_HEAD: usize = 8192;
+
+#[derive(Debug, Clone)]
+enum Published {
+    Frame { device: String, time_secs: f64, channels: Arc<Vec<String>>, values: Vec<f64> },
+    Feature { device: String, name: String, time_secs: f64, labels: Arc<Vec<String>>, values: Vec<f64> },
+    Event { device: String, time_secs: f64, label: String },
+    Catalog,
+}
+
+#[derive(Debug, Clone, Default)]
+struct DeviceCatalog {
+    channels: Vec<String>,
+    /// Feature name to value labels.
+    features: BTreeMap<String, Vec<String>>,
+}
+
+/// Fan-out point between pipeline sinks and connected clients. Clones share one stream.
+#[derive(Debug, Clone)]
+pub struct StreamHub {
+    tx: broadcast::Sender<Arc<Published>>,
+    catalog: Catalog,
+}
+
+impl Default for StreamHub {
+    fn default() -> Self {
+        StreamHub::new()
+    }
+}
+
+impl StreamHub {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CLIENT_BACKLOG);
+        StreamHub {
+            tx,
+            catalog: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Sink publishing a device's raw frames, with one label per value.
+    pub fn frame_sink(&self, device: &str, channels: Vec<String>) -> WebSocketSink {
+        self.catalog.lock().unwrap().entry(device.to_string()).or_default().channels = channels.clone();
+        self.publish(Published::Catalog);
+        WebSocketSink {
+            hub: self.clone(),
+            device: device.to_string(),
+            feature: None,
+            labels: Arc::new(channels),
+        }
+    }
+
+    /// Sink publishing a derived feature stream (RMS, band powers...) under `name`.
+    pub fn feature_sink(&self, device: &str, name: &str, labels: Vec<String>) -> WebSocketSink {
+        self.catalog
+            .lock()
+            .unwrap()
+            .entry(device.to_string())
+            .or_default()
+            .features
+            .insert(name.to_string(), labels.clone());
+        self.publish(Published::Catalog);
+        WebSocketSink {
+            hub: self.clone(),
+            device: device.to_string(),
+            feature: Some(name.to_string()),
+            labels: Arc::new(labels),
+        }
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    fn publish(&self, item: Published) {
+        // No subscribers is fine; nobody is watching.
+        let _ = self.tx.send(Arc::new(item));
+    }
+}
+
+type Catalog = Arc<Mutex<BTreeMap<String, DeviceCatalog>>>;
+
+fn catalog_json(catalog: &Catalog) -> Value {
+    let devices: Vec<Value> = catalog
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(device, catalog)| json!({ "device": device, "channels": catalog.channels, "features": catalog.features }))
+        .collect();
+    json!({ "type": "catalog", "devices": devices })
+}
+
+/// Publishes every frame it receives to the hub; frame events go out as event messages.
+pub struct WebSocketSink {
+    hub: StreamHub,
+    device: String,
+    /// `None` for raw frames.
+    feature: Option<String>,
+    labels: Arc<Vec<String>>,
+}
+
+#[async_trait]
+impl Sink for WebSocketSink {
+    async fn consume(&mut self, frame: &Frame) -> Result<(), String> {
+        for label in &frame.events {
+            self.hub.publish(Published::Event {
+                device: self.device.clone(),
+                time_secs: frame.time_secs,
+                label: label.clone(),
+            });
+        }
+        let item = match &self.feature {
+            Some(name) => Published::Feature {
+                device: self.device.clone(),
+                name: name.clone(),
+                time_secs: frame.time_secs,
+                labels: self.labels.clone(),
+                values: frame.values.clone(),
+            },
+            None => Published::Frame {
+                device: self.device.clone(),
+                time_secs: frame.time_secs,
+                channels: self.labels.clone(),
+                values: frame.values.clone(),
+            },
+        };
+        self.hub.publish(item);
+        Ok(())
+    }
+}
+
+/// What one client asked for. `None` lists allow everything.
+#[derive(Debug, Clone)]
+struct Subscription {
+    devices: Option<Vec<String>>,
+    channels: Option<Vec<String>>,
+    features: Option<Vec<String>>,
+    events: bool,
+    min_interval: Option<Duration>,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Subscription {
+            devices: None,
+            channels: None,
+            features: None,
+            events: true,
+            min_interval: None,
+        }
+    }
+}
+
+impl Subscription {
+    fn parse(text: &str) -> Result<Self, String> {
+        let request: Value = serde_json::from_str(text).map_err(|e| format!("Invalid JSON: {}", e))?;
+        let request = request.as_object().ok_or("Subscription must be a JSON object")?;
+        let list = |key: &str| -> Result<Option<Vec<String>>, String> {
+            match request.get(key) {
+                None | Some(Value::Null) => Ok(None),
+                Some(Value::Array(items)) => items
+                    .iter()
+                    .map(|item| item.as_str().map(str::to_string).ok_or_else(|| format!("'{}' must list strings", key)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Some),
+                Some(_) => Err(format!("'{}' must be an array of strings", key)),
+            }
+        };
+        let max_rate = match request.get("max_rate") {
+            None | Some(Value::Null) => None,
+            Some(rate) => Some(rate.as_f64().filter(|rate| *rate >= 0.0).ok_or("'max_rate' must be a non-negative number")?),
+        };
+        Ok(Subscription {
+            devices: list("devices")?,
+            channels: list("channels")?,
+            features: list("features")?,
+            events: request.get("events").and_then(Value::as_bool).unwrap_or(true),
+            min_interval: max_rate.filter(|rate| *rate > 0.0).map(|rate| Duration::from_secs_f64(1.0 / rate)),
+        })
+    }
+
+    fn allows(list: &Option<Vec<String>>, item: &str) -> bool {
+        list.as_ref().is_none_or(|list| list.iter().any(|allowed| allowed == item))
+    }
+}
+
+/// Per-connection filtering and rate limiting.
+struct Client {
+    subscription: Subscription,
+    last_sent: HashMap<String, Instant>,
+}
+
+impl Client {
+    /// Applies the rate limit for a stream; true if this message may go out now.
+    fn due(&mut self, stream: String) -> bool {
+        let Some(interval) = self.subscription.min_interval else {
+            return true;
+        };
+        let now = Instant::now();
+        match self.last_sent.get(&stream) {
+            Some(last) if now.duration_since(*last) < interval => false,
+            _ => {
+                self.last_sent.insert(stream, now);
+                true
+            }
+        }
+    }
+
+    fn render(&mut self, item: &Published, catalog: &Catalog) -> Option<Value> {
+        let subscription = &self.subscription;
+        match item {
+            Published::Frame { device, time_secs, channels, values } => {
+                if !Subscription::allows(&subscription.devices, device) {
+                    return None;
+                }
+                let (channels, values): (Vec<&String>, Vec<f64>) = channels
+                    .iter()
+                    .zip(values)
+                    .filter(|(channel, _)| Subscription::allows(&subscription.channels, channel))
+                    .unzip();
+                if channels.is_empty() || !self.due(format!("frame/{}", device)) {
+                    return None;
+                }
+                Some(json!({ "type": "frame", "device": device, "time": time_secs, "channels": channels, "values": values }))
+            }
+            Published::Feature { device, name, time_secs, labels, values } => {
+                if !Subscription::allows(&subscription.devices, device) || !Subscription::allows(&subscription.features, name) {
+                    return None;
+                }
+                if !self.due(format!("feature/{}/{}", device, name)) {
+                    return None;
+                }
+                Some(json!({ "type": "feature", "device": device, "name": name, "time": time_secs, "labels": labels.as_slice(), "values": values }))
+            }
+            Published::Event { device, time_secs, label } => (subscription.events && Subscription::allows(&subscription.devices, device))
+                .then(|| json!({ "type": "event", "device": device, "time": time_secs, "label": label })),
+            Published::Catalog => Some(catalog_json(catalog)),
+        }
+    }
+}
+
+/// Accepts connections on `addr` until the returned task is aborted. Returns the bound address,
+/// so `127.0.0.1:0` can be used to pick a free port. Connected clients are closed once the task
+/// is aborted and every sink from `hub` has finished.
+pub async fn start_server(addr: &str, hub: StreamHub) -> Result<(SocketAddr, JoinHandle<()>), String> {
+    let listener = TcpListener::bind(addr).await.map_err(|e| format!("Cannot listen on {}: {}", addr, e))?;
+    let local_addr = listener.local_addr().map_err(|e| e.to_string())?;
+    let task = tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    eprintln!("Stream server accept failed: {}", e);
+                    continue;
+                }
+            };
+            let hub = hub.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, hub).await {
+                    eprintln!("Stream client {}: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok((local_addr, task))
+}
+
+/// Reads the HTTP request head (the client sends nothing else before the handshake completes).
+async fn read_request_head(stream: &mut TcpStream) -> Result<String, String> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err("Request head too large".to_string());
+        }
+        let read = stream.read(&mut chunk).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("Connection closed during request".to_string());
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+    String::from_utf8(head).map_err(|_| "Request head is not UTF-8".to_string())
+}
+
+async fn handle_connection(mut stream: TcpStream, hub: StreamHub) -> Result<(), String> {
+    let head = read_request_head(&mut stream).await?;
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let is_upgrade = headers.get("upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+
+    if !is_upgrade {
+        let (status, content_type, body) = match (request_line.starts_with("GET "), path) {
+            (true, "/" | "/index.html") => ("200 OK", "text/html; charset=utf-8", VIEWER_PAGE),
+            (true, _) => ("404 Not Found", "text/plain", "not found\n"),
+            (false, _) => ("405 Method Not Allowed", "text/plain", "method not allowed\n"),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        return stream.write_all(response.as_bytes()).await.map_err(|e| e.to_string());
+    }
+
+    let key = headers.get("sec-websocket-key").ok_or("WebSocket request without Sec-WebSocket-Key")?;
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    stream.write_all(response.as_bytes()).await.map_err(|e| e.to_string())?;
+    let socket = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+    // Only a receiver is kept, so the client sees the stream close once every sink is gone.
+    let StreamHub { tx, catalog } = hub;
+    let published = tx.subscribe();
+    drop(tx);
+    run_client(socket, published, catalog).await
+}
+
+async fn run_client(socket: WebSocketStream<TcpStream>, mut published: broadcast::Receiver<Arc<Published>>, catalog: Catalog) -> Result<(), String> {
+    let (mut outgoing, mut incoming) = socket.split();
+    let mut client = Client {
+        subscription: Subscription::default(),
+        last_sent: HashMap::new(),
+    };
+    let send = |value: Value| Message::Text(value.to_string());
+    outgoing.send(send(catalog_json(&catalog))).await.map_err(|e| e.to_string())?;
+
+    loop {
+        tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match Subscription::parse(&text) {
+                        Ok(subscription) => {
+                            client.subscription = subscription;
+                            client.last_sent.clear();
+                            json!({ "type": "subscribed" })
+                        }
+                        Err(e) => json!({ "type": "error", "message": e }),
+                    };
+                    outgoing.send(send(reply)).await.map_err(|e| e.to_string())?;
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.to_string()),
+            },
+            item = published.recv() => match item {
+                Ok(item) => {
+                    if let Some(value) = client.render(&item, &catalog) {
+                        outgoing.send(send(value)).await.map_err(|e| e.to_string())?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    outgoing.send(send(json!({ "type": "dropped", "count": count }))).await.map_err(|e| e.to_string())?;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+    let _ = outgoing.send(Message::Close(None)).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::MaybeTlsStream;
+
+    type TestClient = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn connect(addr: SocketAddr) -> TestClient {
+        let (client, _) = tokio_tungstenite::connect_async(format!("ws://{}/", addr)).await.unwrap();
+        client
+    }
+
+    /// The next text message as JSON, or `None` once the server closes the stream.
+    async fn next_json(client: &mut TestClient) -> Option<Value> {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(2), client.next()).await.expect("no message within 2 s");
+            match message {
+                Some(Ok(Message::Text(text))) => return Some(serde_json::from_str(&text).unwrap()),
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => return None,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    async fn subscribe(client: &mut TestClient, subscription: Value) {
+        client.send(Message::Text(subscription.to_string())).await.unwrap();
+        assert_eq!(next_json(client).await.unwrap()["type"], json!("subscribed"));
+    }
+
+    fn labels(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn subscriptions_filter_devices_channels_features_and_events() {
+        let hub = StreamHub::new();
+        let mut d1_frames = hub.frame_sink("D1", labels(&["O1", "O2", "Cz"]));
+        let mut d1_alpha = hub.feature_sink("D1", "alpha", labels(&["O1"]));
+        let mut d2_frames = hub.frame_sink("D2", labels(&["O2"]));
+        let (addr, server) = start_server("127.0.0.1:0", hub.clone()).await.unwrap();
+        let mut client = connect(addr).await;
+
+        let catalog = next_json(&mut client).await.unwrap();
+        assert_eq!(catalog["type"], json!("catalog"));
+        assert_eq!(catalog["devices"][0], json!({ "device": "D1", "channels": ["O1", "O2", "Cz"], "features": { "alpha": ["O1"] } }));
+        assert_eq!(catalog["devices"][1]["device"], json!("D2"));
+        let error = {
+            client.send(Message::Text(json!({ "channels": "O2" }).to_string())).await.unwrap();
+            next_json(&mut client).await.unwrap()
+        };
+        assert_eq!(error["type"], json!("error"));
+
+        subscribe(&mut client, json!({ "devices": ["D1"], "channels": ["O2"], "features": [], "events": false })).await;
+        d2_frames.consume(&Frame::new(0.0, vec![9.0])).await.unwrap();
+        d1_alpha.consume(&Frame::new(0.0, vec![5.0])).await.unwrap();
+        let mut marked = Frame::new(0.5, vec![1.0, 2.0, 3.0]);
+        marked.events.push("blink".to_string());
+        d1_frames.consume(&marked).await.unwrap();
+        let frame = next_json(&mut client).await.unwrap();
+        assert_eq!(frame, json!({ "type": "frame", "device": "D1", "time": 0.5, "channels": ["O2"], "values": [2.0] }));
+
+        subscribe(&mut client, json!({ "devices": ["D1"], "features": ["alpha"], "channels": [] })).await;
+        d1_frames.consume(&marked).await.unwrap();
+        d1_alpha.consume(&Frame::new(1.0, vec![5.0])).await.unwrap();
+        assert_eq!(next_json(&mut client).await.unwrap(), json!({ "type": "event", "device": "D1", "time": 0.5, "label": "blink" }));
+        let feature = next_json(&mut client).await.unwrap();
+        assert_eq!(feature, json!({ "type": "feature", "device": "D1", "name": "alpha", "time": 1.0, "labels": ["O1"], "values": [5.0] }));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn max_rate_decimates_streams_but_not_events() {
+        let hub = StreamHub::new();
+        let mut sink = hub.frame_sink("D1", labels(&["O1"]));
+        let (addr, server) = start_server("127.0.0.1:0", hub.clone()).await.unwrap();
+        let mut client = connect(addr).await;
+        next_json(&mut client).await.unwrap();
+        subscribe(&mut client, json!({ "max_rate": 4 })).await;
+
+        for i in 0..50 {
+            let mut frame = Frame::new(i as f64, vec![i as f64]);
+            if i % 10 == 0 {
+                frame.events.push(format!("mark {}", i));
+            }
+            sink.consume(&frame).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        sink.consume(&Frame::new(50.0, vec![50.0])).await.unwrap();
+        drop((sink, hub));
+        server.abort();
+
+        let mut frames = vec![];
+        let mut events = vec![];
+        while let Some(message) = next_json(&mut client).await {
+            match message["type"].as_str().unwrap() {
+                "frame" => frames.push(message["time"].as_f64().unwrap()),
+                "event" => events.push(message["label"].as_str().unwrap().to_string()),
+                other => panic!("unexpected {} message", other),
+            }
+        }
+        assert_eq!(frames, vec![0.0, 50.0]);
+        assert_eq!(events, vec!["mark 0", "mark 10", "mark 20", "mark 30", "mark 40"]);
+    }
+
+    #[tokio::test]
+    async fn clients_are_closed_once_the_server_and_sinks_are_gone() {
+        let hub = StreamHub::new();
+        let sink = hub.frame_sink("D1", labels(&["O1"]));
+        let (addr, server) = start_server("127.0.0.1:0", hub.clone()).await.unwrap();
+        let mut client = connect(addr).await;
+        next_json(&mut client).await.unwrap();
+        assert_eq!(hub.client_count(), 1);
+
+        server.abort();
+        drop(hub);
+        drop(sink);
+        assert_eq!(next_json(&mut client).await, None);
+    }
+
+    #[tokio::test]
+    async fn plain_http_gets_the_viewer_page() {
+        let (addr, server) = start_server("127.0.0.1:0", StreamHub::new()).await.unwrap();
+        let request = |head: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let page = request("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(page.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(page.contains("Content-Type: text/html"));
+        assert!(page.ends_with(VIEWER_PAGE));
+        assert!(request("GET /missing HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 404 "));
+        assert!(request("POST / HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 405 "));
+        server.abort();
+    }
+}
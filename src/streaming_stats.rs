@@ -0,0 +1,427 @@
+use std::collections::VecDeque;
+
+/// Running mean and variance (Welford's algorithm).
+#[derive(Debug, Clone, Default)]
+pub struct Welford {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Welford {
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    /// Sample variance; needs at least two values.
+    pub fn variance(&self) -> Option<f64> {
+        (self.count > 1).then(|| self.m2 / (self.count - 1) as f64)
+    }
+
+    pub fn std_dev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MinMax {
+    range: Option<(f64, f64)>,
+}
+
+impl MinMax {
+    pub fn push(&mut self, x: f64) {
+        self.range = Some(match self.range {
+            Some((min, max)) => (min.min(x), max.max(x)),
+            None => (x, x),
+        });
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.range.map(|(min, _)| min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.range.map(|(_, max)| max)
+    }
+}
+
+/// Constant-memory quantile estimate (Jain & Chlamtac P² algorithm).
+#[derive(Debug, Clone)]
+pub struct P2Quantile {
+    p: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    pub fn new(p: f64) -> Self {
+        let p = p.clamp(0.0, 1.0);
+        P2Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [0.0, 1.0, 2.0, 3.0, 4.0],
+            desired: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    pub fn push(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        let h = &mut self.heights;
+        let k = if x < h[0] {
+            h[0] = x;
+            0
+        } else if x >= h[4] {
+            h[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| h[i] <= x && x < h[i + 1]).unwrap_or(3)
+        };
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(&self.increments) {
+            *desired += increment;
+        }
+
+        let n = &mut self.positions;
+        for i in 1..4 {
+            let d = self.desired[i] - n[i];
+            if (d >= 1.0 && n[i + 1] - n[i] > 1.0) || (d <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = d.signum();
+                let parabolic = h[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (h[i + 1] - h[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (h[i] - h[i - 1]) / (n[i] - n[i - 1]));
+                h[i] = if h[i - 1] < parabolic && parabolic < h[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    h[i] + d * (h[j] - h[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            count if count < 5 => {
+                let mut seen = self.heights[..count].to_vec();
+                seen.sort_by(f64::total_cmp);
+                Some(seen[((count - 1) as f64 * self.p).round() as usize])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
+/// Exponentially weighted moving average; `alpha` is the weight of the newest value.
+#[derive(Debug, Clone)]
+pub struct Ewma {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ewma {
+    pub fn new(alpha: f64) -> Self {
+        Ewma {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    pub fn push(&mut self, x: f64) {
+        self.value = Some(match self.value {
+            Some(value) => value + self.alpha * (x - value),
+            None => x,
+        });
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+/// The most recent `capacity` values.
+#[derive(Debug, Clone)]
+pub struct SlidingWindow {
+    capacity: usize,
+    values: VecDeque<f64>,
+    sum: f64,
+}
+
+impl SlidingWindow {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        SlidingWindow {
+            capacity,
+            values: VecDeque::with_capacity(capacity),
+            sum: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, x: f64) {
+        if self.values.len() == self.capacity {
+            if let Some(oldest) = self.values.pop_front() {
+                self.sum -= oldest;
+            }
+        }
+        self.values.push_back(x);
+        self.sum += x;
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.values.len() == self.capacity
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (!self.values.is_empty()).then(|| self.sum / self.values.len() as f64)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &f64> {
+        self.values.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.sum = 0.0;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsSummary {
+    pub count: u64,
+    pub mean: f64,
+    pub std_dev: Option<f64>,
+    pub min: f64,
+    pub max: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub ewma: f64,
+    pub recent_mean: f64,
+}
+
+/// Bounded-memory replacement for accumulating every sample in a `Vec`.
+/// Non-finite values are counted and skipped rather than poisoning the mean.
+#[derive(Debug, Clone)]
+pub struct StreamingStats {
+    moments: Welford,
+    range: MinMax,
+    median: P2Quantile,
+    p95: P2Quantile,
+    ewma: Ewma,
+    recent: SlidingWindow,
+    skipped: u64,
+}
+
+impl StreamingStats {
+    pub fn new(recent_window: usize) -> Self {
+        StreamingStats {
+            moments: Welford::default(),
+            range: MinMax::default(),
+            median: P2Quantile::new(0.5),
+            p95: P2Quantile::new(0.95),
+            ewma: Ewma::new(0.1),
+            recent: SlidingWindow::new(recent_window),
+            skipped: 0,
+        }
+    }
+
+    pub fn push(&mut self, x: f64) {
+        if !x.is_finite() {
+            self.skipped += 1;
+            return;
+        }
+        self.moments.push(x);
+        self.range.push(x);
+        self.median.push(x);
+        self.p95.push(x);
+        self.ewma.push(x);
+        self.recent.push(x);
+    }
+
+    pub fn extend<I: IntoIterator<Item = f64>>(&mut self, values: I) {
+        for x in values {
+            self.push(x);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.moments.count()
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moments.count() == 0
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        self.moments.mean()
+    }
+
+    pub fn std_dev(&self) -> Option<f64> {
+        self.moments.std_dev()
+    }
+
+    pub fn recent(&self) -> &SlidingWindow {
+        &self.recent
+    }
+
+    /// `None` until at least one value has been seen.
+    pub fn summary(&self) -> Option<StatsSummary> {
+        Some(StatsSummary {
+            count: self.moments.count(),
+            mean: self.moments.mean()?,
+            std_dev: self.moments.std_dev(),
+            min: self.range.min()?,
+            max: self.range.max()?,
+            median: self.median.estimate()?,
+            p95: self.p95.estimate()?,
+            ewma: self.ewma.value()?,
+            recent_mean: self.recent.mean()?,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        *self = StreamingStats::new(self.recent.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    fn exact_quantile(values: &[f64], p: f64) -> f64 {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+    }
+
+    #[test]
+    fn welford_matches_two_pass_mean_and_variance() {
+        let mut rng = StdRng::seed_from_u64(7);
+        // A large offset is where the naive sum-of-squares formula loses precision.
+        let offsets: Vec<f64> = (0..10_000).map(|_| rng.gen_range(-50.0..50.0)).collect();
+        let mut moments = Welford::default();
+        offsets.iter().for_each(|x| moments.push(1e9 + x));
+
+        // The reference works on the offsets, so it is exact to well below the tolerances.
+        let offset_mean = offsets.iter().sum::<f64>() / offsets.len() as f64;
+        let variance = offsets.iter().map(|x| (x - offset_mean).powi(2)).sum::<f64>() / (offsets.len() - 1) as f64;
+        assert_eq!(moments.count(), 10_000);
+        assert!((moments.mean().unwrap() - (1e9 + offset_mean)).abs() < 1e-5);
+        assert!((moments.variance().unwrap() - variance).abs() / variance < 1e-9);
+        assert!((moments.std_dev().unwrap() - variance.sqrt()).abs() < 1e-6);
+
+        let mut small = Welford::default();
+        [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter().for_each(|x| small.push(*x));
+        assert_eq!(small.mean(), Some(5.0));
+        assert!((small.variance().unwrap() - 32.0 / 7.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn p2_quantiles_track_exact_quantiles() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut uniform: Vec<f64> = (0..10_000).map(f64::from).collect();
+        uniform.shuffle(&mut rng);
+        let normal_ish: Vec<f64> = (0..10_000).map(|_| (0..12).map(|_| rng.gen::<f64>()).sum::<f64>() - 6.0).collect();
+
+        for (values, spread) in [(&uniform, 10_000.0), (&normal_ish, 6.0)] {
+            for p in [0.1, 0.5, 0.95] {
+                let mut estimate = P2Quantile::new(p);
+                values.iter().for_each(|x| estimate.push(*x));
+                let (estimate, exact) = (estimate.estimate().unwrap(), exact_quantile(values, p));
+                assert!((estimate - exact).abs() < 0.01 * spread, "p{}: estimated {}, exact {}", p, estimate, exact);
+            }
+        }
+    }
+
+    #[test]
+    fn p2_is_exact_before_five_samples() {
+        let mut median = P2Quantile::new(0.5);
+        let mut p95 = P2Quantile::new(0.95);
+        assert_eq!(median.estimate(), None);
+        for x in [9.0, 1.0, 5.0] {
+            median.push(x);
+            p95.push(x);
+        }
+        assert_eq!(median.estimate(), Some(5.0));
+        assert_eq!(p95.estimate(), Some(9.0));
+    }
+
+    #[test]
+    fn empty_and_single_sample_state() {
+        let mut stats = StreamingStats::new(4);
+        assert!(stats.is_empty());
+        assert_eq!((stats.mean(), stats.std_dev(), stats.summary()), (None, None, None));
+        assert_eq!((Welford::default().mean(), Welford::default().variance()), (None, None));
+        assert_eq!((MinMax::default().min(), Ewma::new(0.5).value(), SlidingWindow::new(3).mean()), (None, None, None));
+
+        stats.push(f64::NAN);
+        assert!(stats.is_empty());
+        stats.push(2.5);
+        let summary = stats.summary().unwrap();
+        assert_eq!(
+            summary,
+            StatsSummary { count: 1, mean: 2.5, std_dev: None, min: 2.5, max: 2.5, median: 2.5, p95: 2.5, ewma: 2.5, recent_mean: 2.5 }
+        );
+        assert_eq!(stats.skipped(), 1);
+
+        stats.reset();
+        assert!(stats.is_empty() && stats.summary().is_none() && stats.skipped() == 0);
+    }
+
+    #[test]
+    fn streaming_stats_summarise_many_samples() {
+        let mut stats = StreamingStats::new(4);
+        stats.extend((1..=100).map(f64::from).chain([f64::INFINITY]));
+        let summary = stats.summary().unwrap();
+        assert_eq!((summary.count, summary.mean, summary.min, summary.max), (100, 50.5, 1.0, 100.0));
+        assert!((summary.std_dev.unwrap() - 29.011491975882016).abs() < 1e-9);
+        assert!((summary.median - 50.5).abs() <= 1.0);
+        assert!((summary.p95 - 95.0).abs() <= 1.5);
+        assert_eq!(summary.recent_mean, 98.5);
+        assert_eq!(stats.recent().iter().copied().collect::<Vec<_>>(), vec![97.0, 98.0, 99.0, 100.0]);
+        assert_eq!(stats.skipped(), 1);
+    }
+}
@@ -0,0 +1,353 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use rand::Rng;
+
+/// Standard BLE Battery Service and its Battery Level characteristic.
+pub const BATTERY_SERVICE_UUID: u16 = 0x180F;
+pub const BATTERY_LEVEL_CHARACTERISTIC_UUID: u16 = 0x2A19;
+
+/// Decodes a Battery Level characteristic value (a single byte, 0-100 %).
+pub fn decode_battery_level(value: &[u8]) -> Result<u8, String> {
+    match value {
+        [level] if *level <= 100 => Ok(*level),
+        [level] => Err(format!("Battery level out of range: {}", level)),
+        _ => Err(format!("Battery level must be 1 byte, got {}", value.len())),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TelemetrySample {
+    pub elapsed: Duration,
+    /// Number of data samples recorded when this telemetry was taken.
+    pub sample_index: usize,
+    /// `None` if the reading failed.
+    pub battery_level: Option<u8>,
+    /// `None` if the reading failed.
+    pub rssi: Option<i16>,
+    pub packet_loss_rate: f32,
+    pub electrode_contact: Vec<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TelemetryThresholds {
+    pub low_battery: u8,
+    pub safe_stop_battery: u8,
+    pub min_rssi: i16,
+    pub max_packet_loss: f32,
+}
+
+impl Default for TelemetryThresholds {
+    fn default() -> Self {
+        TelemetryThresholds {
+            low_battery: 20,
+            safe_stop_battery: 5,
+            min_rssi: -85,
+            max_packet_loss: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryWarning {
+    LowBattery(u8),
+    WeakSignal(i16),
+    PacketLoss(f32),
+    ElectrodeContactLost(Vec<usize>),
+}
+
+#[async_trait]
+pub trait TelemetrySource: Debug + Send {
+    /// Raw value of the Battery Level characteristic.
+    async fn read_battery_level(&mut self) -> Result<Vec<u8>, String>;
+    async fn read_rssi(&mut self) -> Result<i16, String>;
+    /// Cumulative (received, expected) packet counts since connection.
+    fn packet_counters(&mut self) -> (u64, u64);
+    fn electrode_contact(&self) -> Vec<bool>;
+}
+
+#[derive(Debug)]
+pub struct TelemetryMonitor {
+    thresholds: TelemetryThresholds,
+    started: Mutex<Instant>,
+    last_counters: Mutex<(u64, u64)>,
+    samples: Mutex<Vec<TelemetrySample>>,
+    safe_stop: AtomicBool,
+}
+
+impl TelemetryMonitor {
+    pub fn new(thresholds: TelemetryThresholds) -> Self {
+        TelemetryMonitor {
+            thresholds,
+            started: Mutex::new(Instant::now()),
+            last_counters: Mutex::new((0, 0)),
+            samples: Mutex::new(vec![]),
+            safe_stop: AtomicBool::new(false),
+        }
+    }
+
+    /// Takes one telemetry reading, records it and returns any threshold warnings.
+    /// A battery at or below the safe-stop level latches `safe_stop_requested`. Failed reads
+    /// are logged and recorded as missing so a flaky characteristic never ends the session.
+    pub async fn poll(&self, device_id: &str, source: &mut dyn TelemetrySource, sample_index: usize) -> Vec<TelemetryWarning> {
+        let log_failure = |what: &str, e: String| println!("Telemetry read failed for device {}: {}: {}", device_id, what, e);
+        let battery_level = match source.read_battery_level().await.and_then(|value| decode_battery_level(&value)) {
+            Ok(level) => Some(level),
+            Err(e) => {
+                log_failure("battery level", e);
+                None
+            }
+        };
+        let rssi = match source.read_rssi().await {
+            Ok(rssi) => Some(rssi),
+            Err(e) => {
+                log_failure("RSSI", e);
+                None
+            }
+        };
+        let packet_loss_rate = {
+            let (received, expected) = source.packet_counters();
+            let mut last = self.last_counters.lock().unwrap();
+            let received_delta = received.saturating_sub(last.0);
+            let expected_delta = expected.saturating_sub(last.1);
+            *last = (received, expected);
+            if expected_delta == 0 {
+                0.0
+            } else {
+                1.0 - received_delta as f32 / expected_delta as f32
+            }
+        };
+        let electrode_contact = source.electrode_contact();
+
+        let mut warnings = vec![];
+        if let Some(level) = battery_level.filter(|level| *level <= self.thresholds.low_battery) {
+            warnings.push(TelemetryWarning::LowBattery(level));
+        }
+        if let Some(rssi) = rssi.filter(|rssi| *rssi < self.thresholds.min_rssi) {
+            warnings.push(TelemetryWarning::WeakSignal(rssi));
+        }
+        if packet_loss_rate > self.thresholds.max_packet_loss {
+            warnings.push(TelemetryWarning::PacketLoss(packet_loss_rate));
+        }
+        let lost: Vec<usize> = electrode_contact
+            .iter()
+            .enumerate()
+            .filter(|(_, in_contact)| !**in_contact)
+            .map(|(channel, _)| channel)
+            .collect();
+        if !lost.is_empty() {
+            warnings.push(TelemetryWarning::ElectrodeContactLost(lost));
+        }
+
+        for warning in &warnings {
+            println!("Telemetry warning for device {}: {:?}", device_id, warning);
+        }
+        if let Some(level) = battery_level.filter(|level| *level <= self.thresholds.safe_stop_battery) {
+            if !self.safe_stop.swap(true, Ordering::SeqCst) {
+                println!("Battery critical for device {} ({}%), requesting safe stop", device_id, level);
+            }
+        }
+
+        self.samples.lock().unwrap().push(TelemetrySample {
+            elapsed: self.started.lock().unwrap().elapsed(),
+            sample_index,
+            battery_level,
+            rssi,
+            packet_loss_rate,
+            electrode_contact,
+        });
+        warnings
+    }
+
+    pub fn thresholds(&self) -> &TelemetryThresholds {
+        &self.thresholds
+    }
+
+    pub fn safe_stop_requested(&self) -> bool {
+        self.safe_stop.load(Ordering::SeqCst)
+    }
+
+    pub fn samples(&self) -> Vec<TelemetrySample> {
+        self.samples.lock().unwrap().clone()
+    }
+
+    pub fn latest(&self) -> Option<TelemetrySample> {
+        self.samples.lock().unwrap().last().cloned()
+    }
+
+    /// Writes the readings as CSV, one row per poll. Failed readings are left empty and
+    /// electrode contact is one `1`/`0` digit per channel.
+    pub fn save_csv(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+        let mut out = BufWriter::new(file);
+        let io = |e: std::io::Error| e.to_string();
+        writeln!(out, "elapsed_secs,sample_index,battery_level,rssi,packet_loss_rate,electrode_contact").map_err(io)?;
+        for sample in self.samples.lock().unwrap().iter() {
+            let contact: String = sample.electrode_contact.iter().map(|in_contact| if *in_contact { '1' } else { '0' }).collect();
+            writeln!(
+                out,
+                "{:.3},{},{},{},{:.4},{}",
+                sample.elapsed.as_secs_f64(),
+                sample.sample_index,
+                sample.battery_level.map_or(String::new(), |level| level.to_string()),
+                sample.rssi.map_or(String::new(), |rssi| rssi.to_string()),
+                sample.packet_loss_rate,
+                contact
+            )
+            .map_err(io)?;
+        }
+        out.flush().map_err(io)
+    }
+
+    pub fn reset(&self) {
+        *self.started.lock().unwrap() = Instant::now();
+        self.samples.lock().unwrap().clear();
+        *self.last_counters.lock().unwrap() = (0, 0);
+        self.safe_stop.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Packets a simulated headset sends per second.
+const SIMULATED_PACKET_RATE: f64 = 50.0;
+
+/// Simulated headset telemetry: a draining battery, a jittery link and the occasional lost packet.
+#[derive(Debug)]
+pub struct SimulatedTelemetry {
+    battery_level: f32,
+    drain_per_read: f32,
+    connected: Instant,
+    received: u64,
+    expected: u64,
+    channels: usize,
+}
+
+impl SimulatedTelemetry {
+    pub fn new(battery_level: u8, drain_per_read: f32, channels: usize) -> Self {
+        SimulatedTelemetry {
+            battery_level: battery_level as f32,
+            drain_per_read,
+            connected: Instant::now(),
+            received: 0,
+            expected: 0,
+            channels,
+        }
+    }
+}
+
+#[async_trait]
+impl TelemetrySource for SimulatedTelemetry {
+    async fn read_battery_level(&mut self) -> Result<Vec<u8>, String> {
+        self.battery_level = (self.battery_level - self.drain_per_read).max(0.0);
+        Ok(vec![self.battery_level.round() as u8])
+    }
+
+    async fn read_rssi(&mut self) -> Result<i16, String> {
+        Ok(rand::thread_rng().gen_range(-90..-55))
+    }
+
+    /// Counts the packets the link carried since the last call, losing about 2 % of them.
+    fn packet_counters(&mut self) -> (u64, u64) {
+        let expected = (self.connected.elapsed().as_secs_f64() * SIMULATED_PACKET_RATE) as u64;
+        let mut rng = rand::thread_rng();
+        let arrived = (self.expected..expected).filter(|_| !rng.gen_bool(0.02)).count() as u64;
+        self.expected = expected;
+        self.received += arrived;
+        (self.received, self.expected)
+    }
+
+    fn electrode_contact(&self) -> Vec<bool> {
+        let mut rng = rand::thread_rng();
+        (0..self.channels).map(|_| rng.gen_bool(0.97)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scripted source: fixed readings, optionally failing reads, counters set by the test.
+    #[derive(Debug, Default)]
+    struct ScriptedSource {
+        battery: Option<u8>,
+        rssi: Option<i16>,
+        counters: (u64, u64),
+    }
+
+    #[async_trait]
+    impl TelemetrySource for ScriptedSource {
+        async fn read_battery_level(&mut self) -> Result<Vec<u8>, String> {
+            self.battery.map(|level| vec![level]).ok_or_else(|| "GATT read timed out".to_string())
+        }
+
+        async fn read_rssi(&mut self) -> Result<i16, String> {
+            self.rssi.ok_or_else(|| "not connected".to_string())
+        }
+
+        fn packet_counters(&mut self) -> (u64, u64) {
+            self.counters
+        }
+
+        fn electrode_contact(&self) -> Vec<bool> {
+            vec![true, false]
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_reads_are_recorded_as_missing() {
+        let monitor = TelemetryMonitor::new(TelemetryThresholds::default());
+        let mut source = ScriptedSource { battery: None, rssi: Some(-95), counters: (3, 4) };
+        let warnings = monitor.poll("T1", &mut source, 0).await;
+        assert_eq!(warnings, vec![TelemetryWarning::WeakSignal(-95), TelemetryWarning::PacketLoss(0.25), TelemetryWarning::ElectrodeContactLost(vec![1])]);
+
+        source.battery = Some(3);
+        source.rssi = None;
+        monitor.poll("T1", &mut source, 10).await;
+        let samples = monitor.samples();
+        assert_eq!((samples[0].battery_level, samples[0].rssi), (None, Some(-95)));
+        assert_eq!((samples[1].battery_level, samples[1].rssi), (Some(3), None));
+        assert_eq!(monitor.latest().map(|latest| latest.sample_index), Some(10));
+        assert!(monitor.safe_stop_requested());
+    }
+
+    #[tokio::test]
+    async fn simulated_rssi_reads_leave_packet_counters_alone() {
+        let mut source = SimulatedTelemetry::new(100, 0.5, 4);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let before = source.packet_counters();
+        for _ in 0..10 {
+            source.read_rssi().await.unwrap();
+        }
+        assert_eq!((source.received, source.expected), before);
+        assert!(before.1 >= 5 && before.0 <= before.1);
+    }
+
+    #[tokio::test]
+    async fn reset_restarts_the_clock() {
+        let monitor = TelemetryMonitor::new(TelemetryThresholds::default());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        monitor.reset();
+        monitor.poll("T1", &mut ScriptedSource { battery: Some(80), rssi: Some(-60), ..Default::default() }, 0).await;
+        assert!(monitor.samples()[0].elapsed < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn csv_has_one_row_per_poll() {
+        let monitor = TelemetryMonitor::new(TelemetryThresholds::default());
+        let mut source = ScriptedSource { battery: Some(80), rssi: None, counters: (100, 100) };
+        monitor.poll("T1", &mut source, 0).await;
+        monitor.poll("T1", &mut source, 4).await;
+
+        let path = std::env::temp_dir().join(format!("bluerain-telemetry-test-{}.csv", std::process::id()));
+        monitor.save_csv(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[2].ends_with(",4,80,,0.0000,10"), "{}", rows[2]);
+    }
+}
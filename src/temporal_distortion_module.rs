@@ -1,25 +1,40 @@
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
 use tokio::task;
 use std::time::{Duration, Instant};
 use rand::Rng;
 
+#[allow(dead_code)]
+mod artifacts;
+#[allow(dead_code)]
+mod ring_buffer;
+#[allow(dead_code)]
+mod streaming_stats;
+
+use artifacts::{ArtifactDetector, ArtifactThresholds, SessionReport, StreamingScreener};
+use ring_buffer::{sample_buffer, Consumer, OverflowPolicy, Producer};
+use streaming_stats::StreamingStats;
+
 #[derive(Debug)]
 struct TemporalDistortionModule {
     device_id: String,
-    temporal_data: Arc<Mutex<Vec<f64>>>,
-    data_sender: mpsc::Sender<f64>,
+    temporal_data: Arc<Mutex<StreamingStats>>,
+    data_sender: Producer<f64>,
+    data_receiver: Mutex<Consumer<f64>>,
     timewarp_factor: Arc<Mutex<f64>>,
+    screener: Mutex<StreamingScreener>,
 }
 
 impl TemporalDistortionModule {
     fn new(device_id: &str) -> Self {
-        let (tx, _) = mpsc::channel(100);
+        let (tx, rx) = sample_buffer(1024, OverflowPolicy::DropOldest);
         TemporalDistortionModule {
             device_id: device_id.to_string(),
-            temporal_data: Arc::new(Mutex::new(vec![])),
+            temporal_data: Arc::new(Mutex::new(StreamingStats::new(100))),
             data_sender: tx,
+            data_receiver: Mutex::new(rx),
             timewarp_factor: Arc::new(Mutex::new(1.0)),
+            // Timewarp signals arrive at 10 Hz; screen them in 1-second windows.
+            screener: Mutex::new(StreamingScreener::new(ArtifactDetector::new(ArtifactThresholds::normalized(), 10.0, 10))),
         }
     }
 
@@ -31,28 +46,56 @@ impl TemporalDistortionModule {
 
     async fn generate_timewarp_signals(&self, duration: Duration) -> Result<(), String> {
         let start = Instant::now();
-        let mut rng = rand::thread_rng();
         while start.elapsed() < duration {
-            let signal = rng.gen_range(0.0..1.0);
-            self.data_sender.send(signal).await.unwrap();
+            let signal = rand::thread_rng().gen_range(0.0..1.0);
+            self.data_sender.send(signal).await.map_err(|_| "Sample buffer closed".to_string())?;
+            self.drain_samples();
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
         Ok(())
     }
 
-    fn evaluate_timewarp(&self) -> f64 {
-        let data = self.temporal_data.lock().unwrap();
-        let total: f64 = data.iter().sum();
-        total / data.len() as f64
+    /// Screens buffered samples and moves the clean windows into the running statistics.
+    fn drain_samples(&self) {
+        let mut screener = self.screener.lock().unwrap();
+        let mut stats = self.temporal_data.lock().unwrap();
+        let mut receiver = self.data_receiver.lock().unwrap();
+        receiver.drain(|batch| {
+            for signal in batch {
+                if let Some(clean) = screener.push(*signal) {
+                    stats.extend(clean);
+                }
+            }
+        });
+        let buffer = receiver.stats();
+        if buffer.dropped > 0 {
+            println!("Device {} overran its sample buffer: {} of {} samples dropped", self.device_id, buffer.dropped, buffer.accepted + buffer.dropped);
+        }
     }
 
-    async fn apply_temporal_distortion(&self) -> Result<(), String> {
-        let distortion_level = self.evaluate_timewarp();
-        let mut factor = self.timewarp_factor.lock().unwrap();
+    /// Mean of the clean windows and the share of windows rejected.
+    fn evaluate_timewarp(&self) -> SessionReport {
+        self.drain_samples();
+        let mut screener = self.screener.lock().unwrap();
+        if let Some(clean) = screener.finish() {
+            self.temporal_data.lock().unwrap().extend(clean);
+        }
+        screener.report(self.temporal_data.lock().unwrap().mean())
+    }
 
+    async fn apply_temporal_distortion(&self) -> Result<(), String> {
+        let report = self.evaluate_timewarp();
+        println!("Temporal data for device {}: {}", self.device_id, report.summary());
+        let distortion_level = match report.mean {
+            Some(level) => level,
+            None => {
+                println!("No clean timewarp signals for device {}", self.device_id);
+                return Ok(());
+            }
+        };
         if distortion_level > 0.75 {
             println!("High temporal distortion detected for device {}: {}", self.device_id, distortion_level);
-            *factor *= 2.0;  // Double the timewarp factor
+            *self.timewarp_factor.lock().unwrap() *= 2.0;  // Double the timewarp factor
             tokio::time::sleep(Duration::from_secs(3)).await;
         } else {
             println!("Minimal temporal distortion for device {}: {}", self.device_id, distortion_level);
@@ -62,8 +105,9 @@ impl TemporalDistortionModule {
     }
 
     fn reset_temporal_data(&self) {
-        let mut data = self.temporal_data.lock().unwrap();
-        data.clear();
+        self.data_receiver.lock().unwrap().drain(|_| {});
+        self.temporal_data.lock().unwrap().reset();
+        self.screener.lock().unwrap().reset();
     }
 }
 
@@ -78,8 +122,8 @@ async fn execute_temporal_distortion(device: &TemporalDistortionModule) -> Resul
 #[tokio::main]
 async fn main() -> Result<(), String> {
     let device = TemporalDistortionModule::new("TDM987");
-    let device_task = task::spawn(execute_temporal_distortion(&device));
+    let device_task = task::spawn(async move { execute_temporal_distortion(&device).await });
 
-    device_task.await??;
+    device_task.await.map_err(|e| e.to_string())??;
     Ok(())
 }
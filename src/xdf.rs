@@ -0,0 +1,832 @@
+//! XDF (Extensible Data Format) files: several streams, e.g. EEG, markers and other sensors,
+//! each with its own clock, in one file.
+//!
+//! A file is `XDF:` followed by chunks of `[length size][length][tag][content]`, all
+//! little-endian. Stream headers and footers are XML; samples are binary; clock offset chunks
+//! record how each stream's clock related to the recorder's, so readers can put every stream
+//! on one time base.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::session_store::{Segment, SessionEvent, StoredSession};
+use super::xml::{element, elements, escape, unescape};
+
+const MAGIC: &[u8] = b"XDF:";
+const TAG_FILE_HEADER: u16 = 1;
+const TAG_STREAM_HEADER: u16 = 2;
+const TAG_SAMPLES: u16 = 3;
+const TAG_CLOCK_OFFSET: u16 = 4;
+const TAG_BOUNDARY: u16 = 5;
+const TAG_STREAM_FOOTER: u16 = 6;
+/// Fixed content of boundary chunks, which let readers resynchronize in a damaged file.
+const BOUNDARY_UUID: [u8; 16] = [
+    0x43, 0xA5, 0x46, 0xDC, 0xCB, 0xF5, 0x41, 0x0F, 0xB3, 0x0E, 0xD5, 0x46, 0x73, 0x83, 0xCB, 0xE4,
+];
+/// Seconds of data between boundary chunks.
+const BOUNDARY_INTERVAL_SECS: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XdfFormat {
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Float32,
+    Double64,
+    String,
+}
+
+impl XdfFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            XdfFormat::Int8 => "int8",
+            XdfFormat::Int16 => "int16",
+            XdfFormat::Int32 => "int32",
+            XdfFormat::Int64 => "int64",
+            XdfFormat::Float32 => "float32",
+            XdfFormat::Double64 => "double64",
+            XdfFormat::String => "string",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "int8" => Ok(XdfFormat::Int8),
+            "int16" => Ok(XdfFormat::Int16),
+            "int32" => Ok(XdfFormat::Int32),
+            "int64" => Ok(XdfFormat::Int64),
+            "float32" => Ok(XdfFormat::Float32),
+            "double64" => Ok(XdfFormat::Double64),
+            "string" => Ok(XdfFormat::String),
+            other => Err(format!("XDF channel format '{}' is not supported", other)),
+        }
+    }
+
+    /// Fewest bytes one channel of one sample can take: strings need at least a length.
+    fn min_value_bytes(&self) -> usize {
+        match self {
+            XdfFormat::Int8 => 1,
+            XdfFormat::Int16 | XdfFormat::String => 2,
+            XdfFormat::Int32 | XdfFormat::Float32 => 4,
+            XdfFormat::Int64 | XdfFormat::Double64 => 8,
+        }
+    }
+}
+
+/// What a stream header says about its stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XdfStreamHeader {
+    pub name: String,
+    pub stream_type: String,
+    pub channel_count: usize,
+    /// Samples per second; 0 for irregular streams such as markers.
+    pub nominal_srate: f64,
+    pub channel_format: XdfFormat,
+    pub source_id: String,
+    /// Stream clock time when the stream was created.
+    pub created_at: f64,
+    /// May be empty, or shorter than the channel count in files from other recorders.
+    pub labels: Vec<String>,
+    pub unit: String,
+}
+
+impl XdfStreamHeader {
+    pub fn new(name: &str, stream_type: &str, channel_count: usize, nominal_srate: f64, channel_format: XdfFormat) -> Self {
+        XdfStreamHeader {
+            name: name.to_string(),
+            stream_type: stream_type.to_string(),
+            channel_count,
+            nominal_srate,
+            channel_format,
+            source_id: String::new(),
+            created_at: 0.0,
+            labels: vec![],
+            unit: String::new(),
+        }
+    }
+
+    pub fn with_source_id(mut self, source_id: &str) -> Self {
+        self.source_id = source_id.to_string();
+        self
+    }
+
+    pub fn with_created_at(mut self, created_at: f64) -> Self {
+        self.created_at = created_at;
+        self
+    }
+
+    pub fn with_channels(mut self, labels: &[String], unit: &str) -> Self {
+        self.labels = labels.to_vec();
+        self.unit = unit.to_string();
+        self
+    }
+
+    /// Channel labels, numbered when the header has none for some channels.
+    pub fn channel_labels(&self) -> Vec<String> {
+        if self.labels.len() == self.channel_count {
+            return self.labels.clone();
+        }
+        (1..=self.channel_count).map(|n| format!("Ch{}", n)).collect()
+    }
+
+    fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\"?>\n<info>\n");
+        let mut field = |tag: &str, value: &str| xml.push_str(&format!("\t<{0}>{1}</{0}>\n", tag, escape(value)));
+        field("name", &self.name);
+        field("type", &self.stream_type);
+        field("channel_count", &self.channel_count.to_string());
+        field("nominal_srate", &format!("{:.15}", self.nominal_srate));
+        field("channel_format", self.channel_format.name());
+        field("source_id", &self.source_id);
+        field("created_at", &format!("{:.15}", self.created_at));
+        if self.labels.is_empty() {
+            xml.push_str("\t<desc />\n</info>\n");
+            return xml;
+        }
+        xml.push_str("\t<desc>\n\t\t<channels>\n");
+        for label in &self.labels {
+            xml.push_str(&format!(
+                "\t\t\t<channel>\n\t\t\t\t<label>{}</label>\n\t\t\t\t<unit>{}</unit>\n\t\t\t\t<type>{}</type>\n\t\t\t</channel>\n",
+                escape(label),
+                escape(&self.unit),
+                escape(&self.stream_type)
+            ));
+        }
+        xml.push_str("\t\t</channels>\n\t</desc>\n</info>\n");
+        xml
+    }
+
+    fn from_xml(xml: &str) -> Result<Self, String> {
+        let info = element(xml, "info").ok_or("XDF stream header is not an <info> document")?;
+        // Header fields come before <desc>, whose free-form content may reuse their names.
+        let header = info.split("<desc").next().unwrap_or(info);
+        let text = |tag: &str| element(header, tag).map(unescape).unwrap_or_default();
+        let number = |tag: &str| -> Result<f64, String> {
+            text(tag)
+                .trim()
+                .parse()
+                .ok()
+                .filter(|value: &f64| value.is_finite())
+                .ok_or_else(|| format!("XDF stream header has an invalid <{}>", tag))
+        };
+        let channel_count = number("channel_count")?;
+        if channel_count < 0.0 || channel_count.fract() != 0.0 || channel_count > u32::MAX as f64 {
+            return Err(format!("XDF stream header has an invalid channel count {}", channel_count));
+        }
+        let nominal_srate = number("nominal_srate")?;
+        if nominal_srate < 0.0 {
+            return Err(format!("XDF stream header has a negative sample rate {}", nominal_srate));
+        }
+        let channels = element(info, "desc").and_then(|desc| element(desc, "channels")).map(|channels| elements(channels, "channel"));
+        let channel_text = |tag: &str| -> Vec<String> {
+            channels
+                .iter()
+                .flatten()
+                .map(|channel| element(channel, tag).map(unescape).unwrap_or_default())
+                .collect()
+        };
+        Ok(XdfStreamHeader {
+            name: text("name"),
+            stream_type: text("type"),
+            channel_count: channel_count as usize,
+            nominal_srate,
+            channel_format: XdfFormat::parse(text("channel_format").trim())?,
+            source_id: text("source_id"),
+            created_at: number("created_at").unwrap_or(0.0),
+            labels: channel_text("label"),
+            unit: channel_text("unit").into_iter().next().unwrap_or_default(),
+        })
+    }
+}
+
+/// Appends a variable-length integer: one byte giving its size (1, 4 or 8), then the value.
+fn put_varlen(out: &mut Vec<u8>, value: u64) {
+    if value <= u8::MAX as u64 {
+        out.push(1);
+        out.push(value as u8);
+    } else if value <= u32::MAX as u64 {
+        out.push(4);
+        out.extend_from_slice(&(value as u32).to_le_bytes());
+    } else {
+        out.push(8);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put_value(out: &mut Vec<u8>, format: XdfFormat, value: f64) {
+    match format {
+        XdfFormat::Int8 => out.extend_from_slice(&(value.round() as i8).to_le_bytes()),
+        XdfFormat::Int16 => out.extend_from_slice(&(value.round() as i16).to_le_bytes()),
+        XdfFormat::Int32 => out.extend_from_slice(&(value.round() as i32).to_le_bytes()),
+        XdfFormat::Int64 => out.extend_from_slice(&(value.round() as i64).to_le_bytes()),
+        XdfFormat::Float32 => out.extend_from_slice(&(value as f32).to_le_bytes()),
+        XdfFormat::Double64 | XdfFormat::String => out.extend_from_slice(&value.to_le_bytes()),
+    }
+}
+
+/// Running totals for a stream's footer.
+#[derive(Debug)]
+struct StreamTally {
+    header: XdfStreamHeader,
+    first_timestamp: Option<f64>,
+    last_timestamp: f64,
+    sample_count: u64,
+    clock_offsets: Vec<(f64, f64)>,
+}
+
+/// Writes an XDF file chunk by chunk, so long recordings never sit in memory. Streams get
+/// their footers from `finish`.
+#[derive(Debug)]
+pub struct XdfWriter {
+    out: BufWriter<File>,
+    streams: BTreeMap<u32, StreamTally>,
+    last_boundary: Option<f64>,
+}
+
+impl XdfWriter {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+        let mut writer = XdfWriter {
+            out: BufWriter::new(file),
+            streams: BTreeMap::new(),
+            last_boundary: None,
+        };
+        writer.out.write_all(MAGIC).map_err(|e| e.to_string())?;
+        writer.chunk(TAG_FILE_HEADER, b"<?xml version=\"1.0\"?>\n<info>\n\t<version>1.0</version>\n</info>\n")?;
+        Ok(writer)
+    }
+
+    fn chunk(&mut self, tag: u16, content: &[u8]) -> Result<(), String> {
+        let mut bytes = Vec::with_capacity(content.len() + 11);
+        put_varlen(&mut bytes, content.len() as u64 + 2);
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        bytes.extend_from_slice(content);
+        self.out.write_all(&bytes).map_err(|e| e.to_string())
+    }
+
+    fn stream(&mut self, id: u32) -> Result<&mut StreamTally, String> {
+        self.streams.get_mut(&id).ok_or_else(|| format!("XDF stream {} was never added", id))
+    }
+
+    /// Writes the stream's header and returns the id its samples are written under.
+    pub fn add_stream(&mut self, header: &XdfStreamHeader) -> Result<u32, String> {
+        let id = self.streams.keys().next_back().map_or(1, |last| last + 1);
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend_from_slice(header.to_xml().as_bytes());
+        self.chunk(TAG_STREAM_HEADER, &content)?;
+        self.streams.insert(
+            id,
+            StreamTally {
+                header: header.clone(),
+                first_timestamp: None,
+                last_timestamp: 0.0,
+                sample_count: 0,
+                clock_offsets: vec![],
+            },
+        );
+        Ok(id)
+    }
+
+    /// Writes numeric samples, each a timestamp on the stream's clock and one value per channel.
+    pub fn write_samples(&mut self, id: u32, samples: &[(f64, Vec<f64>)]) -> Result<(), String> {
+        let stream = self.stream(id)?;
+        let (format, channel_count) = (stream.header.channel_format, stream.header.channel_count);
+        if format == XdfFormat::String {
+            return Err(format!("XDF stream {} holds strings; write markers to it", stream.header.name));
+        }
+        if let Some((_, values)) = samples.iter().find(|(_, values)| values.len() != channel_count) {
+            return Err(format!("XDF stream {} has {} channels, got a sample with {}", stream.header.name, channel_count, values.len()));
+        }
+        let mut content = id.to_le_bytes().to_vec();
+        put_varlen(&mut content, samples.len() as u64);
+        for (timestamp, values) in samples {
+            content.push(8);
+            content.extend_from_slice(&timestamp.to_le_bytes());
+            for value in values {
+                put_value(&mut content, format, *value);
+            }
+        }
+        self.append(id, TAG_SAMPLES, &content, samples.iter().map(|(timestamp, _)| *timestamp))
+    }
+
+    /// Writes events to a single-channel string stream.
+    pub fn write_markers(&mut self, id: u32, markers: &[(f64, &str)]) -> Result<(), String> {
+        let stream = self.stream(id)?;
+        if stream.header.channel_format != XdfFormat::String || stream.header.channel_count != 1 {
+            return Err(format!("XDF stream {} is not a single-channel string stream", stream.header.name));
+        }
+        let mut content = id.to_le_bytes().to_vec();
+        put_varlen(&mut content, markers.len() as u64);
+        for (timestamp, marker) in markers {
+            content.push(8);
+            content.extend_from_slice(&timestamp.to_le_bytes());
+            put_varlen(&mut content, marker.len() as u64);
+            content.extend_from_slice(marker.as_bytes());
+        }
+        self.append(id, TAG_SAMPLES, &content, markers.iter().map(|(timestamp, _)| *timestamp))
+    }
+
+    fn append(&mut self, id: u32, tag: u16, content: &[u8], timestamps: impl Iterator<Item = f64>) -> Result<(), String> {
+        self.chunk(tag, content)?;
+        let stream = self.stream(id)?;
+        for timestamp in timestamps {
+            stream.first_timestamp.get_or_insert(timestamp);
+            stream.last_timestamp = timestamp;
+            stream.sample_count += 1;
+        }
+        let latest = stream.last_timestamp;
+        match self.last_boundary {
+            Some(boundary) if latest - boundary < BOUNDARY_INTERVAL_SECS => Ok(()),
+            Some(_) => {
+                self.last_boundary = Some(latest);
+                self.chunk(TAG_BOUNDARY, &BOUNDARY_UUID)
+            }
+            None => {
+                self.last_boundary = Some(latest);
+                Ok(())
+            }
+        }
+    }
+
+    /// Records that at `collection_time` on the recorder's clock, adding `offset` to the
+    /// stream's timestamps gave recorder time.
+    pub fn write_clock_offset(&mut self, id: u32, collection_time: f64, offset: f64) -> Result<(), String> {
+        self.stream(id)?.clock_offsets.push((collection_time, offset));
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend_from_slice(&collection_time.to_le_bytes());
+        content.extend_from_slice(&offset.to_le_bytes());
+        self.chunk(TAG_CLOCK_OFFSET, &content)
+    }
+
+    /// Writes every stream's footer and flushes the file.
+    pub fn finish(mut self) -> Result<(), String> {
+        let footers: Vec<(u32, String)> = self
+            .streams
+            .iter()
+            .map(|(id, stream)| {
+                let offsets: String = stream
+                    .clock_offsets
+                    .iter()
+                    .map(|(time, value)| format!("<offset><time>{:.15}</time><value>{:.15}</value></offset>", time, value))
+                    .collect();
+                let xml = format!(
+                    "<?xml version=\"1.0\"?>\n<info>\n\t<first_timestamp>{:.15}</first_timestamp>\n\t<last_timestamp>{:.15}</last_timestamp>\n\t<sample_count>{}</sample_count>\n\t<clock_offsets>{}</clock_offsets>\n</info>\n",
+                    stream.first_timestamp.unwrap_or(0.0),
+                    stream.last_timestamp,
+                    stream.sample_count,
+                    offsets
+                );
+                (*id, xml)
+            })
+            .collect();
+        for (id, xml) in footers {
+            let mut content = id.to_le_bytes().to_vec();
+            content.extend_from_slice(xml.as_bytes());
+            self.chunk(TAG_STREAM_FOOTER, &content)?;
+        }
+        self.out.flush().map_err(|e| e.to_string())
+    }
+}
+
+/// One stream read back from a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XdfStream {
+    pub id: u32,
+    pub header: XdfStreamHeader,
+    /// On the stream's own clock; see `synchronized_timestamps`.
+    pub timestamps: Vec<f64>,
+    /// One entry per sample for numeric streams.
+    pub values: Vec<Vec<f64>>,
+    /// One entry per sample for string streams.
+    pub strings: Vec<Vec<String>>,
+    /// (collection time, offset) pairs, in recorder clock seconds.
+    pub clock_offsets: Vec<(f64, f64)>,
+}
+
+impl XdfStream {
+    pub fn is_numeric(&self) -> bool {
+        self.header.channel_format != XdfFormat::String
+    }
+
+    /// Timestamps on the recorder's clock. Offsets are interpolated between measurements and
+    /// held constant before the first and after the last.
+    pub fn synchronized_timestamps(&self) -> Vec<f64> {
+        let offsets = &self.clock_offsets;
+        let offset_at = |timestamp: f64| -> f64 {
+            let (Some(first), Some(last)) = (offsets.first(), offsets.last()) else {
+                return 0.0;
+            };
+            // Measurements are made on the recorder's clock; compare in the stream's.
+            let next = offsets.partition_point(|(time, value)| time - value <= timestamp);
+            if next == 0 {
+                return first.1;
+            }
+            if next == offsets.len() {
+                return last.1;
+            }
+            let ((t0, v0), (t1, v1)) = (offsets[next - 1], offsets[next]);
+            let (s0, s1) = (t0 - v0, t1 - v1);
+            if s1 <= s0 {
+                return v1;
+            }
+            v0 + (v1 - v0) * (timestamp - s0) / (s1 - s0)
+        };
+        self.timestamps.iter().map(|timestamp| timestamp + offset_at(*timestamp)).collect()
+    }
+}
+
+/// Reads little-endian fields from a chunk.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset.saturating_add(count))
+            .ok_or_else(|| "XDF chunk is truncated".to_string())?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn varlen(&mut self) -> Result<u64, String> {
+        match self.array::<1>()?[0] {
+            1 => Ok(self.array::<1>()?[0] as u64),
+            4 => Ok(u32::from_le_bytes(self.array()?) as u64),
+            8 => Ok(u64::from_le_bytes(self.array()?)),
+            other => Err(format!("XDF has an invalid length size {}", other)),
+        }
+    }
+
+    fn value(&mut self, format: XdfFormat) -> Result<f64, String> {
+        Ok(match format {
+            XdfFormat::Int8 => i8::from_le_bytes(self.array()?) as f64,
+            XdfFormat::Int16 => i16::from_le_bytes(self.array()?) as f64,
+            XdfFormat::Int32 => i32::from_le_bytes(self.array()?) as f64,
+            XdfFormat::Int64 => i64::from_le_bytes(self.array()?) as f64,
+            XdfFormat::Float32 => f32::from_le_bytes(self.array()?) as f64,
+            XdfFormat::Double64 | XdfFormat::String => f64::from_le_bytes(self.array()?),
+        })
+    }
+}
+
+fn read_samples(content: &mut Cursor, stream: &mut XdfStream) -> Result<(), String> {
+    let count = content.varlen()?;
+    let format = stream.header.channel_format;
+    // Counts come from the file, so check they fit in the chunk before allocating for them.
+    let remaining = content.bytes.len() - content.offset;
+    let too_big = stream
+        .header
+        .channel_count
+        .checked_mul(format.min_value_bytes())
+        .and_then(|per_sample| per_sample.checked_add(1))
+        .and_then(|per_sample| per_sample.checked_mul(usize::try_from(count).ok()?))
+        .is_none_or(|needed| needed > remaining);
+    if too_big {
+        return Err(format!(
+            "XDF stream {} claims {} samples of {} channels in a {}-byte chunk",
+            stream.header.name, count, stream.header.channel_count, remaining
+        ));
+    }
+    let interval = if stream.header.nominal_srate > 0.0 { 1.0 / stream.header.nominal_srate } else { 0.0 };
+    for _ in 0..count {
+        let timestamp = match content.array::<1>()?[0] {
+            8 => f64::from_le_bytes(content.array()?),
+            // Left out when it follows from the previous sample and the nominal rate.
+            0 => stream.timestamps.last().map_or(0.0, |last| last + interval),
+            other => return Err(format!("XDF sample has an invalid timestamp size {}", other)),
+        };
+        stream.timestamps.push(timestamp);
+        if format == XdfFormat::String {
+            let mut sample = Vec::with_capacity(stream.header.channel_count);
+            for _ in 0..stream.header.channel_count {
+                let length = content.varlen()? as usize;
+                sample.push(String::from_utf8_lossy(content.take(length)?).to_string());
+            }
+            stream.strings.push(sample);
+        } else {
+            let sample = (0..stream.header.channel_count).map(|_| content.value(format)).collect::<Result<_, _>>()?;
+            stream.values.push(sample);
+        }
+    }
+    Ok(())
+}
+
+/// Reads every stream in a file. A recording cut off mid-chunk keeps what was complete.
+pub fn read_xdf(path: &Path) -> Result<Vec<XdfStream>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    if !bytes.starts_with(MAGIC) {
+        return Err(format!("{} is not an XDF file", path.display()));
+    }
+    let mut file = Cursor { bytes: &bytes, offset: MAGIC.len() };
+    let mut streams: BTreeMap<u32, XdfStream> = BTreeMap::new();
+    while file.offset < bytes.len() {
+        let Ok(length) = file.varlen() else { break };
+        let Ok(chunk) = file.take(length as usize) else { break };
+        let mut chunk = Cursor { bytes: chunk, offset: 0 };
+        let tag = u16::from_le_bytes(chunk.array()?);
+        if !matches!(tag, TAG_STREAM_HEADER | TAG_SAMPLES | TAG_CLOCK_OFFSET) {
+            // File header, boundaries and footers carry nothing we need; unknown tags are skipped.
+            continue;
+        }
+        let id = u32::from_le_bytes(chunk.array()?);
+        if tag == TAG_STREAM_HEADER {
+            let header = XdfStreamHeader::from_xml(&String::from_utf8_lossy(&chunk.bytes[chunk.offset..]))?;
+            streams.insert(
+                id,
+                XdfStream {
+                    id,
+                    header,
+                    timestamps: vec![],
+                    values: vec![],
+                    strings: vec![],
+                    clock_offsets: vec![],
+                },
+            );
+            continue;
+        }
+        let stream = streams.get_mut(&id).ok_or_else(|| format!("XDF has data for stream {} before its header", id))?;
+        if tag == TAG_SAMPLES {
+            read_samples(&mut chunk, stream)?;
+        } else {
+            let collection_time = f64::from_le_bytes(chunk.array()?);
+            let offset = f64::from_le_bytes(chunk.array()?);
+            stream.clock_offsets.push((collection_time, offset));
+        }
+    }
+    Ok(streams.into_values().collect())
+}
+
+/// Writes a session as an EEG stream plus, when it has events, a marker stream carrying them.
+/// Values are stored as double64 so exports round-trip exactly.
+pub fn write_session(session: &StoredSession, path: &Path) -> Result<(), String> {
+    if session.channels.is_empty() {
+        return Err("Session has no channels".to_string());
+    }
+    let mut writer = XdfWriter::create(path)?;
+    let eeg = XdfStreamHeader::new(&session.device_id, "EEG", session.channels.len(), session.sample_rate, XdfFormat::Double64)
+        .with_source_id(&session.device_id)
+        .with_channels(&session.channels, "microvolts");
+    let eeg = writer.add_stream(&eeg)?;
+    let markers = if session.events.is_empty() {
+        None
+    } else {
+        let header = XdfStreamHeader::new("Markers", "Markers", 1, 0.0, XdfFormat::String)
+            .with_source_id(&format!("{}-markers", session.device_id));
+        Some(writer.add_stream(&header)?)
+    };
+
+    // Samples go out a second at a time, with the events that fall in it, so streams interleave.
+    let per_chunk = (session.sample_rate.round() as usize).max(1);
+    let mut events = session.events.iter().peekable();
+    for segment in &session.segments {
+        for (i, chunk) in segment.frames.chunks(per_chunk).enumerate() {
+            let start = segment.start_secs + (i * per_chunk) as f64 / session.sample_rate;
+            let samples: Vec<(f64, Vec<f64>)> = chunk
+                .iter()
+                .enumerate()
+                .map(|(j, frame)| (start + j as f64 / session.sample_rate, frame.clone()))
+                .collect();
+            writer.write_samples(eeg, &samples)?;
+            let end = start + chunk.len() as f64 / session.sample_rate;
+            let due: Vec<(f64, &str)> = std::iter::from_fn(|| events.next_if(|event| event.time_secs < end))
+                .map(|event| (event.time_secs, event.label.as_str()))
+                .collect();
+            if let (Some(markers), false) = (markers, due.is_empty()) {
+                writer.write_markers(markers, &due)?;
+            }
+        }
+    }
+    let rest: Vec<(f64, &str)> = events.map(|event| (event.time_secs, event.label.as_str())).collect();
+    if let (Some(markers), false) = (markers, rest.is_empty()) {
+        writer.write_markers(markers, &rest)?;
+    }
+    writer.finish()
+}
+
+/// Reads one numeric stream as a session: `stream` by name, or else the first EEG stream, or
+/// else the first numeric one. Every string stream's samples become events, and all times are
+/// synchronized and counted from the earliest sample in the file. A break in the timestamps
+/// longer than 1.5 sample intervals starts a new segment.
+pub fn read_session(path: &Path, stream: Option<&str>) -> Result<StoredSession, String> {
+    let streams = read_xdf(path)?;
+    let chosen = match stream {
+        Some(name) => streams.iter().find(|candidate| candidate.header.name == name).ok_or_else(|| {
+            let names: Vec<&str> = streams.iter().map(|stream| stream.header.name.as_str()).collect();
+            format!("XDF file has no stream named '{}' (streams: {})", name, names.join(", "))
+        })?,
+        None => streams
+            .iter()
+            .find(|candidate| candidate.is_numeric() && candidate.header.stream_type.eq_ignore_ascii_case("EEG"))
+            .or_else(|| streams.iter().find(|candidate| candidate.is_numeric()))
+            .ok_or("XDF file has no numeric streams")?,
+    };
+    if !chosen.is_numeric() {
+        return Err(format!("XDF stream {} holds strings, not samples", chosen.header.name));
+    }
+    let sample_rate = chosen.header.nominal_srate;
+    if sample_rate <= 0.0 {
+        return Err(format!("XDF stream {} has no nominal sample rate", chosen.header.name));
+    }
+
+    let synchronized: Vec<(&XdfStream, Vec<f64>)> = streams.iter().map(|stream| (stream, stream.synchronized_timestamps())).collect();
+    let origin = synchronized
+        .iter()
+        .filter_map(|(_, timestamps)| timestamps.first().copied())
+        .fold(f64::INFINITY, f64::min);
+    let device_id = if chosen.header.source_id.is_empty() { &chosen.header.name } else { &chosen.header.source_id };
+    let mut session = StoredSession::new(device_id, sample_rate, chosen.header.channel_labels());
+    for (stream, timestamps) in &synchronized {
+        if stream.id == chosen.id {
+            let mut previous: Option<f64> = None;
+            for (timestamp, values) in timestamps.iter().zip(&stream.values) {
+                let time_secs = timestamp - origin;
+                if previous.is_none_or(|previous| time_secs - previous > 1.5 / sample_rate) {
+                    session.segments.push(Segment { start_secs: time_secs, frames: vec![] });
+                }
+                if let Some(segment) = session.segments.last_mut() {
+                    segment.frames.push(values.clone());
+                }
+                previous = Some(time_secs);
+            }
+        } else if !stream.is_numeric() {
+            session.events.extend(timestamps.iter().zip(&stream.strings).map(|(timestamp, sample)| SessionEvent {
+                time_secs: timestamp - origin,
+                label: sample.join(" "),
+            }));
+        }
+    }
+    session.events.sort_by(|a, b| a.time_secs.total_cmp(&b.time_secs));
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bluerain-xdf-test-{}-{}.xdf", std::process::id(), name))
+    }
+
+    /// Builds a file by hand, for contents `XdfWriter` refuses to produce.
+    fn raw_file(chunks: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for (tag, content) in chunks {
+            put_varlen(&mut bytes, content.len() as u64 + 2);
+            bytes.extend_from_slice(&tag.to_le_bytes());
+            bytes.extend_from_slice(content);
+        }
+        bytes
+    }
+
+    fn header_chunk(id: u32, channel_count: &str, format: &str) -> (u16, Vec<u8>) {
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend_from_slice(
+            format!(
+                "<info><name>Raw</name><type>EEG</type><channel_count>{}</channel_count><nominal_srate>250</nominal_srate><channel_format>{}</channel_format></info>",
+                channel_count, format
+            )
+            .as_bytes(),
+        );
+        (TAG_STREAM_HEADER, content)
+    }
+
+    #[test]
+    fn streams_round_trip_through_the_writer() {
+        let path = temp_path("round-trip");
+        let labels = vec!["C3".to_string(), "C4 <ref>".to_string()];
+        let mut writer = XdfWriter::create(&path).unwrap();
+        let eeg_header = XdfStreamHeader::new("EEG & co", "EEG", 2, 250.0, XdfFormat::Float32)
+            .with_source_id("headset-1")
+            .with_created_at(12.5)
+            .with_channels(&labels, "microvolts");
+        let counts_header = XdfStreamHeader::new("Counts", "Misc", 1, 10.0, XdfFormat::Int16);
+        let markers_header = XdfStreamHeader::new("Markers", "Markers", 1, 0.0, XdfFormat::String);
+        let eeg = writer.add_stream(&eeg_header).unwrap();
+        let counts = writer.add_stream(&counts_header).unwrap();
+        let markers = writer.add_stream(&markers_header).unwrap();
+        let eeg_samples: Vec<(f64, Vec<f64>)> = (0..500).map(|i| (i as f64 / 250.0, vec![i as f64 * 0.5, -(i as f64)])).collect();
+        for chunk in eeg_samples.chunks(100) {
+            writer.write_samples(eeg, chunk).unwrap();
+            writer.write_samples(counts, &[(chunk[0].0, vec![chunk[0].1[1]])]).unwrap();
+        }
+        writer.write_markers(markers, &[(0.5, "eyes closed"), (1.5, "eyes open")]).unwrap();
+        writer.write_clock_offset(eeg, 1.0, 0.25).unwrap();
+        assert!(writer.write_markers(eeg, &[(0.0, "wrong stream")]).is_err());
+        assert!(writer.write_samples(eeg, &[(0.0, vec![1.0])]).is_err());
+        writer.finish().unwrap();
+
+        let streams = read_xdf(&path).unwrap();
+        assert_eq!(streams.len(), 3);
+        let [read_eeg, read_counts, read_markers] = &streams[..] else { unreachable!() };
+        assert_eq!(read_eeg.header, eeg_header);
+        assert_eq!(read_eeg.header.channel_labels(), labels);
+        assert_eq!(read_eeg.timestamps, eeg_samples.iter().map(|(timestamp, _)| *timestamp).collect::<Vec<_>>());
+        assert_eq!(read_eeg.values, eeg_samples.iter().map(|(_, values)| values.clone()).collect::<Vec<_>>());
+        assert_eq!(read_eeg.clock_offsets, vec![(1.0, 0.25)]);
+        assert_eq!(read_counts.header, counts_header);
+        assert_eq!(read_counts.values, vec![vec![0.0], vec![-100.0], vec![-200.0], vec![-300.0], vec![-400.0]]);
+        assert_eq!(read_markers.timestamps, vec![0.5, 1.5]);
+        assert_eq!(read_markers.strings, vec![vec!["eyes closed".to_string()], vec!["eyes open".to_string()]]);
+        assert!(read_markers.values.is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sessions_round_trip_with_gaps_and_events() {
+        let path = temp_path("session");
+        let mut session = StoredSession::new("headset-2", 100.0, vec!["Fz".into(), "Pz".into()]);
+        session.segments.push(Segment { start_secs: 0.0, frames: (0..150).map(|i| vec![i as f64, 0.5]).collect() });
+        session.segments.push(Segment { start_secs: 3.0, frames: (0..50).map(|i| vec![-(i as f64), 1.5]).collect() });
+        session.events.push(SessionEvent { time_secs: 0.2, label: "start".into() });
+        session.events.push(SessionEvent { time_secs: 3.1, label: "after gap".into() });
+        write_session(&session, &path).unwrap();
+
+        let read = read_session(&path, None).unwrap();
+        assert_eq!((read.device_id.as_str(), read.sample_rate, &read.channels), ("headset-2", 100.0, &session.channels));
+        assert_eq!(read.segments.len(), 2);
+        for (read, written) in read.segments.iter().zip(&session.segments) {
+            assert!((read.start_secs - written.start_secs).abs() < 1e-9);
+            assert_eq!(read.frames, written.frames);
+        }
+        assert_eq!(read.events, session.events);
+        assert!(read_session(&path, Some("Markers")).is_err());
+        assert!(read_session(&path, Some("Missing")).unwrap_err().contains("headset-2, Markers"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn clock_offsets_are_interpolated_and_held_at_the_ends() {
+        let stream = XdfStream {
+            id: 1,
+            header: XdfStreamHeader::new("EEG", "EEG", 1, 1.0, XdfFormat::Double64),
+            timestamps: vec![-1.0, 0.0, 5.0, 10.0, 12.0],
+            values: vec![vec![0.0]; 5],
+            strings: vec![],
+            // At stream times 0 and 10 the offset was 5 and then 10 seconds.
+            clock_offsets: vec![(5.0, 5.0), (20.0, 10.0)],
+        };
+        let synchronized = stream.synchronized_timestamps();
+        let expected = [4.0, 5.0, 12.5, 20.0, 22.0];
+        for (actual, expected) in synchronized.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-9, "{:?}", synchronized);
+        }
+
+        let unsynchronized = XdfStream { clock_offsets: vec![], ..stream };
+        assert_eq!(unsynchronized.synchronized_timestamps(), unsynchronized.timestamps);
+    }
+
+    #[test]
+    fn truncated_files_keep_complete_chunks() {
+        let path = temp_path("truncated");
+        let mut writer = XdfWriter::create(&path).unwrap();
+        let eeg = writer.add_stream(&XdfStreamHeader::new("EEG", "EEG", 1, 10.0, XdfFormat::Double64)).unwrap();
+        writer.write_samples(eeg, &[(0.0, vec![1.0]), (0.1, vec![2.0])]).unwrap();
+        writer.write_samples(eeg, &[(0.2, vec![3.0]), (0.3, vec![4.0])]).unwrap();
+        writer.finish().unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        // Cut inside the second samples chunk (footers come last).
+        let second_chunk = bytes.windows(8).rposition(|window| window == 0.2f64.to_le_bytes()).unwrap();
+        fs::write(&path, &bytes[..second_chunk]).unwrap();
+        let streams = read_xdf(&path).unwrap();
+        assert_eq!(streams[0].values, vec![vec![1.0], vec![2.0]]);
+
+        fs::write(&path, &bytes[..MAGIC.len() + 1]).unwrap();
+        assert!(read_xdf(&path).unwrap().is_empty());
+        fs::write(&path, b"XD").unwrap();
+        assert!(read_xdf(&path).unwrap_err().contains("not an XDF file"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn implausible_counts_are_rejected_without_allocating() {
+        let path = temp_path("counts");
+        for channel_count in ["1e18", "-1", "NaN", "inf", "2.5"] {
+            fs::write(&path, raw_file(&[header_chunk(1, channel_count, "string")])).unwrap();
+            let error = read_xdf(&path).unwrap_err();
+            assert!(error.contains("channel"), "{}: {}", channel_count, error);
+        }
+
+        // A header within limits, then a samples chunk promising far more than it holds.
+        let mut samples = 1u32.to_le_bytes().to_vec();
+        put_varlen(&mut samples, u64::MAX);
+        samples.push(0);
+        fs::write(&path, raw_file(&[header_chunk(1, "4000000000", "string"), (TAG_SAMPLES, samples.clone())])).unwrap();
+        assert!(read_xdf(&path).unwrap_err().contains("claims"));
+        fs::write(&path, raw_file(&[header_chunk(1, "2", "double64"), (TAG_SAMPLES, samples)])).unwrap();
+        assert!(read_xdf(&path).unwrap_err().contains("claims"));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,40 @@
+//! The little XML the LSL and XDF formats need: flat elements, no namespaces or CDATA.
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/// Content of the first `<tag>...</tag>`; `Some("")` for `<tag />`.
+pub fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    elements(xml, tag).into_iter().next()
+}
+
+pub fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}", tag), format!("</{}>", tag));
+    let mut found = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        // Skip longer tags sharing the prefix, e.g. <channels> when looking for <channel>.
+        let Some(end_of_tag) = after.find('>') else { break };
+        let attributes = &after[..end_of_tag];
+        if !(attributes.is_empty() || attributes.starts_with(' ') || attributes.starts_with('/')) {
+            rest = after;
+            continue;
+        }
+        if attributes.ends_with('/') {
+            found.push("");
+            rest = &after[end_of_tag + 1..];
+            continue;
+        }
+        let body = &after[end_of_tag + 1..];
+        let Some(end) = body.find(&close) else { break };
+        found.push(&body[..end]);
+        rest = &body[end + close.len()..];
+    }
+    found
+}